@@ -1,5 +1,7 @@
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Context;
 use figment::Figment;
@@ -78,6 +80,16 @@ impl ServerConfig {
 	pub async fn load_users_config(&self) -> anyhow::Result<UsersConfig> {
 		Self::load_config(self.paths.config_dir.join(USERS_CONFIG_NAME)).await
 	}
+	
+	pub async fn save_users_config(&self, users_config: &UsersConfig) -> anyhow::Result<()> {
+		let data = serde_yaml::to_string(users_config).context("Serializing users config")?;
+		
+		tokio::fs::create_dir_all(&self.paths.config_dir).await?;
+		
+		// Losing this to a crash in the middle of a write would lock everyone out
+		utils::write_data_file(&self.paths.config_dir.join(USERS_CONFIG_NAME), data.as_bytes()).await
+			.context("Writing users config")
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -101,6 +113,12 @@ pub struct GeneralConfig {
 	pub transcoding: TranscodingConfig,
 	pub transcription: TranscriptionConfig,
 	pub caches: CachesConfig,
+	pub forward_auth: ForwardAuthConfig,
+	pub oidc: OidcConfig,
+	pub watch_history: WatchHistoryConfig,
+	pub scrobble: ScrobbleConfig,
+	pub viewing_stats: ViewingStatsConfig,
+	pub prewarm: PrewarmConfig,
 	pub show_hidden_files: bool,
 }
 
@@ -111,6 +129,12 @@ impl Default for GeneralConfig {
 			transcoding: TranscodingConfig::default(),
 			transcription: TranscriptionConfig::default(),
 			caches: CachesConfig::default(),
+			forward_auth: ForwardAuthConfig::default(),
+			oidc: OidcConfig::default(),
+			watch_history: WatchHistoryConfig::default(),
+			scrobble: ScrobbleConfig::default(),
+			viewing_stats: ViewingStatsConfig::default(),
+			prewarm: PrewarmConfig::default(),
 			show_hidden_files: false,
 		}
 	}
@@ -151,6 +175,11 @@ pub enum TranscodingBackend {
 pub struct TranscodingConfig {
 	pub backend: TranscodingBackend,
 	pub concurrent_tasks: usize,
+	pub min_prefetch_segments: usize,
+	pub max_prefetch_segments: usize,
+	#[serde(deserialize_with = "utils::deserialize_suffixed_number")]
+	pub prefetch_budget: u64,
+	pub prefetch_idle_timeout: u64,
 }
 
 impl Default for TranscodingConfig {
@@ -158,6 +187,10 @@ impl Default for TranscodingConfig {
 		Self {
 			backend: TranscodingBackend::Software,
 			concurrent_tasks: 2,
+			min_prefetch_segments: 1,
+			max_prefetch_segments: 6,
+			prefetch_budget: 50_000_000, // 50 MB
+			prefetch_idle_timeout: 30,
 		}
 	}
 }
@@ -181,6 +214,10 @@ impl Default for TranscriptionConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct CachesConfig {
+	// Where finished entries are kept. The cache dirs are still used while generating.
+	pub storage: CacheStorageBackend,
+	pub s3: S3StorageConfig,
+	
 	pub segments_cache_dir: PathBuf,
 	#[serde(deserialize_with = "utils::deserialize_suffixed_number")]
 	pub segments_cache_size_limit: u64,
@@ -204,11 +241,34 @@ pub struct CachesConfig {
 	pub auto_subtitles_cache_dir: PathBuf,
 	#[serde(deserialize_with = "utils::deserialize_suffixed_number")]
 	pub auto_subtitles_cache_size_limit: u64,
+	
+	// Entries that haven't been used for this many days are removed, however much space there is
+	pub max_entry_age_days: Option<u64>,
+	pub budget: CacheBudgetConfig,
+}
+
+impl CachesConfig {
+	// The caches' own limits are ignored when the budget is enabled, its min sizes and weights divide up the space
+	//  instead. No cache can grow past the whole budget though.
+	pub fn size_limit(&self, cache_size_limit: u64) -> u64 {
+		if self.budget.enabled {
+			self.budget.size_limit
+		} else {
+			cache_size_limit
+		}
+	}
+	
+	pub fn max_entry_age(&self) -> Option<Duration> {
+		self.max_entry_age_days.map(|days| Duration::from_secs(days * 24 * 60 * 60))
+	}
 }
 
 impl Default for CachesConfig {
 	fn default() -> Self {
 		Self {
+			storage: CacheStorageBackend::Filesystem,
+			s3: S3StorageConfig::default(),
+			
 			segments_cache_dir: PathBuf::from("transcoded-segments"),
 			segments_cache_size_limit: 5_000_000_000, // 5 GB
 			
@@ -226,10 +286,222 @@ impl Default for CachesConfig {
 			
 			auto_subtitles_cache_dir: PathBuf::from("auto-subtitles"),
 			auto_subtitles_cache_size_limit: 100_000_000, // 100 MB
+			
+			max_entry_age_days: None,
+			budget: CacheBudgetConfig::default(),
+		}
+	}
+}
+
+// A limit on the total size of all caches, which replaces their own limits, so that space one cache isn't using can be
+//  used by the others. When it's exceeded, the entries that have gone unused the longest are evicted first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheBudgetConfig {
+	pub enabled: bool,
+	#[serde(deserialize_with = "utils::deserialize_suffixed_number")]
+	pub size_limit: u64,
+	// By cache name, e.g. segments or thumbnails
+	pub caches: HashMap<String, CacheShareConfig>,
+}
+
+impl Default for CacheBudgetConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			size_limit: 10_000_000_000, // 10 GB
+			caches: HashMap::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheShareConfig {
+	// Never evicted for the sake of other caches while the cache is this size or smaller
+	#[serde(default, deserialize_with = "utils::deserialize_suffixed_number")]
+	pub min_size: u64,
+	// Entries of a cache with a higher weight are kept longer, a weight of 2 treats them as if they were last used
+	//  half as long ago. 1 if not set.
+	pub weight: Option<f64>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStorageBackend {
+	Filesystem,
+	// An S3 compatible object store, which lets several servers share what they generate
+	S3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3StorageConfig {
+	// e.g. https://s3.us-east-1.amazonaws.com or http://minio:9000, buckets are addressed by path
+	pub endpoint: String,
+	pub bucket: String,
+	pub region: String,
+	pub access_key: String,
+	pub secret_key: String,
+	// Put in front of the name of each cache's directory to get the prefix of its objects
+	pub prefix: String,
+}
+
+impl Default for S3StorageConfig {
+	fn default() -> Self {
+		Self {
+			endpoint: String::new(),
+			bucket: String::new(),
+			region: "us-east-1".to_owned(),
+			access_key: String::new(),
+			secret_key: String::new(),
+			prefix: String::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardAuthConfig {
+	pub enabled: bool,
+	pub username_header: String,
+	pub trusted_proxies: Vec<IpAddr>,
+	pub auto_provision_users: bool,
+	pub default_allowed_libraries: Vec<String>,
+}
+
+impl Default for ForwardAuthConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			username_header: "Remote-User".to_owned(),
+			trusted_proxies: vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+			auto_provision_users: false,
+			default_allowed_libraries: Vec::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+	pub enabled: bool,
+	pub issuer_url: String,
+	pub client_id: String,
+	pub client_secret: Option<String>,
+	// Must point at /api/oidc/callback on the address users reach the server at
+	pub redirect_url: String,
+	pub scopes: Vec<String>,
+	pub username_claim: String,
+	pub display_name_claim: String,
+	pub groups_claim: String,
+	// Maps group names to the libraries members of that group can see. If this is non-empty, the allowed
+	//  libraries of non-admin users logging in through OIDC are replaced on every login.
+	pub group_libraries: HashMap<String, Vec<String>>,
+	pub auto_provision_users: bool,
+	pub default_allowed_libraries: Vec<String>,
+}
+
+impl Default for OidcConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			issuer_url: String::new(),
+			client_id: String::new(),
+			client_secret: None,
+			redirect_url: String::new(),
+			scopes: vec!["openid".to_owned(), "profile".to_owned(), "email".to_owned()],
+			username_claim: "preferred_username".to_owned(),
+			display_name_claim: "name".to_owned(),
+			groups_claim: "groups".to_owned(),
+			group_libraries: HashMap::new(),
+			auto_provision_users: false,
+			default_allowed_libraries: Vec::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchHistoryConfig {
+	// Fraction of a video's duration after which it counts as finished, so that skipping the credits still does
+	pub finished_threshold: f64,
+}
+
+impl Default for WatchHistoryConfig {
+	fn default() -> Self {
+		Self {
+			finished_threshold: 0.9,
 		}
 	}
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewingStatsConfig {
+	pub enabled: bool,
+	// Sessions older than this are dropped
+	pub retention_days: u32,
+	// Oldest sessions are dropped past this many, to keep the file from growing without bound on busy servers
+	pub max_sessions: usize,
+}
+
+impl Default for ViewingStatsConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			retention_days: 365,
+			max_sessions: 100_000,
+		}
+	}
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrobbleConfig {
+	pub webhooks: Vec<ScrobbleWebhookConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrobbleWebhookConfig {
+	pub url: String,
+	// Sent with every request, e.g. for an API key
+	#[serde(default)]
+	pub headers: HashMap<String, String>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrewarmConfig {
+	pub jobs: Vec<PrewarmJobConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrewarmJobConfig {
+	pub id: String,
+	pub library: String,
+	// Directory within the library, the whole library if empty
+	#[serde(default)]
+	pub path: String,
+	#[serde(default)]
+	pub thumbnails: bool,
+	#[serde(default)]
+	pub scaled_thumbnails: bool,
+	#[serde(default)]
+	pub thumbnail_sheets: bool,
+	// Segments from the start of every video to transcode, at each of the quality levels
+	#[serde(default)]
+	pub hls_segments: usize,
+	#[serde(default)]
+	pub quality_levels: Vec<String>,
+	// Runs at startup and then this often. Jobs without a trigger only run when started through the API.
+	pub interval_hours: Option<u64>,
+	// Runs once nothing has been played for this long, and stops as soon as something is
+	pub idle_minutes: Option<u64>,
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct LibrariesConfig {
@@ -243,6 +515,19 @@ pub struct LibraryConfig {
 	pub display_name: String,
 	pub path: PathBuf,
 	pub global_connections_file: Option<PathBuf>,
+	#[serde(default)]
+	pub cache_keys: CacheKeyStrategy,
+}
+
+// How files are identified in the caches
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKeyStrategy {
+	// The file's path, size and modification time. Cheap, but moving or touching the file regenerates everything.
+	#[default]
+	Metadata,
+	// Samples of the file's contents, so that cached artifacts survive the library being moved or remounted
+	Content,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -257,6 +542,86 @@ pub struct UserConfig {
 	pub id: String,
 	pub display_name: String,
 	pub username: String,
-	pub password: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub password: Option<String>,
+	/// Argon2 hash of the password, takes precedence over `password`. Users saved by the server only store this.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub password_hash: Option<String>,
 	pub allowed_libraries: Vec<String>,
+	#[serde(default)]
+	pub admin: bool,
+	#[serde(default)]
+	pub disabled: bool,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub path_rules: Vec<PathRuleConfig>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_content_rating: Option<ContentRating>,
+	// Hide videos without a rating when max_content_rating is set
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub block_unrated: bool,
+	// PIN that temporarily lifts the content rating restriction, hashed the same way as the password
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rating_pin: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rating_pin_hash: Option<String>,
+	// The OIDC identity that logs in as this user, set when the user was provisioned or linked by an admin
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub oidc_link: Option<OidcLinkConfig>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathRuleConfig {
+	pub library: String,
+	// Glob relative to the library root, supports * and ? within a path component and ** across components
+	pub path: String,
+	pub access: PathAccess,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathAccess {
+	Allow,
+	Deny,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcLinkConfig {
+	pub issuer: String,
+	// The sub claim, which unlike the username claim the provider never reassigns
+	pub subject: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum ContentRating {
+	#[serde(rename = "G")]
+	G,
+	#[serde(rename = "PG")]
+	PG,
+	#[serde(rename = "PG-13")]
+	PG13,
+	#[serde(rename = "R")]
+	R,
+	#[serde(rename = "NC-17")]
+	NC17,
+}
+
+impl ContentRating {
+	// Parses MPA and US TV ratings, as well as the "mpaa|PG-13|300|" format used by iTunes
+	pub fn from_label(label: &str) -> Option<Self> {
+		let label = match label.split('|').collect::<Vec<_>>().as_slice() {
+			[_system, rating, ..] => *rating,
+			_ => label,
+		};
+		
+		match label.trim().to_lowercase().as_str() {
+			"g" | "tv-y" | "tv-y7" | "tv-y7-fv" | "tv-g" => Some(Self::G),
+			"pg" | "tv-pg" => Some(Self::PG),
+			"pg-13" | "pg13" | "tv-14" => Some(Self::PG13),
+			"r" | "tv-ma" | "explicit" => Some(Self::R),
+			"nc-17" | "nc17" | "x" | "xxx" => Some(Self::NC17),
+			_ => None,
+		}
+	}
 }
@@ -1,9 +1,10 @@
 use argh::FromArgs;
 use std::path::PathBuf;
-use tracing::{info, Level};
+use tracing::{error, info, Level};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 use crate::config::ServerConfig;
+use crate::web_server::cache_admin;
 
 mod config;
 mod web_server;
@@ -19,6 +20,66 @@ struct Args {
 	/// path to the directory containing cache files
 	#[argh(option, default = "PathBuf::from(\"cache\")")]
 	cache_dir: PathBuf,
+	#[argh(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+	Cache(CacheCommand),
+}
+
+#[derive(FromArgs)]
+/// manage the artifact caches, which can be shared with a running server
+#[argh(subcommand, name = "cache")]
+struct CacheCommand {
+	#[argh(subcommand)]
+	action: CacheAction,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum CacheAction {
+	List(ListCachesArgs),
+	Purge(PurgeCacheArgs),
+	Scan(ScanCachesArgs),
+	Prewarm(PrewarmCacheArgs),
+}
+
+#[derive(FromArgs)]
+/// list the caches with their size, entry count and limit
+#[argh(subcommand, name = "list")]
+struct ListCachesArgs {}
+
+#[derive(FromArgs)]
+/// remove entries from a cache, or from all of them
+#[argh(subcommand, name = "purge")]
+struct PurgeCacheArgs {
+	/// name of the cache to purge, all caches when not given
+	#[argh(option)]
+	cache: Option<String>,
+	/// only remove what was generated from this media file
+	#[argh(option)]
+	media_file: Option<PathBuf>,
+}
+
+#[derive(FromArgs)]
+/// remove entries whose data or metadata files are missing
+#[argh(subcommand, name = "scan")]
+struct ScanCachesArgs {
+	/// name of the cache to scan, all caches when not given
+	#[argh(option)]
+	cache: Option<String>,
+}
+
+#[derive(FromArgs)]
+/// run one of the pre-warming jobs from general.yml and wait for it to finish
+#[argh(subcommand, name = "prewarm")]
+struct PrewarmCacheArgs {
+	/// id of the job to run
+	#[argh(positional)]
+	job: String,
 }
 
 #[tokio::main]
@@ -28,6 +89,25 @@ async fn main() {
 	
 	let args: Args = argh::from_env();
 	
+	if let Some(Command::Cache(cache_command)) = args.command {
+		let config = ServerConfig::load(args.data_dir, args.cache_dir).await.expect("Loading config");
+		
+		let result = match cache_command.action {
+			CacheAction::List(_) => cache_admin::list_caches(&config).await,
+			CacheAction::Purge(args) =>
+				cache_admin::purge_caches(&config, args.cache.as_deref(), args.media_file.as_deref()).await,
+			CacheAction::Scan(args) => cache_admin::scan_caches(&config, args.cache.as_deref()).await,
+			CacheAction::Prewarm(args) => cache_admin::run_prewarm_job(&config, &args.job).await,
+		};
+		
+		if let Err(err) = result {
+			error!("{:?}", err);
+			std::process::exit(1);
+		}
+		
+		return;
+	}
+	
 	info!("Starting server");
 	
 	let config = ServerConfig::load(args.data_dir, args.cache_dir).await.expect("Loading config");
@@ -3,13 +3,14 @@ use std::num::NonZeroUsize;
 use std::ops::Range;
 
 use anyhow::Context;
-use ffmpeg_next::{decoder, Discard, format, frame, Rational, Rescale, Stream, encoder, codec, StreamMut, rescale};
+use ffmpeg_next::{decoder, Discard, format, frame, Rational, Rescale, Stream, encoder, codec, StreamMut, rescale, Packet};
 use ffmpeg_next::packet::Mut;
 use ffmpeg_sys_next::{AVERROR, ENOMEM};
 use image::flat::SampleLayout;
 use image::FlatSamples;
 
 pub mod in_memory_muxer;
+pub mod writer_muxer;
 pub mod hardware_device;
 pub mod frame_scaler;
 pub mod resource_pool;
@@ -140,6 +141,12 @@ pub fn seek_to_bounds_beginning(
 	Ok(())
 }
 
+// Splits packets into those starting before the time and the rest, keeping their order
+pub fn split_packets_at(packets: Vec<Packet>, time: f64, time_base: Rational) -> (Vec<Packet>, Vec<Packet>) {
+	packets.into_iter()
+		.partition(|packet| packet.pts().is_none_or(|pts| scale_to_f64_secs(pts, time_base) < time))
+}
+
 pub fn av_error(code: c_int) -> Result<c_int, ffmpeg_next::Error> {
 	match code {
 		0.. => Ok(code),
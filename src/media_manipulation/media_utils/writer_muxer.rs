@@ -0,0 +1,114 @@
+use std::ffi::CString;
+use std::io;
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{null_mut, slice_from_raw_parts};
+use std::ptr;
+
+use ffmpeg_next::format;
+use ffmpeg_sys_next::{av_free, av_malloc, AVERROR_EXTERNAL, AVFMT_FLAG_CUSTOM_IO, avformat_alloc_output_context2, avio_alloc_context};
+use turbojpeg::libc;
+
+use crate::media_manipulation::media_utils::av_error;
+
+// Like InMemoryMuxer, but passes the output on to a writer as it's produced
+pub struct WriterMuxer<'a> {
+	output: format::context::Output,
+	boxed_state: Box<WriterState<'a>>,
+}
+
+struct WriterState<'a> {
+	writer: &'a mut dyn Write,
+	// FFmpeg only gets an error code, this keeps the actual error
+	error: Option<io::Error>,
+}
+
+impl<'a> WriterMuxer<'a> {
+	const BUFFER_SIZE: usize = 64 * 1024;
+	
+	pub fn new(format: &str, writer: &'a mut dyn Write) -> Result<Self, ffmpeg_next::Error> {
+		let mut boxed_state = Box::new(WriterState {
+			writer,
+			error: None,
+		});
+		
+		unsafe {
+			let box_ptr: *mut WriterState = boxed_state.as_mut();
+			let mut ctx = null_mut();
+			let format = CString::new(format).unwrap();
+			
+			av_error(avformat_alloc_output_context2(&mut ctx, ptr::null(), format.as_ptr(), ptr::null()))?;
+			
+			let buffer = av_malloc(Self::BUFFER_SIZE);
+			
+			let avio = avio_alloc_context(
+				buffer.cast(), Self::BUFFER_SIZE as _,
+				1,
+				box_ptr.cast(),
+				None,
+				Some(Self::write_packet),
+				None,
+			);
+			
+			(*ctx).pb = avio;
+			(*ctx).flags |= AVFMT_FLAG_CUSTOM_IO;
+			
+			let output = format::context::Output::wrap(ctx);
+			
+			Ok(Self {
+				output,
+				boxed_state,
+			})
+		}
+	}
+	
+	// The error behind a failed write, if there was one
+	pub fn take_write_error(&mut self) -> Option<io::Error> {
+		self.boxed_state.error.take()
+	}
+	
+	unsafe extern "C" fn write_packet(opaque: *mut libc::c_void, buf_ptr: *const u8, buf_size: libc::c_int) -> libc::c_int {
+		let state: &mut WriterState = unsafe { &mut *opaque.cast() };
+		
+		if state.error.is_some() {
+			return AVERROR_EXTERNAL;
+		}
+		
+		let buf = unsafe { &*slice_from_raw_parts(buf_ptr, buf_size as usize) };
+		
+		match state.writer.write_all(buf) {
+			Ok(()) => buf_size,
+			Err(err) => {
+				state.error = Some(err);
+				
+				AVERROR_EXTERNAL
+			}
+		}
+	}
+}
+
+impl Deref for WriterMuxer<'_> {
+	type Target = format::context::Output;
+	
+	fn deref(&self) -> &Self::Target {
+		&self.output
+	}
+}
+
+impl DerefMut for WriterMuxer<'_> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut self.output
+	}
+}
+
+impl Drop for WriterMuxer<'_> {
+	fn drop(&mut self) {
+		unsafe {
+			let avio = (*self.as_mut_ptr()).pb;
+			(*self.as_mut_ptr()).pb = null_mut();
+			
+			av_free((*avio).buffer.cast());
+			av_free(avio.cast());
+		}
+	}
+}
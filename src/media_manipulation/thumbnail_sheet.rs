@@ -1,12 +1,15 @@
+use std::ffi::c_int;
+use std::io;
+use std::io::Write;
 use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use anyhow::Context;
-use bytes::Bytes;
+use anyhow::{anyhow, bail, Context};
 use ffmpeg_next::{decoder, format, frame, media, rescale, Discard, Rescale};
 use ffmpeg_sys_next::AV_CODEC_FLAG_COPY_OPAQUE;
 use image::{GenericImage, Rgb, RgbImage};
+use libwebp_sys::{WebPConfig, WebPEncode, WebPPicture, WebPPictureFree, WebPPictureImportRGB};
 use serde::{Deserialize, Serialize};
 
 use crate::media_manipulation::backends::{BackendFactory, VideoDecoderParams};
@@ -46,7 +49,11 @@ pub fn calculate_sheet_params(video_duration: Duration, video_width: u32, video_
 	}
 }
 
-pub fn generate_sheet(backend_factory: &impl BackendFactory, media_path: PathBuf) -> anyhow::Result<(Bytes, ThumbnailSheetParams)> {
+pub fn generate_sheet(
+	backend_factory: &impl BackendFactory,
+	media_path: PathBuf,
+	output: &mut impl Write,
+) -> anyhow::Result<ThumbnailSheetParams> {
 	let mut demuxer = format::input(&media_path).context("Opening video file")?;
 	
 	let video_stream = demuxer.streams().best(media::Type::Video).unwrap();
@@ -112,13 +119,74 @@ pub fn generate_sheet(backend_factory: &impl BackendFactory, media_path: PathBuf
 	decoder.send_eof()?;
 	receive_frames(&mut decoder)?;
 	
-	let final_image = sprite_sheet.into();
+	encode_webp(&sprite_sheet, WEBP_QUALITY, output)?;
 	
-	let encoder = webp::Encoder::from_image(&final_image)
-		.map_err(|err| anyhow::anyhow!("webp error: {}", err))?;
+	Ok(sheet_params)
+}
+
+struct WebPWriterState<'a> {
+	writer: &'a mut dyn Write,
+	// libwebp only gets told that the write failed, this keeps the actual error
+	error: Option<io::Error>,
+}
+
+// Sheets are large, so the encoded image goes straight to the output instead of being built up in memory first
+fn encode_webp(image: &RgbImage, quality: f32, output: &mut dyn Write) -> anyhow::Result<()> {
+	let mut config = WebPConfig::new().map_err(|_| anyhow!("Initializing WebP config"))?;
+	config.quality = quality;
+	
+	let mut picture = WebPPicture::new().map_err(|_| anyhow!("Initializing WebP picture"))?;
+	picture.use_argb = 1;
+	picture.width = image.width() as i32;
+	picture.height = image.height() as i32;
+	
+	let mut state = WebPWriterState {
+		writer: output,
+		error: None,
+	};
 	
-	let encoded_image = encoder.encode(WEBP_QUALITY);
-	let output_bytes = encoded_image.to_vec().into();
+	let (encoded, error_code) = unsafe {
+		if WebPPictureImportRGB(&mut picture, image.as_raw().as_ptr(), image.width() as i32 * 3) == 0 {
+			WebPPictureFree(&mut picture);
+			bail!("Importing sheet into WebP picture");
+		}
+		
+		picture.writer = Some(write_webp_data);
+		picture.custom_ptr = (&raw mut state).cast();
+		
+		let encoded = WebPEncode(&config, &mut picture) != 0;
+		let error_code = picture.error_code;
+		
+		WebPPictureFree(&mut picture);
+		
+		(encoded, error_code)
+	};
 	
-	Ok((output_bytes, sheet_params))
+	if let Some(err) = state.error {
+		return Err(anyhow::Error::new(err).context("Writing sheet"));
+	}
+	
+	if !encoded {
+		bail!("WebP encoding failed: {:?}", error_code);
+	}
+	
+	Ok(())
+}
+
+unsafe extern "C" fn write_webp_data(data: *const u8, data_size: usize, picture: *const WebPPicture) -> c_int {
+	if data_size == 0 {
+		return 1;
+	}
+	
+	let state: &mut WebPWriterState = unsafe { &mut *(*picture).custom_ptr.cast() };
+	let data = unsafe { std::slice::from_raw_parts(data, data_size) };
+	
+	match state.writer.write_all(data) {
+		Ok(()) => 1,
+		Err(err) => {
+			state.error = Some(err);
+			
+			0
+		}
+	}
 }
\ No newline at end of file
@@ -201,6 +201,13 @@ impl AudioTranscoder {
 		Ok(())
 	}
 	
+	pub fn has_output_after(&self, time: f64) -> bool {
+		let scaled_time = media_utils::scale_from_f64_secs(time, self.rate_time_base);
+		
+		self.output_packet_queue.iter()
+			.any(|packet| packet.pts().is_some_and(|pts| pts >= scaled_time))
+	}
+	
 	pub fn add_output_stream(&mut self, muxer: &mut format::context::Output) -> anyhow::Result<()> {
 		let mut out_stream = media_utils::add_output_stream(muxer, &self.encoder)?;
 		out_stream.set_time_base(self.rate_time_base);
@@ -210,11 +217,17 @@ impl AudioTranscoder {
 		Ok(())
 	}
 	
-	pub fn write_output_packets(&mut self, muxer: &mut format::context::Output) -> anyhow::Result<()> {
+	// Packets starting at or after end_time are kept for the next output
+	pub fn write_output_packets(&mut self, muxer: &mut format::context::Output, end_time: f64) -> anyhow::Result<()> {
 		let Some(out_stream_index) = self.out_stream_index else { return Ok(()); };
 		let stream_time_base = muxer.stream(out_stream_index).expect("Unknown stream").time_base();
 		
-		for mut out_packet in self.output_packet_queue.drain(..) {
+		let (to_write, remaining) = media_utils::split_packets_at(
+			std::mem::take(&mut self.output_packet_queue), end_time, self.rate_time_base);
+		
+		self.output_packet_queue = remaining;
+		
+		for mut out_packet in to_write {
 			out_packet.rescale_ts(self.rate_time_base, stream_time_base);
 			out_packet.set_stream(out_stream_index);
 			
@@ -1,19 +1,21 @@
+use std::io::Write;
 use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use anyhow::{anyhow, Context};
-use bytes::Bytes;
+use anyhow::{anyhow, bail, Context};
 use ffmpeg_next::{codec, encoder, format, media, rescale, Dictionary, Rescale};
 
 use crate::media_manipulation::backends::BackendFactory;
 use crate::media_manipulation::media_utils;
-use crate::media_manipulation::media_utils::in_memory_muxer::InMemoryMuxer;
+use crate::media_manipulation::media_utils::writer_muxer::WriterMuxer;
 use crate::media_manipulation::media_utils::scale_from_f64_secs;
 use crate::media_manipulation::transcoding::audio::{AudioTranscoder, AudioTranscoderParams};
 use crate::media_manipulation::transcoding::video::{VideoTranscoder, VideoTranscoderParams};
 
 mod audio;
 mod video;
+pub mod session;
 pub mod subtitle;
 
 const START_PADDING: f64 = 0.3;
@@ -31,55 +33,21 @@ pub struct TranscodingOptions<'a> {
 	pub video_codec: codec::Id,
 	pub video_bitrate: usize,
 	pub audio_bitrate: usize,
+	// Checked between packets, transcoding stops early once it's set
+	pub cancel_flag: &'a AtomicBool,
 }
 
-pub fn transcode_segment(opts: TranscodingOptions, mut time_bounds: Range<f64>) -> anyhow::Result<Bytes> {
+// Writes the segment to the output as it's muxed
+pub fn transcode_segment(opts: TranscodingOptions, mut time_bounds: Range<f64>, output: &mut dyn Write) -> anyhow::Result<()> {
 	let mut demuxer = format::input(&opts.media_path).context("Opening video file")?;
-	let mut muxer = InMemoryMuxer::new("mpegts").context("Opening output")?;
+	let muxer = WriterMuxer::new("mpegts", output).context("Opening output")?;
 	
-	let mut video_stream_index = usize::MAX;
-	let mut audio_stream_index = usize::MAX;
-	
-	let mut video_transcoder = None;
-	let mut audio_transcoder = None;
-	
-	if let Some(video_stream) = demuxer.streams().best(media::Type::Video) {
-		let video_backend = opts.backend_factory.create_video_backend()
-			.context("Creating video backend")?;
-		
-		let params = VideoTranscoderParams {
-			in_stream: &video_stream,
-			muxer: &muxer,
-			backend: video_backend,
-			output_codec: opts.video_codec,
-			target_height: opts.target_video_height,
-			bit_rate: opts.video_bitrate,
-			encoder_options: Dictionary::new(),
-		};
-		
-		video_stream_index = video_stream.index();
-		video_transcoder = Some(VideoTranscoder::new(params).context("Creating video transcoder")?);
-	}
-	
-	if let Some(audio_stream) = demuxer.streams().best(media::Type::Audio) {
-		let audio_codec = encoder::find(codec::Id::AAC).unwrap().audio()
-			.context("Getting audio codec")?;
-		
-		let params = AudioTranscoderParams {
-			in_stream: &audio_stream,
-			muxer: &muxer,
-			encoder_codec: audio_codec,
-			bit_rate: opts.audio_bitrate,
-			encoder_options: Dictionary::new(),
-		};
-		
-		audio_stream_index = audio_stream.index();
-		audio_transcoder = Some(AudioTranscoder::new(params).context("Creating audio transcoder")?);
-	}
-	
-	if video_transcoder.is_none() && audio_transcoder.is_none() {
-		return Err(anyhow!("Media has neither audio nor video"));
-	}
+	let StreamTranscoders {
+		video_stream_index,
+		audio_stream_index,
+		mut video_transcoder,
+		mut audio_transcoder,
+	} = create_transcoders(&opts, &demuxer, &muxer)?;
 	
 	media_utils::seek_to_bounds_beginning(&mut demuxer, &mut time_bounds, START_PADDING).context("Seeking")?;
 	
@@ -90,6 +58,10 @@ pub fn transcode_segment(opts: TranscodingOptions, mut time_bounds: Range<f64>)
 	let mut has_more_audio = audio_transcoder.is_some();
 	
 	for (stream, packet) in demuxer.packets() {
+		if opts.cancel_flag.load(Ordering::Relaxed) {
+			bail!("Transcoding cancelled");
+		}
+		
 		if [video_stream_index, audio_stream_index].contains(&stream.index()) {
 			let dts = packet.dts()
 				.ok_or_else(|| anyhow!("Video/audio packet had no DTS"))?;
@@ -131,12 +103,101 @@ pub fn transcode_segment(opts: TranscodingOptions, mut time_bounds: Range<f64>)
 	
 	// Now mux
 	
+	mux_segment(muxer, video_transcoder.as_mut(), audio_transcoder.as_mut(), f64::INFINITY)
+}
+
+struct StreamTranscoders {
+	video_stream_index: usize,
+	audio_stream_index: usize,
+	video_transcoder: Option<VideoTranscoder>,
+	audio_transcoder: Option<AudioTranscoder>,
+}
+
+fn create_transcoders(
+	opts: &TranscodingOptions,
+	demuxer: &format::context::Input,
+	muxer: &format::context::Output,
+) -> anyhow::Result<StreamTranscoders> {
+	let mut video_stream_index = usize::MAX;
+	let mut audio_stream_index = usize::MAX;
+	
+	let mut video_transcoder = None;
+	let mut audio_transcoder = None;
+	
+	if let Some(video_stream) = demuxer.streams().best(media::Type::Video) {
+		let video_backend = opts.backend_factory.create_video_backend()
+			.context("Creating video backend")?;
+		
+		let params = VideoTranscoderParams {
+			in_stream: &video_stream,
+			muxer,
+			backend: video_backend,
+			output_codec: opts.video_codec,
+			target_height: opts.target_video_height,
+			bit_rate: opts.video_bitrate,
+			encoder_options: Dictionary::new(),
+		};
+		
+		video_stream_index = video_stream.index();
+		video_transcoder = Some(VideoTranscoder::new(params).context("Creating video transcoder")?);
+	}
+	
+	if let Some(audio_stream) = demuxer.streams().best(media::Type::Audio) {
+		let audio_codec = encoder::find(codec::Id::AAC).unwrap().audio()
+			.context("Getting audio codec")?;
+		
+		let params = AudioTranscoderParams {
+			in_stream: &audio_stream,
+			muxer,
+			encoder_codec: audio_codec,
+			bit_rate: opts.audio_bitrate,
+			encoder_options: Dictionary::new(),
+		};
+		
+		audio_stream_index = audio_stream.index();
+		audio_transcoder = Some(AudioTranscoder::new(params).context("Creating audio transcoder")?);
+	}
+	
+	if video_transcoder.is_none() && audio_transcoder.is_none() {
+		return Err(anyhow!("Media has neither audio nor video"));
+	}
+	
+	Ok(StreamTranscoders {
+		video_stream_index,
+		audio_stream_index,
+		video_transcoder,
+		audio_transcoder,
+	})
+}
+
+// Writes the transcoded packets that start before end_time as an MPEG-TS segment
+fn mux_segment(
+	mut muxer: WriterMuxer,
+	video_transcoder: Option<&mut VideoTranscoder>,
+	audio_transcoder: Option<&mut AudioTranscoder>,
+	end_time: f64,
+) -> anyhow::Result<()> {
+	let result = write_segment(&mut muxer, video_transcoder, audio_transcoder, end_time);
+	
+	// A failed write shows up as an FFmpeg error, the actual cause is more useful
+	match muxer.take_write_error() {
+		Some(err) => Err(anyhow::Error::new(err).context("Writing segment")),
+		None => result,
+	}
+}
+
+fn write_segment(
+	muxer: &mut format::context::Output,
+	mut video_transcoder: Option<&mut VideoTranscoder>,
+	mut audio_transcoder: Option<&mut AudioTranscoder>,
+	end_time: f64,
+) -> anyhow::Result<()> {
 	if let Some(ref mut video_transcoder) = video_transcoder {
-		video_transcoder.add_output_stream(&mut muxer).context("Adding video output stream")?;
+		video_transcoder.add_output_stream(muxer).context("Adding video output stream")?;
 	}
 	
 	if let Some(ref mut audio_transcoder) = audio_transcoder {
-		audio_transcoder.add_output_stream(&mut muxer).context("Adding video output stream")?;
+		audio_transcoder.add_output_stream(muxer).context("Adding video output stream")?;
 	}
 	
 	let mut mux_options = Dictionary::new();
@@ -145,14 +206,14 @@ pub fn transcode_segment(opts: TranscodingOptions, mut time_bounds: Range<f64>)
 	muxer.write_header_with(mux_options).context("Writing header")?;
 	
 	if let Some(ref mut video_transcoder) = video_transcoder {
-		video_transcoder.write_output_packets(&mut muxer).context("Writing video packets")?;
+		video_transcoder.write_output_packets(muxer, end_time).context("Writing video packets")?;
 	}
 	
 	if let Some(ref mut audio_transcoder) = audio_transcoder {
-		audio_transcoder.write_output_packets(&mut muxer).context("Writing audio packets")?;
+		audio_transcoder.write_output_packets(muxer, end_time).context("Writing audio packets")?;
 	}
 	
 	muxer.write_trailer().context("Writing trailer")?;
 	
-	Ok(muxer.into_output_buffer().into())
-}
\ No newline at end of file
+	Ok(())
+}
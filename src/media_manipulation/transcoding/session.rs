@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{bail, Context};
+use ffmpeg_next::format;
+
+use crate::media_manipulation::media_utils;
+use crate::media_manipulation::media_utils::in_memory_muxer::InMemoryMuxer;
+use crate::media_manipulation::media_utils::writer_muxer::WriterMuxer;
+use crate::media_manipulation::transcoding::{create_transcoders, mux_segment, StreamTranscoders, TranscodingOptions, START_PADDING};
+
+// Sessions read until every stream has output past the end of the requested segment, rather than up to a fixed
+//  end, so this only needs to be longer than any media
+const MAX_SESSION_LENGTH: f64 = 100.0 * 60.0 * 60.0;
+
+// Keeps one demuxer, decoder and encoder running across consecutive segments, instead of opening the file, seeking
+//  and setting up the codecs again for every segment
+pub struct TranscodingSession {
+	demuxer: format::context::Input,
+	transcoders: StreamTranscoders,
+	time_bounds: Range<f64>,
+	input_finished: bool,
+}
+
+impl TranscodingSession {
+	// The cancel flag in the options isn't used, each segment is given its own instead
+	pub fn new(opts: TranscodingOptions, start_time: f64) -> anyhow::Result<Self> {
+		let mut demuxer = format::input(&opts.media_path).context("Opening video file")?;
+		
+		// Only used to decide the output format flags, each segment gets its own muxer
+		let muxer = InMemoryMuxer::new("mpegts").context("Opening output")?;
+		let transcoders = create_transcoders(&opts, &demuxer, &muxer)?;
+		
+		let mut time_bounds = start_time..(start_time + MAX_SESSION_LENGTH);
+		media_utils::seek_to_bounds_beginning(&mut demuxer, &mut time_bounds, START_PADDING).context("Seeking")?;
+		
+		Ok(Self {
+			demuxer,
+			transcoders,
+			time_bounds,
+			input_finished: false,
+		})
+	}
+	
+	// Transcodes from where the previous segment ended up to end_time, writing the segment to the output. The session
+	//  can't be used anymore if this fails.
+	pub fn transcode_next_segment(&mut self, end_time: f64, cancel_flag: &AtomicBool, output: &mut dyn Write) -> anyhow::Result<()> {
+		let transcoders = &mut self.transcoders;
+		
+		// The next segment has to start with a keyframe
+		if let Some(video_transcoder) = &mut transcoders.video_transcoder {
+			video_transcoder.force_keyframe_at(end_time);
+		}
+		
+		while !self.input_finished {
+			if cancel_flag.load(Ordering::Relaxed) {
+				bail!("Transcoding cancelled");
+			}
+			
+			let video_done = transcoders.video_transcoder.as_ref()
+				.is_none_or(|transcoder| transcoder.has_output_after(end_time));
+			let audio_done = transcoders.audio_transcoder.as_ref()
+				.is_none_or(|transcoder| transcoder.has_output_after(end_time));
+			
+			if video_done && audio_done {
+				break;
+			}
+			
+			let Some((stream, packet)) = self.demuxer.packets().next() else {
+				if let Some(video_transcoder) = &mut transcoders.video_transcoder {
+					video_transcoder.send_eof(self.time_bounds.clone()).context("Flushing video")?;
+				}
+				
+				if let Some(audio_transcoder) = &mut transcoders.audio_transcoder {
+					audio_transcoder.send_eof(self.time_bounds.clone()).context("Flushing audio")?;
+				}
+				
+				self.input_finished = true;
+				break;
+			};
+			
+			if stream.index() == transcoders.video_stream_index
+				&& let Some(video_transcoder) = &mut transcoders.video_transcoder
+			{
+				video_transcoder.receive_input_packet(&stream, packet, self.time_bounds.clone())
+					.context("Processing video packet")?;
+			}
+			else if stream.index() == transcoders.audio_stream_index
+				&& let Some(audio_transcoder) = &mut transcoders.audio_transcoder
+			{
+				audio_transcoder.receive_input_packet(&stream, packet, self.time_bounds.clone())
+					.context("Processing audio packet")?;
+			}
+		}
+		
+		let muxer = WriterMuxer::new("mpegts", output).context("Opening output")?;
+		
+		mux_segment(muxer, transcoders.video_transcoder.as_mut(), transcoders.audio_transcoder.as_mut(), end_time)
+	}
+}
@@ -18,6 +18,8 @@ pub struct VideoTranscoder {
 	
 	time_base: Rational,
 	first_frame: bool,
+	// The next frame at or after this is made a keyframe, so that the output can be split there
+	forced_keyframe_pts: Option<i64>,
 	
 	backend: Box<dyn VideoBackend>,
 	has_global_header: bool,
@@ -68,6 +70,7 @@ impl VideoTranscoder {
 			
 			time_base: params.in_stream.time_base(),
 			first_frame: true,
+			forced_keyframe_pts: None,
 			
 			backend: params.backend,
 			has_global_header,
@@ -230,10 +233,16 @@ impl VideoTranscoder {
 			
 			// Only pass frames in the time bounds to the encoder
 			if scaled_time_bounds.contains(&pts) {
+				let is_forced_keyframe = self.forced_keyframe_pts.is_some_and(|keyframe_pts| pts >= keyframe_pts);
+				
 				// Make the first frame an Iframe (probably unnecessary)
-				if self.first_frame {
+				if self.first_frame || is_forced_keyframe {
 					out_frame.set_kind(picture::Type::I);
 					self.first_frame = false;
+					
+					if is_forced_keyframe {
+						self.forced_keyframe_pts = None;
+					}
 				} else {
 					out_frame.set_kind(picture::Type::None);
 				}
@@ -266,6 +275,19 @@ impl VideoTranscoder {
 		Ok(())
 	}
 	
+	pub fn force_keyframe_at(&mut self, time: f64) {
+		self.forced_keyframe_pts = Some(media_utils::scale_from_f64_secs(time, self.time_base));
+	}
+	
+	// Packets come out of the encoder in decode order, so once one starting at or after the forced keyframe shows up,
+	//  everything before that keyframe has been encoded
+	pub fn has_output_after(&self, time: f64) -> bool {
+		let scaled_time = media_utils::scale_from_f64_secs(time, self.time_base);
+		
+		self.output_packet_queue.iter()
+			.any(|packet| packet.pts().is_some_and(|pts| pts >= scaled_time))
+	}
+	
 	pub fn add_output_stream(&mut self, muxer: &mut format::context::Output) -> anyhow::Result<()> {
 		if let Some(encoder) = &self.encoder {
 			let mut out_stream = media_utils::add_output_stream(muxer, encoder)?;
@@ -277,11 +299,17 @@ impl VideoTranscoder {
 		Ok(())
 	}
 	
-	pub fn write_output_packets(&mut self, muxer: &mut format::context::Output) -> anyhow::Result<()> {
+	// Packets starting at or after end_time are kept for the next output
+	pub fn write_output_packets(&mut self, muxer: &mut format::context::Output, end_time: f64) -> anyhow::Result<()> {
 		let Some(out_stream_index) = self.out_stream_index else { return Ok(()); };
 		let stream_time_base = muxer.stream(out_stream_index).expect("Unknown stream").time_base();
 		
-		for mut out_packet in self.output_packet_queue.drain(..) {
+		let (to_write, remaining) = media_utils::split_packets_at(
+			std::mem::take(&mut self.output_packet_queue), end_time, self.time_base);
+		
+		self.output_packet_queue = remaining;
+		
+		for mut out_packet in to_write {
 			// println!("Out DTS: {}, PTS: {}", out_packet.dts().unwrap(), out_packet.pts().unwrap());
 			
 			out_packet.set_stream(out_stream_index);
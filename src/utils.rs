@@ -1,8 +1,12 @@
 use std::fmt::Formatter;
 use std::path::{Path, PathBuf};
 
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use serde::{de, Deserializer};
 use serde::de::Visitor;
+use tokio::io::AsyncWriteExt;
 
 pub fn add_extension(path: &Path, extension: impl AsRef<Path>) -> PathBuf {
 	let mut path = path.to_owned();
@@ -20,6 +24,47 @@ pub fn add_extension(path: &Path, extension: impl AsRef<Path>) -> PathBuf {
 	path
 }
 
+const TEMP_EXT: &str = "tmp";
+pub const BACKUP_EXT: &str = "bak";
+
+// Writes to a temporary file first so that the file is never left half written, and keeps the previous
+//  version as a backup. If this gets interrupted between the two renames the backup gets loaded instead.
+pub async fn write_data_file(data_file: &Path, data: &[u8]) -> anyhow::Result<()> {
+	let temp_file = add_extension(data_file, TEMP_EXT);
+	
+	let mut file = tokio::fs::File::create(&temp_file).await?;
+	file.write_all(data).await?;
+	file.sync_all().await?;
+	drop(file);
+	
+	if tokio::fs::try_exists(data_file).await? {
+		tokio::fs::rename(data_file, add_extension(data_file, BACKUP_EXT)).await?;
+	}
+	
+	tokio::fs::rename(&temp_file, data_file).await?;
+	
+	// Make sure the renames themselves are on disk
+	if let Some(parent) = data_file.parent() &&
+		let Ok(dir) = tokio::fs::File::open(parent).await {
+		let _ = dir.sync_all().await;
+	}
+	
+	Ok(())
+}
+
+// For playlists, bookmarks and anything else that needs an id nobody can guess
+pub fn generate_random_id() -> String {
+	generate_random_string(12)
+}
+
+// URL-safe, from the given number of random bytes
+pub fn generate_random_string(byte_count: usize) -> String {
+	let mut bytes = vec![0u8; byte_count];
+	OsRng.fill_bytes(&mut bytes);
+	
+	URL_SAFE_NO_PAD.encode(bytes)
+}
+
 const POWER_UNITS: &[char] = &['k', 'M', 'G', 'T', 'P', 'E', 'Z', 'Y'];
 
 pub fn abbreviate_number(num: u64) -> String {
@@ -12,6 +12,19 @@ pub enum ApiError {
 	FileNotFound,
 	NotADirectory,
 	Unauthorized,
+	Forbidden,
+	UserNotFound,
+	UserAlreadyExists,
+	PlaylistNotFound,
+	TooManyPlaylists,
+	TooManyFavorites,
+	TooManyBookmarks,
+	TooManyWatchParties,
+	CacheNotFound,
+	PrewarmJobNotFound,
+	CannotModifySelf,
+	IncorrectPin,
+	TooManyAttempts,
 	InvalidBody,
 	InvalidQuery,
 	UnknownQualityLevel,
@@ -30,6 +43,19 @@ impl ApiError {
 			Self::FileNotFound => (StatusCode::NOT_FOUND, "file_not_found"),
 			Self::NotADirectory => (StatusCode::BAD_REQUEST, "not_a_directory"),
 			Self::Unauthorized => (StatusCode::BAD_REQUEST, "unauthorized"),
+			Self::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
+			Self::UserNotFound => (StatusCode::NOT_FOUND, "user_not_found"),
+			Self::UserAlreadyExists => (StatusCode::CONFLICT, "user_already_exists"),
+			Self::PlaylistNotFound => (StatusCode::NOT_FOUND, "playlist_not_found"),
+			Self::TooManyPlaylists => (StatusCode::CONFLICT, "too_many_playlists"),
+			Self::TooManyFavorites => (StatusCode::CONFLICT, "too_many_favorites"),
+			Self::TooManyBookmarks => (StatusCode::CONFLICT, "too_many_bookmarks"),
+			Self::TooManyWatchParties => (StatusCode::CONFLICT, "too_many_watch_parties"),
+			Self::CacheNotFound => (StatusCode::NOT_FOUND, "cache_not_found"),
+			Self::PrewarmJobNotFound => (StatusCode::NOT_FOUND, "prewarm_job_not_found"),
+			Self::CannotModifySelf => (StatusCode::BAD_REQUEST, "cannot_modify_self"),
+			Self::IncorrectPin => (StatusCode::BAD_REQUEST, "incorrect_pin"),
+			Self::TooManyAttempts => (StatusCode::TOO_MANY_REQUESTS, "too_many_attempts"),
 			Self::InvalidBody => (StatusCode::BAD_REQUEST, "invalid_body"),
 			Self::InvalidQuery => (StatusCode::BAD_REQUEST, "invalid_query"),
 			Self::UnknownQualityLevel => (StatusCode::BAD_REQUEST, "unknown_quality_level"),
@@ -0,0 +1,136 @@
+use http::Method;
+use relative_path::RelativePathBuf;
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_types::{ApiCacheInfo, ApiCacheScanResult, ApiPurgeCacheResponse};
+use crate::web_server::cache_admin;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::services::artifact_cache::ManagedCache;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn list_caches_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let caches: Vec<ApiCacheInfo> = server_state.artifact_caches().into_iter()
+		.map(|(name, cache)| {
+			let stats = cache.stats();
+			
+			ApiCacheInfo {
+				name: name.to_owned(),
+				size: stats.size,
+				entry_count: stats.entry_count,
+				size_limit: stats.size_limit,
+			}
+		})
+		.collect();
+	
+	Ok(json_response(&caches, request.headers()).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn purge_cache_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: PurgeCacheParams = web_utils::parse_json_body(body).await?;
+	
+	let caches = selected_caches(server_state, params.cache.as_deref())?;
+	
+	let mut response = ApiPurgeCacheResponse {
+		removed_entries: 0,
+		removed_bytes: 0,
+	};
+	
+	let media_file = match (params.library_id, params.media_path) {
+		(Some(library_id), Some(media_path)) => Some(server_state.libraries.resolve_path(&library_id, media_path)?),
+		(None, None) => None,
+		_ => return Err(ApiError::InvalidBody),
+	};
+	
+	// Purging every cache completely has to be asked for by name
+	if params.cache.is_none() && media_file.is_none() {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	let media_file_filter = match &media_file {
+		Some(media_file) => Some(cache_admin::media_file_filter(&server_state.cache_keys, media_file).await?),
+		None => None,
+	};
+	
+	for (name, cache) in caches {
+		let purge_stats = match &media_file_filter {
+			Some(filter) => cache.purge(filter).await?,
+			None => cache.purge(&|_| true).await?,
+		};
+		
+		info!("Purged {} entries from {} cache", purge_stats.removed_entries, name);
+		
+		response.removed_entries += purge_stats.removed_entries;
+		response.removed_bytes += purge_stats.removed_bytes;
+	}
+	
+	Ok(json_response(&response, &request.headers).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn scan_caches_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: ScanCachesParams = web_utils::parse_json_body(body).await?;
+	
+	let mut results = Vec::new();
+	
+	for (name, cache) in selected_caches(server_state, params.cache.as_deref())? {
+		let scan_stats = cache.scan().await?;
+		
+		info!("Scanned {} cache, removed {} entries", name, scan_stats.removed_entries);
+		
+		results.push(ApiCacheScanResult {
+			name: name.to_owned(),
+			valid_entries: scan_stats.valid_entries,
+			removed_entries: scan_stats.removed_entries,
+		});
+	}
+	
+	Ok(json_response(&results, &request.headers).await?)
+}
+
+// Either the one named cache, or all of them
+fn selected_caches<'a>(
+	server_state: &'a ServerState,
+	cache_name: Option<&str>,
+) -> Result<Vec<(&'static str, &'a dyn ManagedCache)>, ApiError> {
+	let caches = server_state.artifact_caches();
+	
+	let Some(cache_name) = cache_name else { return Ok(caches) };
+	
+	let selected: Vec<_> = caches.into_iter()
+		.filter(|(name, _)| *name == cache_name)
+		.collect();
+	
+	if selected.is_empty() {
+		return Err(ApiError::CacheNotFound);
+	}
+	
+	Ok(selected)
+}
+
+#[derive(Debug, Deserialize)]
+struct PurgeCacheParams {
+	// All caches when missing
+	cache: Option<String>,
+	// Only purges what was generated from this file when set
+	library_id: Option<String>,
+	media_path: Option<RelativePathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScanCachesParams {
+	// All caches when missing
+	cache: Option<String>,
+}
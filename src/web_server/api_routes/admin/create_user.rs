@@ -0,0 +1,71 @@
+use http::Method;
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::config::{ContentRating, PathRuleConfig, UserConfig};
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::admin;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn create_user_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: CreateUserParams = web_utils::parse_json_body(body).await?;
+	
+	// The user id is used in file names, so keep it to a safe set of characters
+	if params.id.is_empty() || !params.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	if params.username.is_empty() || params.password.is_empty() {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	admin::verify_libraries_exist(server_state, &params.allowed_libraries)?;
+	admin::verify_rating_pin(params.rating_pin.as_deref())?;
+	admin::parse_path_rules(server_state, params.path_rules.clone())?;
+	
+	let user = server_state.auth_manager.create_user(UserConfig {
+		id: params.id,
+		display_name: params.display_name,
+		username: params.username,
+		password: Some(params.password),
+		password_hash: None,
+		allowed_libraries: params.allowed_libraries,
+		admin: params.admin,
+		disabled: false,
+		path_rules: params.path_rules,
+		max_content_rating: params.max_content_rating,
+		block_unrated: params.block_unrated,
+		rating_pin: params.rating_pin,
+		rating_pin_hash: None,
+		oidc_link: None,
+	})?;
+	
+	server_state.auth_manager.save_config(&server_state.config).await?;
+	
+	info!("Created user {}", user.id);
+	
+	Ok(json_response(&admin::create_user_entry(&user), &request.headers).await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateUserParams {
+	pub id: String,
+	pub display_name: String,
+	pub username: String,
+	pub password: String,
+	pub allowed_libraries: Vec<String>,
+	#[serde(default)]
+	pub admin: bool,
+	#[serde(default)]
+	pub path_rules: Vec<PathRuleConfig>,
+	pub max_content_rating: Option<ContentRating>,
+	#[serde(default)]
+	pub block_unrated: bool,
+	pub rating_pin: Option<String>,
+}
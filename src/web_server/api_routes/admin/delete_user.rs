@@ -0,0 +1,47 @@
+use http::{Method, Response};
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{empty_body, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn delete_user_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: DeleteUserParams = web_utils::parse_json_body(body).await?;
+	
+	let current_user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	if current_user.id == params.user_id {
+		return Err(ApiError::CannotModifySelf);
+	}
+	
+	let user = server_state.auth_manager.delete_user(&params.user_id)?;
+	
+	server_state.user_watch_histories.lock().unwrap()
+		.remove_user(&user.id);
+	
+	server_state.playlists.lock().unwrap()
+		.remove_user(&user.id);
+	
+	server_state.user_favorites.lock().unwrap()
+		.remove_user(&user.id);
+	
+	server_state.viewing_stats.lock().unwrap()
+		.remove_user(&user.id);
+	
+	server_state.auth_manager.save_config(&server_state.config).await?;
+	
+	info!("Deleted user {}", user.id);
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteUserParams {
+	pub user_id: String,
+}
@@ -0,0 +1,20 @@
+use http::Method;
+use tracing::instrument;
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::admin;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn list_users_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let mut users: Vec<_> = server_state.auth_manager.list_users().iter()
+		.map(|user| admin::create_user_entry(user))
+		.collect();
+	
+	users.sort_by(|a, b| natord::compare(&a.username, &b.username));
+	
+	Ok(json_response(&users, request.headers()).await?)
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::config::PathRuleConfig;
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_types::ApiAdminUserEntry;
+use crate::web_server::auth::User;
+use crate::web_server::path_rules::PathRule;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils::{HyperRequest, HyperResponse};
+
+mod list_users;
+mod create_user;
+mod update_user;
+mod delete_user;
+mod reset_password;
+mod remap_watch_history;
+mod viewing_stats;
+mod caches;
+mod prewarm_jobs;
+
+const MIN_RATING_PIN_LENGTH: usize = 4;
+
+pub async fn route_request(server_state: &ServerState, request: HyperRequest, path: &[&str]) -> Result<HyperResponse, ApiError> {
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	if !user.admin {
+		return Err(ApiError::Forbidden);
+	}
+	
+	match path {
+		["users"] => list_users::list_users_route(server_state, &request).await,
+		["create_user"] => create_user::create_user_route(server_state, request).await,
+		["update_user"] => update_user::update_user_route(server_state, request).await,
+		["delete_user"] => delete_user::delete_user_route(server_state, request).await,
+		["reset_password"] => reset_password::reset_password_route(server_state, request).await,
+		["remap_watch_history"] => remap_watch_history::remap_watch_history_route(server_state, request).await,
+		["relocate_moved_videos"] => remap_watch_history::relocate_moved_videos_route(server_state, request).await,
+		["viewing_stats"] => viewing_stats::viewing_stats_route(server_state, &request).await,
+		["caches"] => caches::list_caches_route(server_state, &request).await,
+		["purge_cache"] => caches::purge_cache_route(server_state, request).await,
+		["scan_caches"] => caches::scan_caches_route(server_state, request).await,
+		["prewarm_jobs"] => prewarm_jobs::list_prewarm_jobs_route(server_state, &request).await,
+		["run_prewarm_job"] => prewarm_jobs::run_prewarm_job_route(server_state, request).await,
+		["cancel_prewarm_job"] => prewarm_jobs::cancel_prewarm_job_route(server_state, request).await,
+		
+		_ => Err(ApiError::NotFound)
+	}
+}
+
+pub fn create_user_entry(user: &User) -> ApiAdminUserEntry {
+	ApiAdminUserEntry {
+		id: user.id.clone(),
+		display_name: user.display_name.clone(),
+		username: user.username.clone(),
+		allowed_libraries: user.allowed_libraries.clone(),
+		admin: user.admin,
+		disabled: user.disabled,
+		path_rules: user.path_rules.iter().map(PathRule::to_config).collect(),
+		max_content_rating: user.max_content_rating,
+		block_unrated: user.block_unrated,
+		has_rating_pin: user.has_rating_pin(),
+		oidc_link: user.oidc_link.clone(),
+	}
+}
+
+fn parse_path_rules(server_state: &ServerState, path_rules: Vec<PathRuleConfig>) -> Result<Vec<PathRule>, ApiError> {
+	path_rules.into_iter()
+		.map(|rule| {
+			if server_state.libraries.get_library(&rule.library).is_none() {
+				return Err(ApiError::LibraryNotFound);
+			}
+			
+			PathRule::from_config(rule).map_err(|_| ApiError::InvalidBody)
+		})
+		.collect()
+}
+
+fn verify_rating_pin(pin: Option<&str>) -> Result<(), ApiError> {
+	if pin.is_some_and(|pin| pin.len() < MIN_RATING_PIN_LENGTH) {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	Ok(())
+}
+
+fn verify_libraries_exist(server_state: &ServerState, library_ids: &[String]) -> Result<(), ApiError> {
+	if library_ids.iter().all(|id| server_state.libraries.get_library(id).is_some()) {
+		Ok(())
+	} else {
+		Err(ApiError::LibraryNotFound)
+	}
+}
+
+// Lets optional fields tell apart being missing from being set to null
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+	T: Deserialize<'de>,
+	D: Deserializer<'de>,
+{
+	T::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,50 @@
+use http::{Method, Response};
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{empty_body, json_response, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn list_prewarm_jobs_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let jobs = server_state.prewarm_jobs.job_statuses();
+	
+	Ok(json_response(&jobs, request.headers()).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn run_prewarm_job_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (_, body) = request.into_parts();
+	let params: PrewarmJobParams = web_utils::parse_json_body(body).await?;
+	
+	server_state.prewarm_jobs.request_run(&params.id)?;
+	
+	info!("Requested pre-warming job {}", &params.id);
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[instrument(skip_all)]
+pub async fn cancel_prewarm_job_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (_, body) = request.into_parts();
+	let params: PrewarmJobParams = web_utils::parse_json_body(body).await?;
+	
+	server_state.prewarm_jobs.cancel(&params.id)?;
+	
+	info!("Cancelled pre-warming job {}", &params.id);
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[derive(Debug, Deserialize)]
+struct PrewarmJobParams {
+	id: String,
+}
@@ -0,0 +1,78 @@
+use http::Method;
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::history_remap;
+use crate::web_server::history_remap::RemappedCounts;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+
+// Points every user's history entries, playlist entries and favorites under one path at another, for after files have
+//  been moved or renamed
+#[instrument(skip_all)]
+pub async fn remap_watch_history_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: RemapWatchHistoryParams = web_utils::parse_json_body(body).await?;
+	
+	let new_library_id = params.new_library_id.as_ref().unwrap_or(&params.library_id);
+	
+	if server_state.libraries.get_library(new_library_id).is_none() {
+		return Err(ApiError::LibraryNotFound);
+	}
+	
+	// Both prefixes get resolved against a library, so they can't escape it
+	if web_utils::sanitize_path(&params.old_prefix).is_none() || web_utils::sanitize_path(&params.new_prefix).is_none() {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	let counts = history_remap::remap_prefix(server_state,
+		&params.library_id, &params.old_prefix, new_library_id, &params.new_prefix);
+	
+	info!("Remapped {:?} from {}/{} to {}/{}", counts, params.library_id, params.old_prefix, new_library_id, params.new_prefix);
+	
+	Ok(json_response(&RemapWatchHistoryResponse::from(counts), &request.headers).await?)
+}
+
+// Finds videos in watch histories that can't be found anymore by their content, and remaps them to where they are now.
+//  This scans whole libraries, so it's only done when asked for.
+#[instrument(skip_all)]
+pub async fn relocate_moved_videos_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let counts = history_remap::relocate_moved_videos(server_state).await;
+	
+	info!("Relocated moved videos, remapped {:?}", counts);
+	
+	Ok(json_response(&RemapWatchHistoryResponse::from(counts), request.headers()).await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct RemapWatchHistoryParams {
+	pub library_id: String,
+	pub old_prefix: RelativePathBuf,
+	// Defaults to the same library
+	pub new_library_id: Option<String>,
+	pub new_prefix: RelativePathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct RemapWatchHistoryResponse {
+	remapped_entries: usize,
+	remapped_playlist_entries: usize,
+	remapped_favorites: usize,
+}
+
+impl From<RemappedCounts> for RemapWatchHistoryResponse {
+	fn from(counts: RemappedCounts) -> Self {
+		Self {
+			remapped_entries: counts.history_entries,
+			remapped_playlist_entries: counts.playlist_entries,
+			remapped_favorites: counts.favorites,
+		}
+	}
+}
@@ -0,0 +1,33 @@
+use http::{Method, Response};
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{empty_body, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn reset_password_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let params: ResetPasswordParams = web_utils::parse_json_body(request.into_body()).await?;
+	
+	if params.new_password.is_empty() {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	let user = server_state.auth_manager.set_password(&params.user_id, &params.new_password)?;
+	
+	server_state.auth_manager.save_config(&server_state.config).await?;
+	
+	info!("Reset password of user {}", user.id);
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetPasswordParams {
+	pub user_id: String,
+	pub new_password: String,
+}
@@ -0,0 +1,106 @@
+use http::Method;
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::config::{ContentRating, OidcLinkConfig, PathRuleConfig};
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::admin;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn update_user_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: UpdateUserParams = web_utils::parse_json_body(body).await?;
+	
+	let current_user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	// Don't let admins lock themselves out
+	if current_user.id == params.user_id && (params.disabled == Some(true) || params.admin == Some(false)) {
+		return Err(ApiError::CannotModifySelf);
+	}
+	
+	if let Some(allowed_libraries) = &params.allowed_libraries {
+		admin::verify_libraries_exist(server_state, allowed_libraries)?;
+	}
+	
+	if let Some(rating_pin) = &params.rating_pin {
+		admin::verify_rating_pin(rating_pin.as_deref())?;
+	}
+	
+	// Two users with the same link would make OIDC logins ambiguous
+	if let Some(Some(oidc_link)) = &params.oidc_link &&
+		let Some(linked_user) = server_state.auth_manager.get_user_by_oidc_link(oidc_link) &&
+		linked_user.id != params.user_id {
+		return Err(ApiError::UserAlreadyExists);
+	}
+	
+	let path_rules = params.path_rules
+		.map(|path_rules| admin::parse_path_rules(server_state, path_rules))
+		.transpose()?;
+	
+	let user = server_state.auth_manager.update_user(&params.user_id, |user| {
+		if let Some(display_name) = params.display_name {
+			user.display_name = display_name;
+		}
+		
+		if let Some(allowed_libraries) = params.allowed_libraries {
+			user.allowed_libraries = allowed_libraries;
+		}
+		
+		if let Some(path_rules) = path_rules {
+			user.path_rules = path_rules;
+		}
+		
+		if let Some(admin) = params.admin {
+			user.admin = admin;
+		}
+		
+		if let Some(disabled) = params.disabled {
+			user.disabled = disabled;
+		}
+		
+		if let Some(max_content_rating) = params.max_content_rating {
+			user.max_content_rating = max_content_rating;
+		}
+		
+		if let Some(block_unrated) = params.block_unrated {
+			user.block_unrated = block_unrated;
+		}
+		
+		if let Some(oidc_link) = params.oidc_link {
+			user.oidc_link = oidc_link;
+		}
+	})?;
+	
+	let user = match params.rating_pin {
+		Some(rating_pin) => server_state.auth_manager.set_rating_pin(&user.id, rating_pin.as_deref())?,
+		None => user,
+	};
+	
+	server_state.auth_manager.save_config(&server_state.config).await?;
+	
+	info!("Updated user {}", user.id);
+	
+	Ok(json_response(&admin::create_user_entry(&user), &request.headers).await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateUserParams {
+	pub user_id: String,
+	pub display_name: Option<String>,
+	pub allowed_libraries: Option<Vec<String>>,
+	pub admin: Option<bool>,
+	pub disabled: Option<bool>,
+	pub path_rules: Option<Vec<PathRuleConfig>>,
+	#[serde(default, deserialize_with = "admin::deserialize_some")]
+	pub max_content_rating: Option<Option<ContentRating>>,
+	pub block_unrated: Option<bool>,
+	#[serde(default, deserialize_with = "admin::deserialize_some")]
+	pub rating_pin: Option<Option<String>>,
+	#[serde(default, deserialize_with = "admin::deserialize_some")]
+	pub oidc_link: Option<Option<OidcLinkConfig>>,
+}
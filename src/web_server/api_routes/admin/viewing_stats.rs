@@ -0,0 +1,27 @@
+use http::Method;
+use tracing::instrument;
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::viewing_stats::ViewingStatsParams;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{viewing_stats, web_utils};
+
+// Viewing activity across every user
+#[instrument(skip_all)]
+pub async fn viewing_stats_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let params: ViewingStatsParams = web_utils::parse_query(request.uri())?;
+	
+	let (from, to) = params.time_range()?;
+	
+	let sessions = server_state.viewing_stats.lock().unwrap()
+		.sessions_between(from, to);
+	
+	let stats = viewing_stats::aggregate(&sessions, params.interval, |user_id| {
+		server_state.auth_manager.get_user_by_id(user_id).map(|user| user.display_name.clone())
+	});
+	
+	Ok(json_response(&stats, request.headers()).await?)
+}
@@ -0,0 +1,133 @@
+use http::{Method, Response};
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_types::ApiBookmark;
+use crate::web_server::media_metadata::BasicMediaMetadata;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils::{empty_body, json_response, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{libraries, web_utils};
+
+const MAX_NAME_LENGTH: usize = 200;
+
+// Every bookmark the user has made, or only the ones in a single video
+#[instrument(skip_all)]
+pub async fn list_bookmarks_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let params: ListBookmarksParams = web_utils::parse_query(request.uri())?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	let bookmarks = {
+		let user_favorites = server_state.user_favorites.lock().unwrap();
+		let favorites = user_favorites.get_favorites(&user.id);
+		
+		match (&params.library_id, &params.media_path) {
+			(Some(library_id), Some(media_path)) => favorites
+				.map(|favorites| favorites.bookmarks_for(library_id, media_path))
+				.unwrap_or_default(),
+			(None, None) => favorites
+				.map(|favorites| favorites.bookmarks.iter().rev().cloned().collect())
+				.unwrap_or_default(),
+			_ => return Err(ApiError::InvalidQuery),
+		}
+	};
+	
+	let bookmarks: Vec<ApiBookmark> = bookmarks.into_iter()
+		.filter(|bookmark| user.can_see_path(&bookmark.library_id, &bookmark.media_path))
+		.map(ApiBookmark::from)
+		.collect();
+	
+	let res = ListBookmarksResponse {
+		bookmarks,
+	};
+	
+	Ok(json_response(&res, request.headers()).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn add_bookmark_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: AddBookmarkParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	let name = params.name.trim();
+	
+	if name.chars().count() > MAX_NAME_LENGTH {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, &params.library_id, params.media_path.clone(), &request.headers).await?;
+	
+	let media_metadata = server_state.metadata_cache.fetch_metadata::<BasicMediaMetadata>(&media_path).await?;
+	
+	if params.timestamp > media_metadata.duration.as_secs() {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	let bookmark = {
+		let mut user_favorites = server_state.user_favorites.lock().unwrap();
+		
+		let bookmark = user_favorites.get_favorites_mut(&user.id)
+			.add_bookmark(&params.library_id, &params.media_path, params.timestamp, name.to_owned())
+			.ok_or(ApiError::TooManyBookmarks)?
+			.clone();
+		
+		user_favorites.mark_dirty();
+		
+		bookmark
+	};
+	
+	Ok(json_response(&ApiBookmark::from(bookmark), &request.headers).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn remove_bookmark_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: RemoveBookmarkParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	let mut user_favorites = server_state.user_favorites.lock().unwrap();
+	
+	if !user_favorites.get_favorites_mut(&user.id).remove_bookmark(&params.bookmark_id) {
+		return Err(ApiError::NotFound);
+	}
+	
+	user_favorites.mark_dirty();
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[derive(Debug, Deserialize)]
+struct ListBookmarksParams {
+	library_id: Option<String>,
+	media_path: Option<RelativePathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddBookmarkParams {
+	library_id: String,
+	media_path: RelativePathBuf,
+	timestamp: u64,
+	name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoveBookmarkParams {
+	bookmark_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ListBookmarksResponse {
+	bookmarks: Vec<ApiBookmark>,
+}
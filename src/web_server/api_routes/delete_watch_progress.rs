@@ -23,6 +23,8 @@ pub async fn delete_watch_progress_route(server_state: &ServerState, request: Hy
 		user_watch_histories.get_watch_history(&user.id)
 			.delete_entry(&params.library_id, &params.media_path);
 		
+		server_state.progress_sync.publish(&user.id, &params.library_id, &params.media_path, None, None);
+		
 		user_watch_histories.mark_dirty();
 	}
 	
@@ -0,0 +1,160 @@
+use http::{Method, Response};
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::list_playlists;
+use crate::web_server::auth::User;
+use crate::web_server::playlists::PlaylistEntry;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{empty_body, json_response, restrict_method, HyperRequest, HyperResponse};
+
+const MAX_NAME_LENGTH: usize = 200;
+const MAX_ENTRIES: usize = 10_000;
+
+#[instrument(skip_all)]
+pub async fn create_playlist_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: CreatePlaylistParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	let name = validate_name(&params.name)?;
+	validate_entries(server_state, &user, &params.entries)?;
+	
+	let res = {
+		let mut playlists = server_state.playlists.lock().unwrap();
+		let playlist = playlists.create_playlist(&user.id, name, params.shared, params.entries)
+			.ok_or(ApiError::TooManyPlaylists)?;
+		
+		info!("User {} created playlist {}", user.id, playlist.id);
+		
+		list_playlists::create_playlist_summary(server_state, &user, playlist)
+	};
+	
+	Ok(json_response(&res, &request.headers).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn update_playlist_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: UpdatePlaylistParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	let name = params.name.as_deref().map(validate_name).transpose()?;
+	
+	if let Some(entries) = &params.entries {
+		validate_entries(server_state, &user, entries)?;
+	}
+	
+	let mut playlists = server_state.playlists.lock().unwrap();
+	let playlist = playlists.get_playlist_mut(&params.playlist_id)
+		.filter(|playlist| playlist.is_visible_to(&user.id))
+		.ok_or(ApiError::PlaylistNotFound)?;
+	
+	if playlist.owner_id != user.id {
+		return Err(ApiError::Forbidden);
+	}
+	
+	if let Some(name) = name {
+		playlist.name = name;
+	}
+	
+	if let Some(shared) = params.shared {
+		playlist.shared = shared;
+	}
+	
+	if let Some(entries) = params.entries {
+		playlist.entries = entries;
+	}
+	
+	playlist.touch();
+	playlists.mark_dirty();
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[instrument(skip_all)]
+pub async fn delete_playlist_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: DeletePlaylistParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	let mut playlists = server_state.playlists.lock().unwrap();
+	
+	let owner_id = playlists.get_playlist(&params.playlist_id)
+		.filter(|playlist| playlist.is_visible_to(&user.id))
+		.map(|playlist| playlist.owner_id.clone())
+		.ok_or(ApiError::PlaylistNotFound)?;
+	
+	if owner_id != user.id {
+		return Err(ApiError::Forbidden);
+	}
+	
+	playlists.delete_playlist(&params.playlist_id);
+	
+	info!("User {} deleted playlist {}", user.id, params.playlist_id);
+	
+	Ok(Response::new(empty_body()))
+}
+
+fn validate_name(name: &str) -> Result<String, ApiError> {
+	let name = name.trim();
+	
+	if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	Ok(name.to_owned())
+}
+
+// Users can only add videos they can see themselves, even if the playlist is shared with someone who could see more
+fn validate_entries(server_state: &ServerState, user: &User, entries: &[PlaylistEntry]) -> Result<(), ApiError> {
+	if entries.len() > MAX_ENTRIES {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	for entry in entries {
+		if server_state.libraries.get_library(&entry.library_id).is_none() ||
+			web_utils::sanitize_path(&entry.media_path).is_none() {
+			return Err(ApiError::InvalidBody);
+		}
+		
+		if !user.can_see_path(&entry.library_id, &entry.media_path) {
+			return Err(ApiError::FileNotFound);
+		}
+	}
+	
+	Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct CreatePlaylistParams {
+	name: String,
+	#[serde(default)]
+	shared: bool,
+	#[serde(default)]
+	entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdatePlaylistParams {
+	playlist_id: String,
+	name: Option<String>,
+	shared: Option<bool>,
+	entries: Option<Vec<PlaylistEntry>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeletePlaylistParams {
+	playlist_id: String,
+}
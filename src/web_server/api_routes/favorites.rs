@@ -0,0 +1,125 @@
+use http::{Method, Response};
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::list_dir;
+use crate::web_server::api_types::ApiFavoriteEntry;
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::favorites::FavoriteEntry;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::video_locator::LocatedFile;
+use crate::web_server::web_utils::{empty_body, json_response, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{content_ratings, libraries, video_locator, web_utils};
+
+#[instrument(skip_all)]
+pub async fn list_favorites_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, request.headers());
+	
+	// Most recently added first
+	let favorite_entries: Vec<FavoriteEntry> = server_state.user_favorites.lock().unwrap()
+		.get_favorites(&user.id)
+		.map(|favorites| favorites.favorites.iter()
+			.rev()
+			.filter(|entry| user.can_see_path(&entry.library_id, &entry.media_path))
+			.cloned()
+			.collect())
+		.unwrap_or_default();
+	
+	let mut entries = Vec::new();
+	
+	for entry in favorite_entries {
+		let Ok((library, resolved_path)) = server_state.libraries.resolve_library_and_path(&entry.library_id, entry.media_path.clone()) else {
+			continue;
+		};
+		
+		let mut file_entry = None;
+		
+		if let Ok(media_path) = video_locator::locate_video(&resolved_path).await.and_then(LocatedFile::file) {
+			if !rating_filter.is_unrestricted() &&
+				!rating_filter.allows(content_ratings::media_rating(server_state, library, &entry.media_path, &media_path).await) {
+				continue;
+			}
+			
+			match list_dir::create_file_entry(server_state, &user, &entry.library_id, &entry.media_path, &media_path).await {
+				Ok(file) => file_entry = Some(file),
+				Err(err) => error!("Error collecting file metadata for {:?}: {:?}", &media_path, err),
+			}
+		}
+		
+		entries.push(ApiFavoriteEntry {
+			library_id: entry.library_id,
+			media_path: entry.media_path,
+			added: entry.added,
+			file: file_entry,
+		});
+	}
+	
+	let res = ListFavoritesResponse {
+		entries,
+	};
+	
+	Ok(json_response(&res, request.headers()).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn add_favorite_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: FavoriteParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	// Makes sure the video exists and that the user is allowed to see it
+	libraries::locate_media_file_with_auth(server_state, &params.library_id, params.media_path.clone(), &request.headers).await?;
+	
+	{
+		let mut user_favorites = server_state.user_favorites.lock().unwrap();
+		
+		let added = user_favorites.get_favorites_mut(&user.id)
+			.add_favorite(&params.library_id, &params.media_path)
+			.ok_or(ApiError::TooManyFavorites)?;
+		
+		if added {
+			user_favorites.mark_dirty();
+		}
+	}
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[instrument(skip_all)]
+pub async fn remove_favorite_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: FavoriteParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	{
+		let mut user_favorites = server_state.user_favorites.lock().unwrap();
+		
+		if user_favorites.get_favorites_mut(&user.id).remove_favorite(&params.library_id, &params.media_path) {
+			user_favorites.mark_dirty();
+		}
+	}
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[derive(Debug, Deserialize)]
+struct FavoriteParams {
+	library_id: String,
+	media_path: RelativePathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct ListFavoritesResponse {
+	entries: Vec<ApiFavoriteEntry>,
+}
@@ -1,18 +1,19 @@
 use std::borrow::Cow;
 use std::ffi::OsStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::media_manipulation::thumbnail_sheet;
 use crate::web_server::api_error::ApiError;
 use crate::web_server::api_routes::{list_dir, thumbnail};
-use crate::web_server::api_types::{ApiCommentThread, ApiDirectoryInfo, ApiFileInfo, ApiSubtitleStream, ApiVideoConnection, ApiVideoInfo};
+use crate::web_server::api_types::{ApiBookmark, ApiCommentThread, ApiDirectoryInfo, ApiFileInfo, ApiSubtitleStream, ApiVideoConnection, ApiVideoInfo};
 use crate::web_server::auth::User;
+use crate::web_server::content_ratings::RatingFilter;
 use crate::web_server::libraries::Library;
 use crate::web_server::media_metadata::{AdvancedMediaMetadata, BasicMediaMetadata, Dimension};
 use crate::web_server::server_state::ServerState;
 use crate::web_server::video_locator::LocatedFile;
 use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
-use crate::web_server::{libraries, media_connections, video_locator};
+use crate::web_server::{content_ratings, libraries, media_connections};
 use anyhow::Context;
 use http::Method;
 use relative_path::{RelativePath, RelativePathBuf};
@@ -31,12 +32,14 @@ pub async fn file_info_route(
 	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
 	
 	let library_path: RelativePathBuf = library_path.iter().collect();
-	let (library, resolved_path) = libraries::resolve_library_and_path_with_auth(
-		server_state, library_id, library_path.clone(), request.headers())?;
+	let (library, located_file) = libraries::locate_video_with_auth(
+		server_state, library_id, library_path.clone(), request.headers()).await?;
 	
-	let res = match video_locator::locate_video(&resolved_path).await? {
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, request.headers());
+	
+	let res = match located_file {
 		LocatedFile::File(file_path) => {
-			let file_info = create_file_info(server_state, user, library, &library_path, &file_path).await?;
+			let file_info = create_file_info(server_state, &user, rating_filter, library, &library_path, &file_path).await?;
 			
 			FileInfoResponse::File(file_info)
 		}
@@ -76,6 +79,7 @@ pub const COMMENTS_FILE_EXT: &str = "comments.json";
 pub async fn create_file_info(
 	server_state: &ServerState,
 	user: &User,
+	rating_filter: RatingFilter,
 	library: &Library,
 	library_path: &RelativePath,
 	media_path: &Path
@@ -87,7 +91,8 @@ pub async fn create_file_info(
 	let advanced_metadata = server_state.metadata_cache
 		.fetch_metadata_with_meta::<AdvancedMediaMetadata>(&media_path, &file_metadata).await?;
 	
-	let adjacent_files = list_dir::collect_video_list(&media_path.parent().context("No parent")?).await?;
+	let adjacent_files = collect_adjacent_videos(server_state, user, rating_filter, library, library_path, media_path).await?;
+	
 	let this_index = adjacent_files.iter().position(|path| path == &media_path).context("Can't find self in file list")?;
 	
 	let video_info = match &advanced_metadata.video_metadata {
@@ -135,10 +140,17 @@ pub async fn create_file_info(
 		.and_then(OsStr::to_str)
 		.map(ToOwned::to_owned);
 	
-	let watch_progress = server_state.user_watch_histories.lock().unwrap()
+	let (watch_progress, watched) = server_state.user_watch_histories.lock().unwrap()
 		.get_watch_history(&user.id)
 		.get_entry(&library.id, &library_path)
-		.map(|entry| entry.progress);
+		.map_or((None, false), |entry| (Some(entry.progress), entry.watched));
+	
+	let (favorite, bookmarks) = server_state.user_favorites.lock().unwrap()
+		.get_favorites(&user.id)
+		.map_or((false, Vec::new()), |favorites| (
+			favorites.is_favorite(&library.id, library_path),
+			favorites.bookmarks_for(&library.id, library_path).into_iter().map(ApiBookmark::from).collect(),
+		));
 	
 	let description = read_file_maybe(media_path, DESCRIPTION_FILE_EXT).await?
 		.and_then(|data| String::from_utf8(data).ok());
@@ -191,12 +203,55 @@ pub async fn create_file_info(
 		prev_video,
 		next_video,
 		watch_progress,
+		watched,
+		favorite,
+		bookmarks,
 		description,
 		connections,
 		comments,
 	})
 }
 
+// The videos in the same directory as a video that the user is allowed to see, in playback order. The video itself
+//  is always included.
+pub async fn collect_adjacent_videos(
+	server_state: &ServerState,
+	user: &User,
+	rating_filter: RatingFilter,
+	library: &Library,
+	library_path: &RelativePath,
+	media_path: &Path
+) -> anyhow::Result<Vec<PathBuf>> {
+	let mut adjacent_files = list_dir::collect_video_list(&media_path.parent().context("No parent")?).await?;
+	
+	let parent_path = library_path.parent().unwrap_or(RelativePath::new(""));
+	
+	if user.has_path_rules(&library.id) {
+		adjacent_files.retain(|path| {
+			path == media_path || path.file_stem()
+				.and_then(OsStr::to_str)
+				.is_some_and(|stem| user.can_see_path(&library.id, &parent_path.join(stem)))
+		});
+	}
+	
+	if !rating_filter.is_unrestricted() {
+		let dir_rating = content_ratings::directory_rating(server_state, library, parent_path).await;
+		let mut allowed_files = Vec::with_capacity(adjacent_files.len());
+		
+		for path in adjacent_files {
+			let rating = content_ratings::file_rating(server_state, &path).await.or(dir_rating);
+			
+			if path == media_path || rating_filter.allows(rating) {
+				allowed_files.push(path);
+			}
+		}
+		
+		adjacent_files = allowed_files;
+	}
+	
+	Ok(adjacent_files)
+}
+
 pub async fn read_file_maybe(media_path: &Path, ext: &str) -> anyhow::Result<Option<Vec<u8>>> {
 	let path = media_path.with_extension(ext);
 	
@@ -6,7 +6,7 @@ use crate::web_server::api_error::ApiError;
 use crate::web_server::server_state::ServerState;
 use crate::web_server::services::transcription_service::AutoSubtitleParams;
 use crate::web_server::web_utils::{restrict_method, serve_file_compressed, HyperRequest, HyperResponse};
-use crate::web_server::{libraries, video_locator};
+use crate::web_server::libraries;
 
 #[instrument(skip(server_state, request))]
 pub async fn auto_subtitle_segment_route(
@@ -27,9 +27,8 @@ pub async fn auto_subtitle_segment_route(
 		return Err(ApiError::FeatureNotSupported);
 	};
 	
-	let resolved_path = libraries::resolve_path_with_auth(
-		server_state, library_id, library_path.iter().collect(), request.headers())?;
-	let media_path = video_locator::locate_video(&resolved_path).await?.file()?;
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, library_id, library_path.iter().collect(), request.headers()).await?;
 	
 	let subtitles = auto_subtitle_generator.get_or_generate(AutoSubtitleParams {
 		media_path,
@@ -37,7 +36,7 @@ pub async fn auto_subtitle_segment_route(
 	}).await?;
 	
 	let res = serve_file_compressed(
-		subtitles.entry_data,
+		subtitles.entry_file.read_all().await?,
 		subtitles.creation_date.into(),
 		mime::Mime::from_str("text/vtt").unwrap(),
 		request.headers()
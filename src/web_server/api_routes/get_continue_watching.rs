@@ -0,0 +1,151 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+
+use http::Method;
+use relative_path::RelativePath;
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::{file_info, list_dir};
+use crate::web_server::api_types::{ApiContinueWatchingEntry, ApiContinueWatchingKind};
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::media_metadata::BasicMediaMetadata;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::video_locator::LocatedFile;
+use crate::web_server::watch_history::WatchHistoryEntry;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{content_ratings, video_locator, watch_history, web_utils};
+
+#[instrument(skip_all)]
+pub async fn get_continue_watching_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let params: ContinueWatchingParams = web_utils::parse_query(request.uri())?;
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, request.headers());
+	let finished_threshold = server_state.config.main_config.watch_history.finished_threshold;
+	
+	let mut history_entries: Vec<WatchHistoryEntry> = server_state.user_watch_histories.lock().unwrap()
+		.get_watch_history(&user.id)
+		.iter_entries()
+		.filter(|entry| user.can_see_path(&entry.library_id, &entry.media_path))
+		.map(Clone::clone)
+		.collect();
+	
+	// Imported and remapped entries aren't necessarily in the order they were watched in
+	history_entries.sort_by_key(|entry| Reverse(entry.last_watched));
+	
+	// Only the most recently watched video in a directory decides what to suggest from it, otherwise rewatching
+	//  episode 2 after finishing episode 5 would bring back episode 3
+	let mut seen_directories: HashSet<(String, String)> = HashSet::new();
+	
+	let mut entries = Vec::new();
+	
+	for entry in history_entries {
+		if entries.len() >= params.limit {
+			break;
+		}
+		
+		let parent_path = entry.media_path.parent().unwrap_or(RelativePath::new(""));
+		
+		let first_in_directory = seen_directories.insert((entry.library_id.clone(), parent_path.as_str().to_lowercase()));
+		
+		// Videos marked as unwatched stay in the history with their progress reset. Skipped before looking anything
+		//  up, so that only videos that can be suggested have their metadata fetched.
+		if entry.progress == 0 {
+			continue;
+		}
+		
+		let Ok((library, resolved_path)) = server_state.libraries.resolve_library_and_path(&entry.library_id, entry.media_path.clone()) else {
+			continue;
+		};
+		
+		let Ok(media_path) = video_locator::locate_video(&resolved_path).await.and_then(LocatedFile::file) else {
+			continue;
+		};
+		
+		if !rating_filter.allows(content_ratings::media_rating(server_state, library, &entry.media_path, &media_path).await) {
+			continue;
+		}
+		
+		let duration = match server_state.metadata_cache.fetch_metadata::<BasicMediaMetadata>(&media_path).await {
+			Ok(metadata) => metadata.duration,
+			Err(err) => {
+				error!("Error collecting file metadata for {:?}: {:?}", &media_path, err);
+				continue;
+			}
+		};
+		
+		let (kind, library_path, item_media_path) = if !watch_history::is_finished(entry.progress, duration, finished_threshold) {
+			(ApiContinueWatchingKind::InProgress, entry.media_path, media_path)
+		} else if first_in_directory {
+			let adjacent_files = match file_info::collect_adjacent_videos(
+				server_state, &user, rating_filter, library, &entry.media_path, &media_path).await
+			{
+				Ok(adjacent_files) => adjacent_files,
+				Err(err) => {
+					error!("Error listing videos next to {:?}: {:?}", &media_path, err);
+					continue;
+				}
+			};
+			
+			let Some(next_path) = adjacent_files.iter()
+				.position(|path| path == &media_path)
+				.and_then(|this_index| adjacent_files.get(this_index + 1))
+			else {
+				continue;
+			};
+			
+			let Some(next_stem) = next_path.file_stem().and_then(OsStr::to_str) else { continue };
+			let next_library_path = parent_path.join(next_stem);
+			
+			// Anything already in the history has either been listed as in progress or was watched before
+			let already_watched = server_state.user_watch_histories.lock().unwrap()
+				.get_watch_history(&user.id)
+				.get_entry(&entry.library_id, &next_library_path)
+				.is_some();
+			
+			if already_watched {
+				continue;
+			}
+			
+			(ApiContinueWatchingKind::NextUp, next_library_path, next_path.clone())
+		} else {
+			continue;
+		};
+		
+		match list_dir::create_file_entry(server_state, &user, &entry.library_id, &library_path, &item_media_path).await {
+			Ok(file) => entries.push(ApiContinueWatchingEntry {
+				kind,
+				library_id: entry.library_id,
+				media_path: library_path,
+				file,
+			}),
+			Err(err) => error!("Error collecting file metadata for {:?}: {:?}", &item_media_path, err),
+		}
+	}
+	
+	let res = ContinueWatchingResponse {
+		entries,
+	};
+	
+	Ok(json_response(&res, request.headers()).await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct ContinueWatchingParams {
+	#[serde(default = "default_limit")]
+	limit: usize,
+}
+
+fn default_limit() -> usize {
+	20
+}
+
+#[derive(Debug, Serialize)]
+struct ContinueWatchingResponse {
+	entries: Vec<ApiContinueWatchingEntry>,
+}
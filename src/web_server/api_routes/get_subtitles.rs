@@ -4,7 +4,7 @@ use tracing::instrument;
 
 use crate::web_server::api_error::ApiError;
 use crate::web_server::server_state::ServerState;
-use crate::web_server::{libraries, video_locator};
+use crate::web_server::libraries;
 use crate::web_server::services::subtitle_service::SubtitleParams;
 use crate::web_server::web_utils::{HyperRequest, HyperResponse, restrict_method, serve_file_compressed};
 
@@ -23,9 +23,8 @@ pub async fn subtitles_route(
 		.parse()
 		.map_err(|_| ApiError::NotFound)?;
 	
-	let resolved_path = libraries::resolve_path_with_auth(
-		server_state, library_id, library_path.iter().collect(), request.headers())?;
-	let media_path = video_locator::locate_video(&resolved_path).await?.file()?;
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, library_id, library_path.iter().collect(), request.headers()).await?;
 	
 	let subtitles = server_state.transcoded_subtitle_generator.get_or_generate(SubtitleParams {
 		media_path,
@@ -33,7 +32,7 @@ pub async fn subtitles_route(
 	}).await?;
 	
 	let res = serve_file_compressed(
-		subtitles.entry_data,
+		subtitles.entry_file.read_all().await?,
 		subtitles.creation_date.into(),
 		mime::Mime::from_str("text/vtt").unwrap(),
 		request.headers()
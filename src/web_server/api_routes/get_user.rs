@@ -15,6 +15,9 @@ pub async fn get_user_route(request: &HyperRequest, auth_manager: &AuthManager)
 	let user_res = ApiUserInfo {
 		display_name: user.display_name.clone(),
 		username: user.username.clone(),
+		admin: user.admin,
+		max_content_rating: user.max_content_rating,
+		ratings_unlocked: auth_manager.ratings_unlocked(&user, request.headers()),
 	};
 	
 	Ok(json_response(&user_res, request.headers()).await?)
@@ -1,14 +1,20 @@
+use std::path::PathBuf;
+
 use http::Method;
+use relative_path::RelativePath;
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
 use crate::web_server::api_error::ApiError;
 use crate::web_server::api_routes::list_dir;
 use crate::web_server::api_types::ApiWatchHistoryEntry;
+use crate::web_server::auth::User;
+use crate::web_server::content_ratings::RatingFilter;
 use crate::web_server::server_state::ServerState;
 use crate::web_server::video_locator::LocatedFile;
+use crate::web_server::watch_history::WatchHistoryEntry;
 use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
-use crate::web_server::{video_locator, web_utils};
+use crate::web_server::{content_ratings, video_locator, web_utils};
 
 #[instrument(skip_all)]
 pub async fn get_watch_history_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
@@ -17,23 +23,45 @@ pub async fn get_watch_history_route(server_state: &ServerState, request: &Hyper
 	let params: WatchHistoryParams = web_utils::parse_query(request.uri())?;
 	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
 	
+	let search_query = params.search_query.as_ref().map(|query| query.to_lowercase());
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, request.headers());
+	
 	let history_entries: Vec<_>;
 	let total_pages;
 	
-	{
+	if !rating_filter.is_unrestricted() {
+		// Ratings have to be read from disk, so every entry needs to be checked outside the lock before paginating
+		let candidate_entries: Vec<WatchHistoryEntry> = server_state.user_watch_histories.lock().unwrap()
+			.get_watch_history(&user.id)
+			.iter_entries()
+			.rev()
+			.filter(|entry| is_entry_visible(&user, search_query.as_deref(), entry))
+			.map(Clone::clone)
+			.collect();
+		
+		let mut allowed_entries = Vec::new();
+		
+		for entry in candidate_entries {
+			if is_entry_rating_allowed(server_state, rating_filter, &entry).await {
+				allowed_entries.push(entry);
+			}
+		}
+		
+		total_pages = allowed_entries.len().div_ceil(params.page_size);
+		
+		history_entries = allowed_entries.into_iter()
+			.skip(params.page * params.page_size)
+			.take(params.page_size)
+			.collect();
+	} else {
 		let mut user_watch_histories = server_state.user_watch_histories.lock().unwrap();
 		let watch_history = user_watch_histories.get_watch_history(&user.id);
 		
-		if let Some(search_query) = &params.search_query {
-			let search_query = search_query.to_lowercase();
-			
+		if search_query.is_some() || !user.path_rules.is_empty() {
 			let mut iter = watch_history
 				.iter_entries()
 				.rev()
-				.filter(|entry| {
-					entry.media_path.file_name()
-						.is_some_and(|file_name| file_name.to_lowercase().contains(&search_query))
-				});
+				.filter(|entry| is_entry_visible(&user, search_query.as_deref(), entry));
 			
 			let mut total_entries = (&mut iter).take(params.page * params.page_size).count();
 			
@@ -57,29 +85,29 @@ pub async fn get_watch_history_route(server_state: &ServerState, request: &Hyper
 				.map(Clone::clone)
 				.collect();
 		}
-	};
+	}
 	
 	let mut entries = Vec::new();
 	
 	for entry in history_entries {
 		let mut file_entry = None;
 		
-		if user.can_see_library(&entry.library_id) {
-			if let Ok(resolved_path) = server_state.libraries.resolve_path(&entry.library_id, entry.media_path.clone()) {
-				if let Ok(media_path) = video_locator::locate_video(&resolved_path).await.and_then(LocatedFile::file) {
-					match list_dir::create_file_entry(server_state, user, &entry.library_id, &entry.media_path, &media_path).await {
-						Ok(file) => file_entry = Some(file),
-						Err(err) => error!("Error collecting file metadata for {:?}: {:?}", &media_path, err),
-					}
-				}
+		if user.can_see_library(&entry.library_id) &&
+			let Some(media_path) = locate_entry(server_state, &entry).await {
+			match list_dir::create_file_entry(server_state, &user, &entry.library_id, &entry.media_path, &media_path).await {
+				Ok(file) => file_entry = Some(file),
+				Err(err) => error!("Error collecting file metadata for {:?}: {:?}", &media_path, err),
 			}
 		}
 		
 		entries.push(ApiWatchHistoryEntry {
 			library_id: entry.library_id,
 			media_path: entry.media_path,
+			first_watched: entry.first_watched,
 			last_watched: entry.last_watched,
 			progress: entry.progress,
+			watched: entry.watched,
+			play_count: entry.play_count,
 			file: file_entry,
 		})
 	}
@@ -92,6 +120,46 @@ pub async fn get_watch_history_route(server_state: &ServerState, request: &Hyper
 	Ok(json_response(&res, request.headers()).await?)
 }
 
+fn is_entry_visible(user: &User, search_query: Option<&str>, entry: &WatchHistoryEntry) -> bool {
+	// Entries under paths hidden by path rules are left out entirely instead of just omitting the file
+	if user.can_see_library(&entry.library_id) && !user.can_see_path(&entry.library_id, &entry.media_path) {
+		return false;
+	}
+	
+	let Some(search_query) = search_query else { return true };
+	
+	entry.media_path.file_name()
+		.is_some_and(|file_name| file_name.to_lowercase().contains(search_query))
+}
+
+async fn locate_entry(server_state: &ServerState, entry: &WatchHistoryEntry) -> Option<PathBuf> {
+	let resolved_path = server_state.libraries.resolve_path(&entry.library_id, entry.media_path.clone()).ok()?;
+	
+	video_locator::locate_video(&resolved_path).await.and_then(LocatedFile::file).ok()
+}
+
+async fn is_entry_rating_allowed(server_state: &ServerState, rating_filter: RatingFilter, entry: &WatchHistoryEntry) -> bool {
+	is_media_rating_allowed(server_state, rating_filter, &entry.library_id, &entry.media_path).await
+}
+
+// Files that can't be found anymore are allowed, there's nothing left to hide
+pub async fn is_media_rating_allowed(
+	server_state: &ServerState,
+	rating_filter: RatingFilter,
+	library_id: &str,
+	library_path: &RelativePath,
+) -> bool {
+	let Ok((library, resolved_path)) = server_state.libraries.resolve_library_and_path(library_id, library_path.to_owned()) else {
+		return true;
+	};
+	
+	let Ok(media_path) = video_locator::locate_video(&resolved_path).await.and_then(LocatedFile::file) else {
+		return true;
+	};
+	
+	rating_filter.allows(content_ratings::media_rating(server_state, library, library_path, &media_path).await)
+}
+
 #[derive(Debug, Deserialize)]
 struct WatchHistoryParams {
 	page: usize,
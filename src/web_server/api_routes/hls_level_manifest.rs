@@ -2,7 +2,7 @@ use http::{Method, Response};
 use http::header::CONTENT_TYPE;
 use tracing::instrument;
 
-use crate::web_server::{libraries, video_locator, web_utils};
+use crate::web_server::{libraries, web_utils};
 use crate::web_server::api_error::ApiError;
 use crate::web_server::media_metadata::AdvancedMediaMetadata;
 use crate::web_server::server_state::ServerState;
@@ -22,9 +22,8 @@ pub async fn hls_level_manifest_route(
 	
 	hls_segment_service::get_quality_level(quality_level)?;
 	
-	let resolved_path = libraries::resolve_path_with_auth(
-		server_state, library_id, library_path.iter().collect(), request.headers())?;
-	let media_path = video_locator::locate_video(&resolved_path).await?.file()?;
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, library_id, library_path.iter().collect(), request.headers()).await?;
 	
 	let advanced_metadata = server_state.metadata_cache
 		.fetch_metadata::<AdvancedMediaMetadata>(&media_path).await?;
@@ -6,10 +6,12 @@ use tracing::{error, info, instrument, Instrument};
 use crate::web_server::services::hls_segment_service::SegmentParams;
 use crate::web_server::api_error::ApiError;
 use crate::web_server::server_state::ServerState;
-use crate::web_server::{libraries, video_locator};
+use crate::web_server::libraries;
 use crate::web_server::media_metadata::AdvancedMediaMetadata;
+use crate::web_server::services::artifact_cache::QueryResult;
 use crate::web_server::services::hls_segment_service;
-use crate::web_server::web_utils::{HyperRequest, HyperResponse, restrict_method, serve_file_basic};
+use crate::web_server::services::task_pool::TaskPriority;
+use crate::web_server::web_utils::{HyperRequest, HyperResponse, restrict_method, serve_file_streamed};
 
 #[instrument(skip(server_state, request))]
 pub async fn hls_segment_route(
@@ -29,9 +31,8 @@ pub async fn hls_segment_route(
 	
 	let quality_level = hls_segment_service::get_quality_level(quality_level)?;
 	
-	let resolved_path = libraries::resolve_path_with_auth(
-		server_state, library_id, library_path.iter().collect(), request.headers())?;
-	let media_path = video_locator::locate_video(&resolved_path).await?.file()?;
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, library_id, library_path.iter().collect(), request.headers()).await?;
 	
 	let advanced_metadata = server_state.metadata_cache
 		.fetch_metadata::<AdvancedMediaMetadata>(&media_path).await?;
@@ -40,43 +41,77 @@ pub async fn hls_segment_route(
 		return Err(ApiError::InvalidSegmentIndex);
 	}
 	
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	server_state.prewarm_jobs.note_activity();
+	
 	let params = SegmentParams {
 		media_path,
 		segment_index,
 		quality_level,
 	};
 	
-	let pending_query = server_state.hls_segment_generator
-		.get_or_reserve(params.clone()).await?;
+	let prefetch_indices = server_state.segment_prefetcher.segment_requested(&user.id, &params);
 	
-	let next_segment_index = segment_index + 1;
+	let pending_query = server_state.hls_segment_generator
+		.get_or_reserve(params.clone(), TaskPriority::Interactive).await?;
 	
-	// If there is a next segment, then start transcoding it in parallel to this segment
-	if hls_segment_service::is_segment_index_valid(next_segment_index, &advanced_metadata) {
-		let server_state = server_state.clone();
+	// Start transcoding the following segments in parallel to this segment
+	for prefetch_index in prefetch_indices {
+		if !hls_segment_service::is_segment_index_valid(prefetch_index, &advanced_metadata) {
+			break;
+		}
 		
-		info!("Pre-generating segment {}", next_segment_index);
+		let prefetch_params = SegmentParams {
+			segment_index: prefetch_index,
+			..params.clone()
+		};
 		
-		tokio::task::spawn(async move {
-			let params_next = SegmentParams {
-				segment_index: next_segment_index,
-				..params
-			};
-			
-			if let Err(err) = server_state.hls_segment_generator.get_or_generate(params_next).await {
-				error!("Failed to pre-generate segment {}: {:?}", next_segment_index, err);
-			}
-		}.in_current_span());
+		let prefetch_task = tokio::task::spawn(
+			prefetch_segment(server_state.clone(), user.id.clone(), prefetch_params.clone()).in_current_span());
+		
+		server_state.segment_prefetcher.add_prefetch(&user.id, &prefetch_params, prefetch_task.abort_handle());
 	}
 	
-	let generated_segment = pending_query.unwrap_or_generate().await?;
+	let generated_segment = match pending_query {
+		QueryResult::Valid(query) => query,
+		QueryResult::Invalid(pending) => pending.generate().await?,
+	};
 	
-	let res = serve_file_basic(
-		generated_segment.entry_data,
+	let res = serve_file_streamed(
+		generated_segment.entry_file.file,
+		generated_segment.entry_file.size,
+		&generated_segment.entry_file.etag,
 		generated_segment.creation_date.into(),
 		mime::Mime::from_str("video/MP2T").unwrap(),
 		request.headers()
 	).await?;
 	
 	Ok(res)
-}
\ No newline at end of file
+}
+
+async fn prefetch_segment(server_state: Arc<ServerState>, user_id: String, params: SegmentParams) {
+	let segment_index = params.segment_index;
+	
+	let result = async {
+		let query_result = server_state.hls_segment_generator
+			.get_or_reserve(params.clone(), TaskPriority::Prefetch).await?;
+		
+		let QueryResult::Invalid(pending) = query_result else { return Ok(()) };
+		
+		// The viewer may have left while this was waiting for a transcoding slot
+		if !server_state.segment_prefetcher.is_session_active(&user_id, &params) {
+			return Ok(());
+		}
+		
+		info!("Pre-generating segment {}", segment_index);
+		
+		pending.generate().await?;
+		
+		anyhow::Ok(())
+	}.await;
+	
+	if let Err(err) = result {
+		error!("Failed to pre-generate segment {}: {:?}", segment_index, err);
+	}
+}
@@ -4,18 +4,20 @@ use std::path::{Path, PathBuf};
 
 use http::Method;
 use relative_path::{RelativePath, RelativePathBuf};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
 use crate::web_server::api_error::ApiError;
 use crate::web_server::api_routes::thumbnail;
 use crate::web_server::api_types::{ApiDirectoryEntry, ApiFileEntry};
 use crate::web_server::auth::User;
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::favorites::{Favorites, FavoritesFilter};
 use crate::web_server::media_metadata::BasicMediaMetadata;
 use crate::web_server::metadata_cache::FileMetadata;
 use crate::web_server::server_state::ServerState;
 use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
-use crate::web_server::{libraries, video_locator};
+use crate::web_server::{content_ratings, libraries, video_locator, web_utils};
 
 #[instrument(skip(server_state, request))]
 pub async fn list_dir_route(
@@ -26,10 +28,12 @@ pub async fn list_dir_route(
 ) -> Result<HyperResponse, ApiError> {
 	restrict_method(request, &[Method::GET, Method::HEAD])?;
 	
+	let params: ListDirParams = web_utils::parse_query(request.uri())?;
+	
 	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
 	
 	let library_path: RelativePathBuf = library_path.iter().collect();
-	let resolved_path = libraries::resolve_path_with_auth(
+	let (library, resolved_path) = libraries::resolve_library_and_path_with_auth(
 		server_state, library_id, library_path.clone(), request.headers())?;
 	
 	let file_metadata = tokio::fs::metadata(&resolved_path).await?;
@@ -38,6 +42,17 @@ pub async fn list_dir_route(
 		return Err(ApiError::NotADirectory);
 	}
 	
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, request.headers());
+	let mut dir_rating = None;
+	
+	if !rating_filter.is_unrestricted() {
+		dir_rating = content_ratings::directory_rating(server_state, library, &library_path).await;
+		
+		if !rating_filter.allows_directory(dir_rating) {
+			return Err(ApiError::FileNotFound);
+		}
+	}
+	
 	let mut read_dir = tokio::fs::read_dir(&resolved_path).await?;
 	
 	let mut files: Vec<ApiFileEntry> = Vec::new();
@@ -48,6 +63,13 @@ pub async fn list_dir_route(
 	
 	let mut file_stem_set: HashSet<String> = HashSet::new();
 	
+	let favorites_filter: Option<FavoritesFilter> = params.favorites_only.then(|| {
+		server_state.user_favorites.lock().unwrap()
+			.get_favorites(&user.id)
+			.map(|favorites| favorites.library_filter(library_id))
+			.unwrap_or_else(|| Favorites::default().library_filter(library_id))
+	});
+	
 	while let Some(entry) = read_dir.next_entry().await? {
 		let path = entry.path();
 		
@@ -68,7 +90,18 @@ pub async fn list_dir_route(
 			
 			let file_library_path = library_path.join(&path_name);
 			
-			match create_file_entry(server_state, user, library_id, &file_library_path, &path).await {
+			if !user.can_see_path(library_id, &file_library_path) { continue; }
+			
+			if let Some(favorites_filter) = &favorites_filter && !favorites_filter.allows_file(&file_library_path) {
+				continue;
+			}
+			
+			if !rating_filter.is_unrestricted() &&
+				!rating_filter.allows(content_ratings::file_rating(server_state, &path).await.or(dir_rating)) {
+				continue;
+			}
+			
+			match create_file_entry(server_state, &user, library_id, &file_library_path, &path).await {
 				Ok(file_entry) => {
 					total_time += file_entry.duration;
 					total_size += file_entry.file_size;
@@ -80,14 +113,42 @@ pub async fn list_dir_route(
 		} else if file_type.is_dir() {
 			let Some(path_name) = path.file_name().and_then(OsStr::to_str) else { continue };
 			
+			let dir_library_path = library_path.join(path_name);
+			
+			if !user.can_see_path(library_id, &dir_library_path) { continue; }
+			
+			if let Some(favorites_filter) = &favorites_filter && !favorites_filter.allows_directory(&dir_library_path) {
+				continue;
+			}
+			
+			if !rating_filter.is_unrestricted() &&
+				!rating_filter.allows_directory(content_ratings::own_directory_rating(server_state, &path).await.or(dir_rating)) {
+				continue;
+			}
+			
 			let mut child_count: u32 = 0;
 			let mut thumbnail_path: Option<String> = None;
 			
-			if let Ok(dir_metadata) = server_state.metadata_cache.fetch_metadata::<BasicDirMetadata>(&path).await {
+			let dir_metadata = if user.has_path_rules(library_id) {
+				// The cached metadata is shared between users, so it would include files this user can't see
+				collect_video_list(&path).await.map(|video_paths| {
+					BasicDirMetadata::from_video_list(video_paths.into_iter()
+						.filter(|video_path| {
+							video_path.file_stem()
+								.and_then(OsStr::to_str)
+								.is_some_and(|stem| user.can_see_path(library_id, &dir_library_path.join(stem)))
+						})
+						.collect())
+				})
+			} else {
+				server_state.metadata_cache.fetch_metadata::<BasicDirMetadata>(&path).await
+			};
+			
+			if let Ok(dir_metadata) = dir_metadata {
 				child_count = dir_metadata.child_count;
 				
 				thumbnail_path = dir_metadata.first_media_file.map(|thumbnail_path_name| {
-					thumbnail::create_scaled_thumbnail_path(&RelativePath::new(library_id).join(&library_path).join(path_name).join(thumbnail_path_name))
+					thumbnail::create_scaled_thumbnail_path(&RelativePath::new(library_id).join(&dir_library_path).join(thumbnail_path_name))
 				});
 			}
 			
@@ -126,10 +187,13 @@ pub async fn create_file_entry(
 	let full_path = RelativePath::new(library_id).join(&library_path);
 	let thumbnail_path = thumbnail::create_scaled_thumbnail_path(&full_path);
 	
-	let watch_progress = server_state.user_watch_histories.lock().unwrap()
+	let history_entry = server_state.user_watch_histories.lock().unwrap()
 		.get_watch_history(&user.id)
 		.get_entry(library_id, &library_path)
-		.map(|entry| entry.progress);
+		.cloned();
+	
+	let favorite = server_state.user_favorites.lock().unwrap()
+		.is_favorite(&user.id, library_id, library_path);
 	
 	Ok(ApiFileEntry {
 		path_name: media_metadata.path_name,
@@ -139,7 +203,11 @@ pub async fn create_file_entry(
 		duration: media_metadata.duration.as_secs(),
 		file_size: media_metadata.file_size,
 		artist: media_metadata.artist,
-		watch_progress,
+		watch_progress: history_entry.as_ref().map(|entry| entry.progress),
+		watched: history_entry.as_ref().is_some_and(|entry| entry.watched),
+		play_count: history_entry.as_ref().map_or(0, |entry| entry.play_count),
+		first_watched: history_entry.as_ref().map(|entry| entry.first_watched),
+		favorite,
 		creation_date: media_metadata.creation_date,
 	})
 }
@@ -176,22 +244,35 @@ struct BasicDirMetadata {
 	child_count: u32,
 }
 
-impl FileMetadata for BasicDirMetadata {
-	async fn fetch_metadata(path: &Path, _file_metadata: &std::fs::Metadata) -> anyhow::Result<Self> {
-		let video_paths = collect_video_list(&path).await?;
-		
+impl BasicDirMetadata {
+	fn from_video_list(video_paths: Vec<PathBuf>) -> Self {
 		let first_media_file = video_paths.first()
 			.and_then(|path| path.file_stem())
 			.and_then(OsStr::to_str)
 			.map(ToOwned::to_owned);
 		
-		Ok(Self {
+		Self {
 			first_media_file,
 			child_count: video_paths.len() as u32,
-		})
+		}
 	}
 }
 
+impl FileMetadata for BasicDirMetadata {
+	async fn fetch_metadata(path: &Path, _file_metadata: &std::fs::Metadata) -> anyhow::Result<Self> {
+		let video_paths = collect_video_list(&path).await?;
+		
+		Ok(Self::from_video_list(video_paths))
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct ListDirParams {
+	// Only lists favorited videos and the directories containing them
+	#[serde(default)]
+	favorites_only: bool,
+}
+
 #[derive(Debug, Serialize)]
 struct ListDirResponse {
 	files: Vec<ApiFileEntry>,
@@ -0,0 +1,124 @@
+use http::Method;
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::list_dir;
+use crate::web_server::api_types::{ApiPlaylistEntry, ApiPlaylistSummary};
+use crate::web_server::auth::User;
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::playlists::Playlist;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::video_locator::LocatedFile;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{content_ratings, video_locator, web_utils};
+
+#[instrument(skip_all)]
+pub async fn list_playlists_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	let playlists: Vec<ApiPlaylistSummary> = server_state.playlists.lock().unwrap()
+		.list_for_user(&user.id)
+		.into_iter()
+		.map(|playlist| create_playlist_summary(server_state, &user, playlist))
+		.collect();
+	
+	let res = ListPlaylistsResponse {
+		playlists,
+	};
+	
+	Ok(json_response(&res, request.headers()).await?)
+}
+
+#[instrument(skip_all)]
+pub async fn get_playlist_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let params: GetPlaylistParams = web_utils::parse_query(request.uri())?;
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	let playlist = server_state.playlists.lock().unwrap()
+		.get_playlist(&params.playlist_id)
+		.filter(|playlist| playlist.is_visible_to(&user.id))
+		.cloned()
+		.ok_or(ApiError::PlaylistNotFound)?;
+	
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, request.headers());
+	
+	let mut entries = Vec::new();
+	
+	for entry in &playlist.entries {
+		// Shared playlists can contain things the viewer isn't allowed to see, those are left out entirely
+		if !user.can_see_path(&entry.library_id, &entry.media_path) {
+			continue;
+		}
+		
+		let Ok((library, resolved_path)) = server_state.libraries.resolve_library_and_path(&entry.library_id, entry.media_path.clone()) else {
+			continue;
+		};
+		
+		let mut file_entry = None;
+		
+		if let Ok(media_path) = video_locator::locate_video(&resolved_path).await.and_then(LocatedFile::file) {
+			if !rating_filter.is_unrestricted() &&
+				!rating_filter.allows(content_ratings::media_rating(server_state, library, &entry.media_path, &media_path).await) {
+				continue;
+			}
+			
+			match list_dir::create_file_entry(server_state, &user, &entry.library_id, &entry.media_path, &media_path).await {
+				Ok(file) => file_entry = Some(file),
+				Err(err) => error!("Error collecting file metadata for {:?}: {:?}", &media_path, err),
+			}
+		}
+		
+		entries.push(ApiPlaylistEntry {
+			library_id: entry.library_id.clone(),
+			media_path: entry.media_path.clone(),
+			file: file_entry,
+		});
+	}
+	
+	let res = GetPlaylistResponse {
+		playlist: create_playlist_summary(server_state, &user, &playlist),
+		entries,
+	};
+	
+	Ok(json_response(&res, request.headers()).await?)
+}
+
+pub fn create_playlist_summary(server_state: &ServerState, user: &User, playlist: &Playlist) -> ApiPlaylistSummary {
+	let owner_name = server_state.auth_manager.get_user_by_id(&playlist.owner_id)
+		.map(|owner| owner.display_name.clone())
+		.unwrap_or_default();
+	
+	ApiPlaylistSummary {
+		id: playlist.id.clone(),
+		name: playlist.name.clone(),
+		owner_name,
+		owned: playlist.owner_id == user.id,
+		shared: playlist.shared,
+		entry_count: playlist.entries.iter()
+			.filter(|entry| user.can_see_path(&entry.library_id, &entry.media_path))
+			.count(),
+		created: playlist.created,
+		updated: playlist.updated,
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct GetPlaylistParams {
+	playlist_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ListPlaylistsResponse {
+	playlists: Vec<ApiPlaylistSummary>,
+}
+
+#[derive(Debug, Serialize)]
+struct GetPlaylistResponse {
+	playlist: ApiPlaylistSummary,
+	entries: Vec<ApiPlaylistEntry>,
+}
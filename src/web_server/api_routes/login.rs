@@ -4,7 +4,7 @@ use serde::Deserialize;
 use tracing::instrument;
 
 use crate::web_server::api_error::ApiError;
-use crate::web_server::auth::{AUTH_COOKIE_NAME, AuthManager};
+use crate::web_server::auth::{AUTH_COOKIE_NAME, AuthManager, User};
 use crate::web_server::web_utils;
 use crate::web_server::web_utils::{full_body, HyperRequest, HyperResponse, restrict_method};
 
@@ -23,18 +23,9 @@ pub async fn login_route(request: HyperRequest, auth_manager: &AuthManager) -> R
 		return Ok(res)
 	};
 	
-	let auth_token = auth_manager.generate_token(user);
-	
-	const ONE_YEAR: u32 = 60 * 60 * 24 * 365;
-	
-	let cookie = format!(
-		"{name}={value}; Max-Age={age}; HttpOnly; SameSite=Strict",
-		name = AUTH_COOKIE_NAME, value = auth_token, age = ONE_YEAR
-	);
-	
 	let res = Response::builder()
 		.status(StatusCode::SEE_OTHER)
-		.header(SET_COOKIE, cookie)
+		.header(SET_COOKIE, auth_cookie(auth_manager, &user))
 		.header(LOCATION, "/")
 		.body(full_body("Redirecting"))
 		.unwrap();
@@ -42,6 +33,17 @@ pub async fn login_route(request: HyperRequest, auth_manager: &AuthManager) -> R
 	Ok(res)
 }
 
+pub fn auth_cookie(auth_manager: &AuthManager, user: &User) -> String {
+	let auth_token = auth_manager.generate_token(user);
+	
+	const ONE_YEAR: u32 = 60 * 60 * 24 * 365;
+	
+	format!(
+		"{name}={value}; Max-Age={age}; HttpOnly; SameSite=Strict",
+		name = AUTH_COOKIE_NAME, value = auth_token, age = ONE_YEAR
+	)
+}
+
 #[derive(Debug, Deserialize)]
 struct LoginParams {
 	username: String,
@@ -0,0 +1,18 @@
+use http::Method;
+use tracing::instrument;
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_types::ApiLoginOptions;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn login_options_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let options = ApiLoginOptions {
+		oidc_enabled: server_state.oidc_client.is_some(),
+	};
+	
+	Ok(json_response(&options, request.headers()).await?)
+}
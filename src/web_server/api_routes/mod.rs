@@ -22,10 +22,37 @@ mod hls_level_manifest;
 mod logout;
 mod get_subtitles;
 mod get_auto_subtitle_segment;
+mod admin;
+mod login_options;
+mod oidc_login;
+mod unlock_ratings;
+mod get_continue_watching;
+mod set_watched;
+mod watch_history_transfer;
+mod list_playlists;
+mod edit_playlist;
+mod favorites;
+mod bookmarks;
+mod watch_progress_events;
+mod watch_party;
+mod viewing_stats;
 
 pub async fn route_request(request: HyperRequest, path: &[&str], server_state: Arc<ServerState>) -> HyperResponse {
-	if let ["login"] = path {
-		return login_route(request, &server_state.auth_manager).await.unwrap_or_else(ApiError::into_response);
+	// These need to be reachable without being logged in
+	let result = match path {
+		["login"] => login_route(request, &server_state.auth_manager).await,
+		["login_options"] => login_options::login_options_route(&server_state, &request).await,
+		["oidc", "login"] => oidc_login::oidc_login_route(&server_state, &request).await,
+		["oidc", "callback"] => oidc_login::oidc_callback_route(&server_state, &request).await,
+		_ => return route_authenticated_request(request, path, server_state).await,
+	};
+	
+	result.unwrap_or_else(ApiError::into_response)
+}
+
+async fn route_authenticated_request(request: HyperRequest, path: &[&str], server_state: Arc<ServerState>) -> HyperResponse {
+	if let Err(err) = server_state.auth_manager.provision_forward_auth_user(request.headers(), &server_state.config).await {
+		return ApiError::from(err).into_response();
 	}
 	
 	if server_state.auth_manager.lookup_from_headers(request.headers()).is_err() {
@@ -38,7 +65,36 @@ pub async fn route_request(request: HyperRequest, path: &[&str], server_state: A
 		["libraries"] => list_libraries::list_libraries_route(&server_state, &request).await,
 		["update_watch_progress"] => update_watch_progress::update_watch_progress_route(&server_state, request).await,
 		["delete_watch_progress"] => delete_watch_progress::delete_watch_progress_route(&server_state, request).await,
+		["set_watched"] => set_watched::set_watched_route(&server_state, request).await,
 		["watch_history"] => get_watch_history::get_watch_history_route(&server_state, &request).await,
+		["watch_history", "export"] => watch_history_transfer::export_watch_history_route(&server_state, &request).await,
+		["watch_history", "import"] => watch_history_transfer::import_watch_history_route(&server_state, request).await,
+		["watch_history", "events"] => watch_progress_events::watch_progress_events_route(&server_state, &request).await,
+		["continue_watching"] => get_continue_watching::get_continue_watching_route(&server_state, &request).await,
+		["viewing_stats"] => viewing_stats::viewing_stats_route(&server_state, &request).await,
+		
+		["playlists"] => list_playlists::list_playlists_route(&server_state, &request).await,
+		["playlists", "get"] => list_playlists::get_playlist_route(&server_state, &request).await,
+		["playlists", "create"] => edit_playlist::create_playlist_route(&server_state, request).await,
+		["playlists", "update"] => edit_playlist::update_playlist_route(&server_state, request).await,
+		["playlists", "delete"] => edit_playlist::delete_playlist_route(&server_state, request).await,
+		
+		["favorites"] => favorites::list_favorites_route(&server_state, &request).await,
+		["favorites", "add"] => favorites::add_favorite_route(&server_state, request).await,
+		["favorites", "remove"] => favorites::remove_favorite_route(&server_state, request).await,
+		["bookmarks"] => bookmarks::list_bookmarks_route(&server_state, &request).await,
+		["bookmarks", "add"] => bookmarks::add_bookmark_route(&server_state, request).await,
+		["bookmarks", "remove"] => bookmarks::remove_bookmark_route(&server_state, request).await,
+		
+		["watch_party", "create"] => watch_party::create_watch_party_route(&server_state, request).await,
+		["watch_party", "events"] => watch_party::watch_party_events_route(&server_state, &request).await,
+		["watch_party", "control"] => watch_party::control_watch_party_route(&server_state, request).await,
+		["watch_party", "close"] => watch_party::close_watch_party_route(&server_state, request).await,
+		
+		["ratings", "unlock"] => unlock_ratings::unlock_ratings_route(&server_state, request).await,
+		["ratings", "lock"] => unlock_ratings::lock_ratings_route(&request).await,
+		
+		["admin", admin_path @ ..] => admin::route_request(&server_state, request, admin_path).await,
 		
 		["file_info", library_id, library_path @ ..] =>
 			file_info::file_info_route(&server_state, &request, library_id, library_path).await,
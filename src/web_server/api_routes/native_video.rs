@@ -5,7 +5,7 @@ use tracing::instrument;
 
 use crate::web_server::api_error::ApiError;
 use crate::web_server::server_state::ServerState;
-use crate::web_server::{libraries, video_locator};
+use crate::web_server::libraries;
 use crate::web_server::web_utils::{HyperRequest, HyperResponse, restrict_method};
 
 #[instrument(skip(server_state, request))]
@@ -17,9 +17,10 @@ pub async fn native_video_route(
 ) -> Result<HyperResponse, ApiError> {
 	restrict_method(&request, &[Method::GET, Method::HEAD])?;
 	
-	let resolved_path = libraries::resolve_path_with_auth(
-		server_state, library_id, library_path.iter().collect(), request.headers())?;
-	let media_path = video_locator::locate_video(&resolved_path).await?.file()?;
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, library_id, library_path.iter().collect(), request.headers()).await?;
+	
+	server_state.prewarm_jobs.note_activity();
 	
 	ServeFile::new(&media_path).try_call(request).await
 		.map(|res| res.map(|body| body.map_err(anyhow::Error::new).boxed_unsync()))
@@ -0,0 +1,105 @@
+use anyhow::anyhow;
+use headers::{Cookie, HeaderMapExt};
+use http::header::{CONTENT_TYPE, LOCATION, SET_COOKIE};
+use http::{Method, Response, StatusCode};
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::login::auth_cookie;
+use crate::web_server::oidc::{OIDC_STATE_COOKIE_NAME, OidcClient};
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{full_body, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn oidc_login_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET])?;
+	
+	let oidc_client = get_oidc_client(server_state)?;
+	let authorization_request = oidc_client.start_login().await?;
+	
+	// Ties the login to this browser so that someone else's callback URL can't log us in as them
+	let state_cookie = format!(
+		"{name}={value}; Max-Age=600; Path=/api/oidc; HttpOnly; SameSite=Lax",
+		name = OIDC_STATE_COOKIE_NAME, value = authorization_request.state
+	);
+	
+	let res = Response::builder()
+		.status(StatusCode::SEE_OTHER)
+		.header(SET_COOKIE, state_cookie)
+		.header(LOCATION, authorization_request.url)
+		.body(full_body("Redirecting"))
+		.unwrap();
+	
+	Ok(res)
+}
+
+#[instrument(skip_all)]
+pub async fn oidc_callback_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET])?;
+	
+	let oidc_client = get_oidc_client(server_state)?;
+	let params: OidcCallbackParams = web_utils::parse_query(request.uri())?;
+	
+	let result = async {
+		if let Some(error) = params.error {
+			return Err(anyhow!("OIDC provider returned error: {}", error));
+		}
+		
+		let code = params.code.ok_or_else(|| anyhow!("Missing code"))?;
+		let state = params.state.ok_or_else(|| anyhow!("Missing state"))?;
+		
+		let cookies = request.headers().typed_get::<Cookie>();
+		
+		if cookies.as_ref().and_then(|cookies| cookies.get(OIDC_STATE_COOKIE_NAME)) != Some(&state) {
+			return Err(anyhow!("State cookie doesn't match"));
+		}
+		
+		let identity = oidc_client.finish_login(&code, &state).await?;
+		
+		oidc_client.resolve_user(&identity, &server_state.auth_manager, &server_state.config).await
+	}.await;
+	
+	let user = match result {
+		Ok(user) => user,
+		Err(err) => {
+			warn!("OIDC login failed: {:?}", err);
+			
+			let res = Response::builder()
+				.status(StatusCode::BAD_REQUEST)
+				.body(full_body("Single sign-on login failed"))
+				.unwrap();
+			
+			return Ok(res);
+		}
+	};
+	
+	let clear_state_cookie = format!(
+		"{name}=; Max-Age=-100; Path=/api/oidc; HttpOnly; SameSite=Lax",
+		name = OIDC_STATE_COOKIE_NAME
+	);
+	
+	// The auth cookie is SameSite=Strict, so browsers won't send it on a redirect that started at the provider.
+	//  Redirecting from a page on our own site works around that.
+	let res = Response::builder()
+		.status(StatusCode::OK)
+		.header(SET_COOKIE, auth_cookie(&server_state.auth_manager, &user))
+		.header(SET_COOKIE, clear_state_cookie)
+		.header(CONTENT_TYPE, mime::TEXT_HTML_UTF_8.as_ref())
+		.body(full_body(r#"<!DOCTYPE html><meta http-equiv="refresh" content="0; url=/"><a href="/">Continue</a>"#))
+		.unwrap();
+	
+	Ok(res)
+}
+
+fn get_oidc_client(server_state: &ServerState) -> Result<&OidcClient, ApiError> {
+	server_state.oidc_client.as_ref().ok_or(ApiError::FeatureNotSupported)
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcCallbackParams {
+	pub code: Option<String>,
+	pub state: Option<String>,
+	pub error: Option<String>,
+}
@@ -0,0 +1,156 @@
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use http::{Method, Response};
+use relative_path::RelativePathBuf;
+use serde::Deserialize;
+use tracing::{error, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::auth::User;
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::libraries::Library;
+use crate::web_server::media_metadata::BasicMediaMetadata;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::video_locator::LocatedFile;
+use crate::web_server::web_utils::{empty_body, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{content_ratings, libraries, video_locator, web_utils};
+
+// Marks a video, or every video in a directory and its subdirectories, as watched or unwatched
+#[instrument(skip_all)]
+pub async fn set_watched_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: SetWatchedParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	let (library, located_file) = libraries::locate_video_with_auth(
+		server_state, &params.library_id, params.media_path.clone(), &request.headers).await?;
+	
+	let is_directory = matches!(located_file, LocatedFile::Directory(_));
+	
+	let videos = match located_file {
+		LocatedFile::File(media_path) => vec![(params.media_path.clone(), media_path)],
+		LocatedFile::Directory(dir_path) => {
+			let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, &request.headers);
+			
+			collect_videos_recursive(server_state, &user, rating_filter, library, params.media_path.clone(), dir_path).await?
+		}
+	};
+	
+	let mut updates = Vec::with_capacity(videos.len());
+	
+	for (library_path, media_path) in videos {
+		// The duration is only needed to move the progress to the end
+		let duration = if params.watched {
+			match server_state.metadata_cache.fetch_metadata::<BasicMediaMetadata>(&media_path).await {
+				Ok(metadata) => metadata.duration,
+				Err(err) => {
+					error!("Error collecting file metadata for {:?}: {:?}", &media_path, err);
+					continue;
+				}
+			}
+		} else {
+			Duration::ZERO
+		};
+		
+		updates.push((library_path, duration));
+	}
+	
+	{
+		let mut user_watch_histories = server_state.user_watch_histories.lock().unwrap();
+		let watch_history = user_watch_histories.get_watch_history(&user.id);
+		
+		for (library_path, duration) in &updates {
+			watch_history.set_watched(&library.id, library_path, params.watched, *duration);
+		}
+		
+		// A large directory would overflow the other devices' event channels with one event per video
+		if is_directory {
+			server_state.progress_sync.publish_directory(&user.id, &library.id, &params.media_path, params.watched);
+		} else if let Some((library_path, _)) = updates.first() {
+			server_state.progress_sync.publish(&user.id, &library.id, library_path,
+				watch_history.get_entry(&library.id, library_path), None);
+		}
+		
+		user_watch_histories.mark_dirty();
+	}
+	
+	Ok(Response::new(empty_body()))
+}
+
+async fn collect_videos_recursive(
+	server_state: &ServerState,
+	user: &User,
+	rating_filter: RatingFilter,
+	library: &Library,
+	library_path: RelativePathBuf,
+	dir_path: PathBuf,
+) -> Result<Vec<(RelativePathBuf, PathBuf)>, ApiError> {
+	let dir_rating = if rating_filter.is_unrestricted() {
+		None
+	} else {
+		content_ratings::directory_rating(server_state, library, &library_path).await
+	};
+	
+	let mut videos = Vec::new();
+	let mut pending_dirs = vec![(library_path, dir_path, dir_rating)];
+	
+	while let Some((library_path, dir_path, dir_rating)) = pending_dirs.pop() {
+		let mut read_dir = tokio::fs::read_dir(&dir_path).await?;
+		
+		while let Some(entry) = read_dir.next_entry().await? {
+			let path = entry.path();
+			
+			let Some(file_name) = path.file_name().and_then(OsStr::to_str) else { continue };
+			
+			if !server_state.config.main_config.show_hidden_files && video_locator::is_hidden(file_name) {
+				continue;
+			}
+			
+			let file_type = entry.file_type().await?;
+			
+			if file_type.is_file() {
+				if !video_locator::is_video(&path) { continue; }
+				
+				let Some(path_name) = path.file_stem().and_then(OsStr::to_str) else { continue };
+				let file_library_path = library_path.join(path_name);
+				
+				if !user.can_see_path(&library.id, &file_library_path) { continue; }
+				
+				if !rating_filter.is_unrestricted() &&
+					!rating_filter.allows(content_ratings::file_rating(server_state, &path).await.or(dir_rating)) {
+					continue;
+				}
+				
+				videos.push((file_library_path, path));
+			} else if file_type.is_dir() {
+				let sub_library_path = library_path.join(file_name);
+				
+				if !user.can_see_path(&library.id, &sub_library_path) { continue; }
+				
+				let mut sub_dir_rating = dir_rating;
+				
+				if !rating_filter.is_unrestricted() {
+					sub_dir_rating = content_ratings::own_directory_rating(server_state, &path).await.or(dir_rating);
+					
+					if !rating_filter.allows_directory(sub_dir_rating) { continue; }
+				}
+				
+				pending_dirs.push((sub_library_path, path, sub_dir_rating));
+			}
+		}
+	}
+	
+	Ok(videos)
+}
+
+#[derive(Debug, Deserialize)]
+struct SetWatchedParams {
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	pub watched: bool,
+}
@@ -5,8 +5,8 @@ use tracing::instrument;
 
 use crate::web_server::api_error::ApiError;
 use crate::web_server::server_state::ServerState;
-use crate::web_server::{libraries, video_locator};
-use crate::web_server::web_utils::{HyperRequest, HyperResponse, restrict_method, serve_file_basic};
+use crate::web_server::libraries;
+use crate::web_server::web_utils::{HyperRequest, HyperResponse, restrict_method, serve_file_streamed};
 
 #[instrument(skip(server_state, request))]
 pub async fn thumbnail_sheet_route(
@@ -17,14 +17,15 @@ pub async fn thumbnail_sheet_route(
 ) -> Result<HyperResponse, ApiError> {
 	restrict_method(request, &[Method::GET, Method::HEAD])?;
 	
-	let resolved_path = libraries::resolve_path_with_auth(
-		server_state, library_id, library_path.iter().collect(), request.headers())?;
-	let media_path = video_locator::locate_video(&resolved_path).await?.file()?;
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, library_id, library_path.iter().collect(), request.headers()).await?;
 	
 	let generated_sprite_sheet = server_state.thumbnail_sheet_generator.get_or_generate(media_path).await?;
 	
-	let res = serve_file_basic(
-		generated_sprite_sheet.entry_data,
+	let res = serve_file_streamed(
+		generated_sprite_sheet.entry_file.file,
+		generated_sprite_sheet.entry_file.size,
+		&generated_sprite_sheet.entry_file.etag,
 		generated_sprite_sheet.creation_date.into(),
 		Mime::from_str("image/webp").unwrap(),
 		request.headers()
@@ -0,0 +1,55 @@
+use http::header::SET_COOKIE;
+use http::{Method, Response};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::auth::{RATING_UNLOCK_COOKIE_NAME, RATING_UNLOCK_LIFETIME};
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{empty_body, restrict_method, HyperRequest, HyperResponse};
+
+#[instrument(skip_all)]
+pub async fn unlock_ratings_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: UnlockRatingsParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	let unlock_token = server_state.auth_manager.unlock_ratings(&user, &params.pin)?;
+	
+	let cookie = format!(
+		"{name}={value}; Max-Age={age}; HttpOnly; SameSite=Strict",
+		name = RATING_UNLOCK_COOKIE_NAME, value = unlock_token, age = RATING_UNLOCK_LIFETIME.as_secs()
+	);
+	
+	let res = Response::builder()
+		.header(SET_COOKIE, cookie)
+		.body(empty_body())
+		.unwrap();
+	
+	Ok(res)
+}
+
+#[instrument(skip_all)]
+pub async fn lock_ratings_route(request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::POST])?;
+	
+	let cookie = format!(
+		"{name}=; Max-Age=-100; HttpOnly; SameSite=Strict",
+		name = RATING_UNLOCK_COOKIE_NAME
+	);
+	
+	let res = Response::builder()
+		.header(SET_COOKIE, cookie)
+		.body(empty_body())
+		.unwrap();
+	
+	Ok(res)
+}
+
+#[derive(Debug, Deserialize)]
+struct UnlockRatingsParams {
+	pub pin: String,
+}
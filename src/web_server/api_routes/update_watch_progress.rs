@@ -1,11 +1,16 @@
 use http::{Method, Response};
 use relative_path::RelativePathBuf;
 use serde::Deserialize;
+use time::OffsetDateTime;
 use tracing::instrument;
 
-use crate::web_server::{libraries, video_locator, web_utils};
+use crate::web_server::{libraries, web_utils};
 use crate::web_server::api_error::ApiError;
+use crate::web_server::services::cache_keys::ContentFingerprint;
+use crate::web_server::media_metadata::BasicMediaMetadata;
+use crate::web_server::scrobble::{PlaybackState, ScrobbleEvent};
 use crate::web_server::server_state::ServerState;
+use crate::web_server::watch_history::Completion;
 use crate::web_server::web_utils::{empty_body, HyperRequest, HyperResponse, restrict_method};
 
 #[instrument(skip_all)]
@@ -17,20 +22,58 @@ pub async fn update_watch_progress_route(server_state: &ServerState, request: Hy
 	
 	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
 	
-	let resolved_path = libraries::resolve_path_with_auth(
-		server_state, &params.library_id, params.media_path.clone(), &request.headers)?;
+	let media_path = libraries::locate_media_file_with_auth(
+		server_state, &params.library_id, params.media_path.clone(), &request.headers).await?;
 	
-	if video_locator::locate_video(&resolved_path).await.is_err() {
-		return Err(ApiError::FileNotFound);
-	}
+	let media_metadata = server_state.metadata_cache.fetch_metadata::<BasicMediaMetadata>(&media_path).await?;
+	
+	let completion = Completion {
+		duration: media_metadata.duration,
+		finished_threshold: server_state.config.main_config.watch_history.finished_threshold,
+	};
+	
+	// Remembered so that the entry can still be matched up with the video if it gets moved
+	let fingerprint = server_state.metadata_cache.fetch_metadata::<ContentFingerprint>(&media_path).await
+		.map(|fingerprint| fingerprint.fingerprint)
+		.ok();
 	
-	{
+	let completed = {
 		let mut user_watch_histories = server_state.user_watch_histories.lock().unwrap();
+		let watch_history = user_watch_histories.get_watch_history(&user.id);
+		
+		let completed = watch_history
+			.update_progress(&params.library_id, &params.media_path, params.new_watch_progress, completion);
+		
+		if let Some(fingerprint) = fingerprint {
+			watch_history.set_fingerprint(&params.library_id, &params.media_path, fingerprint);
+		}
 		
-		user_watch_histories.get_watch_history(&user.id)
-			.update_progress(&params.library_id, &params.media_path, params.new_watch_progress);
+		server_state.progress_sync.publish(&user.id, &params.library_id, &params.media_path,
+			watch_history.get_entry(&params.library_id, &params.media_path), params.client_id.clone());
 		
 		user_watch_histories.mark_dirty();
+		
+		completed
+	};
+	
+	server_state.viewing_stats.lock().unwrap()
+		.record_progress(&user.id, &params.library_id, &params.media_path, params.new_watch_progress);
+	
+	let actions = server_state.scrobbler.playback_actions(
+		&user.id, &params.library_id, params.media_path.as_str(), params.playback_state, completed);
+	
+	for action in actions {
+		server_state.scrobbler.dispatch(ScrobbleEvent {
+			action,
+			user_id: user.id.clone(),
+			username: user.username.clone(),
+			library_id: params.library_id.clone(),
+			media_path: params.media_path.clone(),
+			title: media_metadata.title.clone(),
+			progress: params.new_watch_progress,
+			duration: media_metadata.duration.as_secs(),
+			timestamp: OffsetDateTime::now_utc(),
+		});
 	}
 	
 	Ok(Response::new(empty_body()))
@@ -41,4 +84,8 @@ struct UpdateWatchHistoryParams {
 	pub library_id: String,
 	pub media_path: RelativePathBuf,
 	pub new_watch_progress: u64,
+	// Lets scrobble hooks know when playback starts and pauses, only completion is reported without it
+	pub playback_state: Option<PlaybackState>,
+	// Identifies the sending device in progress sync events
+	pub client_id: Option<String>,
 }
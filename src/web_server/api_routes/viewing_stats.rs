@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use http::Method;
+use serde::Deserialize;
+use time::{Date, OffsetDateTime, Time};
+use tracing::instrument;
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::get_watch_history;
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::viewing_stats::{PlaybackSession, StatsInterval};
+use crate::web_server::web_utils::{json_response, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{viewing_stats, web_utils};
+
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+// The current user's own viewing activity
+#[instrument(skip_all)]
+pub async fn viewing_stats_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let params: ViewingStatsParams = web_utils::parse_query(request.uri())?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, request.headers());
+	
+	let (from, to) = params.time_range()?;
+	
+	let sessions: Vec<PlaybackSession> = server_state.viewing_stats.lock().unwrap()
+		.sessions_between(from, to)
+		.into_iter()
+		.filter(|session| session.user_id == user.id && user.can_see_path(&session.library_id, &session.media_path))
+		.collect();
+	
+	let sessions = filter_by_rating(server_state, rating_filter, sessions).await;
+	
+	let stats = viewing_stats::aggregate(&sessions, params.interval, |_| Some(user.display_name.clone()));
+	
+	Ok(json_response(&stats, request.headers()).await?)
+}
+
+// Ratings are looked up once per file, since the same ones usually get watched over several sessions
+async fn filter_by_rating(server_state: &ServerState, rating_filter: RatingFilter, sessions: Vec<PlaybackSession>) -> Vec<PlaybackSession> {
+	if rating_filter.is_unrestricted() {
+		return sessions;
+	}
+	
+	let mut allowed = HashMap::new();
+	let mut filtered_sessions = Vec::new();
+	
+	for session in sessions {
+		let key = (session.library_id.clone(), session.media_path.clone());
+		
+		let is_allowed = match allowed.get(&key) {
+			Some(is_allowed) => *is_allowed,
+			None => {
+				let is_allowed = get_watch_history::is_media_rating_allowed(
+					server_state, rating_filter, &session.library_id, &session.media_path).await;
+				
+				allowed.insert(key, is_allowed);
+				is_allowed
+			}
+		};
+		
+		if is_allowed {
+			filtered_sessions.push(session);
+		}
+	}
+	
+	filtered_sessions
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ViewingStatsParams {
+	// Both days are included, and default to the last 30 days
+	pub from: Option<Date>,
+	pub to: Option<Date>,
+	#[serde(default)]
+	pub interval: StatsInterval,
+}
+
+impl ViewingStatsParams {
+	pub fn time_range(&self) -> Result<(OffsetDateTime, OffsetDateTime), ApiError> {
+		let to = self.to.unwrap_or_else(|| OffsetDateTime::now_utc().date());
+		let from = self.from.unwrap_or(to - time::Duration::days(DEFAULT_RANGE_DAYS - 1));
+		
+		if from > to {
+			return Err(ApiError::InvalidQuery);
+		}
+		
+		let end = to.next_day().ok_or(ApiError::InvalidQuery)?;
+		
+		Ok((from.with_time(Time::MIDNIGHT).assume_utc(), end.with_time(Time::MIDNIGHT).assume_utc()))
+	}
+}
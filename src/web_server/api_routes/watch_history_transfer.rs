@@ -0,0 +1,106 @@
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use http::{Method, Response};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::watch_history::{HistoryMigration, SerializedWatchHistory};
+use crate::web_server::web_utils;
+use crate::web_server::web_utils::{full_body, json_response, restrict_method, HyperRequest, HyperResponse};
+
+const MAX_IMPORT_SIZE: usize = 50_000_000;
+
+#[instrument(skip_all)]
+pub async fn export_watch_history_route(server_state: &ServerState, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET, Method::HEAD])?;
+	
+	let params: TransferParams = web_utils::parse_query(request.uri())?;
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	let mut watch_history = server_state.user_watch_histories.lock().unwrap()
+		.get_watch_history(&user.id)
+		.serialize();
+	
+	watch_history.entries.retain(|entry| user.can_see_path(&entry.library_id, &entry.media_path));
+	
+	let (data, content_type, ext) = match params.format {
+		TransferFormat::Json => (serde_json::to_vec_pretty(&watch_history).unwrap(), mime::APPLICATION_JSON, "json"),
+		TransferFormat::Csv => (watch_history.to_csv()?, mime::TEXT_CSV_UTF_8, "csv"),
+	};
+	
+	let res = Response::builder()
+		.header(CONTENT_TYPE, content_type.as_ref())
+		.header(CONTENT_DISPOSITION, format!("attachment; filename=\"watch-history.{}\"", ext))
+		.body(full_body(data))
+		.unwrap();
+	
+	Ok(res)
+}
+
+#[instrument(skip_all)]
+pub async fn import_watch_history_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let params: TransferParams = web_utils::parse_query(request.uri())?;
+	
+	let (request, body) = request.into_parts();
+	let data = web_utils::collect_body_limited(body, MAX_IMPORT_SIZE).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	let migration = HistoryMigration {
+		libraries: &server_state.libraries,
+		metadata_cache: &server_state.metadata_cache,
+		finished_threshold: server_state.config.main_config.watch_history.finished_threshold,
+	};
+	
+	let watch_history = match params.format {
+		TransferFormat::Json => SerializedWatchHistory::from_json(&data, &migration).await,
+		TransferFormat::Csv => SerializedWatchHistory::from_csv(&data),
+	}.map_err(|_| ApiError::InvalidBody)?;
+	
+	let total_entries = watch_history.entries.len();
+	
+	// Entries for libraries that don't exist here are left out, as are ones the user couldn't have watched
+	let entries: Vec<_> = watch_history.entries.into_iter()
+		.filter(|entry| server_state.libraries.get_library(&entry.library_id).is_some())
+		.filter(|entry| user.can_see_path(&entry.library_id, &entry.media_path))
+		.collect();
+	
+	let imported_entries = entries.len();
+	
+	{
+		let mut user_watch_histories = server_state.user_watch_histories.lock().unwrap();
+		
+		user_watch_histories.get_watch_history(&user.id).import_entries(entries);
+		user_watch_histories.mark_dirty();
+	}
+	
+	info!("Imported {} of {} watch history entries for user {}", imported_entries, total_entries, user.id);
+	
+	let res = ImportResponse {
+		imported_entries,
+		skipped_entries: total_entries - imported_entries,
+	};
+	
+	Ok(json_response(&res, &request.headers).await?)
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransferFormat {
+	Json,
+	Csv,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransferParams {
+	format: TransferFormat,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportResponse {
+	imported_entries: usize,
+	skipped_entries: usize,
+}
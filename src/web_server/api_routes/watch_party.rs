@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::{HeaderMap, Method, Response};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, instrument};
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_types::ApiWatchPartyState;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::watch_party::{PartyAction, RoomEvent};
+use crate::web_server::web_utils::{empty_body, json_response, restrict_method, HyperRequest, HyperResponse};
+use crate::web_server::{libraries, web_utils};
+
+// How often participants are sent the expected position so that they can correct any drift. This also keeps the
+//  connection from being closed as idle.
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+#[instrument(skip_all)]
+pub async fn create_watch_party_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: CreateWatchPartyParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	libraries::locate_media_file_with_auth(server_state, &params.library_id, params.media_path.clone(), &request.headers).await?;
+	
+	let room_id = server_state.watch_parties.create_room(&user.id, &user.display_name, &params.library_id, params.media_path)
+		.ok_or(ApiError::TooManyWatchParties)?;
+	
+	info!("User {} created watch party {}", user.id, room_id);
+	
+	let res = CreateWatchPartyResponse {
+		room_id,
+	};
+	
+	Ok(json_response(&res, &request.headers).await?)
+}
+
+// Joins a room for as long as the server-sent events stream stays open
+#[instrument(skip_all)]
+pub async fn watch_party_events_route(server_state: &Arc<ServerState>, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET])?;
+	
+	let params: RoomParams = web_utils::parse_query(request.uri())?;
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	
+	verify_room_access(server_state, &params.room_id, request.headers()).await?;
+	
+	let (guard, receiver) = server_state.watch_parties.join(&params.room_id, &user.display_name)
+		.ok_or(ApiError::NotFound)?;
+	
+	let server_state = server_state.clone();
+	let headers = request.headers().clone();
+	
+	let events = futures_util::stream::unfold(Some((guard, receiver)), move |state| {
+		let server_state = server_state.clone();
+		let headers = headers.clone();
+		let user_id = user.id.clone();
+		
+		async move {
+			let (guard, mut receiver) = state?;
+			
+			loop {
+				let state = match tokio::time::timeout(SYNC_INTERVAL, receiver.recv()).await {
+					Ok(Ok(RoomEvent::State(state))) => Some(state),
+					Ok(Ok(RoomEvent::Closed)) | Ok(Err(RecvError::Closed)) => None,
+					// Missed updates don't matter since every one carries the whole state
+					Ok(Err(RecvError::Lagged(_))) => continue,
+					// Also missing if the room was removed without being closed
+					Err(_) => guard.current_state(),
+				};
+				
+				let Some(state) = state else {
+					return Some((Ok(Frame::data(Bytes::from("event: closed\ndata: {}\n\n"))), None));
+				};
+				
+				// The user could have been deleted or disabled, or could have lost access to the video, since they
+				//  joined
+				server_state.auth_manager.lookup_from_headers(&headers).ok()
+					.filter(|user| user.id == user_id)?;
+				verify_room_access(&server_state, &state.room_id, &headers).await.ok()?;
+				
+				return Some((Ok(Frame::data(Bytes::from(state_message(&state)))), Some((guard, receiver))));
+			}
+		}
+	});
+	
+	let res = Response::builder()
+		.header(CONTENT_TYPE, mime::TEXT_EVENT_STREAM.as_ref())
+		.header(CACHE_CONTROL, "no-cache")
+		.body(StreamBody::new(events).boxed_unsync())
+		.unwrap();
+	
+	Ok(res)
+}
+
+#[instrument(skip_all)]
+pub async fn control_watch_party_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: ControlWatchPartyParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	if !params.position.is_finite() || params.position < 0.0 {
+		return Err(ApiError::InvalidBody);
+	}
+	
+	verify_room_access(server_state, &params.room_id, &request.headers).await?;
+	
+	if !server_state.watch_parties.control(&params.room_id, params.action, params.position, &user.display_name) {
+		return Err(ApiError::NotFound);
+	}
+	
+	Ok(Response::new(empty_body()))
+}
+
+#[instrument(skip_all)]
+pub async fn close_watch_party_route(server_state: &ServerState, request: HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(&request, &[Method::POST])?;
+	
+	let (request, body) = request.into_parts();
+	let params: RoomParams = web_utils::parse_json_body(body).await?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(&request.headers)?;
+	
+	match server_state.watch_parties.close(&params.room_id, &user.id) {
+		Some(true) => Ok(Response::new(empty_body())),
+		Some(false) => Err(ApiError::Forbidden),
+		None => Err(ApiError::NotFound),
+	}
+}
+
+// Everyone in a room needs to be able to see its video themselves
+async fn verify_room_access(server_state: &ServerState, room_id: &str, headers: &HeaderMap) -> Result<(), ApiError> {
+	let (library_id, media_path) = server_state.watch_parties.room_media(room_id)
+		.ok_or(ApiError::NotFound)?;
+	
+	libraries::locate_media_file_with_auth(server_state, &library_id, media_path, headers).await
+		.map_err(|_| ApiError::NotFound)?;
+	
+	Ok(())
+}
+
+fn state_message(state: &ApiWatchPartyState) -> String {
+	format!("event: state\ndata: {}\n\n", serde_json::to_string(state).unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateWatchPartyParams {
+	library_id: String,
+	media_path: RelativePathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoomParams {
+	room_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ControlWatchPartyParams {
+	room_id: String,
+	action: PartyAction,
+	// Seconds into the video
+	position: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateWatchPartyResponse {
+	room_id: String,
+}
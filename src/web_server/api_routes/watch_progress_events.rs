@@ -0,0 +1,103 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::{HeaderMap, Method, Response};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::instrument;
+
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_routes::get_watch_history;
+use crate::web_server::auth::User;
+use crate::web_server::content_ratings;
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::progress_sync::ProgressEvent;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils::{restrict_method, HyperRequest, HyperResponse};
+
+// Keeps proxies from closing the connection while nothing is being watched
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Server-sent events stream of the user's watch progress changes
+#[instrument(skip_all)]
+pub async fn watch_progress_events_route(server_state: &Arc<ServerState>, request: &HyperRequest) -> Result<HyperResponse, ApiError> {
+	restrict_method(request, &[Method::GET])?;
+	
+	let user = server_state.auth_manager.lookup_from_headers(request.headers())?;
+	let receiver = server_state.progress_sync.subscribe(&user.id);
+	
+	let server_state = server_state.clone();
+	let headers = request.headers().clone();
+	
+	let events = futures_util::stream::unfold(receiver, move |mut receiver| {
+		let server_state = server_state.clone();
+		let headers = headers.clone();
+		let user_id = user.id.clone();
+		
+		async move {
+			loop {
+				let result = tokio::time::timeout(KEEP_ALIVE_INTERVAL, receiver.recv()).await;
+				
+				// The user could have been deleted or disabled, or their login could have expired, since the stream
+				//  was opened. Looking them up again also picks up changes to what they're allowed to see.
+				let user = server_state.auth_manager.lookup_from_headers(&headers).ok()
+					.filter(|user| user.id == user_id)?;
+				
+				let message = match result {
+					Err(_) => ": keep-alive\n\n".to_owned(),
+					Ok(Ok(event)) => {
+						if !is_event_visible(&server_state, &user, &headers, &event).await {
+							continue;
+						}
+						
+						match event {
+							ProgressEvent::Progress(event) =>
+								format!("event: progress\ndata: {}\n\n", serde_json::to_string(&event).unwrap()),
+							ProgressEvent::Directory(event) =>
+								format!("event: directory\ndata: {}\n\n", serde_json::to_string(&event).unwrap()),
+						}
+					}
+					// Some updates were missed, so the client needs to reload everything it's showing
+					Ok(Err(RecvError::Lagged(_))) => "event: resync\ndata: {}\n\n".to_owned(),
+					Ok(Err(RecvError::Closed)) => return None,
+				};
+				
+				return Some((Ok(Frame::data(Bytes::from(message))), receiver));
+			}
+		}
+	});
+	
+	let res = Response::builder()
+		.header(CONTENT_TYPE, mime::TEXT_EVENT_STREAM.as_ref())
+		.header(CACHE_CONTROL, "no-cache")
+		.body(StreamBody::new(events).boxed_unsync())
+		.unwrap();
+	
+	Ok(res)
+}
+
+// Applies the same path rules and rating limit as listing the video or directory would
+async fn is_event_visible(server_state: &ServerState, user: &User, headers: &HeaderMap, event: &ProgressEvent) -> bool {
+	if !user.can_see_path(event.library_id(), event.media_path()) {
+		return false;
+	}
+	
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, user, headers);
+	
+	if rating_filter.is_unrestricted() {
+		return true;
+	}
+	
+	match event {
+		ProgressEvent::Progress(event) =>
+			get_watch_history::is_media_rating_allowed(server_state, rating_filter, &event.library_id, &event.media_path).await,
+		ProgressEvent::Directory(event) => {
+			let Some(library) = server_state.libraries.get_library(&event.library_id) else { return false };
+			
+			rating_filter.allows_directory(content_ratings::directory_rating(server_state, library, &event.media_path).await)
+		}
+	}
+}
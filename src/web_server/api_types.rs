@@ -1,13 +1,39 @@
 use relative_path::RelativePathBuf;
 use serde::{Deserialize, Serialize};
-use time::OffsetDateTime;
+use time::{Date, OffsetDateTime};
 
+use crate::config::{ContentRating, OidcLinkConfig, PathRuleConfig};
+use crate::web_server::favorites::Bookmark;
+use crate::web_server::watch_party::PartyAction;
 use crate::web_server::media_metadata::Dimension;
 
 #[derive(Debug, Serialize)]
 pub struct ApiUserInfo {
 	pub display_name: String,
 	pub username: String,
+	pub admin: bool,
+	pub max_content_rating: Option<ContentRating>,
+	pub ratings_unlocked: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiLoginOptions {
+	pub oidc_enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiAdminUserEntry {
+	pub id: String,
+	pub display_name: String,
+	pub username: String,
+	pub allowed_libraries: Vec<String>,
+	pub admin: bool,
+	pub disabled: bool,
+	pub path_rules: Vec<PathRuleConfig>,
+	pub max_content_rating: Option<ContentRating>,
+	pub block_unrated: bool,
+	pub has_rating_pin: bool,
+	pub oidc_link: Option<OidcLinkConfig>,
 }
 
 #[derive(Debug, Serialize)]
@@ -26,6 +52,11 @@ pub struct ApiFileEntry {
 	pub file_size: u64,
 	pub artist: Option<String>,
 	pub watch_progress: Option<u64>,
+	pub watched: bool,
+	pub play_count: u32,
+	#[serde(with = "time::serde::iso8601::option")]
+	pub first_watched: Option<OffsetDateTime>,
+	pub favorite: bool,
 	#[serde(with = "time::serde::iso8601")]
 	pub creation_date: OffsetDateTime,
 }
@@ -56,6 +87,9 @@ pub struct ApiFileInfo {
 	pub prev_video: Option<String>,
 	pub next_video: Option<String>,
 	pub watch_progress: Option<u64>,
+	pub watched: bool,
+	pub favorite: bool,
+	pub bookmarks: Vec<ApiBookmark>,
 	pub description: Option<String>,
 	pub connections: Vec<ApiVideoConnection>,
 	pub comments: Vec<ApiCommentThread>,
@@ -110,12 +144,206 @@ pub struct ApiDirectoryInfo {
 	pub display_name: String,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiContinueWatchingKind {
+	InProgress,
+	NextUp,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiContinueWatchingEntry {
+	pub kind: ApiContinueWatchingKind,
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	pub file: ApiFileEntry,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ApiWatchHistoryEntry {
 	pub library_id: String,
 	pub media_path: RelativePathBuf,
 	#[serde(with = "time::serde::iso8601")]
+	pub first_watched: OffsetDateTime,
+	#[serde(with = "time::serde::iso8601")]
 	pub last_watched: OffsetDateTime,
 	pub progress: u64,
+	pub watched: bool,
+	pub play_count: u32,
+	pub file: Option<ApiFileEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiPlaylistSummary {
+	pub id: String,
+	pub name: String,
+	pub owner_name: String,
+	pub owned: bool,
+	pub shared: bool,
+	pub entry_count: usize,
+	#[serde(with = "time::serde::iso8601")]
+	pub created: OffsetDateTime,
+	#[serde(with = "time::serde::iso8601")]
+	pub updated: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiPlaylistEntry {
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	pub file: Option<ApiFileEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiWatchProgressEvent {
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	// Missing if the entry was deleted
+	pub progress: Option<u64>,
+	pub watched: bool,
+	pub play_count: u32,
+	#[serde(with = "time::serde::iso8601::option")]
+	pub last_watched: Option<OffsetDateTime>,
+	// Whichever client made the change, so that it can ignore its own updates
+	pub client_id: Option<String>,
+}
+
+// Everything in a directory and its subdirectories was marked as watched or unwatched at once
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiWatchedDirectoryEvent {
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	pub watched: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiWatchPartyState {
+	pub room_id: String,
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	pub host_name: String,
+	pub playing: bool,
+	// Where playback should be as of sent_at, clients that have drifted too far from it should seek
+	pub position: f64,
+	pub participants: Vec<String>,
+	// What caused this update, missing for periodic syncs and people joining or leaving
+	pub action: Option<PartyAction>,
+	pub actor: Option<String>,
+	#[serde(with = "time::serde::iso8601")]
+	pub sent_at: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiFavoriteEntry {
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	#[serde(with = "time::serde::iso8601")]
+	pub added: OffsetDateTime,
 	pub file: Option<ApiFileEntry>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct ApiBookmark {
+	pub id: String,
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	pub timestamp: u64,
+	pub name: String,
+	#[serde(with = "time::serde::iso8601")]
+	pub created: OffsetDateTime,
+}
+
+impl From<Bookmark> for ApiBookmark {
+	fn from(bookmark: Bookmark) -> Self {
+		Self {
+			id: bookmark.id,
+			library_id: bookmark.library_id,
+			media_path: bookmark.media_path,
+			timestamp: bookmark.timestamp,
+			name: bookmark.name,
+			created: bookmark.created,
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiViewingStats {
+	pub users: Vec<ApiUserViewingStats>,
+	pub directories: Vec<ApiDirectoryViewingStats>,
+	pub libraries: Vec<ApiLibraryActivity>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiUserViewingStats {
+	pub user_id: String,
+	// Missing for users that have since been deleted
+	pub display_name: Option<String>,
+	pub hours: f64,
+	pub sessions: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiDirectoryViewingStats {
+	pub library_id: String,
+	pub path: RelativePathBuf,
+	pub hours: f64,
+	pub sessions: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiLibraryActivity {
+	pub library_id: String,
+	pub buckets: Vec<ApiActivityBucket>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiActivityBucket {
+	pub start: Date,
+	pub hours: f64,
+	pub sessions: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiCacheInfo {
+	pub name: String,
+	pub size: u64,
+	pub entry_count: usize,
+	pub size_limit: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiPurgeCacheResponse {
+	pub removed_entries: usize,
+	pub removed_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiCacheScanResult {
+	pub name: String,
+	pub valid_entries: usize,
+	pub removed_entries: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiPrewarmJob {
+	pub id: String,
+	pub library_id: String,
+	pub path: String,
+	pub state: ApiPrewarmJobState,
+	#[serde(with = "time::serde::iso8601::option")]
+	pub last_started: Option<OffsetDateTime>,
+	#[serde(with = "time::serde::iso8601::option")]
+	pub last_finished: Option<OffsetDateTime>,
+	pub files_total: usize,
+	pub files_done: usize,
+	pub files_failed: usize,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiPrewarmJobState {
+	Idle,
+	// Waiting for another job to finish
+	Queued,
+	Running,
+}
@@ -1,24 +1,99 @@
-use crate::config::UsersConfig;
+use crate::config::{ContentRating, ForwardAuthConfig, OidcLinkConfig, ServerConfig, UserConfig, UsersConfig};
 use crate::web_server::api_error::ApiError;
+use crate::web_server::path_rules;
+use crate::web_server::path_rules::PathRule;
 use argon2::password_hash::rand_core::{OsRng, RngCore};
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use headers::{Cookie, HeaderMapExt};
+use http::header::HeaderName;
 use http::HeaderMap;
 use jsonwebtoken::{DecodingKey, EncodingKey, Validation};
+use relative_path::RelativePath;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::Permissions;
+use std::net::IpAddr;
 use std::path::Path;
-use std::time::{Duration, SystemTime};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::info;
 
 pub const AUTH_COOKIE_NAME: &str = "media_server_access_token";
 pub const AUTH_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 365); // 1 year
 
+pub const RATING_UNLOCK_COOKIE_NAME: &str = "media_server_rating_unlock";
+pub const RATING_UNLOCK_LIFETIME: Duration = Duration::from_secs(60 * 60); // 1 hour
+const RATING_UNLOCK_AUDIENCE: &str = "rating_unlock";
+
+const MAX_PIN_ATTEMPTS: u32 = 5;
+const PIN_LOCKOUT_DURATION: Duration = Duration::from_secs(5 * 60);
+
 pub struct AuthManager {
-	users: HashMap<String, User>,
-	username_to_id: HashMap<String, String>,
+	users: RwLock<UserTable>,
 	secrets: AuthSecrets,
+	forward_auth: Option<ForwardAuth>,
+	save_lock: tokio::sync::Mutex<()>,
+	failed_pin_attempts: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+struct ForwardAuth {
+	username_header: HeaderName,
+	trusted_proxies: Vec<IpAddr>,
+	auto_provision_users: bool,
+	default_allowed_libraries: Vec<String>,
+}
+
+#[derive(Default)]
+struct UserTable {
+	users: HashMap<String, Arc<User>>,
+	username_to_id: HashMap<String, String>,
+}
+
+impl UserTable {
+	fn insert(&mut self, user: User) -> Arc<User> {
+		let user = Arc::new(user);
+		
+		self.username_to_id.insert(user.username.clone(), user.id.clone());
+		self.users.insert(user.id.clone(), user.clone());
+		
+		user
+	}
+	
+	fn find_by_oidc_link(&self, oidc_link: &OidcLinkConfig) -> Option<&Arc<User>> {
+		self.users.values().find(|user| user.oidc_link.as_ref() == Some(oidc_link))
+	}
+	
+	// The id is derived from the username since it has to be safe to use in file names
+	fn new_provisioned_user(&self, username: &str, display_name: &str, allowed_libraries: Vec<String>) -> User {
+		let base_id: String = username.chars()
+			.map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+			.collect();
+		
+		let mut id = base_id.clone();
+		let mut suffix = 2;
+		
+		while id.is_empty() || self.users.contains_key(&id) {
+			id = format!("{}_{}", base_id, suffix);
+			suffix += 1;
+		}
+		
+		User {
+			id,
+			display_name: display_name.to_owned(),
+			username: username.to_owned(),
+			allowed_libraries,
+			admin: false,
+			disabled: false,
+			path_rules: Vec::new(),
+			max_content_rating: None,
+			block_unrated: false,
+			oidc_link: None,
+			
+			password_hash: None,
+			rating_pin_hash: None,
+		}
+	}
 }
 
 pub struct AuthSecrets {
@@ -31,48 +106,214 @@ struct JwtClaims {
 	exp: u64,
 }
 
+// Has an audience so that it can't be used as an auth token, since those are validated without one
+#[derive(Clone, Serialize, Deserialize)]
+struct RatingUnlockClaims {
+	sub: String,
+	exp: u64,
+	aud: String,
+}
+
 impl AuthManager {
-	pub fn from_config(users_config: UsersConfig, secrets: AuthSecrets) -> Self {
-		let argon = Argon2::default();
+	pub fn from_config(users_config: UsersConfig, secrets: AuthSecrets) -> anyhow::Result<Self> {
+		let mut user_table = UserTable::default();
 		
-		let users: HashMap<String, User> = users_config.users.into_iter()
-			.map(|cfg| {
-				let salt = SaltString::generate(&mut OsRng);
-				let password_hash = argon.hash_password(cfg.password.as_bytes(), &salt).unwrap();
-				
-				let user = User {
-					id: cfg.id.clone(),
-					display_name: cfg.display_name,
-					username: cfg.username,
-					allowed_libraries: cfg.allowed_libraries,
-					
-					password_hash: password_hash.to_string(),
-				};
-				
-				(cfg.id, user)
-			})
-			.collect();
+		for cfg in users_config.users {
+			if user_table.users.contains_key(&cfg.id) {
+				anyhow::bail!("Duplicate user id {}", cfg.id);
+			}
+			
+			if user_table.username_to_id.contains_key(&cfg.username) {
+				anyhow::bail!("Duplicate username {}", cfg.username);
+			}
+			
+			if cfg.oidc_link.is_some() && user_table.users.values().any(|user| user.oidc_link == cfg.oidc_link) {
+				anyhow::bail!("User {} has the same OIDC link as another user", cfg.id);
+			}
+			
+			user_table.insert(User::from_config(cfg)?);
+		}
+		
+		Ok(Self {
+			users: RwLock::new(user_table),
+			secrets,
+			forward_auth: None,
+			save_lock: tokio::sync::Mutex::new(()),
+			failed_pin_attempts: Mutex::new(HashMap::new()),
+		})
+	}
+	
+	pub fn with_forward_auth(mut self, forward_auth_config: ForwardAuthConfig) -> anyhow::Result<Self> {
+		if !forward_auth_config.enabled {
+			self.forward_auth = None;
+			return Ok(self);
+		}
+		
+		let username_header = HeaderName::from_bytes(forward_auth_config.username_header.as_bytes())
+			.map_err(|_| anyhow::anyhow!("Invalid forward auth header name {:?}", forward_auth_config.username_header))?;
+		
+		self.forward_auth = Some(ForwardAuth {
+			username_header,
+			trusted_proxies: forward_auth_config.trusted_proxies.iter()
+				.map(IpAddr::to_canonical)
+				.collect(),
+			auto_provision_users: forward_auth_config.auto_provision_users,
+			default_allowed_libraries: forward_auth_config.default_allowed_libraries,
+		});
+		
+		Ok(self)
+	}
+	
+	pub fn list_users(&self) -> Vec<Arc<User>> {
+		self.users.read().unwrap().users.values().cloned().collect()
+	}
+	
+	pub fn get_user_by_id(&self, id: &str) -> Option<Arc<User>> {
+		self.users.read().unwrap().users.get(id).cloned()
+	}
+	
+	pub fn get_user_by_username(&self, username: &str) -> Option<Arc<User>> {
+		let user_table = self.users.read().unwrap();
 		
-		let username_to_id = users.values()
-			.map(|user| (user.username.clone(), user.id.clone()))
+		user_table.username_to_id.get(username)
+			.and_then(|id| user_table.users.get(id))
+			.cloned()
+	}
+	
+	pub fn get_user_by_oidc_link(&self, oidc_link: &OidcLinkConfig) -> Option<Arc<User>> {
+		self.users.read().unwrap().find_by_oidc_link(oidc_link).cloned()
+	}
+	
+	pub fn to_config(&self) -> UsersConfig {
+		let mut users: Vec<UserConfig> = self.users.read().unwrap().users.values()
+			.map(|user| user.to_config())
 			.collect();
 		
-		Self {
+		users.sort_by(|a, b| a.id.cmp(&b.id));
+		
+		UsersConfig {
 			users,
-			username_to_id,
-			secrets,
 		}
 	}
 	
-	pub fn iter_users(&self) -> impl Iterator<Item = &User> {
-		self.users.values()
+	// Saves are serialized so that the last save to finish always reflects the newest state
+	pub async fn save_config(&self, config: &ServerConfig) -> anyhow::Result<()> {
+		let _guard = self.save_lock.lock().await;
+		
+		config.save_users_config(&self.to_config()).await
+	}
+	
+	pub fn create_user(&self, user_config: UserConfig) -> Result<Arc<User>, ApiError> {
+		// Hash the password before taking the lock, it's slow
+		let user = User::from_config(user_config)?;
+		
+		let mut user_table = self.users.write().unwrap();
+		
+		if user_table.users.contains_key(&user.id) || user_table.username_to_id.contains_key(&user.username) {
+			return Err(ApiError::UserAlreadyExists);
+		}
+		
+		Ok(user_table.insert(user))
+	}
+	
+	pub fn delete_user(&self, id: &str) -> Result<Arc<User>, ApiError> {
+		let mut user_table = self.users.write().unwrap();
+		
+		let user = user_table.users.remove(id).ok_or(ApiError::UserNotFound)?;
+		user_table.username_to_id.remove(&user.username);
+		
+		Ok(user)
+	}
+	
+	// Users are swapped out rather than mutated, existing handles keep the old state until their request finishes
+	pub fn update_user(&self, id: &str, update_func: impl FnOnce(&mut User)) -> Result<Arc<User>, ApiError> {
+		let mut user_table = self.users.write().unwrap();
+		
+		let mut user = User::clone(user_table.users.get(id).ok_or(ApiError::UserNotFound)?);
+		update_func(&mut user);
+		
+		Ok(user_table.insert(user))
+	}
+	
+	pub fn set_password(&self, id: &str, password: &str) -> Result<Arc<User>, ApiError> {
+		let password_hash = hash_password(password)?;
+		
+		self.update_user(id, |user| user.password_hash = Some(password_hash))
+	}
+	
+	// Creates a password-less user for a username that was authenticated by something else
+	pub fn provision_user(&self, username: &str, display_name: &str, allowed_libraries: Vec<String>) -> Arc<User> {
+		let mut user_table = self.users.write().unwrap();
+		
+		if let Some(user) = user_table.username_to_id.get(username).and_then(|id| user_table.users.get(id)) {
+			return user.clone();
+		}
+		
+		let user = user_table.new_provisioned_user(username, display_name, allowed_libraries);
+		
+		user_table.insert(user)
+	}
+	
+	// Unlike provision_user this never hands out an existing user with the same username, since a provider might let
+	//  people pick their own username. The link is what identifies the user from then on.
+	pub fn provision_oidc_user(&self, username: &str, display_name: &str, allowed_libraries: Vec<String>, oidc_link: OidcLinkConfig) -> Result<Arc<User>, ApiError> {
+		let mut user_table = self.users.write().unwrap();
+		
+		// Another login for the same identity might have just provisioned it
+		if let Some(user) = user_table.find_by_oidc_link(&oidc_link) {
+			return Ok(user.clone());
+		}
+		
+		if user_table.username_to_id.contains_key(username) {
+			return Err(ApiError::UserAlreadyExists);
+		}
+		
+		let mut user = user_table.new_provisioned_user(username, display_name, allowed_libraries);
+		user.oidc_link = Some(oidc_link);
+		
+		Ok(user_table.insert(user))
 	}
 	
-	pub fn get_user_by_id(&self, id: &str) -> Option<&User> {
-		self.users.get(id)
+	// Strips the forward auth header from requests that didn't come from a trusted proxy, so that the header
+	//  can be trusted everywhere else
+	pub fn sanitize_forward_auth_headers(&self, headers: &mut HeaderMap, remote_addr: IpAddr) {
+		let Some(forward_auth) = &self.forward_auth else { return };
+		
+		if !forward_auth.trusted_proxies.contains(&remote_addr.to_canonical()) {
+			headers.remove(&forward_auth.username_header);
+		}
+	}
+	
+	fn forward_auth_username<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+		let forward_auth = self.forward_auth.as_ref()?;
+		
+		headers.get(&forward_auth.username_header)
+			.and_then(|value| value.to_str().ok())
+			.map(str::trim)
+			.filter(|username| !username.is_empty())
+	}
+	
+	pub async fn provision_forward_auth_user(&self, headers: &HeaderMap, config: &ServerConfig) -> anyhow::Result<()> {
+		let Some(forward_auth) = &self.forward_auth else { return Ok(()) };
+		
+		if !forward_auth.auto_provision_users {
+			return Ok(());
+		}
+		
+		let Some(username) = self.forward_auth_username(headers) else { return Ok(()) };
+		
+		if self.get_user_by_username(username).is_some() {
+			return Ok(());
+		}
+		
+		let user = self.provision_user(username, username, forward_auth.default_allowed_libraries.clone());
+		
+		info!("Provisioned user {} for forward auth username {}", user.id, username);
+		
+		self.save_config(config).await
 	}
 	
-	pub fn decode_token(&self, token: &str) -> anyhow::Result<&User> {
+	pub fn decode_token(&self, token: &str) -> anyhow::Result<Arc<User>> {
 		let claims = jsonwebtoken::decode::<JwtClaims>(
 			token,
 			&DecodingKey::from_secret(&self.secrets.jwt_key),
@@ -81,7 +322,13 @@ impl AuthManager {
 		
 		let user_id = claims.sub;
 		
-		self.get_user_by_id(&user_id).ok_or_else(|| anyhow::anyhow!("Unknown user id"))
+		let user = self.get_user_by_id(&user_id).ok_or_else(|| anyhow::anyhow!("Unknown user id"))?;
+		
+		if user.disabled {
+			return Err(anyhow::anyhow!("User is disabled"));
+		}
+		
+		Ok(user)
 	}
 	
 	pub fn generate_token(&self, user: &User) -> String {
@@ -103,20 +350,35 @@ impl AuthManager {
 		).expect("Failed to generate JWT token")
 	}
 	
-	pub fn login(&self, username: &str, password: &str) -> anyhow::Result<&User> {
-		let user_id = self.username_to_id.get(username)
-			.ok_or_else(|| anyhow::anyhow!("Unknown username"))?;
-		
-		let user = &self.users[user_id];
+	pub fn login(&self, username: &str, password: &str) -> anyhow::Result<Arc<User>> {
+		let user = {
+			let user_table = self.users.read().unwrap();
+			
+			let user_id = user_table.username_to_id.get(username)
+				.ok_or_else(|| anyhow::anyhow!("Unknown username"))?;
+			
+			user_table.users[user_id].clone()
+		};
 		
 		if !user.verify_password(password) {
 			return Err(anyhow::anyhow!("Invalid password"));
 		}
 		
+		if user.disabled {
+			return Err(anyhow::anyhow!("User is disabled"));
+		}
+		
 		Ok(user)
 	}
 	
-	pub fn lookup_from_headers(&self, headers: &HeaderMap) -> Result<&User, ApiError> {
+	pub fn lookup_from_headers(&self, headers: &HeaderMap) -> Result<Arc<User>, ApiError> {
+		// If the proxy sent a username then it's authoritative, don't fall back to the cookie
+		if let Some(username) = self.forward_auth_username(headers) {
+			return self.get_user_by_username(username)
+				.filter(|user| !user.disabled)
+				.ok_or(ApiError::Unauthorized);
+		}
+		
 		let cookies = headers.typed_get::<Cookie>();
 		
 		cookies.as_ref()
@@ -124,27 +386,187 @@ impl AuthManager {
 			.and_then(|auth_token| self.decode_token(auth_token).ok())
 			.ok_or(ApiError::Unauthorized)
 	}
+	
+	pub fn set_rating_pin(&self, id: &str, pin: Option<&str>) -> Result<Arc<User>, ApiError> {
+		let rating_pin_hash = pin.map(hash_password).transpose()?;
+		
+		self.update_user(id, |user| user.rating_pin_hash = rating_pin_hash)
+	}
+	
+	// Checks the PIN and returns a token that lifts the user's content rating restriction for a while
+	pub fn unlock_ratings(&self, user: &User, pin: &str) -> Result<String, ApiError> {
+		{
+			let mut failed_pin_attempts = self.failed_pin_attempts.lock().unwrap();
+			
+			if let Some(&(attempts, last_attempt)) = failed_pin_attempts.get(&user.id) {
+				if last_attempt.elapsed() >= PIN_LOCKOUT_DURATION {
+					failed_pin_attempts.remove(&user.id);
+				} else if attempts >= MAX_PIN_ATTEMPTS {
+					return Err(ApiError::TooManyAttempts);
+				}
+			}
+		}
+		
+		if !user.verify_rating_pin(pin) {
+			let mut failed_pin_attempts = self.failed_pin_attempts.lock().unwrap();
+			let entry = failed_pin_attempts.entry(user.id.clone()).or_insert((0, Instant::now()));
+			
+			*entry = (entry.0 + 1, Instant::now());
+			
+			return Err(ApiError::IncorrectPin);
+		}
+		
+		self.failed_pin_attempts.lock().unwrap().remove(&user.id);
+		
+		let expire_time_unix = (SystemTime::now() + RATING_UNLOCK_LIFETIME).duration_since(std::time::UNIX_EPOCH)
+			.expect("Time went backwards")
+			.as_secs();
+		
+		let claims = RatingUnlockClaims {
+			sub: user.id.clone(),
+			exp: expire_time_unix,
+			aud: RATING_UNLOCK_AUDIENCE.to_owned(),
+		};
+		
+		Ok(jsonwebtoken::encode(
+			&jsonwebtoken::Header::default(),
+			&claims,
+			&EncodingKey::from_secret(&self.secrets.jwt_key)
+		).expect("Failed to generate JWT token"))
+	}
+	
+	pub fn ratings_unlocked(&self, user: &User, headers: &HeaderMap) -> bool {
+		// Changing or removing the PIN locks everything again
+		if user.rating_pin_hash.is_none() {
+			return false;
+		}
+		
+		let Some(cookies) = headers.typed_get::<Cookie>() else { return false };
+		let Some(token) = cookies.get(RATING_UNLOCK_COOKIE_NAME) else { return false };
+		
+		let mut validation = Validation::default();
+		validation.set_audience(&[RATING_UNLOCK_AUDIENCE]);
+		
+		jsonwebtoken::decode::<RatingUnlockClaims>(token, &DecodingKey::from_secret(&self.secrets.jwt_key), &validation)
+			.is_ok_and(|token| token.claims.sub == user.id)
+	}
 }
 
+#[derive(Clone)]
 pub struct User {
 	pub id: String,
 	pub display_name: String,
 	pub username: String,
 	pub allowed_libraries: Vec<String>,
+	pub admin: bool,
+	pub disabled: bool,
+	pub path_rules: Vec<PathRule>,
+	pub max_content_rating: Option<ContentRating>,
+	pub block_unrated: bool,
+	pub oidc_link: Option<OidcLinkConfig>,
 	
-	password_hash: String,
+	password_hash: Option<String>,
+	rating_pin_hash: Option<String>,
 }
 
 impl User {
-	pub fn verify_password(&self, password: &str) -> bool {
-		let password_hash = PasswordHash::new(&self.password_hash).unwrap();
+	fn from_config(cfg: UserConfig) -> anyhow::Result<Self> {
+		let password_hash = load_password_hash(cfg.password_hash, cfg.password)
+			.map_err(|err| anyhow::anyhow!("Invalid password hash for user {}: {}", cfg.id, err))?;
+		let rating_pin_hash = load_password_hash(cfg.rating_pin_hash, cfg.rating_pin)
+			.map_err(|err| anyhow::anyhow!("Invalid rating PIN hash for user {}: {}", cfg.id, err))?;
 		
-		Argon2::default().verify_password(password.as_bytes(), &password_hash).is_ok()
+		let path_rules = cfg.path_rules.into_iter()
+			.map(PathRule::from_config)
+			.collect::<anyhow::Result<_>>()?;
+		
+		Ok(Self {
+			id: cfg.id,
+			display_name: cfg.display_name,
+			username: cfg.username,
+			allowed_libraries: cfg.allowed_libraries,
+			admin: cfg.admin,
+			disabled: cfg.disabled,
+			path_rules,
+			max_content_rating: cfg.max_content_rating,
+			block_unrated: cfg.block_unrated,
+			oidc_link: cfg.oidc_link,
+			
+			password_hash,
+			rating_pin_hash,
+		})
+	}
+	
+	fn to_config(&self) -> UserConfig {
+		UserConfig {
+			id: self.id.clone(),
+			display_name: self.display_name.clone(),
+			username: self.username.clone(),
+			password: None,
+			password_hash: self.password_hash.clone(),
+			allowed_libraries: self.allowed_libraries.clone(),
+			admin: self.admin,
+			disabled: self.disabled,
+			path_rules: self.path_rules.iter().map(PathRule::to_config).collect(),
+			max_content_rating: self.max_content_rating,
+			block_unrated: self.block_unrated,
+			rating_pin: None,
+			rating_pin_hash: self.rating_pin_hash.clone(),
+			oidc_link: self.oidc_link.clone(),
+		}
+	}
+	
+	pub fn verify_password(&self, password: &str) -> bool {
+		verify_hash(self.password_hash.as_deref(), password)
+	}
+	
+	fn verify_rating_pin(&self, pin: &str) -> bool {
+		verify_hash(self.rating_pin_hash.as_deref(), pin)
+	}
+	
+	pub fn has_rating_pin(&self) -> bool {
+		self.rating_pin_hash.is_some()
 	}
 	
 	pub fn can_see_library(&self, library_id: &str) -> bool {
 		self.allowed_libraries.iter().any(|s| s == library_id)
 	}
+	
+	pub fn can_see_path(&self, library_id: &str, path: &RelativePath) -> bool {
+		self.can_see_library(library_id) && path_rules::is_path_visible(&self.path_rules, library_id, path)
+	}
+	
+	pub fn has_path_rules(&self, library_id: &str) -> bool {
+		self.path_rules.iter().any(|rule| rule.library_id == library_id)
+	}
+}
+
+fn load_password_hash(hash: Option<String>, plain: Option<String>) -> anyhow::Result<Option<String>> {
+	match (hash, plain) {
+		(Some(hash), _) => {
+			PasswordHash::new(&hash).map_err(|err| anyhow::anyhow!("{}", err))?;
+			
+			Ok(Some(hash))
+		}
+		(None, Some(plain)) => Ok(Some(hash_password(&plain)?)),
+		(None, None) => Ok(None),
+	}
+}
+
+fn verify_hash(hash: Option<&str>, password: &str) -> bool {
+	let Some(hash) = hash else { return false };
+	let hash = PasswordHash::new(hash).unwrap();
+	
+	Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+}
+
+fn hash_password(password: &str) -> anyhow::Result<String> {
+	let salt = SaltString::generate(&mut OsRng);
+	
+	let password_hash = Argon2::default().hash_password(password.as_bytes(), &salt)
+		.map_err(|err| anyhow::anyhow!("Hashing password: {}", err))?;
+	
+	Ok(password_hash.to_string())
 }
 
 impl AuthSecrets {
@@ -191,7 +613,9 @@ struct AuthSecretsSerialized {
 
 #[cfg(test)]
 mod tests {
-	use crate::config::{UserConfig, UsersConfig};
+	use std::sync::Arc;
+	use crate::config::{ForwardAuthConfig, UserConfig, UsersConfig};
+	use crate::web_server::api_error::ApiError;
 	use crate::web_server::auth::{AuthManager, AuthSecrets, User};
 	use argon2::password_hash::SaltString;
 	use argon2::{Argon2, PasswordHasher};
@@ -208,7 +632,14 @@ mod tests {
 			display_name: "Joe".to_string(),
 			username: "joe".to_string(),
 			allowed_libraries: vec!["lib_a".to_string(), "lib_b".to_string()],
-			password_hash: password_hash.to_string(),
+			admin: false,
+			disabled: false,
+			path_rules: Vec::new(),
+			max_content_rating: None,
+			block_unrated: false,
+			oidc_link: None,
+			password_hash: Some(password_hash.to_string()),
+			rating_pin_hash: None,
 		}
 	}
 	
@@ -242,15 +673,33 @@ mod tests {
 					id: "joe".to_string(),
 					display_name: "Joe Moe".to_string(),
 					username: "joemoe".to_string(),
-					password: "hunter42".to_string(),
+					password: Some("hunter42".to_string()),
+					password_hash: None,
 					allowed_libraries: vec!["lib_a".to_string(), "lib_b".to_string()],
+					admin: true,
+					disabled: false,
+					path_rules: Vec::new(),
+					max_content_rating: None,
+					block_unrated: false,
+					rating_pin: None,
+					rating_pin_hash: None,
+					oidc_link: None,
 				},
 				UserConfig {
 					id: "bob".to_string(),
 					display_name: "Bob Kleuksi".to_string(),
 					username: "bobk".to_string(),
-					password: "hfudsfh8ffhuuihufu9".to_string(),
+					password: Some("hfudsfh8ffhuuihufu9".to_string()),
+					password_hash: None,
 					allowed_libraries: vec!["lib_c".to_string()],
+					admin: false,
+					disabled: false,
+					path_rules: Vec::new(),
+					max_content_rating: None,
+					block_unrated: false,
+					rating_pin: None,
+					rating_pin_hash: None,
+					oidc_link: None,
 				},
 			],
 		}
@@ -259,10 +708,10 @@ mod tests {
 	#[test]
 	fn test_auth_manager_init() {
 		let secrets = AuthSecrets::generate();
-		let auth_manager = AuthManager::from_config(create_test_user_config(), secrets);
+		let auth_manager = AuthManager::from_config(create_test_user_config(), secrets).unwrap();
 		
-		let joe = &auth_manager.users["joe"];
-		let bob = &auth_manager.users["bob"];
+		let joe = auth_manager.get_user_by_id("joe").unwrap();
+		let bob = auth_manager.get_user_by_id("bob").unwrap();
 		
 		assert_eq!(joe.id, "joe");
 		assert!(joe.verify_password("hunter42"));
@@ -285,7 +734,7 @@ mod tests {
 		let joe_login = auth_manager.login("joemoe", "hunter42").unwrap();
 		assert_eq!(joe_login.id, "joe");
 		
-		let joe_token = auth_manager.generate_token(joe_login);
+		let joe_token = auth_manager.generate_token(&joe_login);
 		
 		assert_eq!(auth_manager.decode_token(&joe_token).unwrap().id, "joe");
 		assert!(auth_manager.decode_token("ababbababababababbabababbbbabababbabababbabbababbabababa").is_err());
@@ -296,4 +745,124 @@ mod tests {
 		
 		assert_eq!(auth_manager.lookup_from_headers(&headers).unwrap().id, "joe");
 	}
-}
\ No newline at end of file
+	
+	#[test]
+	fn test_user_management() {
+		let secrets = AuthSecrets::generate();
+		let auth_manager = AuthManager::from_config(create_test_user_config(), secrets).unwrap();
+		
+		assert!(auth_manager.get_user_by_id("joe").unwrap().admin);
+		assert!(!auth_manager.get_user_by_id("bob").unwrap().admin);
+		
+		let zoe_config = UserConfig {
+			id: "zoe".to_string(),
+			display_name: "Zoe".to_string(),
+			username: "zoe".to_string(),
+			password: Some("zoe_password".to_string()),
+			password_hash: None,
+			allowed_libraries: vec!["lib_a".to_string()],
+			admin: false,
+			disabled: false,
+			path_rules: Vec::new(),
+			max_content_rating: None,
+			block_unrated: false,
+			rating_pin: None,
+			rating_pin_hash: None,
+			oidc_link: None,
+		};
+		
+		let zoe = auth_manager.create_user(zoe_config.clone()).unwrap();
+		assert_eq!(zoe.id, "zoe");
+		assert_eq!(auth_manager.login("zoe", "zoe_password").unwrap().id, "zoe");
+		
+		assert!(matches!(auth_manager.create_user(zoe_config.clone()), Err(ApiError::UserAlreadyExists)));
+		assert!(matches!(auth_manager.create_user(UserConfig { id: "zoe2".to_string(), ..zoe_config.clone() }), Err(ApiError::UserAlreadyExists)));
+		
+		let zoe_token = auth_manager.generate_token(&zoe);
+		assert_eq!(auth_manager.decode_token(&zoe_token).unwrap().id, "zoe");
+		
+		auth_manager.update_user("zoe", |user| user.disabled = true).unwrap();
+		
+		assert!(auth_manager.login("zoe", "zoe_password").is_err());
+		assert!(auth_manager.decode_token(&zoe_token).is_err());
+		
+		auth_manager.update_user("zoe", |user| {
+			user.disabled = false;
+			user.allowed_libraries = vec!["lib_b".to_string()];
+		}).unwrap();
+		
+		let zoe = auth_manager.decode_token(&zoe_token).unwrap();
+		assert!(zoe.can_see_library("lib_b"));
+		assert!(!zoe.can_see_library("lib_a"));
+		
+		auth_manager.set_password("zoe", "new_password").unwrap();
+		
+		assert!(auth_manager.login("zoe", "zoe_password").is_err());
+		assert_eq!(auth_manager.login("zoe", "new_password").unwrap().id, "zoe");
+		
+		let users_config = auth_manager.to_config();
+		assert_eq!(users_config.users.iter().map(|u| u.id.as_str()).collect::<Vec<_>>(), &["bob", "joe", "zoe"]);
+		assert!(users_config.users.iter().all(|u| u.password.is_none() && u.password_hash.is_some()));
+		
+		let reloaded = AuthManager::from_config(users_config, AuthSecrets::generate()).unwrap();
+		assert_eq!(reloaded.login("zoe", "new_password").unwrap().id, "zoe");
+		assert_eq!(reloaded.login("joemoe", "hunter42").unwrap().id, "joe");
+		
+		auth_manager.delete_user("zoe").unwrap();
+		
+		assert!(auth_manager.get_user_by_id("zoe").is_none());
+		assert!(auth_manager.login("zoe", "new_password").is_err());
+		assert!(auth_manager.decode_token(&zoe_token).is_err());
+		assert!(matches!(auth_manager.delete_user("zoe"), Err(ApiError::UserNotFound)));
+	}
+	
+	#[test]
+	fn test_forward_auth() {
+		let forward_auth_config = ForwardAuthConfig {
+			enabled: true,
+			username_header: "Remote-User".to_string(),
+			trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+			auto_provision_users: true,
+			default_allowed_libraries: vec!["lib_a".to_string()],
+		};
+		
+		let auth_manager = AuthManager::from_config(create_test_user_config(), AuthSecrets::generate()).unwrap()
+			.with_forward_auth(forward_auth_config).unwrap();
+		
+		let mut headers = HeaderMap::new();
+		headers.insert("Remote-User", "bobk".parse().unwrap());
+		
+		assert_eq!(auth_manager.lookup_from_headers(&headers).unwrap().id, "bob");
+		
+		// IPv4-mapped addresses should be treated the same as plain IPv4 ones
+		let mut trusted_headers = headers.clone();
+		auth_manager.sanitize_forward_auth_headers(&mut trusted_headers, "::ffff:10.0.0.1".parse().unwrap());
+		assert_eq!(auth_manager.lookup_from_headers(&trusted_headers).unwrap().id, "bob");
+		
+		let mut untrusted_headers = headers.clone();
+		auth_manager.sanitize_forward_auth_headers(&mut untrusted_headers, "10.0.0.2".parse().unwrap());
+		assert!(untrusted_headers.get("Remote-User").is_none());
+		assert!(auth_manager.lookup_from_headers(&untrusted_headers).is_err());
+		
+		// The header takes precedence over the cookie
+		let joe_token = auth_manager.generate_token(&auth_manager.get_user_by_id("joe").unwrap());
+		headers.insert(COOKIE, format!("media_server_access_token={}", joe_token).parse().unwrap());
+		assert_eq!(auth_manager.lookup_from_headers(&headers).unwrap().id, "bob");
+		
+		headers.insert("Remote-User", "zoe@example.com".parse().unwrap());
+		assert!(auth_manager.lookup_from_headers(&headers).is_err());
+		
+		let zoe = auth_manager.provision_user("zoe@example.com", "zoe@example.com", vec!["lib_a".to_string()]);
+		assert_eq!(zoe.id, "zoe_example_com");
+		assert!(zoe.can_see_library("lib_a"));
+		assert!(!zoe.verify_password(""));
+		assert_eq!(auth_manager.lookup_from_headers(&headers).unwrap().id, "zoe_example_com");
+		
+		assert!(Arc::ptr_eq(&auth_manager.provision_user("zoe@example.com", "Zoe", Vec::new()), &zoe));
+		assert_eq!(auth_manager.provision_user("zoe.example.com", "Zoe", Vec::new()).id, "zoe_example_com_2");
+		
+		auth_manager.update_user("bob", |user| user.disabled = true).unwrap();
+		headers.insert("Remote-User", "bobk".parse().unwrap());
+		assert!(auth_manager.lookup_from_headers(&headers).is_err());
+	}
+}
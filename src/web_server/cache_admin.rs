@@ -0,0 +1,211 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+
+use crate::config::{CacheStorageBackend, ServerConfig};
+use crate::utils;
+use crate::web_server::libraries::Libraries;
+use crate::web_server::media_backend_factory::MediaBackendFactory;
+use crate::web_server::metadata_cache::FileMetadataCache;
+use crate::web_server::prewarm::{self, PrewarmJobs, PrewarmTargets};
+use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator, ArtifactOutput};
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_keys::CacheKeys;
+use crate::web_server::services::segment_prefetcher::SegmentPrefetcher;
+use crate::web_server::services::task_pool::TaskPool;
+use crate::web_server::services::{cache_storage, hls_segment_service, scaled_thumbnail_service, thumbnail_service, thumbnail_sheet_service};
+
+// Names the caches go by in the admin API and the CLI
+pub const SEGMENTS_CACHE: &str = "segments";
+pub const THUMBNAILS_CACHE: &str = "thumbnails";
+pub const SCALED_THUMBNAILS_CACHE: &str = "scaled_thumbnails";
+pub const THUMBNAIL_SHEETS_CACHE: &str = "thumbnail_sheets";
+pub const SUBTITLES_CACHE: &str = "subtitles";
+pub const AUTO_SUBTITLES_CACHE: &str = "auto_subtitles";
+
+pub struct CacheLocation {
+	pub name: &'static str,
+	pub dir: PathBuf,
+	pub size_limit: u64,
+}
+
+pub fn cache_locations(config: &ServerConfig) -> Vec<CacheLocation> {
+	let paths = &config.paths;
+	let caches = &config.main_config.caches;
+	
+	let location = |name, dir: &PathBuf, size_limit| CacheLocation {
+		name,
+		dir: dir.clone(),
+		size_limit,
+	};
+	
+	vec![
+		location(SEGMENTS_CACHE, &paths.transcoded_segments_cache_dir, caches.size_limit(caches.segments_cache_size_limit)),
+		location(THUMBNAILS_CACHE, &paths.thumbnail_cache_dir, caches.size_limit(caches.thumbnail_cache_size_limit)),
+		location(SCALED_THUMBNAILS_CACHE, &paths.scaled_thumbnail_cache_dir, caches.size_limit(caches.scaled_thumbnail_cache_size_limit)),
+		location(THUMBNAIL_SHEETS_CACHE, &paths.thumbnail_sheet_cache_dir, caches.size_limit(caches.thumbnail_sheet_cache_size_limit)),
+		location(SUBTITLES_CACHE, &paths.subtitles_cache_dir, caches.size_limit(caches.subtitles_cache_size_limit)),
+		location(AUTO_SUBTITLES_CACHE, &paths.auto_subtitles_cache_dir, caches.size_limit(caches.auto_subtitles_cache_size_limit)),
+	]
+}
+
+// Everything generated from a media file has a cache key starting with the file's key. Scaled thumbnails are keyed by
+//  the image they were scaled from instead, so they aren't matched.
+pub async fn media_file_filter(cache_keys: &CacheKeys, media_path: &Path) -> anyhow::Result<impl Fn(&str) -> bool + Sync + use<>> {
+	let file_hash = cache_keys.file_key(media_path).await?;
+	
+	Ok(move |cache_key: &str| cache_key.starts_with(&file_hash))
+}
+
+// Opens the caches without any of their generators, so that their files can be managed while the server isn't running
+pub async fn open_stored_caches(config: &ServerConfig) -> anyhow::Result<Vec<(&'static str, ArtifactCache<StoredFilesOnly>)>> {
+	let task_pool = Arc::new(TaskPool::new(1));
+	let mut caches = Vec::new();
+	
+	for location in cache_locations(config) {
+		// Optional caches that were never enabled
+		if config.main_config.caches.storage == CacheStorageBackend::Filesystem && !tokio::fs::try_exists(&location.dir).await? {
+			continue;
+		}
+		
+		let cache = artifact_cache::builder()
+			.storage(cache_storage::create_storage(&config.main_config.caches, &location.dir)?)
+			.cache_dir(location.dir)
+			.task_pool(task_pool.clone())
+			.file_size_limit(location.size_limit)
+			.build(StoredFilesOnly)
+			.await?;
+		
+		caches.push((location.name, cache));
+	}
+	
+	Ok(caches)
+}
+
+pub async fn list_caches(config: &ServerConfig) -> anyhow::Result<()> {
+	for (name, cache) in &open_stored_caches(config).await? {
+		let stats = cache.stats();
+		
+		println!("{}: {}B in {} entries, {}B max", name,
+			utils::abbreviate_number(stats.size), stats.entry_count, utils::abbreviate_number(stats.size_limit));
+	}
+	
+	Ok(())
+}
+
+pub async fn purge_caches(config: &ServerConfig, cache_name: Option<&str>, media_file: Option<&Path>) -> anyhow::Result<()> {
+	if cache_name.is_none() && media_file.is_none() {
+		bail!("Purging every cache needs to be asked for by name, or limited to a media file");
+	}
+	
+	let caches = open_stored_caches(config).await?;
+	
+	let cache_keys = CacheKeys::new(&config.load_libraries_config().await?, Arc::new(FileMetadataCache::new()));
+	
+	let media_file_filter = match media_file {
+		Some(media_file) => Some(media_file_filter(&cache_keys, media_file).await.context("Reading media file")?),
+		None => None,
+	};
+	
+	for (name, cache) in select_caches(&caches, cache_name)? {
+		let purge_stats = match &media_file_filter {
+			Some(filter) => cache.purge(filter).await?,
+			None => cache.purge(|_| true).await?,
+		};
+		
+		println!("{}: removed {} entries, {}B", name,
+			purge_stats.removed_entries, utils::abbreviate_number(purge_stats.removed_bytes));
+	}
+	
+	Ok(())
+}
+
+pub async fn scan_caches(config: &ServerConfig, cache_name: Option<&str>) -> anyhow::Result<()> {
+	let caches = open_stored_caches(config).await?;
+	
+	for (name, cache) in select_caches(&caches, cache_name)? {
+		// Opening the cache already scanned it and logged what was removed, so this only reports the result
+		let scan_stats = cache.scan().await?;
+		
+		println!("{}: {} valid entries", name, scan_stats.valid_entries);
+	}
+	
+	Ok(())
+}
+
+// Only sets up the caches that pre-warming fills, along with their generators
+pub async fn run_prewarm_job(config: &ServerConfig, job_id: &str) -> anyhow::Result<()> {
+	let libraries_config = config.load_libraries_config().await?;
+	
+	let metadata_cache = Arc::new(FileMetadataCache::new());
+	let cache_keys = Arc::new(CacheKeys::new(&libraries_config, metadata_cache.clone()));
+	
+	let libraries = Libraries::from_config(libraries_config);
+	let prewarm_jobs = PrewarmJobs::from_config(&config.main_config.prewarm, &libraries)?;
+	
+	let media_backend_factory = Arc::new(MediaBackendFactory::new(config.main_config.transcoding.backend)?);
+	let task_pool = Arc::new(TaskPool::new(config.main_config.transcoding.concurrent_tasks));
+	
+	// Enforced by the server, if it's running
+	let cache_names: Vec<&str> = cache_locations(config).iter().map(|location| location.name).collect();
+	let cache_budget = Arc::new(CacheBudget::from_config(&config.main_config.caches.budget, &cache_names)?);
+	
+	// Nobody is watching, so it only gets told how long segments took
+	let segment_prefetcher = Arc::new(SegmentPrefetcher::new(&config.main_config.transcoding));
+	
+	let hls_segment_generator = hls_segment_service::init_service(config, task_pool.clone(),
+		media_backend_factory.clone(), cache_keys.clone(), cache_budget.clone(), segment_prefetcher).await?;
+	
+	let thumbnail_generator = thumbnail_service::init_service(config, task_pool.clone(),
+		media_backend_factory.clone(), cache_keys.clone(), cache_budget.clone()).await?;
+	
+	let scaled_thumbnail_generator = scaled_thumbnail_service::init_service(config, cache_budget.clone()).await?;
+	
+	let thumbnail_sheet_generator = thumbnail_sheet_service::init_service(config, task_pool.clone(),
+		media_backend_factory.clone(), cache_keys.clone(), cache_budget.clone()).await?;
+	
+	let targets = PrewarmTargets {
+		libraries: &libraries,
+		show_hidden_files: config.main_config.show_hidden_files,
+		metadata_cache: &metadata_cache,
+		media_backend_factory: &media_backend_factory,
+		hls_segment_generator: &hls_segment_generator,
+		thumbnail_generator: &thumbnail_generator,
+		scaled_thumbnail_generator: &scaled_thumbnail_generator,
+		thumbnail_sheet_generator: &thumbnail_sheet_generator,
+	};
+	
+	prewarm::run_job_now(&targets, &prewarm_jobs, job_id).await
+}
+
+fn select_caches<'a>(
+	caches: &'a [(&'static str, ArtifactCache<StoredFilesOnly>)],
+	cache_name: Option<&str>,
+) -> anyhow::Result<Vec<&'a (&'static str, ArtifactCache<StoredFilesOnly>)>> {
+	let selected: Vec<_> = caches.iter()
+		.filter(|(name, _)| cache_name.is_none_or(|cache_name| *name == cache_name))
+		.collect();
+	
+	if let Some(cache_name) = cache_name && selected.is_empty() {
+		bail!("Unknown cache {}", cache_name);
+	}
+	
+	Ok(selected)
+}
+
+pub struct StoredFilesOnly;
+
+impl ArtifactGenerator for StoredFilesOnly {
+	type Input = ();
+	// Accepts the metadata of any cache
+	type Metadata = serde_json::Value;
+	
+	async fn create_cache_key(&self, _input: &Self::Input) -> anyhow::Result<String> {
+		bail!("Artifacts can't be looked up outside of the server")
+	}
+	
+	async fn generate_artifact(&self, _input: Self::Input, _output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
+		bail!("Artifacts can't be generated outside of the server")
+	}
+}
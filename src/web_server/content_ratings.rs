@@ -0,0 +1,173 @@
+use std::path::{Path, PathBuf};
+
+use http::HeaderMap;
+use relative_path::RelativePath;
+
+use crate::config::ContentRating;
+use crate::web_server::auth::{AuthManager, User};
+use crate::web_server::libraries::Library;
+use crate::web_server::media_metadata::BasicMediaMetadata;
+use crate::web_server::metadata_cache::FileMetadata;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::web_utils;
+
+// Sidecar file next to a video, e.g. "Video.rating" for "Video.mp4"
+pub const RATING_FILE_EXT: &str = "rating";
+// Rating for everything in a directory and its subdirectories that doesn't have its own rating
+pub const DIRECTORY_RATING_FILE_NAME: &str = ".rating";
+
+#[derive(Debug, Copy, Clone)]
+pub struct RatingFilter {
+	max_rating: Option<ContentRating>,
+	block_unrated: bool,
+}
+
+impl RatingFilter {
+	pub fn for_request(auth_manager: &AuthManager, user: &User, headers: &HeaderMap) -> Self {
+		if auth_manager.ratings_unlocked(user, headers) {
+			return Self::unrestricted();
+		}
+		
+		Self {
+			max_rating: user.max_content_rating,
+			block_unrated: user.block_unrated,
+		}
+	}
+	
+	pub fn unrestricted() -> Self {
+		Self {
+			max_rating: None,
+			block_unrated: false,
+		}
+	}
+	
+	pub fn is_unrestricted(&self) -> bool {
+		self.max_rating.is_none()
+	}
+	
+	pub fn allows(&self, rating: Option<ContentRating>) -> bool {
+		let Some(max_rating) = self.max_rating else { return true };
+		
+		match rating {
+			Some(rating) => rating <= max_rating,
+			None => !self.block_unrated,
+		}
+	}
+	
+	// Unrated directories are left alone even with block_unrated, the videos inside of them get checked instead
+	pub fn allows_directory(&self, rating: Option<ContentRating>) -> bool {
+		rating.is_none_or(|rating| self.allows(Some(rating)))
+	}
+}
+
+#[derive(Clone)]
+struct RatingFile {
+	rating: Option<ContentRating>,
+}
+
+impl FileMetadata for RatingFile {
+	async fn fetch_metadata(path: &Path, _file_metadata: &std::fs::Metadata) -> anyhow::Result<Self> {
+		let contents = tokio::fs::read_to_string(path).await?;
+		
+		let rating = contents.lines()
+			.map(str::trim)
+			.find(|line| !line.is_empty())
+			.and_then(ContentRating::from_label);
+		
+		Ok(Self {
+			rating,
+		})
+	}
+}
+
+async fn read_rating_file(server_state: &ServerState, path: &Path) -> Option<ContentRating> {
+	server_state.metadata_cache.fetch_metadata::<RatingFile>(path).await
+		.ok()
+		.and_then(|rating_file| rating_file.rating)
+}
+
+// The rating of a single video from its sidecar file or container tags, ignoring directory ratings
+pub async fn file_rating(server_state: &ServerState, media_path: &Path) -> Option<ContentRating> {
+	let sidecar_path = media_path.with_extension(RATING_FILE_EXT);
+	
+	if let Some(rating) = read_rating_file(server_state, &sidecar_path).await {
+		return Some(rating);
+	}
+	
+	server_state.metadata_cache.fetch_metadata::<BasicMediaMetadata>(media_path).await
+		.ok()
+		.and_then(|metadata| metadata.content_rating)
+}
+
+// The rating file in a directory itself, ignoring its parents
+pub async fn own_directory_rating(server_state: &ServerState, dir_path: &Path) -> Option<ContentRating> {
+	read_rating_file(server_state, &dir_path.join(DIRECTORY_RATING_FILE_NAME)).await
+}
+
+// The rating of a directory, inherited from the closest parent with a rating file
+pub async fn directory_rating(server_state: &ServerState, library: &Library, dir_library_path: &RelativePath) -> Option<ContentRating> {
+	let sanitized_path = web_utils::sanitize_path(dir_library_path)?;
+	let mut dir_path: PathBuf = library.root_path.join(sanitized_path);
+	
+	loop {
+		if let Some(rating) = own_directory_rating(server_state, &dir_path).await {
+			return Some(rating);
+		}
+		
+		if dir_path == library.root_path || !dir_path.pop() {
+			return None;
+		}
+	}
+}
+
+pub async fn media_rating(
+	server_state: &ServerState,
+	library: &Library,
+	library_path: &RelativePath,
+	media_path: &Path,
+) -> Option<ContentRating> {
+	if let Some(rating) = file_rating(server_state, media_path).await {
+		return Some(rating);
+	}
+	
+	directory_rating(server_state, library, library_path.parent().unwrap_or(RelativePath::new(""))).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn test_from_label() {
+		assert_eq!(ContentRating::from_label("PG-13"), Some(ContentRating::PG13));
+		assert_eq!(ContentRating::from_label(" tv-ma\n"), Some(ContentRating::R));
+		assert_eq!(ContentRating::from_label("mpaa|PG|200|"), Some(ContentRating::PG));
+		assert_eq!(ContentRating::from_label("us-tv|TV-Y7|100|"), Some(ContentRating::G));
+		assert_eq!(ContentRating::from_label("Unrated"), None);
+		assert_eq!(ContentRating::from_label(""), None);
+	}
+	
+	#[test]
+	fn test_rating_filter() {
+		let filter = RatingFilter {
+			max_rating: Some(ContentRating::PG),
+			block_unrated: false,
+		};
+		
+		assert!(filter.allows(Some(ContentRating::G)));
+		assert!(filter.allows(Some(ContentRating::PG)));
+		assert!(!filter.allows(Some(ContentRating::PG13)));
+		assert!(filter.allows(None));
+		
+		let filter = RatingFilter {
+			block_unrated: true,
+			..filter
+		};
+		
+		assert!(!filter.allows(None));
+		assert!(filter.allows_directory(None));
+		assert!(!filter.allows_directory(Some(ContentRating::R)));
+		
+		assert!(RatingFilter::unrestricted().allows(Some(ContentRating::NC17)));
+	}
+}
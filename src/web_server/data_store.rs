@@ -0,0 +1,212 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinSet;
+use tracing::{error, warn};
+
+use crate::utils::{add_extension, write_data_file, BACKUP_EXT};
+
+// Changes made right after a save wait this long, so that a burst of them is written out at once
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Data under the data directory that's saved in the background some time after it changes
+pub trait DataStore: Send + 'static {
+	// Writes out whatever changed since the last save
+	fn save(arc_self: &Arc<Mutex<Self>>) -> impl Future<Output = ()> + Send;
+	
+	// Called right before the last save when the server shuts down
+	fn prepare_shutdown(&mut self) {}
+}
+
+// Runs the save task of every data store, so that they all get saved one last time on shutdown
+pub struct DataStores {
+	shutdown: watch::Sender<bool>,
+	save_tasks: Mutex<JoinSet<()>>,
+}
+
+impl DataStores {
+	pub fn new() -> Self {
+		Self {
+			shutdown: watch::Sender::new(false),
+			save_tasks: Mutex::new(JoinSet::new()),
+		}
+	}
+	
+	// Saves the store whenever the notify is triggered, at most once per SAVE_INTERVAL
+	pub fn start_saving<T: DataStore>(&self, store: Arc<Mutex<T>>, dirty_notify: Arc<Notify>) {
+		let mut shutdown = self.shutdown.subscribe();
+		
+		self.save_tasks.lock().unwrap().spawn(async move {
+			loop {
+				tokio::select! {
+					_ = dirty_notify.notified() => {}
+					_ = shutdown.wait_for(|&shutdown| shutdown) => break,
+				}
+				
+				T::save(&store).await;
+				
+				tokio::select! {
+					_ = tokio::time::sleep(SAVE_INTERVAL) => {}
+					_ = shutdown.wait_for(|&shutdown| shutdown) => break,
+				}
+			}
+			
+			store.lock().unwrap().prepare_shutdown();
+			
+			T::save(&store).await;
+		});
+	}
+	
+	// Waits for every store to be saved
+	pub async fn shutdown(&self) {
+		self.shutdown.send_replace(true);
+		
+		let mut save_tasks = std::mem::take(&mut *self.save_tasks.lock().unwrap());
+		
+		while let Some(result) = save_tasks.join_next().await {
+			if let Err(err) = result {
+				error!("Error in data store save task: {:?}", err);
+			}
+		}
+	}
+}
+
+impl Default for DataStores {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// Data kept as a single versioned JSON file, like playlists
+pub trait JsonData: Send + 'static {
+	// What the data is called in log messages
+	const NAME: &'static str;
+	const VERSION: u32;
+	// Large files that nobody reads by hand are better off compact
+	const PRETTY: bool = true;
+	
+	type Serialized: Serialize + DeserializeOwned;
+	
+	fn json_file(&mut self) -> &mut JsonFile;
+	
+	fn to_serialized(&mut self) -> Self::Serialized;
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionedData<S> {
+	version: u32,
+	#[serde(flatten)]
+	data: S,
+}
+
+// Where JsonData is saved, and whether it changed since it was last saved
+pub struct JsonFile {
+	path: PathBuf,
+	dirty: bool,
+	dirty_notify: Arc<Notify>,
+	// Keeps a periodic save and a shutdown flush from writing the file at once
+	save_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl JsonFile {
+	pub fn new(path: PathBuf) -> Self {
+		Self {
+			path,
+			dirty: false,
+			dirty_notify: Arc::new(Notify::new()),
+			save_lock: Arc::new(tokio::sync::Mutex::new(())),
+		}
+	}
+	
+	// Falls back to the backup from the previous save if the file is damaged. Returns None if neither exists yet,
+	//  and fails if both are unreadable.
+	pub async fn load<T: JsonData>(path: PathBuf) -> anyhow::Result<(Self, Option<T::Serialized>)> {
+		let backup_path = add_extension(&path, BACKUP_EXT);
+		let mut json_file = Self::new(path);
+		
+		let data = match Self::load_file(&json_file.path, T::VERSION).await {
+			Ok(Some(data)) => Some(data),
+			Ok(None) if !tokio::fs::try_exists(&backup_path).await? => None,
+			main_result => {
+				let data = Self::load_file(&backup_path, T::VERSION).await
+					.and_then(|data| data.context("No backup"))
+					.with_context(|| format!("The {} file {:?} and its backup are unreadable", T::NAME, &json_file.path))?;
+				
+				warn!("The {} file {:?} is unreadable, restored it from backup: {:?}", T::NAME, &json_file.path, main_result.err());
+				
+				json_file.mark_dirty();
+				
+				Some(data)
+			}
+		};
+		
+		Ok((json_file, data))
+	}
+	
+	async fn load_file<S: DeserializeOwned>(path: &Path, version: u32) -> anyhow::Result<Option<S>> {
+		if !tokio::fs::try_exists(path).await? {
+			return Ok(None);
+		}
+		
+		let data = tokio::fs::read(path).await?;
+		let versioned: VersionedData<S> = serde_json::from_slice(&data)?;
+		
+		if versioned.version != version {
+			bail!("Unsupported version {}", versioned.version);
+		}
+		
+		Ok(Some(versioned.data))
+	}
+	
+	pub fn dirty_notify(&self) -> Arc<Notify> {
+		self.dirty_notify.clone()
+	}
+	
+	pub fn mark_dirty(&mut self) {
+		self.dirty = true;
+		self.dirty_notify.notify_one();
+	}
+	
+	pub async fn save<T: JsonData>(arc_self: &Arc<Mutex<T>>) {
+		let save_lock = arc_self.lock().unwrap().json_file().save_lock.clone();
+		let _save_guard = save_lock.lock().await;
+		
+		let (path, data) = {
+			let mut mut_self = arc_self.lock().unwrap();
+			let json_file = mut_self.json_file();
+			
+			if !json_file.dirty {
+				return;
+			}
+			
+			json_file.dirty = false;
+			
+			let path = json_file.path.clone();
+			
+			let versioned = VersionedData {
+				version: T::VERSION,
+				data: mut_self.to_serialized(),
+			};
+			
+			let serialized = if T::PRETTY {
+				serde_json::to_vec_pretty(&versioned)
+			} else {
+				serde_json::to_vec(&versioned)
+			}.unwrap();
+			
+			(path, serialized)
+		};
+		
+		if let Err(err) = write_data_file(&path, &data).await {
+			error!("Error saving {}: {:?}", T::NAME, err);
+			
+			arc_self.lock().unwrap().json_file().mark_dirty();
+		}
+	}
+}
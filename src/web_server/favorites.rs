@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use relative_path::{RelativePath, RelativePathBuf};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::utils;
+use crate::web_server::data_store::{DataStore, DataStores, JsonData, JsonFile};
+use crate::web_server::{history_remap, watch_history};
+
+const FAVORITES_VERSION: u32 = 1;
+
+// Keeps the single favorites file from growing without bound
+pub const MAX_FAVORITES_PER_USER: usize = 10_000;
+pub const MAX_BOOKMARKS_PER_FILE: usize = 100;
+
+// Starred videos and bookmarks for every user, kept in a single file like playlists
+pub struct UserFavorites {
+	users: HashMap<String, Favorites>,
+	json_file: JsonFile,
+}
+
+impl UserFavorites {
+	pub async fn load(favorites_file: PathBuf, data_stores: &DataStores) -> anyhow::Result<Arc<Mutex<Self>>> {
+		let (json_file, serialized) = JsonFile::load::<Self>(favorites_file).await?;
+		let dirty_notify = json_file.dirty_notify();
+		
+		let arc_self = Arc::new(Mutex::new(Self {
+			users: serialized.map_or_else(HashMap::new, |serialized| serialized.users),
+			json_file,
+		}));
+		
+		data_stores.start_saving(arc_self.clone(), dirty_notify);
+		
+		Ok(arc_self)
+	}
+	
+	pub fn mark_dirty(&mut self) {
+		self.json_file.mark_dirty();
+	}
+	
+	pub fn get_favorites(&self, user_id: &str) -> Option<&Favorites> {
+		self.users.get(user_id)
+	}
+	
+	pub fn get_favorites_mut(&mut self, user_id: &str) -> &mut Favorites {
+		self.users.entry(user_id.to_owned()).or_default()
+	}
+	
+	pub fn is_favorite(&self, user_id: &str, library_id: &str, media_path: &RelativePath) -> bool {
+		self.get_favorites(user_id)
+			.is_some_and(|favorites| favorites.is_favorite(library_id, media_path))
+	}
+	
+	pub fn remove_user(&mut self, user_id: &str) {
+		self.users.remove(user_id);
+		
+		self.mark_dirty();
+	}
+	
+	// Points every user's favorites and bookmarks at the new location of moved files. Returns how many were changed.
+	pub fn remap_prefix(
+		&mut self,
+		library_id: &str,
+		old_prefix: &RelativePath,
+		new_library_id: &str,
+		new_prefix: &RelativePath,
+	) -> usize {
+		let moved_count = self.users.values_mut()
+			.map(|favorites| favorites.remap_prefix(library_id, old_prefix, new_library_id, new_prefix))
+			.sum();
+		
+		if moved_count > 0 {
+			self.mark_dirty();
+		}
+		
+		moved_count
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Favorites {
+	pub favorites: Vec<FavoriteEntry>,
+	pub bookmarks: Vec<Bookmark>,
+}
+
+impl Favorites {
+	pub fn is_favorite(&self, library_id: &str, media_path: &RelativePath) -> bool {
+		self.favorites.iter().any(|entry| is_same_media(&entry.library_id, &entry.media_path, library_id, media_path))
+	}
+	
+	// For only listing the favorites in a library and the directories leading to them
+	pub fn library_filter(&self, library_id: &str) -> FavoritesFilter {
+		FavoritesFilter {
+			media_keys: self.favorites.iter()
+				.filter(|entry| entry.library_id == library_id)
+				.map(|entry| media_key(&entry.media_path))
+				.collect(),
+		}
+	}
+	
+	// Returns false if it was already a favorite, or None if the user already has as many favorites as they can
+	pub fn add_favorite(&mut self, library_id: &str, media_path: &RelativePath) -> Option<bool> {
+		if self.is_favorite(library_id, media_path) {
+			return Some(false);
+		}
+		
+		if self.favorites.len() >= MAX_FAVORITES_PER_USER {
+			return None;
+		}
+		
+		self.favorites.push(FavoriteEntry {
+			library_id: library_id.to_owned(),
+			media_path: watch_history::normalize_path(media_path),
+			added: OffsetDateTime::now_utc(),
+		});
+		
+		Some(true)
+	}
+	
+	pub fn remove_favorite(&mut self, library_id: &str, media_path: &RelativePath) -> bool {
+		let count = self.favorites.len();
+		
+		self.favorites.retain(|entry| !is_same_media(&entry.library_id, &entry.media_path, library_id, media_path));
+		
+		self.favorites.len() != count
+	}
+	
+	// Returns None if the video already has as many bookmarks as it can
+	pub fn add_bookmark(&mut self, library_id: &str, media_path: &RelativePath, timestamp: u64, name: String) -> Option<&Bookmark> {
+		let existing_count = self.bookmarks.iter()
+			.filter(|bookmark| is_same_media(&bookmark.library_id, &bookmark.media_path, library_id, media_path))
+			.count();
+		
+		if existing_count >= MAX_BOOKMARKS_PER_FILE {
+			return None;
+		}
+		
+		self.bookmarks.push(Bookmark {
+			id: utils::generate_random_id(),
+			library_id: library_id.to_owned(),
+			media_path: watch_history::normalize_path(media_path),
+			timestamp,
+			name,
+			created: OffsetDateTime::now_utc(),
+		});
+		
+		self.bookmarks.last()
+	}
+	
+	pub fn remove_bookmark(&mut self, bookmark_id: &str) -> bool {
+		let count = self.bookmarks.len();
+		
+		self.bookmarks.retain(|bookmark| bookmark.id != bookmark_id);
+		
+		self.bookmarks.len() != count
+	}
+	
+	pub fn remap_prefix(
+		&mut self,
+		library_id: &str,
+		old_prefix: &RelativePath,
+		new_library_id: &str,
+		new_prefix: &RelativePath,
+	) -> usize {
+		let mut moved_count = 0;
+		
+		let favorite_locations = self.favorites.iter_mut().map(|entry| (&mut entry.library_id, &mut entry.media_path));
+		let bookmark_locations = self.bookmarks.iter_mut().map(|bookmark| (&mut bookmark.library_id, &mut bookmark.media_path));
+		
+		for (entry_library_id, media_path) in favorite_locations.chain(bookmark_locations) {
+			if entry_library_id.as_str() == library_id &&
+				let Some(new_path) = history_remap::remap_path(media_path, old_prefix, new_prefix) {
+				*entry_library_id = new_library_id.to_owned();
+				*media_path = new_path;
+				
+				moved_count += 1;
+			}
+		}
+		
+		// The new location might have been a favorite already
+		let mut seen = HashSet::new();
+		self.favorites.retain(|entry| seen.insert((entry.library_id.clone(), media_key(&entry.media_path))));
+		
+		moved_count
+	}
+	
+	// The bookmarks in a single video, in the order they appear in it
+	pub fn bookmarks_for(&self, library_id: &str, media_path: &RelativePath) -> Vec<Bookmark> {
+		let mut bookmarks: Vec<Bookmark> = self.bookmarks.iter()
+			.filter(|bookmark| is_same_media(&bookmark.library_id, &bookmark.media_path, library_id, media_path))
+			.cloned()
+			.collect();
+		
+		bookmarks.sort_by_key(|bookmark| bookmark.timestamp);
+		
+		bookmarks
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FavoriteEntry {
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	#[serde(with = "time::serde::iso8601")]
+	pub added: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+	pub id: String,
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	// Seconds into the video, like watch progress
+	pub timestamp: u64,
+	pub name: String,
+	#[serde(with = "time::serde::iso8601")]
+	pub created: OffsetDateTime,
+}
+
+impl JsonData for UserFavorites {
+	const NAME: &'static str = "favorites";
+	const VERSION: u32 = FAVORITES_VERSION;
+	
+	type Serialized = SerializedFavorites;
+	
+	fn json_file(&mut self) -> &mut JsonFile {
+		&mut self.json_file
+	}
+	
+	fn to_serialized(&mut self) -> SerializedFavorites {
+		SerializedFavorites {
+			users: self.users.clone(),
+		}
+	}
+}
+
+impl DataStore for UserFavorites {
+	fn save(arc_self: &Arc<Mutex<Self>>) -> impl Future<Output = ()> + Send {
+		JsonFile::save(arc_self)
+	}
+}
+
+pub struct FavoritesFilter {
+	media_keys: Vec<String>,
+}
+
+impl FavoritesFilter {
+	pub fn allows_file(&self, media_path: &RelativePath) -> bool {
+		self.media_keys.contains(&media_key(media_path))
+	}
+	
+	pub fn allows_directory(&self, dir_path: &RelativePath) -> bool {
+		let dir_prefix = format!("{}/", dir_path.normalize().as_str().to_lowercase());
+		
+		self.media_keys.iter().any(|media_key| media_key.starts_with(&dir_prefix))
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializedFavorites {
+	users: HashMap<String, Favorites>,
+}
+
+// Paths are matched case insensitively and without the video's extension, the same as watch history entries
+fn media_key(media_path: &RelativePath) -> String {
+	watch_history::normalize_path(media_path).as_str().to_lowercase()
+}
+
+fn is_same_media(library_id: &str, media_path: &RelativePath, other_library_id: &str, other_media_path: &RelativePath) -> bool {
+	library_id == other_library_id && media_key(media_path) == media_key(other_media_path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn test_favorites() {
+		let mut favorites = Favorites::default();
+		
+		assert_eq!(favorites.add_favorite("lib", RelativePath::new("Movies/Movie")), Some(true));
+		assert_eq!(favorites.add_favorite("lib", RelativePath::new("movies/movie")), Some(false));
+		assert_eq!(favorites.add_favorite("other", RelativePath::new("Movies/Movie")), Some(true));
+		
+		assert!(favorites.is_favorite("lib", RelativePath::new("Movies/./Movie")));
+		assert!(!favorites.is_favorite("lib", RelativePath::new("Movies/Other")));
+		
+		assert!(favorites.remove_favorite("lib", RelativePath::new("MOVIES/Movie")));
+		assert!(!favorites.remove_favorite("lib", RelativePath::new("Movies/Movie")));
+		assert!(favorites.is_favorite("other", RelativePath::new("Movies/Movie")));
+		
+		let path = RelativePath::new("Shows/Episode 1");
+		
+		let late_id = favorites.add_bookmark("lib", path, 600, "Ending".to_owned()).unwrap().id.clone();
+		favorites.add_bookmark("lib", path, 30, "Intro".to_owned());
+		favorites.add_bookmark("lib", RelativePath::new("Shows/Episode 2"), 10, "Elsewhere".to_owned());
+		
+		let names = |favorites: &Favorites| favorites.bookmarks_for("lib", path).into_iter()
+			.map(|bookmark| bookmark.name)
+			.collect::<Vec<_>>();
+		
+		assert_eq!(names(&favorites), vec!["Intro", "Ending"]);
+		
+		assert!(favorites.remove_bookmark(&late_id));
+		assert!(!favorites.remove_bookmark(&late_id));
+		assert_eq!(names(&favorites), vec!["Intro"]);
+		
+		// Moving into a favorite that already exists doesn't duplicate it
+		favorites.add_favorite("lib", RelativePath::new("Old/Movie"));
+		favorites.add_favorite("other", RelativePath::new("Old/Movie"));
+		
+		assert_eq!(favorites.remap_prefix("other", RelativePath::new("old"), "other", RelativePath::new("Movies")), 1);
+		assert_eq!(favorites.remap_prefix("lib", RelativePath::new("shows"), "lib", RelativePath::new("TV")), 2);
+		
+		assert_eq!(favorites.favorites.len(), 2);
+		assert!(favorites.is_favorite("other", RelativePath::new("Movies/Movie")));
+		assert!(favorites.is_favorite("lib", RelativePath::new("Old/Movie")));
+		assert_eq!(names(&favorites), Vec::<String>::new());
+		assert_eq!(favorites.bookmarks_for("lib", RelativePath::new("TV/Episode 1")).len(), 1);
+		
+		let full_path = RelativePath::new("Shows/Episode 3");
+		
+		for i in 0..MAX_BOOKMARKS_PER_FILE {
+			assert!(favorites.add_bookmark("lib", full_path, i as u64, "Bookmark".to_owned()).is_some());
+		}
+		
+		assert!(favorites.add_bookmark("lib", RelativePath::new("shows/episode 3"), 0, "Extra".to_owned()).is_none());
+		assert!(favorites.add_bookmark("lib", RelativePath::new("Shows/Episode 4"), 0, "Elsewhere".to_owned()).is_some());
+	}
+	
+	#[test]
+	fn test_favorite_limit() {
+		// Filled in directly since adding them one at a time checks every existing favorite
+		let mut favorites = Favorites {
+			favorites: (0..MAX_FAVORITES_PER_USER).map(|i| FavoriteEntry {
+				library_id: "lib".to_owned(),
+				media_path: RelativePathBuf::from(format!("movies/movie {i}")),
+				added: OffsetDateTime::now_utc(),
+			}).collect(),
+			bookmarks: Vec::new(),
+		};
+		
+		assert_eq!(favorites.add_favorite("lib", RelativePath::new("Movies/Extra")), None);
+		// Adding one that's already a favorite isn't an error
+		assert_eq!(favorites.add_favorite("lib", RelativePath::new("movies/movie 0")), Some(false));
+		
+		assert!(favorites.remove_favorite("lib", RelativePath::new("Movies/Movie 0")));
+		assert_eq!(favorites.add_favorite("lib", RelativePath::new("Movies/Extra")), Some(true));
+	}
+	
+	#[test]
+	fn test_library_filter() {
+		let mut favorites = Favorites::default();
+		
+		// Favorited from the file itself rather than how list_dir lists it
+		assert_eq!(favorites.add_favorite("lib", RelativePath::new("Movies/Action/Movie.mp4")), Some(true));
+		assert_eq!(favorites.add_favorite("lib", RelativePath::new("movies/action/movie")), Some(false));
+		assert!(favorites.is_favorite("lib", RelativePath::new("Movies/Action/Movie.mkv")));
+		favorites.add_favorite("other", RelativePath::new("Shows/Episode"));
+		
+		let filter = favorites.library_filter("lib");
+		
+		assert!(filter.allows_file(RelativePath::new("Movies/Action/Movie")));
+		assert!(!filter.allows_file(RelativePath::new("Movies/Action/Other")));
+		assert!(!filter.allows_file(RelativePath::new("Shows/Episode")));
+		
+		assert!(filter.allows_directory(RelativePath::new("Movies")));
+		assert!(filter.allows_directory(RelativePath::new("movies/Action")));
+		assert!(!filter.allows_directory(RelativePath::new("Movies/Drama")));
+		assert!(!filter.allows_directory(RelativePath::new("Shows")));
+		assert!(!filter.allows_directory(RelativePath::new("Movies/Action/Movie")));
+	}
+}
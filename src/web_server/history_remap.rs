@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+use relative_path::{RelativePath, RelativePathBuf};
+use tracing::info;
+
+use crate::web_server::libraries::Library;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::services::cache_keys::{self, ContentFingerprint};
+use crate::web_server::video_locator;
+
+struct ScannedVideo {
+	library_path: RelativePathBuf,
+	media_path: PathBuf,
+	file_size: u64,
+}
+
+// Videos in each library, listed at most once per job since scanning a whole library is slow
+#[derive(Default)]
+pub struct LibraryScans {
+	libraries: HashMap<String, Vec<ScannedVideo>>,
+}
+
+impl LibraryScans {
+	async fn get(&mut self, server_state: &ServerState, library: &Library) -> &[ScannedVideo] {
+		if !self.libraries.contains_key(&library.id) {
+			let videos = scan_library(server_state, library).await;
+			
+			self.libraries.insert(library.id.clone(), videos);
+		}
+		
+		&self.libraries[&library.id]
+	}
+}
+
+async fn scan_library(server_state: &ServerState, library: &Library) -> Vec<ScannedVideo> {
+	let mut videos = Vec::new();
+	let mut pending_dirs = vec![(RelativePathBuf::new(), library.root_path.clone())];
+	
+	while let Some((library_path, dir_path)) = pending_dirs.pop() {
+		let Ok(mut read_dir) = tokio::fs::read_dir(&dir_path).await else { continue };
+		
+		while let Ok(Some(entry)) = read_dir.next_entry().await {
+			let path = entry.path();
+			
+			let Some(file_name) = path.file_name().and_then(OsStr::to_str) else { continue };
+			
+			if !server_state.config.main_config.show_hidden_files && video_locator::is_hidden(file_name) {
+				continue;
+			}
+			
+			let Ok(metadata) = entry.metadata().await else { continue };
+			
+			if metadata.is_dir() {
+				pending_dirs.push((library_path.join(file_name), path));
+			} else if metadata.is_file() && video_locator::is_video(&path) {
+				let Some(stem) = path.file_stem().and_then(OsStr::to_str) else { continue };
+				
+				videos.push(ScannedVideo {
+					library_path: library_path.join(stem),
+					media_path: path,
+					file_size: metadata.len(),
+				});
+			}
+		}
+	}
+	
+	videos
+}
+
+// Where a path ends up when everything under old_prefix moves to new_prefix, or None if it isn't under old_prefix.
+//  Paths are matched case insensitively, the same as history entries.
+pub fn remap_path(media_path: &RelativePath, old_prefix: &RelativePath, new_prefix: &RelativePath) -> Option<RelativePathBuf> {
+	let old_prefix = RelativePathBuf::from(old_prefix.normalize().as_str().to_lowercase());
+	
+	if !RelativePath::new(&media_path.as_str().to_lowercase()).starts_with(&old_prefix) {
+		return None;
+	}
+	
+	let rest: RelativePathBuf = media_path.components().skip(old_prefix.components().count()).collect();
+	
+	Some(new_prefix.normalize().join(rest))
+}
+
+#[derive(Debug, Default)]
+pub struct RemappedCounts {
+	pub history_entries: usize,
+	pub playlist_entries: usize,
+	pub favorites: usize,
+}
+
+// Moves everything that refers to paths under the old prefix over to the new one, for every user
+pub fn remap_prefix(
+	server_state: &ServerState,
+	library_id: &str,
+	old_prefix: &RelativePath,
+	new_library_id: &str,
+	new_prefix: &RelativePath,
+) -> RemappedCounts {
+	RemappedCounts {
+		history_entries: server_state.user_watch_histories.lock().unwrap()
+			.remap_prefix(library_id, old_prefix, new_library_id, new_prefix),
+		playlist_entries: server_state.playlists.lock().unwrap()
+			.remap_prefix(library_id, old_prefix, new_library_id, new_prefix),
+		favorites: server_state.user_favorites.lock().unwrap()
+			.remap_prefix(library_id, old_prefix, new_library_id, new_prefix),
+	}
+}
+
+// Looks for videos in everyone's history that went missing by their content, and moves everything that refers to
+//  them to where they were found. Each library is scanned at most once, and only if something in it went missing.
+pub async fn relocate_moved_videos(server_state: &ServerState) -> RemappedCounts {
+	// Several users can have the same video in their history
+	let fingerprinted_entries: HashMap<(String, RelativePathBuf), String> = server_state.user_watch_histories.lock().unwrap()
+		.iter_all_entries()
+		.filter_map(|entry| {
+			let fingerprint = entry.fingerprint.clone()?;
+			
+			Some(((entry.library_id.clone(), entry.media_path.clone()), fingerprint))
+		})
+		.collect();
+	
+	let mut library_scans = LibraryScans::default();
+	let mut counts = RemappedCounts::default();
+	
+	for ((library_id, media_path), fingerprint) in fingerprinted_entries {
+		let Ok((library, resolved_path)) = server_state.libraries.resolve_library_and_path(&library_id, media_path.clone()) else {
+			continue;
+		};
+		
+		if video_locator::locate_video(&resolved_path).await.is_ok() {
+			continue;
+		}
+		
+		let Some(new_path) = find_video(server_state, library, &fingerprint, &mut library_scans).await else { continue };
+		
+		info!("Found moved video {:?} at {:?}", &media_path, &new_path);
+		
+		let remapped = remap_prefix(server_state, &library_id, &media_path, &library_id, &new_path);
+		
+		counts.history_entries += remapped.history_entries;
+		counts.playlist_entries += remapped.playlist_entries;
+		counts.favorites += remapped.favorites;
+	}
+	
+	counts
+}
+
+async fn find_video(
+	server_state: &ServerState,
+	library: &Library,
+	fingerprint: &str,
+	library_scans: &mut LibraryScans,
+) -> Option<RelativePathBuf> {
+	let file_size = cache_keys::fingerprint_file_size(fingerprint)?;
+	
+	for video in library_scans.get(server_state, library).await {
+		// Only files of the same size need to be fingerprinted
+		if video.file_size != file_size {
+			continue;
+		}
+		
+		let Ok(candidate) = server_state.metadata_cache.fetch_metadata::<ContentFingerprint>(&video.media_path).await else {
+			continue;
+		};
+		
+		if candidate.fingerprint == fingerprint {
+			return Some(video.library_path.clone());
+		}
+	}
+	
+	None
+}
@@ -7,7 +7,9 @@ use relative_path::{RelativePath, RelativePathBuf};
 use crate::config::LibrariesConfig;
 use crate::web_server::api_error::ApiError;
 use crate::web_server::server_state::ServerState;
-use crate::web_server::{video_locator, web_utils};
+use crate::web_server::content_ratings::RatingFilter;
+use crate::web_server::video_locator::LocatedFile;
+use crate::web_server::{content_ratings, video_locator, web_utils};
 
 pub struct Libraries {
 	library_table: LinkedHashMap<String, Library>,
@@ -78,6 +80,10 @@ fn verify_library_path_perms<'a>(
 		return Err(ApiError::LibraryNotFound);
 	}
 	
+	if !user.can_see_path(library_id, path) {
+		return Err(ApiError::FileNotFound);
+	}
+	
 	if !server_state.config.main_config.show_hidden_files && path.iter().any(video_locator::is_hidden) {
 		return Err(ApiError::FileNotFound);
 	}
@@ -96,13 +102,40 @@ pub fn resolve_library_and_path_with_auth<'a>(
 	server_state.libraries.resolve_library_and_path(library_id, path)
 }
 
-pub fn resolve_path_with_auth(
+// Resolves a path to a video or directory, also hiding anything above the user's content rating limit
+pub async fn locate_video_with_auth<'a>(
+	server_state: &'a ServerState,
+	library_id: &str,
+	path: RelativePathBuf,
+	headers: &HeaderMap
+) -> Result<(&'a Library, LocatedFile), ApiError> {
+	let user = server_state.auth_manager.lookup_from_headers(headers)?;
+	let (library, resolved_path) = resolve_library_and_path_with_auth(server_state, library_id, path.clone(), headers)?;
+	let located_file = video_locator::locate_video(&resolved_path).await?;
+	
+	let rating_filter = RatingFilter::for_request(&server_state.auth_manager, &user, headers);
+	
+	if !rating_filter.is_unrestricted() {
+		let allowed = match &located_file {
+			LocatedFile::File(media_path) => rating_filter.allows(
+				content_ratings::media_rating(server_state, library, &path, media_path).await),
+			LocatedFile::Directory(_) => rating_filter.allows_directory(
+				content_ratings::directory_rating(server_state, library, &path).await),
+		};
+		
+		if !allowed {
+			return Err(ApiError::FileNotFound);
+		}
+	}
+	
+	Ok((library, located_file))
+}
+
+pub async fn locate_media_file_with_auth(
 	server_state: &ServerState,
 	library_id: &str,
 	path: RelativePathBuf,
 	headers: &HeaderMap
 ) -> Result<PathBuf, ApiError> {
-	verify_library_path_perms(server_state, library_id, &path, headers)?;
-	
-	server_state.libraries.resolve_path(library_id, path)
+	locate_video_with_auth(server_state, library_id, path, headers).await?.1.file()
 }
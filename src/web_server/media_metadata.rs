@@ -2,6 +2,7 @@ use std::ffi::OsStr;
 use std::path::Path;
 use std::time::Duration;
 
+use crate::config::ContentRating;
 use crate::media_manipulation::media_utils::MILLIS_TIME_BASE;
 use crate::web_server::metadata_cache::FileMetadata;
 use crate::web_server::video_locator::{MKV_EXTENSIONS, MP4_EXTENSIONS};
@@ -9,6 +10,8 @@ use anyhow::{anyhow, Context};
 use ffmpeg_next::media::Type;
 use ffmpeg_next::{codec, format, rescale, Rational, Rescale};
 use matroska::TagValue;
+use mp4ameta::ident::{FreeformIdentStatic, APPLE_ITUNES_MEAN};
+use mp4ameta::FreeformIdent;
 use serde::{Deserialize, Serialize};
 use time::format_description::BorrowedFormatItem;
 use time::macros::format_description;
@@ -22,6 +25,7 @@ pub struct BasicMediaMetadata {
 	pub title: String,
 	pub artist: Option<String>,
 	pub creation_date: OffsetDateTime,
+	pub content_rating: Option<ContentRating>,
 }
 
 impl FileMetadata for BasicMediaMetadata {
@@ -69,6 +73,8 @@ impl FileMetadata for AdvancedMediaMetadata {
 }
 
 const YT_DLP_DATE_FORMAT: &[BorrowedFormatItem] = format_description!("[year][month][day]");
+// Where iTunes keeps the rating, in the "mpaa|PG-13|300|" format
+const ITUNES_RATING_IDENT: FreeformIdentStatic = FreeformIdent::new_static(APPLE_ITUNES_MEAN, "iTunEXTC");
 
 fn extract_basic_metadata(
 	media_path: &Path,
@@ -87,6 +93,7 @@ fn extract_basic_metadata(
 	let title;
 	let mut artist;
 	let mut creation_date;
+	let mut content_rating = None;
 	
 	if extension.is_some_and(|ext| MP4_EXTENSIONS.contains(&ext)) {
 		let mut read_config = mp4ameta::ReadConfig::NONE;
@@ -100,6 +107,13 @@ fn extract_basic_metadata(
 		title = tag.title().map(ToOwned::to_owned);
 		artist = tag.artist().map(ToOwned::to_owned);
 		creation_date = tag.year().map(ToOwned::to_owned);
+		
+		content_rating = tag.strings_of(&ITUNES_RATING_IDENT).find_map(ContentRating::from_label);
+		
+		// The rtng atom only distinguishes explicit content from everything else
+		if content_rating.is_none() && let Some(mp4ameta::AdvisoryRating::Explicit { .. }) = tag.advisory_rating() {
+			content_rating = Some(ContentRating::R);
+		}
 	} else if extension.is_some_and(|ext| MKV_EXTENSIONS.contains(&ext)) {
 		let mkv = matroska::open(media_path).context("Reading mkv metadata")?;
 		
@@ -121,6 +135,8 @@ fn extract_basic_metadata(
 				match simple_tag.name.as_str() {
 					"ARTIST" => artist = convert_tag_value(simple_tag.value),
 					"DATE" => creation_date = convert_tag_value(simple_tag.value),
+					"LAW_RATING" => content_rating = convert_tag_value(simple_tag.value)
+						.and_then(|rating| ContentRating::from_label(&rating)),
 					_ => {}
 				}
 			}
@@ -138,6 +154,7 @@ fn extract_basic_metadata(
 		title = demuxer.metadata().get("title").map(ToOwned::to_owned);
 		artist = demuxer.metadata().get("artist").map(ToOwned::to_owned);
 		creation_date = demuxer.metadata().get("date").map(ToOwned::to_owned);
+		content_rating = demuxer.metadata().get("rating").and_then(ContentRating::from_label);
 	}
 	
 	let title = title.unwrap_or_else(|| path_name.clone());
@@ -161,6 +178,7 @@ fn extract_basic_metadata(
 		title,
 		artist,
 		creation_date,
+		content_rating,
 	})
 }
 
@@ -216,3 +234,65 @@ fn extract_advanced_metadata(media_path: &Path) -> anyhow::Result<AdvancedMediaM
 		subtitle_streams,
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use mp4ameta::{Data, Tag};
+	use tempfile::TempDir;
+	
+	fn mp4_box(name: &[u8; 4], content: &[u8]) -> Vec<u8> {
+		let mut data = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+		data.extend_from_slice(name);
+		data.extend_from_slice(content);
+		data
+	}
+	
+	// Just the atoms that are needed for tagging it
+	fn write_empty_mp4(path: &Path) {
+		let mut ftyp = b"M4V ".to_vec();
+		ftyp.extend_from_slice(&[0; 4]);
+		ftyp.extend_from_slice(b"M4V isom");
+		
+		let mut mvhd = vec![0; 100];
+		// Time scale of 1000, 90 seconds long
+		mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+		mvhd[16..20].copy_from_slice(&90_000u32.to_be_bytes());
+		
+		let mut data = mp4_box(b"ftyp", &ftyp);
+		data.extend(mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd)));
+		data.extend(mp4_box(b"mdat", &[]));
+		
+		std::fs::write(path, data).unwrap();
+	}
+	
+	#[test]
+	fn test_mp4_content_rating() {
+		let temp_dir = TempDir::new().unwrap();
+		
+		let extract_rating = |rating_tag: Option<&str>, explicit: bool| {
+			let path = temp_dir.path().join("video.mp4");
+			write_empty_mp4(&path);
+			
+			let mut tag = Tag::default();
+			
+			if let Some(rating_tag) = rating_tag {
+				tag.set_data(ITUNES_RATING_IDENT, Data::Utf8(rating_tag.to_owned()));
+			}
+			
+			if explicit {
+				tag.set_advisory_rating(mp4ameta::AdvisoryRating::Explicit);
+			}
+			
+			tag.write_to_path(&path).unwrap();
+			
+			extract_basic_metadata(&path, &std::fs::metadata(&path).unwrap()).unwrap().content_rating
+		};
+		
+		assert_eq!(extract_rating(None, false), None);
+		assert_eq!(extract_rating(Some("mpaa|PG-13|300|"), false), Some(ContentRating::PG13));
+		assert_eq!(extract_rating(Some("us-tv|TV-PG|400|"), true), Some(ContentRating::PG));
+		assert_eq!(extract_rating(None, true), Some(ContentRating::R));
+		assert_eq!(extract_rating(Some("mpaa|Unrated|???|"), true), Some(ContentRating::R));
+	}
+}
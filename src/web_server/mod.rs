@@ -23,6 +23,7 @@ use tracing::{debug, info, instrument, trace};
 use server_state::ServerState;
 
 use crate::config::ServerConfig;
+use crate::web_server::prewarm::PrewarmJobs;
 use crate::web_server::web_utils::{full_body, HyperRequest, HyperResponse};
 
 mod api_routes;
@@ -34,11 +35,24 @@ pub(crate) mod media_backend_factory;
 mod services;
 mod server_state;
 mod auth;
+mod oidc;
+mod path_rules;
+pub(crate) mod content_ratings;
+mod data_store;
 mod watch_history;
+mod scrobble;
+mod history_remap;
+mod playlists;
+mod favorites;
+mod progress_sync;
+mod viewing_stats;
+mod watch_party;
 mod media_connections;
 mod api_types;
 mod api_error;
 mod metadata_cache;
+mod prewarm;
+pub mod cache_admin;
 
 #[instrument(skip_all)]
 async fn route_request(request: HyperRequest, path: &[&str], server_state: Arc<ServerState>) -> HyperResponse {
@@ -79,6 +93,13 @@ pub async fn run(config: ServerConfig) {
 	let server_state = Arc::new(ServerState::init(config.clone()).await
 		.expect("Error initializing server state"));
 	
+	PrewarmJobs::start(&server_state);
+	
+	let budget_state = server_state.clone();
+	tokio::spawn(async move {
+		budget_state.cache_budget.run(&budget_state.artifact_caches()).await;
+	});
+	
 	let mut servers = Vec::new();
 	
 	if config.main_config.server.enable_http {
@@ -98,13 +119,42 @@ pub async fn run(config: ServerConfig) {
 	
 	info!("Started server");
 	
-	join_all(servers).await
-		.into_iter()
-		.collect::<Result<Vec<_>, _>>()
-		.expect("Server failed");
+	tokio::select! {
+		results = join_all(servers) => {
+			results.into_iter()
+				.collect::<Result<Vec<_>, _>>()
+				.expect("Server failed");
+		}
+		_ = shutdown_signal() => {
+			info!("Shutting down");
+			
+			server_state.data_stores.shutdown().await;
+		}
+	}
 }
 
-async fn handle_request(request: HyperRequest, server_state: Arc<ServerState>) -> Result<HyperResponse, Infallible> {
+async fn shutdown_signal() {
+	let interrupt = tokio::signal::ctrl_c();
+	
+	#[cfg(unix)]
+	let terminate = async {
+		tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+			.expect("Installing SIGTERM handler")
+			.recv().await;
+	};
+	
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+	
+	tokio::select! {
+		_ = interrupt => {}
+		_ = terminate => {}
+	}
+}
+
+async fn handle_request(mut request: HyperRequest, remote_addr: SocketAddr, server_state: Arc<ServerState>) -> Result<HyperResponse, Infallible> {
+	server_state.auth_manager.sanitize_forward_auth_headers(request.headers_mut(), remote_addr.ip());
+	
 	let path_owned = match web_utils::split_path(request.uri().path()) {
 		Ok(path) => path,
 		Err(_) => return Ok(Response::builder()
@@ -174,13 +224,13 @@ async fn serve(socket_addr: SocketAddr, server_state: Arc<ServerState>, tls_acce
 	let listener = TcpListener::bind(socket_addr).await?;
 	
 	loop {
-		let (socket, _remote_addr) = listener.accept().await?;
+		let (socket, remote_addr) = listener.accept().await?;
 		let server_state = server_state.clone();
 		let tls_acceptor = tls_acceptor.clone();
 		
 		tokio::spawn(async move {
 			let connection_builder = conn::auto::Builder::new(TokioExecutor::new());
-			let service = service_fn(move |request| handle_request(request, server_state.clone()));
+			let service = service_fn(move |request| handle_request(request, remote_addr, server_state.clone()));
 			
 			let result = if let Some(tls_acceptor) = tls_acceptor {
 				match tls_acceptor.accept(socket).await {
@@ -0,0 +1,649 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hashlink::LinkedHashMap;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tracing::info;
+
+use crate::config::{OidcConfig, OidcLinkConfig, ServerConfig};
+use crate::utils;
+use crate::web_server::auth::{AuthManager, User};
+
+const PENDING_LOGIN_EXPIRY: Duration = Duration::from_secs(10 * 60);
+// Anyone can start a login, so the oldest ones are dropped once there are this many
+const MAX_PENDING_LOGINS: usize = 1000;
+
+pub const OIDC_STATE_COOKIE_NAME: &str = "oidc_state";
+
+pub struct OidcClient {
+	config: OidcConfig,
+	http_client: reqwest::Client,
+	provider_metadata: OnceCell<ProviderMetadata>,
+	jwks: RwLock<Option<Arc<JwkSet>>>,
+	// In the order they were started
+	pending_logins: Mutex<LinkedHashMap<String, PendingLogin>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+	issuer: String,
+	authorization_endpoint: String,
+	token_endpoint: String,
+	jwks_uri: String,
+}
+
+struct PendingLogin {
+	nonce: String,
+	code_verifier: String,
+	created: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+	id_token: String,
+}
+
+pub struct AuthorizationRequest {
+	pub url: String,
+	pub state: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+	pub oidc_link: OidcLinkConfig,
+	pub username: String,
+	pub display_name: Option<String>,
+	pub groups: Vec<String>,
+}
+
+impl OidcClient {
+	pub fn new(config: OidcConfig) -> anyhow::Result<Self> {
+		if config.issuer_url.is_empty() || config.client_id.is_empty() || config.redirect_url.is_empty() {
+			bail!("OIDC requires issuer_url, client_id, and redirect_url to be set");
+		}
+		
+		let http_client = reqwest::Client::builder()
+			.timeout(Duration::from_secs(15))
+			.build()?;
+		
+		Ok(Self {
+			config,
+			http_client,
+			provider_metadata: OnceCell::new(),
+			jwks: RwLock::new(None),
+			pending_logins: Mutex::new(LinkedHashMap::new()),
+		})
+	}
+	
+	async fn provider_metadata(&self) -> anyhow::Result<&ProviderMetadata> {
+		self.provider_metadata.get_or_try_init(|| async {
+			let discovery_url = format!("{}/.well-known/openid-configuration", self.config.issuer_url.trim_end_matches('/'));
+			
+			let metadata: ProviderMetadata = self.http_client.get(&discovery_url)
+				.send().await?
+				.error_for_status()?
+				.json().await
+				.context("Parsing OIDC provider metadata")?;
+			
+			if metadata.issuer.trim_end_matches('/') != self.config.issuer_url.trim_end_matches('/') {
+				bail!("OIDC provider reported issuer {} which doesn't match the configured issuer", metadata.issuer);
+			}
+			
+			Ok(metadata)
+		}).await
+	}
+	
+	async fn fetch_jwks(&self) -> anyhow::Result<Arc<JwkSet>> {
+		let provider_metadata = self.provider_metadata().await?;
+		
+		let jwks: JwkSet = self.http_client.get(&provider_metadata.jwks_uri)
+			.send().await?
+			.error_for_status()?
+			.json().await
+			.context("Parsing OIDC provider JWKS")?;
+		
+		let jwks = Arc::new(jwks);
+		*self.jwks.write().unwrap() = Some(jwks.clone());
+		
+		Ok(jwks)
+	}
+	
+	async fn decoding_key(&self, kid: Option<&str>) -> anyhow::Result<DecodingKey> {
+		fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<anyhow::Result<DecodingKey>> {
+			let jwk = match kid {
+				Some(kid) => jwks.find(kid)?,
+				None if jwks.keys.len() == 1 => &jwks.keys[0],
+				None => return None,
+			};
+			
+			Some(DecodingKey::from_jwk(jwk).map_err(Into::into))
+		}
+		
+		let cached_jwks = self.jwks.read().unwrap().clone();
+		
+		if let Some(key) = cached_jwks.and_then(|jwks| find_key(&jwks, kid)) {
+			return key;
+		}
+		
+		// The provider might have rotated its keys since we last fetched them
+		let jwks = self.fetch_jwks().await?;
+		
+		find_key(&jwks, kid).ok_or_else(|| anyhow!("No matching key found for ID token"))?
+	}
+	
+	pub async fn start_login(&self) -> anyhow::Result<AuthorizationRequest> {
+		let provider_metadata = self.provider_metadata().await?;
+		
+		let state = utils::generate_random_id();
+		let nonce = utils::generate_random_id();
+		// PKCE requires at least 43 characters
+		let code_verifier = utils::generate_random_string(32);
+		
+		let query = serde_urlencoded::to_string([
+			("response_type", "code"),
+			("client_id", &self.config.client_id),
+			("redirect_uri", &self.config.redirect_url),
+			("scope", &self.config.scopes.join(" ")),
+			("state", &state),
+			("nonce", &nonce),
+			("code_challenge", &pkce_challenge(&code_verifier)),
+			("code_challenge_method", "S256"),
+		])?;
+		
+		let separator = if provider_metadata.authorization_endpoint.contains('?') { '&' } else { '?' };
+		let url = format!("{}{}{}", provider_metadata.authorization_endpoint, separator, query);
+		
+		let mut pending_logins = self.pending_logins.lock().unwrap();
+		
+		while pending_logins.len() >= MAX_PENDING_LOGINS
+			|| pending_logins.front().is_some_and(|(_, pending_login)| pending_login.created.elapsed() >= PENDING_LOGIN_EXPIRY)
+		{
+			pending_logins.pop_front();
+		}
+		
+		pending_logins.insert(state.clone(), PendingLogin {
+			nonce,
+			code_verifier,
+			created: Instant::now(),
+		});
+		
+		Ok(AuthorizationRequest {
+			url,
+			state,
+		})
+	}
+	
+	pub async fn finish_login(&self, code: &str, state: &str) -> anyhow::Result<OidcIdentity> {
+		let pending_login = self.pending_logins.lock().unwrap().remove(state)
+			.filter(|pending_login| pending_login.created.elapsed() < PENDING_LOGIN_EXPIRY)
+			.ok_or_else(|| anyhow!("Unknown or expired OIDC login state"))?;
+		
+		let provider_metadata = self.provider_metadata().await?;
+		
+		let mut token_request = self.http_client.post(&provider_metadata.token_endpoint)
+			.form(&[
+				("grant_type", "authorization_code"),
+				("code", code),
+				("redirect_uri", &self.config.redirect_url),
+				("client_id", &self.config.client_id),
+				("code_verifier", &pending_login.code_verifier),
+			]);
+		
+		if let Some(client_secret) = &self.config.client_secret {
+			token_request = token_request.basic_auth(&self.config.client_id, Some(client_secret));
+		}
+		
+		let token_response: TokenResponse = token_request
+			.send().await?
+			.error_for_status()?
+			.json().await
+			.context("Parsing OIDC token response")?;
+		
+		let claims = self.validate_id_token(&token_response.id_token, &pending_login.nonce).await?;
+		
+		self.identity_from_claims(&claims)
+	}
+	
+	async fn validate_id_token(&self, id_token: &str, nonce: &str) -> anyhow::Result<Map<String, Value>> {
+		let provider_metadata = self.provider_metadata().await?;
+		let header = jsonwebtoken::decode_header(id_token)?;
+		
+		// Symmetric algorithms would let anyone who knows the client secret forge tokens
+		if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+			bail!("Unsupported ID token algorithm {:?}", header.alg);
+		}
+		
+		let decoding_key = self.decoding_key(header.kid.as_deref()).await?;
+		
+		let mut validation = Validation::new(header.alg);
+		validation.set_issuer(&[&provider_metadata.issuer]);
+		validation.set_audience(&[&self.config.client_id]);
+		validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+		
+		let claims = jsonwebtoken::decode::<Map<String, Value>>(id_token, &decoding_key, &validation)?.claims;
+		
+		if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+			bail!("ID token nonce doesn't match");
+		}
+		
+		Ok(claims)
+	}
+	
+	fn identity_from_claims(&self, claims: &Map<String, Value>) -> anyhow::Result<OidcIdentity> {
+		// Both were checked when validating the token
+		let oidc_link = OidcLinkConfig {
+			issuer: claims.get("iss").and_then(Value::as_str).unwrap_or_default().to_owned(),
+			subject: claims.get("sub").and_then(Value::as_str).unwrap_or_default().to_owned(),
+		};
+		
+		let username = claims.get(&self.config.username_claim)
+			.and_then(Value::as_str)
+			.filter(|username| !username.is_empty())
+			.ok_or_else(|| anyhow!("ID token is missing the {} claim", self.config.username_claim))?
+			.to_owned();
+		
+		let display_name = claims.get(&self.config.display_name_claim)
+			.and_then(Value::as_str)
+			.map(str::to_owned);
+		
+		let groups = match claims.get(&self.config.groups_claim) {
+			Some(Value::Array(groups)) => groups.iter()
+				.filter_map(Value::as_str)
+				.map(str::to_owned)
+				.collect(),
+			Some(Value::String(group)) => vec![group.clone()],
+			_ => Vec::new(),
+		};
+		
+		Ok(OidcIdentity {
+			oidc_link,
+			username,
+			display_name,
+			groups,
+		})
+	}
+	
+	pub fn libraries_for_groups(&self, groups: &[String]) -> Vec<String> {
+		let mut libraries = self.config.default_allowed_libraries.clone();
+		
+		for group in groups {
+			for library_id in self.config.group_libraries.get(group).into_iter().flatten() {
+				if !libraries.contains(library_id) {
+					libraries.push(library_id.clone());
+				}
+			}
+		}
+		
+		libraries
+	}
+	
+	pub async fn resolve_user(&self, identity: &OidcIdentity, auth_manager: &AuthManager, config: &ServerConfig) -> anyhow::Result<Arc<User>> {
+		let (user, changed) = self.link_user(identity, auth_manager)?;
+		
+		if changed {
+			auth_manager.save_config(config).await?;
+		}
+		
+		if user.disabled {
+			bail!("User {} is disabled", user.id);
+		}
+		
+		Ok(user)
+	}
+	
+	// Finds the user linked to an identity, creating it if enabled, and syncs its libraries from the group claims.
+	//  Users are only ever found through their link, which is either made by provisioning a new non-admin user or
+	//  set by an admin, so nobody gets into an existing account (admin or not) by picking its username at the
+	//  provider. Also returns whether the users config changed.
+	fn link_user(&self, identity: &OidcIdentity, auth_manager: &AuthManager) -> anyhow::Result<(Arc<User>, bool)> {
+		let allowed_libraries = self.libraries_for_groups(&identity.groups);
+		let mut changed = false;
+		
+		let mut user = match auth_manager.get_user_by_oidc_link(&identity.oidc_link) {
+			Some(user) => user,
+			None if self.config.auto_provision_users => {
+				let display_name = identity.display_name.as_deref().unwrap_or(&identity.username);
+				let user = auth_manager.provision_oidc_user(&identity.username, display_name, allowed_libraries.clone(), identity.oidc_link.clone())
+					.map_err(|_| anyhow!("Can't provision OIDC subject {}, username {} is already taken", identity.oidc_link.subject, identity.username))?;
+				
+				info!("Provisioned user {} for OIDC subject {}", user.id, identity.oidc_link.subject);
+				changed = true;
+				
+				user
+			}
+			None => bail!("No user is linked to OIDC subject {} (username {})", identity.oidc_link.subject, identity.username),
+		};
+		
+		// Admins' libraries are managed by hand
+		if !user.admin && !self.config.group_libraries.is_empty() && user.allowed_libraries != allowed_libraries {
+			user = auth_manager.update_user(&user.id, |user| user.allowed_libraries = allowed_libraries)
+				.map_err(|_| anyhow!("User {} was removed during login", user.id))?;
+			changed = true;
+		}
+		
+		Ok((user, changed))
+	}
+}
+
+fn pkce_challenge(code_verifier: &str) -> String {
+	URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::{UserConfig, UsersConfig};
+	use crate::web_server::auth::AuthSecrets;
+	use bytes::Bytes;
+	use http::{Request, Response, StatusCode};
+	use http_body_util::{BodyExt, Full};
+	use hyper::body::Incoming;
+	use hyper::service::service_fn;
+	use hyper_util::rt::TokioIo;
+	use jsonwebtoken::{EncodingKey, Header};
+	use rcgen::KeyPair;
+	use serde_json::json;
+	use std::collections::HashMap;
+	use std::convert::Infallible;
+	use std::time::SystemTime;
+	use tokio::net::TcpListener;
+	
+	struct MockProvider {
+		issuer: String,
+		key_pair: KeyPair,
+		code_challenge: Mutex<Option<String>>,
+		nonce: Mutex<Option<String>>,
+		subject: Mutex<&'static str>,
+		username: Mutex<&'static str>,
+	}
+	
+	impl MockProvider {
+		fn id_token(&self) -> String {
+			let exp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() + 300;
+			
+			let claims = json!({
+				"iss": self.issuer,
+				"aud": "media-server",
+				"sub": *self.subject.lock().unwrap(),
+				"exp": exp,
+				"nonce": self.nonce.lock().unwrap().clone(),
+				"preferred_username": *self.username.lock().unwrap(),
+				"name": "Zoe",
+				"groups": ["family", "other"],
+			});
+			
+			let mut header = Header::new(Algorithm::ES256);
+			header.kid = Some("test-key".to_owned());
+			
+			let key = EncodingKey::from_ec_pem(self.key_pair.serialize_pem().as_bytes()).unwrap();
+			
+			jsonwebtoken::encode(&header, &claims, &key).unwrap()
+		}
+		
+		async fn handle(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+			let body = match request.uri().path() {
+				"/.well-known/openid-configuration" => json!({
+					"issuer": self.issuer,
+					"authorization_endpoint": format!("{}/authorize", self.issuer),
+					"token_endpoint": format!("{}/token", self.issuer),
+					"jwks_uri": format!("{}/jwks", self.issuer),
+				}),
+				"/jwks" => {
+					// Uncompressed SEC1 point, 0x04 followed by the x and y coordinates
+					let public_key = self.key_pair.public_key_raw();
+					
+					json!({
+						"keys": [{
+							"kty": "EC",
+							"crv": "P-256",
+							"kid": "test-key",
+							"alg": "ES256",
+							"x": URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+							"y": URL_SAFE_NO_PAD.encode(&public_key[33..65]),
+						}],
+					})
+				}
+				"/token" => {
+					let body = request.into_body().collect().await.unwrap().to_bytes();
+					let params: HashMap<String, String> = serde_urlencoded::from_bytes(&body).unwrap();
+					
+					let code_challenge = self.code_challenge.lock().unwrap().clone();
+					
+					if params["code"] != "test_code" || Some(pkce_challenge(&params["code_verifier"])) != code_challenge {
+						return Response::builder()
+							.status(StatusCode::BAD_REQUEST)
+							.body(Full::new(Bytes::from_static(b"{\"error\":\"invalid_grant\"}")))
+							.unwrap();
+					}
+					
+					json!({
+						"access_token": "unused",
+						"token_type": "Bearer",
+						"id_token": self.id_token(),
+					})
+				}
+				_ => return Response::builder()
+					.status(StatusCode::NOT_FOUND)
+					.body(Full::new(Bytes::new()))
+					.unwrap(),
+			};
+			
+			Response::new(Full::new(Bytes::from(body.to_string())))
+		}
+	}
+	
+	async fn start_mock_provider() -> Arc<MockProvider> {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		
+		let provider = Arc::new(MockProvider {
+			issuer: format!("http://{}", listener.local_addr().unwrap()),
+			key_pair: KeyPair::generate().unwrap(),
+			code_challenge: Mutex::new(None),
+			nonce: Mutex::new(None),
+			subject: Mutex::new("1234"),
+			username: Mutex::new("zoe"),
+		});
+		
+		let server_provider = provider.clone();
+		
+		tokio::spawn(async move {
+			loop {
+				let (stream, _) = listener.accept().await.unwrap();
+				let provider = server_provider.clone();
+				
+				tokio::spawn(async move {
+					let service = service_fn(|request| {
+						let provider = provider.clone();
+						
+						async move { Ok::<_, Infallible>(provider.handle(request).await) }
+					});
+					
+					let _ = hyper::server::conn::http1::Builder::new()
+						.serve_connection(TokioIo::new(stream), service)
+						.await;
+				});
+			}
+		});
+		
+		provider
+	}
+	
+	fn create_test_config(issuer_url: &str) -> OidcConfig {
+		OidcConfig {
+			enabled: true,
+			issuer_url: issuer_url.to_owned(),
+			client_id: "media-server".to_owned(),
+			client_secret: Some("secret".to_owned()),
+			redirect_url: "http://localhost:8000/api/oidc/callback".to_owned(),
+			group_libraries: HashMap::from([
+				("family".to_owned(), vec!["lib_a".to_owned(), "lib_b".to_owned()]),
+				("admins".to_owned(), vec!["lib_c".to_owned()]),
+			]),
+			default_allowed_libraries: vec!["lib_b".to_owned()],
+			..OidcConfig::default()
+		}
+	}
+	
+	fn start_query(authorization_request: &AuthorizationRequest) -> HashMap<String, String> {
+		let (_, query) = authorization_request.url.split_once('?').unwrap();
+		
+		serde_urlencoded::from_str(query).unwrap()
+	}
+	
+	#[test]
+	fn test_pkce_challenge() {
+		// base64url(sha256(verifier)) without padding
+		assert_eq!(pkce_challenge("test_verifier"), "0Ku4rR8EgR1w3HyHLBCxVLtPsAAks5HOlpmTEt0XhVA");
+	}
+	
+	#[test]
+	fn test_libraries_for_groups() {
+		let client = OidcClient::new(create_test_config("http://localhost")).unwrap();
+		
+		assert_eq!(client.libraries_for_groups(&[]), vec!["lib_b"]);
+		assert_eq!(client.libraries_for_groups(&["family".to_owned(), "unknown".to_owned()]), vec!["lib_b", "lib_a"]);
+		assert_eq!(client.libraries_for_groups(&["admins".to_owned(), "family".to_owned()]), vec!["lib_b", "lib_c", "lib_a"]);
+	}
+	
+	#[tokio::test]
+	async fn test_login_flow() {
+		let provider = start_mock_provider().await;
+		let client = OidcClient::new(create_test_config(&provider.issuer)).unwrap();
+		
+		let authorization_request = client.start_login().await.unwrap();
+		let query = start_query(&authorization_request);
+		
+		assert!(authorization_request.url.starts_with(&format!("{}/authorize?", provider.issuer)));
+		assert_eq!(query["client_id"], "media-server");
+		assert_eq!(query["code_challenge_method"], "S256");
+		assert_eq!(query["state"], authorization_request.state);
+		
+		*provider.code_challenge.lock().unwrap() = Some(query["code_challenge"].clone());
+		*provider.nonce.lock().unwrap() = Some(query["nonce"].clone());
+		
+		assert!(client.finish_login("test_code", "unknown_state").await.is_err());
+		
+		let identity = client.finish_login("test_code", &authorization_request.state).await.unwrap();
+		assert_eq!(identity.oidc_link, OidcLinkConfig { issuer: provider.issuer.clone(), subject: "1234".to_owned() });
+		assert_eq!(identity.username, "zoe");
+		assert_eq!(identity.display_name.as_deref(), Some("Zoe"));
+		assert_eq!(identity.groups, vec!["family", "other"]);
+		
+		// States can only be used once
+		assert!(client.finish_login("test_code", &authorization_request.state).await.is_err());
+		
+		// A token issued for a different login is rejected
+		let authorization_request = client.start_login().await.unwrap();
+		let query = start_query(&authorization_request);
+		*provider.code_challenge.lock().unwrap() = Some(query["code_challenge"].clone());
+		
+		assert!(client.finish_login("test_code", &authorization_request.state).await.is_err());
+		
+		// As is a code without the matching PKCE verifier
+		let authorization_request = client.start_login().await.unwrap();
+		let query = start_query(&authorization_request);
+		*provider.nonce.lock().unwrap() = Some(query["nonce"].clone());
+		
+		assert!(client.finish_login("test_code", &authorization_request.state).await.is_err());
+		
+		// Logins that are never finished only pile up so far
+		let first_request = client.start_login().await.unwrap();
+		
+		for _ in 0..MAX_PENDING_LOGINS {
+			client.start_login().await.unwrap();
+		}
+		
+		assert_eq!(client.pending_logins.lock().unwrap().len(), MAX_PENDING_LOGINS);
+		assert!(!client.pending_logins.lock().unwrap().contains_key(&first_request.state));
+	}
+	
+	async fn log_in(client: &OidcClient, provider: &MockProvider, subject: &'static str, username: &'static str) -> OidcIdentity {
+		*provider.subject.lock().unwrap() = subject;
+		*provider.username.lock().unwrap() = username;
+		
+		let authorization_request = client.start_login().await.unwrap();
+		let query = start_query(&authorization_request);
+		
+		*provider.code_challenge.lock().unwrap() = Some(query["code_challenge"].clone());
+		*provider.nonce.lock().unwrap() = Some(query["nonce"].clone());
+		
+		client.finish_login("test_code", &authorization_request.state).await.unwrap()
+	}
+	
+	fn create_user_config(id: &str, admin: bool, oidc_link: Option<OidcLinkConfig>) -> UserConfig {
+		UserConfig {
+			id: id.to_owned(),
+			display_name: id.to_owned(),
+			username: id.to_owned(),
+			password: None,
+			password_hash: None,
+			allowed_libraries: vec!["lib_c".to_owned()],
+			admin,
+			disabled: false,
+			path_rules: Vec::new(),
+			max_content_rating: None,
+			block_unrated: false,
+			rating_pin: None,
+			rating_pin_hash: None,
+			oidc_link,
+		}
+	}
+	
+	#[tokio::test]
+	async fn test_link_user() {
+		let provider = start_mock_provider().await;
+		let client = OidcClient::new(OidcConfig {
+			auto_provision_users: true,
+			..create_test_config(&provider.issuer)
+		}).unwrap();
+		
+		let link = |subject: &str| Some(OidcLinkConfig { issuer: provider.issuer.clone(), subject: subject.to_owned() });
+		
+		let auth_manager = AuthManager::from_config(UsersConfig {
+			users: vec![
+				create_user_config("zoe", true, None),
+				create_user_config("alice", false, link("1234")),
+				create_user_config("root", true, link("9999")),
+			],
+		}, AuthSecrets::generate()).unwrap();
+		
+		// Someone naming themselves after an existing user gets neither that account nor a new one
+		let identity = log_in(&client, &provider, "5678", "zoe").await;
+		assert!(client.link_user(&identity, &auth_manager).is_err());
+		assert_eq!(auth_manager.get_user_by_username("zoe").unwrap().oidc_link, None);
+		
+		// A linked user keeps getting their own account after changing their username to someone else's
+		let identity = log_in(&client, &provider, "1234", "zoe").await;
+		let (user, changed) = client.link_user(&identity, &auth_manager).unwrap();
+		assert_eq!(user.id, "alice");
+		assert_eq!(user.allowed_libraries, vec!["lib_b", "lib_a"]);
+		assert!(changed);
+		
+		// Admins are found through their link too, but keep their libraries
+		let identity = log_in(&client, &provider, "9999", "whoever").await;
+		let (user, changed) = client.link_user(&identity, &auth_manager).unwrap();
+		assert_eq!(user.id, "root");
+		assert_eq!(user.allowed_libraries, vec!["lib_c"]);
+		assert!(!changed);
+		
+		// Unknown identities get a new user that's linked to them
+		let identity = log_in(&client, &provider, "4321", "newbie").await;
+		let (user, changed) = client.link_user(&identity, &auth_manager).unwrap();
+		assert_eq!(user.id, "newbie");
+		assert!(!user.admin);
+		assert_eq!(user.oidc_link, link("4321"));
+		assert!(changed);
+		
+		let (user, changed) = client.link_user(&identity, &auth_manager).unwrap();
+		assert_eq!(user.id, "newbie");
+		assert!(!changed);
+	}
+}
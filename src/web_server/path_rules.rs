@@ -0,0 +1,253 @@
+use anyhow::bail;
+use relative_path::RelativePath;
+
+use crate::config::{PathAccess, PathRuleConfig};
+use crate::web_server::{video_locator, web_utils};
+
+// A glob relative to a library's root that allows or denies access to everything it matches, including the
+//  contents of matched directories. Rules are checked in order and the last matching rule wins.
+#[derive(Debug, Clone)]
+pub struct PathRule {
+	pub library_id: String,
+	pub path: String,
+	pub access: PathAccess,
+	
+	pattern: Vec<String>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum MatchMode {
+	// The pattern matches the path or one of its parents
+	Covers,
+	// The pattern could match something inside of the path
+	Descendant,
+}
+
+impl PathRule {
+	pub fn from_config(cfg: PathRuleConfig) -> anyhow::Result<Self> {
+		let mut pattern = Vec::new();
+		
+		for component in cfg.path.split(['/', '\\']) {
+			if component.is_empty() || component == "." {
+				continue;
+			}
+			
+			if component == ".." {
+				bail!("Path rule {:?} can't contain ..", cfg.path);
+			}
+			
+			pattern.push(component.to_lowercase());
+		}
+		
+		// Videos are matched by their path without the extension, so a rule like "**/*.mp4" would never match anything
+		if let Some(last_component) = pattern.last() &&
+			let Some((_, extension)) = last_component.rsplit_once('.') &&
+			video_locator::MEDIA_EXTENSIONS.contains(&extension) {
+			bail!("Path rule {:?} can't match on a video's file extension, videos are matched without it", cfg.path);
+		}
+		
+		Ok(Self {
+			library_id: cfg.library,
+			path: cfg.path,
+			access: cfg.access,
+			
+			pattern,
+		})
+	}
+	
+	pub fn to_config(&self) -> PathRuleConfig {
+		PathRuleConfig {
+			library: self.library_id.clone(),
+			path: self.path.clone(),
+			access: self.access,
+		}
+	}
+}
+
+pub fn is_path_visible(rules: &[PathRule], library_id: &str, path: &RelativePath) -> bool {
+	let mut rules = rules.iter().filter(|rule| rule.library_id == library_id).peekable();
+	
+	if rules.peek().is_none() {
+		return true;
+	}
+	
+	// Normalize the path the same way it will be resolved, so that rules can't be sidestepped with things like
+	//  "./Private" or "a/../Private". Matching is case-insensitive since the file system might be too.
+	let Some(sanitized_path) = web_utils::sanitize_path(path) else { return false };
+	
+	let components: Vec<String> = sanitized_path.iter()
+		.map(|component| component.to_string_lossy().to_lowercase())
+		.collect();
+	
+	// The library root is always visible, otherwise nothing in it could be reached
+	if components.is_empty() {
+		return true;
+	}
+	
+	let rules: Vec<&PathRule> = rules.collect();
+	
+	let last_match = rules.iter()
+		.rposition(|rule| match_pattern(&rule.pattern, &components, MatchMode::Covers));
+	
+	match last_match {
+		None => true,
+		Some(index) if rules[index].access == PathAccess::Allow => true,
+		// Denied directories can still be traversed to reach anything allowed inside of them
+		Some(index) => rules[index + 1..].iter()
+			.any(|rule| rule.access == PathAccess::Allow && match_pattern(&rule.pattern, &components, MatchMode::Descendant)),
+	}
+}
+
+fn match_pattern(pattern: &[String], path: &[String], mode: MatchMode) -> bool {
+	match (pattern.split_first(), path.split_first()) {
+		(None, _) => mode == MatchMode::Covers,
+		(Some(_), None) if mode == MatchMode::Descendant => true,
+		(Some((first, rest)), _) if first == "**" => {
+			match_pattern(rest, path, mode) || (!path.is_empty() && match_pattern(pattern, &path[1..], mode))
+		}
+		(Some(_), None) => false,
+		(Some((first, rest)), Some((component, path_rest))) => {
+			let first: Vec<char> = first.chars().collect();
+			let component: Vec<char> = component.chars().collect();
+			
+			match_component(&first, &component) && match_pattern(rest, path_rest, mode)
+		}
+	}
+}
+
+fn match_component(pattern: &[char], text: &[char]) -> bool {
+	match (pattern.split_first(), text.split_first()) {
+		(None, None) => true,
+		(Some(('*', rest)), _) => match_component(rest, text) || (!text.is_empty() && match_component(pattern, &text[1..])),
+		(Some(('?', rest)), Some((_, text_rest))) => match_component(rest, text_rest),
+		(Some((p, rest)), Some((t, text_rest))) => p == t && match_component(rest, text_rest),
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn rule(library: &str, path: &str, access: PathAccess) -> PathRule {
+		PathRule::from_config(PathRuleConfig {
+			library: library.to_owned(),
+			path: path.to_owned(),
+			access,
+		}).unwrap()
+	}
+	
+	fn visible(rules: &[PathRule], path: &str) -> bool {
+		is_path_visible(rules, "family", RelativePath::new(path))
+	}
+	
+	#[test]
+	fn test_deny_rules() {
+		let rules = vec![
+			rule("family", "Private", PathAccess::Deny),
+			rule("family", "**/*.secret", PathAccess::Deny),
+		];
+		
+		assert!(visible(&rules, ""));
+		assert!(visible(&rules, "Public"));
+		assert!(visible(&rules, "Public/Video"));
+		assert!(visible(&rules, "Privateer"));
+		
+		assert!(!visible(&rules, "Private"));
+		assert!(!visible(&rules, "Private/Video"));
+		assert!(!visible(&rules, "Private/Nested/Video"));
+		assert!(!visible(&rules, "Video.secret"));
+		assert!(!visible(&rules, "Public/Nested/Video.secret"));
+		
+		// Rules only apply to their own library
+		assert!(is_path_visible(&rules, "other", RelativePath::new("Private/Video")));
+	}
+	
+	#[test]
+	fn test_allow_rules() {
+		let rules = vec![
+			rule("family", "*", PathAccess::Deny),
+			rule("family", "Shows/Kids", PathAccess::Allow),
+			rule("family", "Shows/Kids/Scary*", PathAccess::Deny),
+		];
+		
+		assert!(!visible(&rules, "Movies"));
+		assert!(!visible(&rules, "Movies/Video"));
+		assert!(!visible(&rules, "Shows/Video"));
+		assert!(!visible(&rules, "Shows/Other"));
+		
+		// Parents of allowed directories can be traversed
+		assert!(visible(&rules, "Shows"));
+		assert!(visible(&rules, "Shows/Kids"));
+		assert!(visible(&rules, "Shows/Kids/Video"));
+		
+		assert!(!visible(&rules, "Shows/Kids/Scary Video"));
+		assert!(!visible(&rules, "Shows/Kids/Scary/Video"));
+	}
+	
+	#[test]
+	fn test_traversal_edge_cases() {
+		let rules = vec![
+			rule("family", "./Private/", PathAccess::Deny),
+		];
+		
+		assert!(!visible(&rules, "private/Video"));
+		assert!(!visible(&rules, "PRIVATE"));
+		assert!(!visible(&rules, "./Private/Video"));
+		assert!(!visible(&rules, "Public/../Private/Video"));
+		assert!(!visible(&rules, "Public/./../Private"));
+		assert!(!visible(&rules, "/Private/Video"));
+		assert!(!visible(&rules, "Private//Video"));
+		assert!(!visible(&rules, "Private/"));
+		assert!(!visible(&rules, "Public/..\\Private"));
+		assert!(!visible(&rules, "..Private"));
+		
+		assert!(visible(&rules, "Public/Video"));
+		assert!(visible(&rules, "Public/"));
+		
+		assert!(PathRule::from_config(PathRuleConfig {
+			library: "family".to_owned(),
+			path: "Public/../Private".to_owned(),
+			access: PathAccess::Deny,
+		}).is_err());
+	}
+	
+	#[test]
+	fn test_extension_rules() {
+		let from_path = |path: &str| PathRule::from_config(PathRuleConfig {
+			library: "family".to_owned(),
+			path: path.to_owned(),
+			access: PathAccess::Deny,
+		});
+		
+		// These could never match since videos don't have their extension in their path
+		assert!(from_path("**/*.mp4").is_err());
+		assert!(from_path("Private/Video.MKV").is_err());
+		
+		// Folders and other extensions are fine
+		assert!(from_path("Videos.mp4/Private").is_ok());
+		assert!(from_path("**/*.private").is_ok());
+		
+		let rules = vec![from_path("**/*.private").unwrap()];
+		
+		assert!(!visible(&rules, "Shows/Episode.private"));
+		assert!(visible(&rules, "Shows/Episode"));
+	}
+	
+	#[test]
+	fn test_match_component() {
+		fn matches(pattern: &str, text: &str) -> bool {
+			match_component(&pattern.chars().collect::<Vec<_>>(), &text.chars().collect::<Vec<_>>())
+		}
+		
+		assert!(matches("*", ""));
+		assert!(matches("*.mp4", "video.mp4"));
+		assert!(matches("v?deo", "video"));
+		assert!(matches("v?deo", "vïdeo"));
+		assert!(matches("a*b*c", "aXXbYYc"));
+		
+		assert!(!matches("*.mp4", "video.mkv"));
+		assert!(!matches("v?deo", "vdeo"));
+		assert!(!matches("video", "video2"));
+	}
+}
@@ -0,0 +1,288 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use relative_path::{RelativePath, RelativePathBuf};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::utils;
+use crate::web_server::data_store::{DataStore, DataStores, JsonData, JsonFile};
+use crate::web_server::history_remap;
+
+const PLAYLISTS_VERSION: u32 = 1;
+
+// Keeps the single playlists file from growing without bound
+pub const MAX_PLAYLISTS_PER_USER: usize = 100;
+
+pub struct Playlists {
+	playlists: Vec<Playlist>,
+	json_file: JsonFile,
+}
+
+impl Playlists {
+	// Unlike watch histories these are made by hand, so refuse to start if they're unreadable instead of throwing them
+	//  away
+	pub async fn load(playlists_file: PathBuf, data_stores: &DataStores) -> anyhow::Result<Arc<Mutex<Self>>> {
+		let (json_file, serialized) = JsonFile::load::<Self>(playlists_file).await?;
+		let dirty_notify = json_file.dirty_notify();
+		
+		let arc_self = Arc::new(Mutex::new(Self {
+			playlists: serialized.map_or_else(Vec::new, |serialized| serialized.playlists),
+			json_file,
+		}));
+		
+		data_stores.start_saving(arc_self.clone(), dirty_notify);
+		
+		Ok(arc_self)
+	}
+	
+	pub fn mark_dirty(&mut self) {
+		self.json_file.mark_dirty();
+	}
+	
+	// The user's own playlists followed by the ones other users have shared
+	pub fn list_for_user(&self, user_id: &str) -> Vec<&Playlist> {
+		let (mut owned, shared): (Vec<&Playlist>, Vec<&Playlist>) = self.playlists.iter()
+			.filter(|playlist| playlist.is_visible_to(user_id))
+			.partition(|playlist| playlist.owner_id == user_id);
+		
+		owned.extend(shared);
+		owned
+	}
+	
+	pub fn get_playlist(&self, id: &str) -> Option<&Playlist> {
+		self.playlists.iter().find(|playlist| playlist.id == id)
+	}
+	
+	pub fn get_playlist_mut(&mut self, id: &str) -> Option<&mut Playlist> {
+		self.playlists.iter_mut().find(|playlist| playlist.id == id)
+	}
+	
+	// Returns None if the user already has as many playlists as they can
+	pub fn create_playlist(&mut self, owner_id: &str, name: String, shared: bool, entries: Vec<PlaylistEntry>) -> Option<&Playlist> {
+		let owned_count = self.playlists.iter()
+			.filter(|playlist| playlist.owner_id == owner_id)
+			.count();
+		
+		if owned_count >= MAX_PLAYLISTS_PER_USER {
+			return None;
+		}
+		
+		let now = OffsetDateTime::now_utc();
+		
+		self.playlists.push(Playlist {
+			id: utils::generate_random_id(),
+			owner_id: owner_id.to_owned(),
+			name,
+			shared,
+			created: now,
+			updated: now,
+			entries,
+		});
+		
+		self.mark_dirty();
+		
+		self.playlists.last()
+	}
+	
+	pub fn delete_playlist(&mut self, id: &str) -> Option<Playlist> {
+		let index = self.playlists.iter().position(|playlist| playlist.id == id)?;
+		let playlist = self.playlists.remove(index);
+		
+		self.mark_dirty();
+		
+		Some(playlist)
+	}
+	
+	pub fn remove_user(&mut self, user_id: &str) {
+		self.playlists.retain(|playlist| playlist.owner_id != user_id);
+		
+		self.mark_dirty();
+	}
+	
+	// Points entries at the new location of moved files. Returns how many entries were changed.
+	pub fn remap_prefix(
+		&mut self,
+		library_id: &str,
+		old_prefix: &RelativePath,
+		new_library_id: &str,
+		new_prefix: &RelativePath,
+	) -> usize {
+		let mut moved_count = 0;
+		
+		for entry in self.playlists.iter_mut().flat_map(|playlist| &mut playlist.entries) {
+			if entry.library_id == library_id &&
+				let Some(new_path) = history_remap::remap_path(&entry.media_path, old_prefix, new_prefix) {
+				entry.library_id = new_library_id.to_owned();
+				entry.media_path = new_path;
+				
+				moved_count += 1;
+			}
+		}
+		
+		if moved_count > 0 {
+			self.mark_dirty();
+		}
+		
+		moved_count
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+	pub id: String,
+	pub owner_id: String,
+	pub name: String,
+	// Shared playlists can be viewed, but not changed, by every other user
+	pub shared: bool,
+	#[serde(with = "time::serde::iso8601")]
+	pub created: OffsetDateTime,
+	#[serde(with = "time::serde::iso8601")]
+	pub updated: OffsetDateTime,
+	pub entries: Vec<PlaylistEntry>,
+}
+
+impl Playlist {
+	pub fn is_visible_to(&self, user_id: &str) -> bool {
+		self.owner_id == user_id || self.shared
+	}
+	
+	pub fn touch(&mut self) {
+		self.updated = OffsetDateTime::now_utc();
+	}
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+}
+
+impl JsonData for Playlists {
+	const NAME: &'static str = "playlists";
+	const VERSION: u32 = PLAYLISTS_VERSION;
+	
+	type Serialized = SerializedPlaylists;
+	
+	fn json_file(&mut self) -> &mut JsonFile {
+		&mut self.json_file
+	}
+	
+	fn to_serialized(&mut self) -> SerializedPlaylists {
+		SerializedPlaylists {
+			playlists: self.playlists.clone(),
+		}
+	}
+}
+
+impl DataStore for Playlists {
+	fn save(arc_self: &Arc<Mutex<Self>>) -> impl Future<Output = ()> + Send {
+		JsonFile::save(arc_self)
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializedPlaylists {
+	playlists: Vec<Playlist>,
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::TempDir;
+	
+	use crate::utils::{add_extension, BACKUP_EXT};
+	
+	use super::*;
+	
+	fn entry(media_path: &str) -> PlaylistEntry {
+		PlaylistEntry {
+			library_id: "lib".to_owned(),
+			media_path: RelativePathBuf::from(media_path),
+		}
+	}
+	
+	#[tokio::test]
+	async fn test_playlists() {
+		let temp_dir = TempDir::new().unwrap();
+		let playlists_file = temp_dir.path().join("playlists.json");
+		let data_stores = DataStores::new();
+		
+		let playlists = Playlists::load(playlists_file.clone(), &data_stores).await.unwrap();
+		
+		let (private_id, shared_id) = {
+			let mut playlists = playlists.lock().unwrap();
+			
+			let private_id = playlists.create_playlist("alice", "Mine".to_owned(), false, vec![entry("Movies/A")]).unwrap().id.clone();
+			let shared_id = playlists.create_playlist("bob", "Ours".to_owned(), true, vec![entry("Shows/B"), entry("Movies/A")]).unwrap().id.clone();
+			playlists.create_playlist("bob", "Bob's".to_owned(), false, Vec::new());
+			
+			let names = |user_id| playlists.list_for_user(user_id).iter().map(|playlist| playlist.name.clone()).collect::<Vec<_>>();
+			
+			assert_eq!(names("alice"), vec!["Mine", "Ours"]);
+			assert_eq!(names("bob"), vec!["Ours", "Bob's"]);
+			assert_eq!(names("carol"), vec!["Ours"]);
+			
+			(private_id, shared_id)
+		};
+		
+		Playlists::save(&playlists).await;
+		
+		let loaded = Playlists::load(playlists_file.clone(), &data_stores).await.unwrap();
+		
+		{
+			let loaded = loaded.lock().unwrap();
+			
+			assert_eq!(loaded.get_playlist(&private_id).unwrap().entries, vec![entry("Movies/A")]);
+			assert_eq!(loaded.get_playlist(&shared_id).unwrap().entries.len(), 2);
+		}
+		
+		loaded.lock().unwrap().remove_user("bob");
+		Playlists::save(&loaded).await;
+		
+		loaded.lock().unwrap().mark_dirty();
+		Playlists::save(&loaded).await;
+		
+		// The previous save is kept as a backup in case the file gets damaged
+		tokio::fs::write(&playlists_file, b"{\"version\": 1, \"playl").await.unwrap();
+		
+		let restored = Playlists::load(playlists_file.clone(), &data_stores).await.unwrap();
+		
+		{
+			let restored = restored.lock().unwrap();
+			
+			assert!(restored.get_playlist(&private_id).is_some());
+			assert!(restored.get_playlist(&shared_id).is_none());
+			assert_eq!(restored.list_for_user("carol").len(), 0);
+		}
+		
+		// Restoring it gets saved in the background, so stop that from writing over the files below
+		data_stores.shutdown().await;
+		
+		tokio::fs::write(&playlists_file, b"not json").await.unwrap();
+		tokio::fs::write(add_extension(&playlists_file, BACKUP_EXT), b"not json").await.unwrap();
+		
+		assert!(Playlists::load(playlists_file, &DataStores::new()).await.is_err());
+	}
+	
+	#[tokio::test]
+	async fn test_playlist_limit() {
+		let temp_dir = TempDir::new().unwrap();
+		let data_stores = DataStores::new();
+		
+		let playlists = Playlists::load(temp_dir.path().join("playlists.json"), &data_stores).await.unwrap();
+		let mut playlists = playlists.lock().unwrap();
+		
+		for i in 0..MAX_PLAYLISTS_PER_USER {
+			assert!(playlists.create_playlist("alice", format!("Playlist {i}"), false, Vec::new()).is_some());
+		}
+		
+		assert!(playlists.create_playlist("alice", "Extra".to_owned(), false, Vec::new()).is_none());
+		assert!(playlists.create_playlist("bob", "Bob's".to_owned(), false, Vec::new()).is_some());
+		
+		let first_id = playlists.list_for_user("alice")[0].id.clone();
+		playlists.delete_playlist(&first_id);
+		
+		assert!(playlists.create_playlist("alice", "Replacement".to_owned(), false, Vec::new()).is_some());
+	}
+}
@@ -0,0 +1,437 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context};
+use relative_path::RelativePath;
+use time::OffsetDateTime;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+use crate::config::{PrewarmConfig, PrewarmJobConfig};
+use crate::web_server::api_error::ApiError;
+use crate::web_server::api_types::{ApiPrewarmJob, ApiPrewarmJobState};
+use crate::web_server::libraries::Libraries;
+use crate::web_server::media_backend_factory::MediaBackendFactory;
+use crate::web_server::media_metadata::AdvancedMediaMetadata;
+use crate::web_server::metadata_cache::FileMetadataCache;
+use crate::web_server::server_state::ServerState;
+use crate::web_server::services::artifact_cache::ArtifactCache;
+use crate::web_server::services::hls_segment_service::{self, HlsQualityLevel, HlsSegmentGenerator, SegmentParams};
+use crate::web_server::services::scaled_thumbnail_service::ScaledThumbnailGenerator;
+use crate::web_server::services::task_pool::TaskPriority;
+use crate::web_server::services::thumbnail_service::{self, ThumbnailGenerator};
+use crate::web_server::services::thumbnail_sheet_service::ThumbnailSheetGenerator;
+use crate::web_server::video_locator::{self, LocatedFile};
+
+// How often jobs with an idle trigger check whether the server has gone idle
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Opt-in jobs that fill the caches for a library ahead of time, for servers that are too slow to generate everything
+//  while someone is waiting
+pub struct PrewarmJobs {
+	jobs: Vec<PrewarmJob>,
+	// Last time something was played
+	last_activity: Mutex<Instant>,
+	// Jobs take turns, so that they don't fight over the same transcoding slots
+	run_lock: tokio::sync::Mutex<()>,
+}
+
+struct PrewarmJob {
+	config: PrewarmJobConfig,
+	quality_levels: Vec<HlsQualityLevel>,
+	run_requested: Notify,
+	cancel_flag: AtomicBool,
+	status: Mutex<JobStatus>,
+}
+
+#[derive(Debug, Clone)]
+struct JobStatus {
+	state: ApiPrewarmJobState,
+	last_run: Option<Instant>,
+	last_started: Option<OffsetDateTime>,
+	last_finished: Option<OffsetDateTime>,
+	files_total: usize,
+	files_done: usize,
+	files_failed: usize,
+}
+
+// Everything the jobs use, so that they can also be run without the rest of the server
+pub struct PrewarmTargets<'a> {
+	pub libraries: &'a Libraries,
+	pub show_hidden_files: bool,
+	pub metadata_cache: &'a FileMetadataCache,
+	pub media_backend_factory: &'a MediaBackendFactory,
+	pub hls_segment_generator: &'a ArtifactCache<HlsSegmentGenerator>,
+	pub thumbnail_generator: &'a ArtifactCache<ThumbnailGenerator>,
+	pub scaled_thumbnail_generator: &'a ArtifactCache<ScaledThumbnailGenerator>,
+	pub thumbnail_sheet_generator: &'a ArtifactCache<ThumbnailSheetGenerator>,
+}
+
+impl<'a> PrewarmTargets<'a> {
+	pub fn from_server_state(server_state: &'a ServerState) -> Self {
+		Self {
+			libraries: &server_state.libraries,
+			show_hidden_files: server_state.config.main_config.show_hidden_files,
+			metadata_cache: &server_state.metadata_cache,
+			media_backend_factory: &server_state.media_backend_factory,
+			hls_segment_generator: &server_state.hls_segment_generator,
+			thumbnail_generator: &server_state.thumbnail_generator,
+			scaled_thumbnail_generator: &server_state.scaled_thumbnail_generator,
+			thumbnail_sheet_generator: &server_state.thumbnail_sheet_generator,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum RunTrigger {
+	Requested,
+	Scheduled,
+	Idle,
+}
+
+impl PrewarmJobs {
+	pub fn from_config(config: &PrewarmConfig, libraries: &Libraries) -> anyhow::Result<Self> {
+		let mut jobs: Vec<PrewarmJob> = Vec::new();
+		
+		for job_config in &config.jobs {
+			if jobs.iter().any(|job| job.config.id == job_config.id) {
+				bail!("Duplicate pre-warming job {}", job_config.id);
+			}
+			
+			let library = libraries.get_library(&job_config.library)
+				.ok_or_else(|| anyhow!("Pre-warming job {} uses unknown library {}", job_config.id, job_config.library))?;
+			
+			if library.resolve_path(RelativePath::new(&job_config.path)).is_none() {
+				bail!("Pre-warming job {} has an invalid path", job_config.id);
+			}
+			
+			let quality_levels = job_config.quality_levels.iter()
+				.map(|id| hls_segment_service::get_quality_level(id)
+					.map_err(|_| anyhow!("Pre-warming job {} uses unknown quality level {}", job_config.id, id)))
+				.collect::<anyhow::Result<Vec<_>>>()?;
+			
+			jobs.push(PrewarmJob {
+				config: job_config.clone(),
+				quality_levels,
+				run_requested: Notify::new(),
+				cancel_flag: AtomicBool::new(false),
+				status: Mutex::new(JobStatus {
+					state: ApiPrewarmJobState::Idle,
+					last_run: None,
+					last_started: None,
+					last_finished: None,
+					files_total: 0,
+					files_done: 0,
+					files_failed: 0,
+				}),
+			});
+		}
+		
+		Ok(Self {
+			jobs,
+			last_activity: Mutex::new(Instant::now()),
+			run_lock: tokio::sync::Mutex::new(()),
+		})
+	}
+	
+	// Starts waiting for each job's triggers
+	pub fn start(server_state: &Arc<ServerState>) {
+		for job_index in 0..server_state.prewarm_jobs.jobs.len() {
+			tokio::spawn(schedule_job(server_state.clone(), job_index));
+		}
+	}
+	
+	// Called whenever something is played, holds off idle jobs and stops the ones that are running
+	pub fn note_activity(&self) {
+		*self.last_activity.lock().unwrap() = Instant::now();
+	}
+	
+	pub fn job_statuses(&self) -> Vec<ApiPrewarmJob> {
+		self.jobs.iter()
+			.map(|job| {
+				let status = job.status.lock().unwrap().clone();
+				
+				ApiPrewarmJob {
+					id: job.config.id.clone(),
+					library_id: job.config.library.clone(),
+					path: job.config.path.clone(),
+					state: status.state,
+					last_started: status.last_started,
+					last_finished: status.last_finished,
+					files_total: status.files_total,
+					files_done: status.files_done,
+					files_failed: status.files_failed,
+				}
+			})
+			.collect()
+	}
+	
+	pub fn request_run(&self, job_id: &str) -> Result<(), ApiError> {
+		let job = self.get_job(job_id)?;
+		
+		if job.status.lock().unwrap().state == ApiPrewarmJobState::Idle {
+			job.run_requested.notify_one();
+		}
+		
+		Ok(())
+	}
+	
+	// Stops the job after whatever it's currently generating
+	pub fn cancel(&self, job_id: &str) -> Result<(), ApiError> {
+		self.get_job(job_id)?.cancel_flag.store(true, Ordering::Relaxed);
+		
+		Ok(())
+	}
+	
+	fn get_job(&self, job_id: &str) -> Result<&PrewarmJob, ApiError> {
+		self.jobs.iter()
+			.find(|job| job.config.id == job_id)
+			.ok_or(ApiError::PrewarmJobNotFound)
+	}
+	
+	fn activity_since(&self, time: Instant) -> bool {
+		*self.last_activity.lock().unwrap() > time
+	}
+}
+
+// For running a job from the command line, next to or instead of the server
+pub async fn run_job_now(targets: &PrewarmTargets<'_>, jobs: &PrewarmJobs, job_id: &str) -> anyhow::Result<()> {
+	let job = jobs.get_job(job_id)
+		.map_err(|_| anyhow!("Unknown pre-warming job {}", job_id))?;
+	
+	run_job(targets, jobs, job, RunTrigger::Requested).await;
+	
+	let status = job.status.lock().unwrap().clone();
+	
+	if status.files_failed > 0 {
+		bail!("{} of {} files couldn't be pre-warmed", status.files_failed, status.files_total);
+	}
+	
+	Ok(())
+}
+
+async fn schedule_job(server_state: Arc<ServerState>, job_index: usize) {
+	let job = &server_state.prewarm_jobs.jobs[job_index];
+	
+	let interval = job.config.interval_hours.map(|hours| Duration::from_secs(hours * 60 * 60));
+	let mut next_scheduled_run = interval.map(|_| Instant::now());
+	
+	loop {
+		let scheduled_run = async {
+			match next_scheduled_run {
+				Some(time) => tokio::time::sleep_until(time.into()).await,
+				None => std::future::pending().await,
+			}
+		};
+		
+		let trigger = tokio::select! {
+			_ = job.run_requested.notified() => RunTrigger::Requested,
+			_ = scheduled_run => RunTrigger::Scheduled,
+			_ = wait_for_idle(&server_state.prewarm_jobs, job) => RunTrigger::Idle,
+		};
+		
+		if trigger == RunTrigger::Scheduled && let Some(interval) = interval {
+			next_scheduled_run = Some(Instant::now() + interval);
+		}
+		
+		run_job(&PrewarmTargets::from_server_state(&server_state), &server_state.prewarm_jobs, job, trigger).await;
+	}
+}
+
+async fn wait_for_idle(jobs: &PrewarmJobs, job: &PrewarmJob) {
+	let Some(idle_minutes) = job.config.idle_minutes else {
+		return std::future::pending().await;
+	};
+	
+	let idle_time = Duration::from_secs(idle_minutes * 60);
+	
+	loop {
+		let last_activity = *jobs.last_activity.lock().unwrap();
+		let last_run = job.status.lock().unwrap().last_run;
+		
+		if is_idle_run_due(Instant::now(), last_activity, last_run, idle_time) {
+			return;
+		}
+		
+		tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+	}
+}
+
+// Only runs once per idle period, there's no point going over the same files again until something was played
+fn is_idle_run_due(now: Instant, last_activity: Instant, last_run: Option<Instant>, idle_time: Duration) -> bool {
+	now.duration_since(last_activity) >= idle_time && last_run.is_none_or(|last_run| last_run < last_activity)
+}
+
+async fn run_job(targets: &PrewarmTargets<'_>, jobs: &PrewarmJobs, job: &PrewarmJob, trigger: RunTrigger) {
+	job.cancel_flag.store(false, Ordering::Relaxed);
+	job.status.lock().unwrap().state = ApiPrewarmJobState::Queued;
+	
+	let _run_guard = jobs.run_lock.lock().await;
+	let start_time = Instant::now();
+	
+	{
+		let mut status = job.status.lock().unwrap();
+		
+		status.state = ApiPrewarmJobState::Running;
+		status.last_run = Some(start_time);
+		status.last_started = Some(OffsetDateTime::now_utc());
+		status.files_total = 0;
+		status.files_done = 0;
+		status.files_failed = 0;
+	}
+	
+	info!("Starting pre-warming job {} ({:?})", &job.config.id, trigger);
+	
+	let should_stop = || {
+		job.cancel_flag.load(Ordering::Relaxed) || (trigger == RunTrigger::Idle && jobs.activity_since(start_time))
+	};
+	
+	match prewarm_files(targets, job, should_stop).await {
+		Ok(()) => info!("Pre-warming job {} finished in {:?}", &job.config.id, start_time.elapsed()),
+		Err(err) => error!("Pre-warming job {} failed: {:?}", &job.config.id, err),
+	}
+	
+	let mut status = job.status.lock().unwrap();
+	
+	status.state = ApiPrewarmJobState::Idle;
+	status.last_finished = Some(OffsetDateTime::now_utc());
+}
+
+async fn prewarm_files(targets: &PrewarmTargets<'_>, job: &PrewarmJob, should_stop: impl Fn() -> bool) -> anyhow::Result<()> {
+	let library = targets.libraries.get_library(&job.config.library).context("Library not found")?;
+	let dir_path = library.resolve_path(RelativePath::new(&job.config.path)).context("Invalid path")?;
+	
+	let media_files = collect_media_files(targets.show_hidden_files, &dir_path).await?;
+	
+	job.status.lock().unwrap().files_total = media_files.len();
+	
+	for media_path in media_files {
+		if should_stop() {
+			info!("Stopping pre-warming job {}", &job.config.id);
+			break;
+		}
+		
+		let result = prewarm_file(targets, job, &media_path, &should_stop).await;
+		
+		let mut status = job.status.lock().unwrap();
+		status.files_done += 1;
+		
+		if let Err(err) = result {
+			warn!("Failed to pre-warm {:?}: {:?}", &media_path, err);
+			status.files_failed += 1;
+		}
+	}
+	
+	Ok(())
+}
+
+async fn prewarm_file(
+	targets: &PrewarmTargets<'_>,
+	job: &PrewarmJob,
+	media_path: &Path,
+	should_stop: impl Fn() -> bool,
+) -> anyhow::Result<()> {
+	let priority = TaskPriority::Background;
+	
+	if job.config.thumbnails || job.config.scaled_thumbnails {
+		let full_thumbnail = thumbnail_service::get_thumbnail(
+			LocatedFile::File(media_path.to_owned()), targets.thumbnail_generator, priority).await?;
+		
+		if job.config.scaled_thumbnails && let Some(full_thumbnail) = full_thumbnail {
+			targets.scaled_thumbnail_generator
+				.get_or_generate_with_priority(full_thumbnail.file_data, priority).await?;
+		}
+	}
+	
+	if job.config.thumbnail_sheets {
+		targets.thumbnail_sheet_generator
+			.get_or_generate_with_priority(media_path.to_owned(), priority).await?;
+	}
+	
+	if job.config.hls_segments == 0 || job.quality_levels.is_empty() {
+		return Ok(());
+	}
+	
+	let advanced_metadata = targets.metadata_cache
+		.fetch_metadata::<AdvancedMediaMetadata>(media_path).await?;
+	
+	let Some(video_metadata) = &advanced_metadata.video_metadata else { return Ok(()) };
+	
+	let quality_levels = job.quality_levels.iter()
+		.filter(|lvl| lvl.supported(video_metadata, targets.media_backend_factory));
+	
+	for quality_level in quality_levels {
+		for segment_index in 0..job.config.hls_segments {
+			if should_stop() || !hls_segment_service::is_segment_index_valid(segment_index, &advanced_metadata) {
+				break;
+			}
+			
+			let params = SegmentParams {
+				media_path: media_path.to_owned(),
+				segment_index,
+				quality_level: quality_level.clone(),
+			};
+			
+			targets.hls_segment_generator.get_or_generate_with_priority(params, priority).await?;
+		}
+	}
+	
+	Ok(())
+}
+
+async fn collect_media_files(show_hidden_files: bool, dir_path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+	let mut media_files = Vec::new();
+	let mut pending_dirs = vec![dir_path.to_owned()];
+	
+	while let Some(dir_path) = pending_dirs.pop() {
+		let mut read_dir = tokio::fs::read_dir(&dir_path).await?;
+		
+		while let Some(entry) = read_dir.next_entry().await? {
+			let path = entry.path();
+			
+			let Some(file_name) = path.file_name().and_then(OsStr::to_str) else { continue };
+			
+			if !show_hidden_files && video_locator::is_hidden(file_name) {
+				continue;
+			}
+			
+			let file_type = entry.file_type().await?;
+			
+			if file_type.is_file() && video_locator::is_video(&path) {
+				media_files.push(path);
+			} else if file_type.is_dir() {
+				pending_dirs.push(path);
+			}
+		}
+	}
+	
+	media_files.sort();
+	
+	Ok(media_files)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn test_idle_run_due() {
+		let start = Instant::now();
+		let idle_time = Duration::from_secs(30 * 60);
+		let minutes = |minutes: u64| start + Duration::from_secs(minutes * 60);
+		
+		// Not idle for long enough yet
+		assert!(!is_idle_run_due(minutes(29), start, None, idle_time));
+		assert!(is_idle_run_due(minutes(30), start, None, idle_time));
+		
+		// Already ran during this idle period
+		assert!(!is_idle_run_due(minutes(90), start, Some(minutes(30)), idle_time));
+		
+		// Something was played since the last run
+		assert!(!is_idle_run_due(minutes(100), minutes(80), Some(minutes(30)), idle_time));
+		assert!(is_idle_run_due(minutes(110), minutes(80), Some(minutes(30)), idle_time));
+	}
+}
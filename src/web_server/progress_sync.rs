@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use relative_path::RelativePath;
+use tokio::sync::broadcast;
+
+use crate::web_server::api_types::{ApiWatchedDirectoryEvent, ApiWatchProgressEvent};
+use crate::web_server::watch_history::WatchHistoryEntry;
+
+// Clients that fall further behind than this get told to refetch instead
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+	Progress(ApiWatchProgressEvent),
+	// Bulk changes are sent as one event for the whole directory, rather than one per video
+	Directory(ApiWatchedDirectoryEvent),
+}
+
+impl ProgressEvent {
+	pub fn library_id(&self) -> &str {
+		match self {
+			ProgressEvent::Progress(event) => &event.library_id,
+			ProgressEvent::Directory(event) => &event.library_id,
+		}
+	}
+	
+	pub fn media_path(&self) -> &RelativePath {
+		match self {
+			ProgressEvent::Progress(event) => &event.media_path,
+			ProgressEvent::Directory(event) => &event.media_path,
+		}
+	}
+}
+
+// Pushes watch history changes to every other device the user has open. Each user has their own channel, so that
+//  one user's changes can't make another's devices fall behind.
+pub struct ProgressSync {
+	senders: Mutex<HashMap<String, broadcast::Sender<ProgressEvent>>>,
+}
+
+impl ProgressSync {
+	pub fn new() -> Self {
+		Self {
+			senders: Mutex::new(HashMap::new()),
+		}
+	}
+	
+	pub fn subscribe(&self, user_id: &str) -> broadcast::Receiver<ProgressEvent> {
+		let mut senders = self.senders.lock().unwrap();
+		
+		// Channels of users that don't have anything open anymore
+		senders.retain(|_, sender| sender.receiver_count() > 0);
+		
+		senders.entry(user_id.to_owned())
+			.or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+			.subscribe()
+	}
+	
+	// Sends the current state of a history entry, which is missing if it was deleted
+	pub fn publish(
+		&self,
+		user_id: &str,
+		library_id: &str,
+		media_path: &RelativePath,
+		entry: Option<&WatchHistoryEntry>,
+		client_id: Option<String>,
+	) {
+		self.send(user_id, ProgressEvent::Progress(ApiWatchProgressEvent {
+			library_id: library_id.to_owned(),
+			media_path: media_path.to_owned(),
+			progress: entry.map(|entry| entry.progress),
+			watched: entry.is_some_and(|entry| entry.watched),
+			play_count: entry.map_or(0, |entry| entry.play_count),
+			last_watched: entry.map(|entry| entry.last_watched),
+			client_id,
+		}));
+	}
+	
+	pub fn publish_directory(&self, user_id: &str, library_id: &str, dir_path: &RelativePath, watched: bool) {
+		self.send(user_id, ProgressEvent::Directory(ApiWatchedDirectoryEvent {
+			library_id: library_id.to_owned(),
+			media_path: dir_path.to_owned(),
+			watched,
+		}));
+	}
+	
+	fn send(&self, user_id: &str, event: ProgressEvent) {
+		let senders = self.senders.lock().unwrap();
+		
+		// Nobody listening isn't an error
+		if let Some(sender) = senders.get(user_id) {
+			let _ = sender.send(event);
+		}
+	}
+}
+
+impl Default for ProgressSync {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	
+	use crate::web_server::watch_history::{Completion, WatchHistory};
+	
+	use super::*;
+	
+	#[tokio::test]
+	async fn test_publish() {
+		let progress_sync = ProgressSync::new();
+		let mut receiver = progress_sync.subscribe("user");
+		let mut other_receiver = progress_sync.subscribe("other");
+		
+		let path = RelativePath::new("Shows/Episode 1");
+		
+		let mut watch_history = WatchHistory::new(Vec::new());
+		watch_history.update_progress("lib", path, 95, Completion {
+			duration: Duration::from_secs(100),
+			finished_threshold: 0.9,
+		});
+		
+		progress_sync.publish("user", "lib", path, watch_history.get_entry("lib", path), Some("phone".to_owned()));
+		progress_sync.publish("user", "lib", path, None, None);
+		progress_sync.publish_directory("user", "lib", RelativePath::new("Shows"), true);
+		
+		let ProgressEvent::Progress(event) = receiver.recv().await.unwrap() else { panic!() };
+		assert_eq!(event.progress, Some(95));
+		assert!(event.watched);
+		assert_eq!(event.client_id.as_deref(), Some("phone"));
+		
+		let ProgressEvent::Progress(event) = receiver.recv().await.unwrap() else { panic!() };
+		assert_eq!(event.progress, None);
+		assert_eq!(event.play_count, 0);
+		
+		let ProgressEvent::Directory(event) = receiver.recv().await.unwrap() else { panic!() };
+		assert_eq!(event.media_path, "Shows");
+		assert!(event.watched);
+		
+		// Other users don't get any of it
+		assert!(other_receiver.try_recv().is_err());
+		
+		// Channels go away once nobody is subscribed to them
+		drop(other_receiver);
+		let _receiver = progress_sync.subscribe("user");
+		assert_eq!(progress_sync.senders.lock().unwrap().len(), 1);
+	}
+}
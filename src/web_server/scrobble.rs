@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::config::{ScrobbleConfig, ScrobbleWebhookConfig};
+
+// Playback that hasn't reported progress in this long is treated as stopped, so resuming it counts as a new start
+const PLAYBACK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+	Playing,
+	Paused,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrobbleAction {
+	Start,
+	Pause,
+	Complete,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrobbleEvent {
+	pub action: ScrobbleAction,
+	pub user_id: String,
+	pub username: String,
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	pub title: String,
+	pub progress: u64,
+	pub duration: u64,
+	#[serde(with = "time::serde::iso8601")]
+	pub timestamp: OffsetDateTime,
+}
+
+// Something that gets told about playback, like an external tracker
+pub trait ScrobbleHook: Send + Sync {
+	fn scrobble<'a>(&'a self, event: &'a ScrobbleEvent) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+// Posts every event as JSON to a URL
+pub struct WebhookScrobbleHook {
+	config: ScrobbleWebhookConfig,
+	http_client: reqwest::Client,
+}
+
+impl WebhookScrobbleHook {
+	pub fn new(config: ScrobbleWebhookConfig) -> anyhow::Result<Self> {
+		let http_client = reqwest::Client::builder()
+			.timeout(Duration::from_secs(15))
+			.build()?;
+		
+		Ok(Self {
+			config,
+			http_client,
+		})
+	}
+}
+
+impl ScrobbleHook for WebhookScrobbleHook {
+	fn scrobble<'a>(&'a self, event: &'a ScrobbleEvent) -> BoxFuture<'a, anyhow::Result<()>> {
+		Box::pin(async move {
+			let mut request = self.http_client.post(&self.config.url).json(event);
+			
+			for (name, value) in &self.config.headers {
+				request = request.header(name, value);
+			}
+			
+			request.send().await?.error_for_status()?;
+			
+			Ok(())
+		})
+	}
+}
+
+pub struct Scrobbler {
+	hooks: Arc<Vec<Box<dyn ScrobbleHook>>>,
+	// Keyed by user and video, only holds videos that are currently playing
+	playing: Mutex<HashMap<(String, String, String), Instant>>,
+}
+
+impl Scrobbler {
+	pub fn from_config(config: &ScrobbleConfig) -> anyhow::Result<Self> {
+		let mut hooks: Vec<Box<dyn ScrobbleHook>> = Vec::new();
+		
+		for webhook_config in &config.webhooks {
+			hooks.push(Box::new(WebhookScrobbleHook::new(webhook_config.clone())?));
+		}
+		
+		Ok(Self::new(hooks))
+	}
+	
+	pub fn new(hooks: Vec<Box<dyn ScrobbleHook>>) -> Self {
+		Self {
+			hooks: Arc::new(hooks),
+			playing: Mutex::new(HashMap::new()),
+		}
+	}
+	
+	// Works out which events a progress update amounts to
+	pub fn playback_actions(
+		&self,
+		user_id: &str,
+		library_id: &str,
+		media_path: &str,
+		playback_state: Option<PlaybackState>,
+		completed: bool,
+	) -> Vec<ScrobbleAction> {
+		let key = (user_id.to_owned(), library_id.to_owned(), media_path.to_lowercase());
+		let mut playing = self.playing.lock().unwrap();
+		let mut actions = Vec::new();
+		
+		playing.retain(|_, last_update| last_update.elapsed() < PLAYBACK_TIMEOUT);
+		
+		let was_playing = match playback_state {
+			Some(PlaybackState::Playing) => playing.insert(key.clone(), Instant::now()).is_some(),
+			Some(PlaybackState::Paused) => playing.remove(&key).is_some(),
+			None => false,
+		};
+		
+		match playback_state {
+			Some(PlaybackState::Playing) if !was_playing => actions.push(ScrobbleAction::Start),
+			Some(PlaybackState::Paused) if was_playing => actions.push(ScrobbleAction::Pause),
+			_ => {}
+		}
+		
+		if completed {
+			playing.remove(&key);
+			actions.push(ScrobbleAction::Complete);
+		}
+		
+		actions
+	}
+	
+	// Sends the event to every hook in the background so that slow trackers don't hold up the request
+	pub fn dispatch(&self, event: ScrobbleEvent) {
+		if self.hooks.is_empty() {
+			return;
+		}
+		
+		let hooks = self.hooks.clone();
+		
+		tokio::spawn(async move {
+			for hook in hooks.iter() {
+				if let Err(err) = hook.scrobble(&event).await {
+					warn!("Error sending {:?} scrobble for {:?}: {:?}", event.action, event.media_path, err);
+				}
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::convert::Infallible;
+	
+	use bytes::Bytes;
+	use http::{Request, Response};
+	use http_body_util::{BodyExt, Full};
+	use hyper::body::Incoming;
+	use hyper::service::service_fn;
+	use hyper_util::rt::TokioIo;
+	use tokio::net::TcpListener;
+	use tokio::sync::mpsc;
+	
+	use super::*;
+	
+	#[test]
+	fn test_playback_actions() {
+		let scrobbler = Scrobbler::new(Vec::new());
+		
+		let actions = |state, completed| scrobbler.playback_actions("user", "lib", "Video", state, completed);
+		
+		assert_eq!(actions(Some(PlaybackState::Playing), false), vec![ScrobbleAction::Start]);
+		assert_eq!(actions(Some(PlaybackState::Playing), false), vec![]);
+		assert_eq!(actions(Some(PlaybackState::Paused), false), vec![ScrobbleAction::Pause]);
+		assert_eq!(actions(Some(PlaybackState::Paused), false), vec![]);
+		assert_eq!(actions(Some(PlaybackState::Playing), false), vec![ScrobbleAction::Start]);
+		assert_eq!(actions(Some(PlaybackState::Playing), true), vec![ScrobbleAction::Complete]);
+		assert_eq!(actions(None, true), vec![ScrobbleAction::Complete]);
+		assert_eq!(actions(None, false), vec![]);
+		
+		// Other users are tracked separately
+		assert_eq!(scrobbler.playback_actions("other", "lib", "Video", Some(PlaybackState::Playing), false),
+			vec![ScrobbleAction::Start]);
+	}
+	
+	#[tokio::test]
+	async fn test_webhook() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}/scrobble", listener.local_addr().unwrap());
+		
+		let (sender, mut receiver) = mpsc::unbounded_channel();
+		
+		tokio::spawn(async move {
+			let (stream, _) = listener.accept().await.unwrap();
+			
+			let service = service_fn(move |request: Request<Incoming>| {
+				let sender = sender.clone();
+				
+				async move {
+					let authorization = request.headers().get("authorization").cloned();
+					let body = request.into_body().collect().await.unwrap().to_bytes();
+					
+					sender.send((authorization, body)).unwrap();
+					
+					Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+				}
+			});
+			
+			let _ = hyper::server::conn::http1::Builder::new()
+				.serve_connection(TokioIo::new(stream), service)
+				.await;
+		});
+		
+		let hook = WebhookScrobbleHook::new(ScrobbleWebhookConfig {
+			url,
+			headers: HashMap::from([("Authorization".to_owned(), "Bearer token".to_owned())]),
+		}).unwrap();
+		
+		let event = ScrobbleEvent {
+			action: ScrobbleAction::Complete,
+			user_id: "user".to_owned(),
+			username: "zoe".to_owned(),
+			library_id: "lib".to_owned(),
+			media_path: RelativePathBuf::from("Shows/Episode 1"),
+			title: "Episode 1".to_owned(),
+			progress: 1500,
+			duration: 1600,
+			timestamp: OffsetDateTime::now_utc(),
+		};
+		
+		hook.scrobble(&event).await.unwrap();
+		
+		let (authorization, body) = receiver.recv().await.unwrap();
+		let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		
+		assert_eq!(authorization.unwrap(), "Bearer token");
+		assert_eq!(body["action"], "complete");
+		assert_eq!(body["media_path"], "Shows/Episode 1");
+		assert_eq!(body["progress"], 1500);
+	}
+}
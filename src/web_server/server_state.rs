@@ -2,10 +2,20 @@ use std::sync::{Arc, Mutex};
 
 use crate::config::ServerConfig;
 use crate::web_server::auth::{AuthManager, AuthSecrets};
+use crate::web_server::data_store::DataStores;
 use crate::web_server::libraries::Libraries;
+use crate::web_server::favorites::UserFavorites;
+use crate::web_server::oidc::OidcClient;
+use crate::web_server::playlists::Playlists;
+use crate::web_server::prewarm::PrewarmJobs;
+use crate::web_server::progress_sync::ProgressSync;
 use crate::web_server::media_backend_factory::MediaBackendFactory;
-use crate::web_server::services::artifact_cache::ArtifactCache;
+use crate::web_server::cache_admin;
+use crate::web_server::services::artifact_cache::{ArtifactCache, ManagedCache};
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_keys::CacheKeys;
 use crate::web_server::services::hls_segment_service::HlsSegmentGenerator;
+use crate::web_server::services::segment_prefetcher::SegmentPrefetcher;
 use crate::web_server::services::task_pool::TaskPool;
 use crate::web_server::services::thumbnail_service::ThumbnailGenerator;
 use crate::web_server::services::thumbnail_sheet_service::ThumbnailSheetGenerator;
@@ -14,19 +24,34 @@ use crate::web_server::services::{hls_segment_service, scaled_thumbnail_service,
 use crate::web_server::services::scaled_thumbnail_service::ScaledThumbnailGenerator;
 use crate::web_server::services::subtitle_service::TranscodedSubtitleGenerator;
 use crate::web_server::services::transcription_service::AutoTranscriptionGenerator;
-use crate::web_server::watch_history::UserWatchHistories;
+use crate::web_server::scrobble::Scrobbler;
+use crate::web_server::watch_history::{HistoryMigration, UserWatchHistories};
+use crate::web_server::viewing_stats::ViewingStats;
+use crate::web_server::watch_party::WatchParties;
 
 pub struct ServerState {
 	pub config: ServerConfig,
 	
 	pub libraries: Libraries,
 	pub auth_manager: AuthManager,
+	pub oidc_client: Option<OidcClient>,
+	pub data_stores: DataStores,
 	pub user_watch_histories: Arc<Mutex<UserWatchHistories>>,
-	pub metadata_cache: FileMetadataCache,
+	pub playlists: Arc<Mutex<Playlists>>,
+	pub user_favorites: Arc<Mutex<UserFavorites>>,
+	pub viewing_stats: Arc<Mutex<ViewingStats>>,
+	pub progress_sync: ProgressSync,
+	pub watch_parties: WatchParties,
+	pub scrobbler: Scrobbler,
+	pub metadata_cache: Arc<FileMetadataCache>,
+	pub cache_keys: Arc<CacheKeys>,
+	pub prewarm_jobs: PrewarmJobs,
+	pub cache_budget: Arc<CacheBudget>,
 	
 	pub media_backend_factory: Arc<MediaBackendFactory>,
 	
 	pub hls_segment_generator: ArtifactCache<HlsSegmentGenerator>,
+	pub segment_prefetcher: Arc<SegmentPrefetcher>,
 	pub thumbnail_generator: ArtifactCache<ThumbnailGenerator>,
 	pub scaled_thumbnail_generator: ArtifactCache<ScaledThumbnailGenerator>,
 	pub thumbnail_sheet_generator: ArtifactCache<ThumbnailSheetGenerator>,
@@ -35,57 +60,124 @@ pub struct ServerState {
 }
 
 impl ServerState {
+	pub fn artifact_caches(&self) -> Vec<(&'static str, &dyn ManagedCache)> {
+		let mut caches: Vec<(&'static str, &dyn ManagedCache)> = vec![
+			(cache_admin::SEGMENTS_CACHE, &self.hls_segment_generator),
+			(cache_admin::THUMBNAILS_CACHE, &self.thumbnail_generator),
+			(cache_admin::SCALED_THUMBNAILS_CACHE, &self.scaled_thumbnail_generator),
+			(cache_admin::THUMBNAIL_SHEETS_CACHE, &self.thumbnail_sheet_generator),
+			(cache_admin::SUBTITLES_CACHE, &self.transcoded_subtitle_generator),
+		];
+		
+		if let Some(auto_subtitle_generator) = &self.auto_subtitle_generator {
+			caches.push((cache_admin::AUTO_SUBTITLES_CACHE, auto_subtitle_generator));
+		}
+		
+		caches
+	}
+	
 	pub async fn init(config: ServerConfig) -> anyhow::Result<Self> {
 		let secrets_dir = config.paths.data_dir.join("secrets");
 		tokio::fs::create_dir_all(&secrets_dir).await?;
 		
-		let libraries = Libraries::from_config(config.load_libraries_config().await?);
+		let libraries_config = config.load_libraries_config().await?;
+		
+		let metadata_cache = Arc::new(FileMetadataCache::new());
+		let cache_keys = Arc::new(CacheKeys::new(&libraries_config, metadata_cache.clone()));
+		
+		let libraries = Libraries::from_config(libraries_config);
 		
 		let auth_secrets = AuthSecrets::load_from_file(&secrets_dir.join("auth-secrets.json")).await?;
-		let auth_manager = AuthManager::from_config(config.load_users_config().await?, auth_secrets);
+		let auth_manager = AuthManager::from_config(config.load_users_config().await?, auth_secrets)?
+			.with_forward_auth(config.main_config.forward_auth.clone())?;
+		
+		let oidc_client = if config.main_config.oidc.enabled {
+			Some(OidcClient::new(config.main_config.oidc.clone())?)
+		} else {
+			None
+		};
+		
+		let data_stores = DataStores::new();
+		
+		let history_migration = HistoryMigration {
+			libraries: &libraries,
+			metadata_cache: &metadata_cache,
+			finished_threshold: config.main_config.watch_history.finished_threshold,
+		};
 		
 		let user_watch_histories = UserWatchHistories::load(&auth_manager,
-			config.paths.data_dir.join("watch-histories")).await?;
+			config.paths.data_dir.join("watch-histories"), &history_migration, &data_stores).await?;
+		
+		let playlists = Playlists::load(config.paths.data_dir.join("playlists.json"), &data_stores).await?;
+		let user_favorites = UserFavorites::load(config.paths.data_dir.join("favorites.json"), &data_stores).await?;
+		let viewing_stats = ViewingStats::load(config.paths.data_dir.join("viewing-stats.json"),
+			config.main_config.viewing_stats.clone(), &data_stores).await?;
+		
+		let scrobbler = Scrobbler::from_config(&config.main_config.scrobble)?;
 		
 		let media_backend_factory = Arc::new(MediaBackendFactory::new(config.main_config.transcoding.backend)?);
 		let transcoding_task_pool = Arc::new(TaskPool::new(config.main_config.transcoding.concurrent_tasks));
 		
+		let cache_names: Vec<&str> = cache_admin::cache_locations(&config).iter().map(|location| location.name).collect();
+		let cache_budget = Arc::new(CacheBudget::from_config(&config.main_config.caches.budget, &cache_names)?);
+		
+		let segment_prefetcher = Arc::new(SegmentPrefetcher::new(&config.main_config.transcoding));
+		
 		let hls_segment_generator = hls_segment_service::init_service(
 			&config,
 			transcoding_task_pool.clone(),
 			media_backend_factory.clone(),
+			cache_keys.clone(),
+			cache_budget.clone(),
+			segment_prefetcher.clone(),
 		).await?;
 		
 		let thumbnail_generator = thumbnail_service::init_service(
 			&config,
 			transcoding_task_pool.clone(),
 			media_backend_factory.clone(),
+			cache_keys.clone(),
+			cache_budget.clone(),
 		).await?;
 
-		let scaled_thumbnail_generator = scaled_thumbnail_service::init_service(&config).await?;
+		let scaled_thumbnail_generator = scaled_thumbnail_service::init_service(&config, cache_budget.clone()).await?;
 
 		let thumbnail_sheet_generator = thumbnail_sheet_service::init_service(
 			&config,
 			transcoding_task_pool.clone(),
 			media_backend_factory.clone(),
+			cache_keys.clone(),
+			cache_budget.clone(),
 		).await?;
 
-		let transcoded_subtitle_generator = subtitle_service::init_service(&config).await?;
-		let auto_subtitle_generator = transcription_service::init_service(&config).await?;
+		let transcoded_subtitle_generator = subtitle_service::init_service(&config, cache_keys.clone(), cache_budget.clone()).await?;
+		let auto_subtitle_generator = transcription_service::init_service(&config, cache_keys.clone(), cache_budget.clone()).await?;
 
-		let metadata_cache = FileMetadataCache::new();
+		let prewarm_jobs = PrewarmJobs::from_config(&config.main_config.prewarm, &libraries)?;
 		
 		Ok(Self {
 			config,
 			
 			libraries,
 			auth_manager,
+			oidc_client,
+			data_stores,
 			user_watch_histories,
+			playlists,
+			user_favorites,
+			viewing_stats,
+			progress_sync: ProgressSync::new(),
+			watch_parties: WatchParties::new(),
+			scrobbler,
 			metadata_cache,
+			cache_keys,
+			prewarm_jobs,
+			cache_budget,
 			
 			media_backend_factory,
 			
 			hls_segment_generator,
+			segment_prefetcher,
 			thumbnail_generator,
 			scaled_thumbnail_generator,
 			thumbnail_sheet_generator,
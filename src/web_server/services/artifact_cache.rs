@@ -1,18 +1,27 @@
 use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
 use hashlink::LinkedHashMap;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fmt::Debug;
+use std::future::Future;
 use std::io;
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 use time::OffsetDateTime;
-use tracing::debug;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
 
 use crate::utils;
-use crate::web_server::services::task_pool::{ReservedTask, TaskPool};
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_storage::{self, CacheStorage, FilesystemStorage, LOCK_EXTENSION, PARTIAL_EXTENSION};
+use crate::web_server::services::task_pool::{ReservedTask, TaskPool, TaskPriority};
 
 pub trait ArtifactGenerator {
 	type Input;
@@ -20,7 +29,9 @@ pub trait ArtifactGenerator {
 	
 	async fn create_cache_key(&self, input: &Self::Input) -> anyhow::Result<String>;
 	
-	async fn generate_artifact(&self, input: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)>;
+	// The artifact can be written to the output bit by bit as it's produced, it only gets added to the cache once this
+	//  returns successfully
+	async fn generate_artifact(&self, input: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata>;
 }
 
 pub fn builder() -> ArtifactCacheBuilder {
@@ -30,7 +41,10 @@ pub fn builder() -> ArtifactCacheBuilder {
 pub struct ArtifactCacheBuilder {
 	cache_dir: Option<PathBuf>,
 	task_pool: Option<Arc<TaskPool>>,
+	storage: Option<Box<dyn CacheStorage>>,
+	budget: Option<Arc<CacheBudget>>,
 	file_size_limit: u64,
+	max_entry_age: Option<Duration>,
 }
 
 impl Default for ArtifactCacheBuilder {
@@ -38,7 +52,10 @@ impl Default for ArtifactCacheBuilder {
 		Self {
 			cache_dir: None,
 			task_pool: None,
+			storage: None,
+			budget: None,
 			file_size_limit: u64::MAX,
+			max_entry_age: None,
 		}
 	}
 }
@@ -54,76 +71,251 @@ impl ArtifactCacheBuilder {
 		self
 	}
 	
+	// Keeps the entries in the cache dir if not set
+	pub fn storage(mut self, storage: Box<dyn CacheStorage>) -> Self {
+		self.storage = Some(storage);
+		self
+	}
+	
+	// Lets the budget know whenever the cache grows
+	pub fn budget(mut self, budget: Arc<CacheBudget>) -> Self {
+		self.budget = Some(budget);
+		self
+	}
+	
 	pub fn file_size_limit(mut self, limit: u64) -> Self {
 		self.file_size_limit = limit;
 		self
 	}
 	
+	// Entries that haven't been accessed for this long get removed
+	pub fn max_entry_age(mut self, max_entry_age: Option<Duration>) -> Self {
+		self.max_entry_age = max_entry_age;
+		self
+	}
+	
 	pub async fn build<G: ArtifactGenerator>(self, generator: G) -> Result<ArtifactCache<G>, io::Error> {
+		let cache_dir = self.cache_dir.expect("No cache dir set");
+		let storage = self.storage.unwrap_or_else(|| Box::new(FilesystemStorage::new(cache_dir.clone())));
+		
+		let mut entry_tracker = EntryTracker::new(Vec::new(), self.file_size_limit);
+		entry_tracker.max_entry_age = self.max_entry_age;
+		
 		ArtifactCache::init(
 			generator,
-			self.cache_dir.expect("No cache dir set"),
+			cache_dir,
+			storage,
 			self.task_pool.expect("No task pool set"),
-			self.file_size_limit
+			entry_tracker,
+			self.budget,
 		).await
 	}
 }
 
-const ENTRY_METADATA_EXTENSION: &str = "meta.json";
-
 pub struct ArtifactCache<G: ArtifactGenerator> {
 	generator: G,
+	// Artifacts are generated here before being handed to the storage
 	cache_dir: PathBuf,
+	storage: Box<dyn CacheStorage>,
 	locks: LockPool<HeldCacheEntryInner>,
 	entry_tracker: Mutex<EntryTracker<G::Metadata>>,
 	task_pool: Arc<TaskPool>,
+	budget: Option<Arc<CacheBudget>>,
 }
 
 impl<G: ArtifactGenerator> ArtifactCache<G> {
-	async fn init(generator: G, cache_dir: PathBuf, task_pool: Arc<TaskPool>, file_size_limit: u64) -> Result<Self, io::Error> {
+	async fn init(
+		generator: G,
+		cache_dir: PathBuf,
+		storage: Box<dyn CacheStorage>,
+		task_pool: Arc<TaskPool>,
+		entry_tracker: EntryTracker<G::Metadata>,
+		budget: Option<Arc<CacheBudget>>,
+	) -> Result<Self, io::Error> {
 		tokio::fs::create_dir_all(&cache_dir).await?;
 		
 		let mut read_dir = tokio::fs::read_dir(&cache_dir).await?;
-		let mut entries = Vec::new();
 		
+		// Left behind by processes that were interrupted. Entries that another process is working on are locked.
 		while let Some(dir_entry) = read_dir.next_entry().await? {
 			let path = dir_entry.path();
 			
 			let Some(cache_key) = path.file_name()
 				.and_then(OsStr::to_str)
-				.and_then(|name| name.strip_suffix(".meta.json"))
+				.and_then(cache_storage::temporary_file_cache_key)
 			else { continue };
 			
-			let cache_entry_path = cache_dir.join(cache_key);
+			if let Ok(Some(_file_lock)) = EntryFileLock::try_lock(&lock_path(&cache_dir, cache_key)).await {
+				let _ = tokio::fs::remove_file(&path).await;
+			}
+		}
+		
+		let cache = Self {
+			generator,
+			cache_dir,
+			storage,
+			locks: LockPool::new(),
+			entry_tracker: Mutex::new(entry_tracker),
+			task_pool,
+			budget,
+		};
+		
+		let scan_stats = cache.scan().await?;
+		
+		if scan_stats.removed_entries > 0 {
+			info!("Removed {} invalid entries from {:?}", scan_stats.removed_entries, &cache.cache_dir);
+		}
+		
+		Ok(cache)
+	}
+	
+	// Reconciles the stored metadata with the stored data, removing entries that are missing either one, and tracking
+	//  entries that were added or removed behind the cache's back. Entries that are in use are left alone.
+	pub async fn scan(&self) -> Result<ScanStats, io::Error> {
+		let mut stats = ScanStats::default();
+		let mut seen_keys = HashSet::new();
+		let mut new_entries = Vec::new();
+		
+		for stored_entry in self.storage.list_entries().await? {
+			let cache_key = stored_entry.cache_key.as_str();
+			seen_keys.insert(stored_entry.cache_key.clone());
+			
+			// Shared entries that this server has no copy of are only looked at once they're used
+			if !stored_entry.is_local && stored_entry.has_data && stored_entry.has_metadata {
+				stats.valid_entries += 1;
+				continue;
+			}
 			
-			let entry_metadata = tokio::fs::read(&path).await.ok()
-				.and_then(|data| serde_json::from_slice::<CacheEntryMetadata<G::Metadata>>(&data).ok());
+			let Some(_held_entry) = self.try_lock_key(cache_key).await else {
+				stats.valid_entries += 1;
+				continue;
+			};
 			
-			let mut valid = false;
+			let entry_metadata = match stored_entry.has_metadata && stored_entry.is_local {
+				true => self.read_stored_metadata(cache_key).await,
+				false => None,
+			};
 			
-			if let Some(entry_metadata) = entry_metadata {
-				if tokio::fs::try_exists(&cache_entry_path).await.unwrap_or(false) {
-					entries.push(entry_metadata);
+			match entry_metadata {
+				Some(entry_metadata) if stored_entry.has_data => {
+					new_entries.push(entry_metadata);
+					stats.valid_entries += 1;
+				}
+				_ => {
+					// If the metadata is invalid then remove the cache entry
+					// Otherwise it'll go untracked forever
+					let _ = self.storage.remove(cache_key).await;
 					
-					valid = true;
+					self.entry_tracker.lock().unwrap().remove_entry(cache_key);
+					stats.removed_entries += 1;
 				}
 			}
+		}
+		
+		// Entries whose files were deleted from outside
+		let missing_keys: Vec<String> = self.entry_tracker.lock().unwrap()
+			.entries.keys()
+			.filter(|key| !seen_keys.contains(*key))
+			.cloned()
+			.collect();
+		
+		for cache_key in missing_keys {
+			let Some(_held_entry) = self.try_lock_key(&cache_key).await else { continue };
 			
-			if !valid {
-				// If the metadata is invalid then remove the cache entry
-				// Otherwise it'll go untracked forever
-				let _ = tokio::fs::remove_file(&path).await;
-				let _ = tokio::fs::remove_file(&cache_entry_path).await;
+			// Could have been added since the entries were listed
+			if self.storage.contains(&cache_key).await.unwrap_or(false) {
+				continue;
 			}
+			
+			self.entry_tracker.lock().unwrap().remove_entry(&cache_key);
+			stats.removed_entries += 1;
 		}
 		
-		Ok(Self {
-			generator,
-			cache_dir,
-			locks: LockPool::new(),
-			entry_tracker: Mutex::new(EntryTracker::new(entries, file_size_limit)),
-			task_pool,
-		})
+		self.entry_tracker.lock().unwrap().add_untracked(new_entries);
+		
+		Ok(stats)
+	}
+	
+	// Removes every entry whose cache key matches, waiting for any that are in use
+	pub async fn purge(&self, filter: impl Fn(&str) -> bool) -> Result<PurgeStats, io::Error> {
+		let mut stats = PurgeStats::default();
+		
+		let mut cache_keys: BTreeSet<String> = self.entry_tracker.lock().unwrap()
+			.entries.keys()
+			.filter(|key| filter(key))
+			.cloned()
+			.collect();
+		
+		// Shared entries that this server never used aren't tracked
+		cache_keys.extend(self.storage.list_entries().await?.into_iter()
+			.map(|stored_entry| stored_entry.cache_key)
+			.filter(|cache_key| filter(cache_key)));
+		
+		for cache_key in cache_keys {
+			let _held_entry = self.lock_key_exclusive(&cache_key).await?;
+			
+			let removed_entry = self.entry_tracker.lock().unwrap().remove_entry(&cache_key);
+			
+			stats.removed_entries += 1;
+			
+			if let Some(removed_entry) = removed_entry {
+				stats.removed_bytes += removed_entry.entry_size;
+			}
+			
+			self.storage.remove(&cache_key).await?;
+		}
+		
+		Ok(stats)
+	}
+	
+	// Removes the entries that aren't in use, entries that are get left alone
+	pub async fn evict(&self, cache_keys: Vec<String>) -> PurgeStats {
+		let mut stats = PurgeStats::default();
+		
+		for cache_key in cache_keys {
+			let Some(_held_entry) = self.try_lock_key(&cache_key).await else { continue };
+			
+			debug!("Evicting cache entry {} from {} cache", cache_key, std::any::type_name::<G>());
+			
+			let removed_entry = self.entry_tracker.lock().unwrap().remove_entry(&cache_key);
+			
+			if let Some(removed_entry) = removed_entry {
+				stats.removed_entries += 1;
+				stats.removed_bytes += removed_entry.entry_size;
+			}
+			
+			let _ = self.storage.evict(&cache_key).await;
+		}
+		
+		stats
+	}
+	
+	pub async fn expire(&self) -> PurgeStats {
+		let expired = self.entry_tracker.lock().unwrap().expired_entries(OffsetDateTime::now_utc());
+		
+		self.evict(expired).await
+	}
+	
+	// From least to most recently used
+	pub fn entry_usages(&self) -> Vec<EntryUsage> {
+		self.entry_tracker.lock().unwrap()
+			.entries.values()
+			.map(|entry| EntryUsage {
+				cache_key: entry.cache_key.clone(),
+				size: entry.entry_size,
+				last_accessed: entry.last_accessed,
+			})
+			.collect()
+	}
+	
+	pub fn stats(&self) -> CacheStats {
+		let entry_tracker = self.entry_tracker.lock().unwrap();
+		
+		CacheStats {
+			size: entry_tracker.total_size,
+			entry_count: entry_tracker.entries.len(),
+			size_limit: entry_tracker.size_limit,
+		}
 	}
 	
 	pub async fn get(&self, input: &G::Input) -> anyhow::Result<Option<CacheQuery<G::Metadata>>> {
@@ -132,44 +324,98 @@ impl<G: ArtifactGenerator> ArtifactCache<G> {
 		self.get_inner(&held_entry).await
 	}
 	
-	pub async fn get_or_reserve(&self, input: G::Input) -> anyhow::Result<QueryResult<'_, G>> {
-		let held_entry = self.lock_entry(&input).await?;
+	// The entry isn't held while waiting for a task slot, otherwise a low priority request stuck in the queue would
+	//  also hold up higher priority requests for the same entry
+	pub async fn get_or_reserve(&self, input: G::Input, priority: TaskPriority) -> anyhow::Result<QueryResult<'_, G>> {
+		let cache_key = self.generator.create_cache_key(&input).await?;
 		
-		match self.get_inner(&held_entry).await? {
-			Some(cache_query) => Ok(QueryResult::Valid(cache_query)),
-			None => {
-				let task_reservation = self.task_pool.reserve().await;
+		loop {
+			{
+				let held_entry = self.lock_key(&cache_key).await;
+				
+				if let Some(cache_query) = self.get_inner(&held_entry).await? {
+					return Ok(QueryResult::Valid(cache_query));
+				}
+			}
+			
+			let task_reservation = self.task_pool.reserve(priority).await;
+			
+			// Someone else is generating it, wait for them without taking up the slot
+			let Some(held_entry) = self.try_lock_key(&cache_key).await else {
+				drop(task_reservation);
+				drop(self.lock_key_exclusive(&cache_key).await?);
 				
-				Ok(QueryResult::Invalid(PendingGeneration {
-					cache: self,
-					input,
-					held_entry,
-					task_reservation,
-				}))
+				continue;
+			};
+			
+			// It might have been generated while waiting for the slot, which leaves a local copy. Shared storages were
+			//  already asked for it above, so they aren't asked again.
+			if self.storage.contains(&cache_key).await? && let Some(cache_query) = self.get_inner(&held_entry).await? {
+				return Ok(QueryResult::Valid(cache_query));
 			}
+			
+			return Ok(QueryResult::Invalid(PendingGeneration {
+				cache: self,
+				input,
+				held_entry,
+				task_reservation,
+			}));
 		}
 	}
 	
 	pub async fn get_or_generate(&self, input: G::Input) -> anyhow::Result<CacheQuery<G::Metadata>> {
-		self.get_or_reserve(input)
+		self.get_or_generate_with_priority(input, TaskPriority::Interactive).await
+	}
+	
+	pub async fn get_or_generate_with_priority(&self, input: G::Input, priority: TaskPriority) -> anyhow::Result<CacheQuery<G::Metadata>> {
+		self.get_or_reserve(input, priority)
 			.await?
 			.unwrap_or_generate()
 			.await
 	}
 	
 	async fn get_inner(&self, held_entry: &HeldCacheEntry) -> anyhow::Result<Option<CacheQuery<G::Metadata>>> {
-		if tokio::fs::metadata(&held_entry.cache_file_path).await.is_ok() {
-			let entry_metadata = self.entry_tracker.lock().unwrap()
+		// Temporary files in the cache dir are only written while holding the file lock, since other processes that
+		//  open the cache remove the ones of entries that aren't locked. Shared storages download a missing local copy.
+		let mut file_lock = None;
+		
+		if held_entry.file_lock.is_none() && !self.storage.contains(&held_entry.cache_key).await? {
+			file_lock = Some(EntryFileLock::lock(&held_entry.lock_path).await?);
+		}
+		
+		// The file is opened while the entry is still locked, so that it stays readable even if it gets evicted while
+		//  it's being sent
+		if let Ok(Some(file)) = self.storage.open_data(&held_entry.cache_key).await {
+			let mut entry_metadata = self.entry_tracker.lock().unwrap()
 				.get_entry(&held_entry.cache_key);
 			
+			// Generated by another process that uses the same cache
+			if entry_metadata.is_none() && let Some(mut stored_metadata) = self.read_stored_metadata(&held_entry.cache_key).await {
+				stored_metadata.last_accessed = OffsetDateTime::now_utc();
+				
+				let to_evict = self.entry_tracker.lock().unwrap().insert(stored_metadata.clone());
+				self.finish_adding_entry(to_evict).await;
+				
+				entry_metadata = Some(stored_metadata);
+			}
+			
 			if let Some(entry_metadata) = entry_metadata {
-				// Looking up the entry updates the last accessed time, so write that change back to the disk.
-				tokio::fs::write(&held_entry.cache_metadata_path, serde_json::to_vec_pretty(&entry_metadata).unwrap()).await?;
+				// Looking up the entry updates the last accessed time, so write that change back to the storage. It's only
+				//  used for eviction, so it's skipped if another process is working on the entry.
+				if held_entry.file_lock.is_none() && file_lock.is_none() {
+					file_lock = EntryFileLock::try_lock(&held_entry.lock_path).await.ok().flatten();
+				}
 				
-				let entry_data = tokio::fs::read(&held_entry.cache_file_path).await?;
+				if held_entry.file_lock.is_some() || file_lock.is_some() {
+					let data = serde_json::to_vec_pretty(&entry_metadata).unwrap();
+					
+					if let Err(err) = self.storage.update_metadata(&held_entry.cache_key, data).await {
+						warn!("Error updating the last accessed time of cache entry {}: {:?}", held_entry.cache_key, err);
+					}
+				}
 				
 				let cache_query = CacheQuery {
-					entry_data: Bytes::from(entry_data),
+					entry_file: CachedFile::new(file, &entry_metadata).await?,
 					creation_date: entry_metadata.creation_date,
 					metadata: entry_metadata.extra_metadata,
 				};
@@ -181,21 +427,64 @@ impl<G: ArtifactGenerator> ArtifactCache<G> {
 		Ok(None)
 	}
 	
+	async fn read_stored_metadata(&self, cache_key: &str) -> Option<CacheEntryMetadata<G::Metadata>> {
+		let data = self.storage.read_metadata(cache_key).await.ok().flatten()?;
+		
+		serde_json::from_slice(&data).ok()
+	}
+	
+	// Evicts whatever doesn't fit anymore after an entry was added
+	async fn finish_adding_entry(&self, to_evict: Vec<String>) {
+		self.evict(to_evict).await;
+		
+		if let Some(budget) = &self.budget {
+			budget.note_growth();
+		}
+	}
+	
 	async fn lock_entry(&self, input: &G::Input) -> anyhow::Result<HeldCacheEntry> {
 		let cache_key = self.generator.create_cache_key(&input).await?;
 		
-		Ok(self.get_entry_lock(&cache_key).lock_owned().await)
+		Ok(self.lock_key(&cache_key).await)
+	}
+	
+	// Only waits for other tasks in this process, which is enough for reading the entry since other processes only
+	//  ever rename finished files into place. Reading takes the file lock itself for the files it writes.
+	async fn lock_key(&self, cache_key: &str) -> HeldCacheEntry {
+		HeldCacheEntry {
+			guard: self.get_entry_lock(cache_key).lock_owned().await,
+			file_lock: None,
+		}
+	}
+	
+	// For changing the entry, waits for other tasks in this process first, and then for other processes
+	async fn lock_key_exclusive(&self, cache_key: &str) -> io::Result<HeldCacheEntry> {
+		let guard = self.get_entry_lock(cache_key).lock_owned().await;
+		let file_lock = EntryFileLock::lock(&guard.lock_path).await?;
+		
+		Ok(HeldCacheEntry {
+			guard,
+			file_lock: Some(file_lock),
+		})
+	}
+	
+	// None if the entry is in use, here or in another process
+	async fn try_lock_key(&self, cache_key: &str) -> Option<HeldCacheEntry> {
+		let guard = self.get_entry_lock(cache_key).try_lock_owned().ok()?;
+		let file_lock = EntryFileLock::try_lock(&guard.lock_path).await.ok()??;
+		
+		Some(HeldCacheEntry {
+			guard,
+			file_lock: Some(file_lock),
+		})
 	}
 	
 	fn get_entry_lock(&self, cache_key: &str) -> Arc<tokio::sync::Mutex<HeldCacheEntryInner>> {
 		self.locks.get_lock(&cache_key, || {
-			let cache_file_path = self.cache_dir.join(&cache_key);
-			let cache_metadata_path = utils::add_extension(&cache_file_path, ENTRY_METADATA_EXTENSION);
-			
 			HeldCacheEntryInner {
 				cache_key: cache_key.to_owned(),
-				cache_file_path,
-				cache_metadata_path,
+				partial_path: utils::add_extension(&self.cache_dir.join(&cache_key), PARTIAL_EXTENSION),
+				lock_path: lock_path(&self.cache_dir, cache_key),
 			}
 		})
 	}
@@ -205,6 +494,77 @@ impl<G: ArtifactGenerator> ArtifactCache<G> {
 	}
 }
 
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CacheStats {
+	pub size: u64,
+	pub entry_count: usize,
+	pub size_limit: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PurgeStats {
+	pub removed_entries: usize,
+	pub removed_bytes: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EntryUsage {
+	pub cache_key: String,
+	pub size: u64,
+	pub last_accessed: OffsetDateTime,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ScanStats {
+	pub valid_entries: usize,
+	pub removed_entries: usize,
+}
+
+// Lets caches with different generators be administered together
+pub trait ManagedCache: Send + Sync {
+	fn stats(&self) -> CacheStats;
+	
+	fn purge<'a>(&'a self, filter: &'a (dyn Fn(&str) -> bool + Sync)) -> BoxFuture<'a, Result<PurgeStats, io::Error>>;
+	
+	fn scan(&self) -> BoxFuture<'_, Result<ScanStats, io::Error>>;
+	
+	fn entry_usages(&self) -> Vec<EntryUsage>;
+	
+	fn evict(&self, cache_keys: Vec<String>) -> BoxFuture<'_, PurgeStats>;
+	
+	fn expire(&self) -> BoxFuture<'_, PurgeStats>;
+}
+
+impl<G> ManagedCache for ArtifactCache<G>
+where
+	G: ArtifactGenerator + Send + Sync,
+	G::Metadata: Send + Sync,
+{
+	fn stats(&self) -> CacheStats {
+		ArtifactCache::stats(self)
+	}
+	
+	fn purge<'a>(&'a self, filter: &'a (dyn Fn(&str) -> bool + Sync)) -> BoxFuture<'a, Result<PurgeStats, io::Error>> {
+		ArtifactCache::purge(self, filter).boxed()
+	}
+	
+	fn scan(&self) -> BoxFuture<'_, Result<ScanStats, io::Error>> {
+		ArtifactCache::scan(self).boxed()
+	}
+	
+	fn entry_usages(&self) -> Vec<EntryUsage> {
+		ArtifactCache::entry_usages(self)
+	}
+	
+	fn evict(&self, cache_keys: Vec<String>) -> BoxFuture<'_, PurgeStats> {
+		ArtifactCache::evict(self, cache_keys).boxed()
+	}
+	
+	fn expire(&self) -> BoxFuture<'_, PurgeStats> {
+		ArtifactCache::expire(self).boxed()
+	}
+}
+
 pub struct PendingGeneration<'a, G: ArtifactGenerator> {
 	cache: &'a ArtifactCache<G>,
 	input: G::Input,
@@ -213,9 +573,26 @@ pub struct PendingGeneration<'a, G: ArtifactGenerator> {
 }
 
 impl<'a, G: ArtifactGenerator> PendingGeneration<'a, G> {
+	// Dropping the returned future before it finishes cancels the generation
 	pub async fn generate(self) -> anyhow::Result<CacheQuery<G::Metadata>> {
-		let (artifact_data, metadata) =
-			self.task_reservation.execute_task(self.cache.generator.generate_artifact(self.input)).await?;
+		let partial_path = self.held_entry.partial_path.clone();
+		
+		let mut cancel_guard = CancelOnDrop {
+			cancelled: Arc::new(AtomicBool::new(false)),
+			partial_path: Some(partial_path.clone()),
+		};
+		
+		let mut output = ArtifactOutput {
+			file: tokio::fs::File::create(&partial_path).await?,
+			cancelled: cancel_guard.cancelled.clone(),
+			task_reservation: self.task_reservation,
+		};
+		
+		let metadata = self.cache.generator.generate_artifact(self.input, &mut output).await?;
+		
+		output.file.flush().await?;
+		let entry_size = output.file.metadata().await?.len();
+		drop(output);
 		
 		let now = OffsetDateTime::now_utc();
 		
@@ -223,48 +600,128 @@ impl<'a, G: ArtifactGenerator> PendingGeneration<'a, G> {
 			cache_key: self.held_entry.cache_key.clone(),
 			creation_date: now,
 			last_accessed: now,
-			entry_size: artifact_data.len() as u64,
+			entry_size,
 			extra_metadata: metadata,
 		};
 		
-		tokio::fs::write(&self.held_entry.cache_file_path, &artifact_data).await?;
-		tokio::fs::write(&self.held_entry.cache_metadata_path, serde_json::to_vec_pretty(&entry_metadata).unwrap()).await?;
+		// The old metadata goes first, so that if this gets interrupted the data is never paired with the wrong metadata
+		self.cache.storage.remove(&self.held_entry.cache_key).await?;
+		
+		let entry_file = self.cache.storage.store_entry(&self.held_entry.cache_key, &partial_path,
+			serde_json::to_vec_pretty(&entry_metadata).unwrap()).await?;
+		cancel_guard.partial_path = None;
+		
+		let entry_file = CachedFile::new(entry_file, &entry_metadata).await?;
 		
 		let to_evict = self.cache.entry_tracker.lock().unwrap()
 			.insert(entry_metadata.clone());
 		
 		drop(self.held_entry);
 		
-		for evicted_cache_key in to_evict {
-			let evicted_entry_lock = self.cache.get_entry_lock(&evicted_cache_key);
-			
-			// Try to evict the entry if it's not being used
-			if let Ok(held_entry) = evicted_entry_lock.try_lock() {
-				debug!("Evicting cache entry {} from {} cache", evicted_cache_key, std::any::type_name::<G>());
-				
-				self.cache.entry_tracker.lock().unwrap()
-					.remove_entry(&evicted_cache_key);
-				
-				let _ = tokio::fs::remove_file(&held_entry.cache_file_path).await;
-				let _ = tokio::fs::remove_file(&held_entry.cache_metadata_path).await;
-			}
-		}
+		self.cache.finish_adding_entry(to_evict).await;
 		
 		Ok(CacheQuery {
-			entry_data: artifact_data,
+			entry_file,
 			creation_date: now,
 			metadata: entry_metadata.extra_metadata,
 		})
 	}
 }
 
-#[derive(Debug, Eq, PartialEq)]
+pub struct ArtifactOutput {
+	file: tokio::fs::File,
+	cancelled: Arc<AtomicBool>,
+	// Held for as long as the generator runs
+	task_reservation: ReservedTask,
+}
+
+impl ArtifactOutput {
+	// Set once nobody is waiting for the artifact anymore, long running generators should check it and give up
+	pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+		self.cancelled.clone()
+	}
+	
+	// Gives the task slot to someone else while waiting for something that doesn't need it
+	pub async fn without_task_slot<T>(&mut self, future: impl Future<Output = T>) -> T {
+		self.task_reservation.yield_while(future).await
+	}
+	
+	pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+		self.file.write_all(data).await
+	}
+	
+	// Throws away what has been written so far, for generators that start over after a failure
+	pub async fn reset(&mut self) -> io::Result<()> {
+		self.file.set_len(0).await?;
+		self.file.rewind().await?;
+		
+		Ok(())
+	}
+	
+	// For generators that produce their output on a blocking thread
+	pub async fn blocking_writer(&mut self) -> io::Result<std::fs::File> {
+		self.file.flush().await?;
+		
+		Ok(self.file.try_clone().await?.into_std().await)
+	}
+}
+
+// Signals the generator to stop and cleans up its output if generation doesn't finish
+struct CancelOnDrop {
+	cancelled: Arc<AtomicBool>,
+	partial_path: Option<PathBuf>,
+}
+
+impl Drop for CancelOnDrop {
+	fn drop(&mut self) {
+		if let Some(partial_path) = &self.partial_path {
+			self.cancelled.store(true, Ordering::Relaxed);
+			
+			let _ = std::fs::remove_file(partial_path);
+		}
+	}
+}
+
+#[derive(Debug)]
 pub struct CacheQuery<M = ()> {
-	pub entry_data: Bytes,
+	pub entry_file: CachedFile,
 	pub creation_date: OffsetDateTime,
 	pub metadata: M,
 }
 
+// An open cache entry, which is streamed from disk rather than read into memory
+#[derive(Debug)]
+pub struct CachedFile {
+	pub file: tokio::fs::File,
+	pub size: u64,
+	// Cache keys are derived from the input, so a new entry for the same key gets a new creation date
+	pub etag: String,
+}
+
+impl CachedFile {
+	async fn new<M>(file: tokio::fs::File, entry_metadata: &CacheEntryMetadata<M>) -> io::Result<Self> {
+		let size = file.metadata().await?.len();
+		
+		let mut hasher = blake3::Hasher::new();
+		hasher.update(entry_metadata.cache_key.as_bytes());
+		hasher.update(&entry_metadata.creation_date.unix_timestamp_nanos().to_le_bytes());
+		
+		Ok(Self {
+			file,
+			size,
+			etag: format!("\"{}\"", &hasher.finalize().to_hex()[..32]),
+		})
+	}
+	
+	// For the small artifacts that get processed further instead of being sent as is
+	pub async fn read_all(mut self) -> io::Result<Bytes> {
+		let mut data = Vec::with_capacity(self.size as usize);
+		self.file.read_to_end(&mut data).await?;
+		
+		Ok(data.into())
+	}
+}
+
 pub enum QueryResult<'a, G: ArtifactGenerator> {
 	Valid(CacheQuery<G::Metadata>),
 	Invalid(PendingGeneration<'a, G>),
@@ -279,13 +736,108 @@ impl<'a, G: ArtifactGenerator> QueryResult<'a, G> {
 	}
 }
 
-type HeldCacheEntry = tokio::sync::OwnedMutexGuard<HeldCacheEntryInner>;
+// The lock within this process, and the one shared with other processes if the entry is being changed
+struct HeldCacheEntry {
+	guard: tokio::sync::OwnedMutexGuard<HeldCacheEntryInner>,
+	file_lock: Option<EntryFileLock>,
+}
+
+impl Deref for HeldCacheEntry {
+	type Target = HeldCacheEntryInner;
+	
+	fn deref(&self) -> &Self::Target {
+		&self.guard
+	}
+}
 
 #[derive(Debug, Eq, PartialEq)]
 struct HeldCacheEntryInner {
 	cache_key: String,
-	cache_file_path: PathBuf,
-	cache_metadata_path: PathBuf,
+	// Where the artifact is written while it's being generated
+	partial_path: PathBuf,
+	lock_path: PathBuf,
+}
+
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+fn lock_path(cache_dir: &Path, cache_key: &str) -> PathBuf {
+	utils::add_extension(&cache_dir.join(cache_key), LOCK_EXTENSION)
+}
+
+// An advisory lock on a file next to the entry, so that processes sharing a cache dir don't generate or remove the same
+//  entry at the same time. The file is removed again when the lock is released.
+struct EntryFileLock {
+	file: std::fs::File,
+	path: PathBuf,
+}
+
+impl EntryFileLock {
+	async fn lock(path: &Path) -> io::Result<Self> {
+		loop {
+			if let Some(file_lock) = Self::try_lock(path).await? {
+				return Ok(file_lock);
+			}
+			
+			tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+		}
+	}
+	
+	async fn try_lock(path: &Path) -> io::Result<Option<Self>> {
+		let path = path.to_owned();
+		
+		tokio::task::spawn_blocking(move || Self::try_lock_blocking(path)).await?
+	}
+	
+	fn try_lock_blocking(path: PathBuf) -> io::Result<Option<Self>> {
+		let file = std::fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(&path)?;
+		
+		match file.try_lock() {
+			Ok(()) => {}
+			Err(std::fs::TryLockError::WouldBlock) => return Ok(None),
+			Err(std::fs::TryLockError::Error(err)) => return Err(err),
+		}
+		
+		// The previous holder removed the file while this was waiting for it, so whoever opens it next gets a new one
+		if !is_same_file(&file, &path) {
+			return Ok(None);
+		}
+		
+		Ok(Some(Self {
+			file,
+			path,
+		}))
+	}
+}
+
+impl Drop for EntryFileLock {
+	fn drop(&mut self) {
+		// Removed while it's still locked, see is_same_file
+		let _ = std::fs::remove_file(&self.path);
+		let _ = self.file.unlock();
+	}
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &std::fs::File, path: &Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	
+	match (file.metadata(), std::fs::metadata(path)) {
+		(Ok(file_metadata), Ok(path_metadata)) => {
+			file_metadata.dev() == path_metadata.dev() && file_metadata.ino() == path_metadata.ino()
+		}
+		_ => false,
+	}
+}
+
+// Open files can't be removed, so the lock files are just left behind
+#[cfg(not(unix))]
+fn is_same_file(_file: &std::fs::File, _path: &Path) -> bool {
+	true
 }
 
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -332,6 +884,7 @@ struct EntryTracker<M> {
 	entries: LinkedHashMap<String, CacheEntryMetadata<M>>,
 	total_size: u64,
 	size_limit: u64,
+	max_entry_age: Option<Duration>,
 }
 
 impl<M: Clone> EntryTracker<M> {
@@ -352,6 +905,7 @@ impl<M: Clone> EntryTracker<M> {
 			entries,
 			total_size,
 			size_limit,
+			max_entry_age: None,
 		}
 	}
 	
@@ -371,6 +925,39 @@ impl<M: Clone> EntryTracker<M> {
 		Some(removed)
 	}
 	
+	// Adds entries that were found on disk, keeping everything ordered by when it was last accessed
+	pub fn add_untracked(&mut self, new_entries: Vec<CacheEntryMetadata<M>>) {
+		let new_entries: Vec<_> = new_entries.into_iter()
+			.filter(|entry| !self.entries.contains_key(&entry.cache_key))
+			.collect();
+		
+		if new_entries.is_empty() {
+			return;
+		}
+		
+		let mut all_entries: Vec<_> = std::mem::take(&mut self.entries).into_iter()
+			.map(|(_, entry)| entry)
+			.collect();
+		
+		all_entries.extend(new_entries);
+		
+		*self = Self {
+			max_entry_age: self.max_entry_age,
+			..Self::new(all_entries, self.size_limit)
+		};
+	}
+	
+	pub fn expired_entries(&self, now: OffsetDateTime) -> Vec<String> {
+		self.entries.values()
+			.take_while(|entry| self.is_expired(entry, now))
+			.map(|entry| entry.cache_key.clone())
+			.collect()
+	}
+	
+	fn is_expired(&self, entry: &CacheEntryMetadata<M>, now: OffsetDateTime) -> bool {
+		self.max_entry_age.is_some_and(|max_entry_age| entry.last_accessed + max_entry_age < now)
+	}
+	
 	pub fn insert(&mut self, new_entry: CacheEntryMetadata<M>) -> Vec<String> {
 		self.total_size += new_entry.entry_size;
 		
@@ -380,9 +967,11 @@ impl<M: Clone> EntryTracker<M> {
 		
 		let mut to_remove = Vec::new();
 		let mut future_size = self.total_size;
+		let now = OffsetDateTime::now_utc();
 		
+		// Expired entries are always the least recently used ones
 		for entry in self.entries.values() {
-			if future_size <= self.size_limit {
+			if future_size <= self.size_limit && !self.is_expired(entry, now) {
 				break;
 			}
 			
@@ -416,13 +1005,13 @@ mod tests {
 	use std::task::Poll;
 	use std::time::Duration;
 	
-	use bytes::Bytes;
 	use futures_util::poll;
 	use tempfile::TempDir;
 	use time::macros::datetime;
 	use time::OffsetDateTime;
-	use crate::web_server::services::artifact_cache::{ArtifactGenerator, CacheEntryMetadata, CacheQuery, EntryTracker, LockPool, ENTRY_METADATA_EXTENSION};
-	use crate::web_server::services::task_pool::TaskPool;
+	use crate::web_server::services::artifact_cache::{ArtifactGenerator, ArtifactOutput, CacheEntryMetadata, EntryTracker, LockPool, QueryResult};
+	use crate::web_server::services::cache_storage::ENTRY_METADATA_EXTENSION;
+	use crate::web_server::services::task_pool::{TaskPool, TaskPriority};
 	
 	#[test]
 	fn test_lru() {
@@ -491,6 +1080,44 @@ mod tests {
 		assert_eq!(lru_state.entries.iter().map(|e| e.1.entry_size).sum::<u64>(), lru_state.total_size);
 	}
 	
+	#[test]
+	fn test_entry_expiry() {
+		let now = OffsetDateTime::now_utc();
+		let hour = Duration::from_secs(60 * 60);
+		
+		let entry = |key: &str, last_accessed: OffsetDateTime| CacheEntryMetadata {
+			cache_key: key.to_owned(),
+			entry_size: 10,
+			creation_date: last_accessed,
+			last_accessed,
+			extra_metadata: (),
+		};
+		
+		let mut lru_state = EntryTracker::new(vec![
+			entry("key1", now - hour * 3),
+			entry("key2", now - hour * 2),
+			entry("key3", now - Duration::from_secs(60)),
+		], u64::MAX);
+		
+		assert!(lru_state.expired_entries(now).is_empty());
+		
+		lru_state.max_entry_age = Some(hour + hour / 2);
+		assert_eq!(lru_state.expired_entries(now), &["key1", "key2"]);
+		
+		// Expired entries are evicted along with anything over the size limit
+		assert_eq!(lru_state.insert(entry("key4", now)), &["key1", "key2"]);
+		
+		lru_state.remove_entry("key1");
+		lru_state.remove_entry("key2");
+		
+		// Entries found later expire too
+		lru_state.add_untracked(vec![entry("key5", now - hour * 4)]);
+		
+		assert_eq!(lru_state.max_entry_age, Some(hour + hour / 2));
+		assert_eq!(lru_state.expired_entries(now), &["key5"]);
+		assert!(lru_state.expired_entries(now - hour * 3).is_empty());
+	}
+	
 	#[tokio::test]
 	async fn test_lock_pool() {
 		let mut lock_pool: LockPool<u32> = LockPool::new();
@@ -542,11 +1169,23 @@ mod tests {
 			Ok(format!("key{}", *input))
 		}
 
-		async fn generate_artifact(&self, input: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)> {
-			let data = format!("stuff{}", input);
-			let metadata = format!("meta{}", input);
+		async fn generate_artifact(&self, input: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
+			// Never finishes, so that it can be cancelled
+			if input == 100 {
+				output.write_all(b"partial").await?;
+				std::future::pending::<()>().await;
+			}
 			
-			Ok((data.into(), metadata))
+			// Fails without leaving anything behind
+			if input == 13 {
+				output.write_all(b"partial").await?;
+				anyhow::bail!("Unlucky");
+			}
+			
+			output.write_all(b"stuff").await?;
+			output.write_all(input.to_string().as_bytes()).await?;
+			
+			Ok(format!("meta{}", input))
 		}
 	}
 	
@@ -588,13 +1227,14 @@ mod tests {
 			assert_eq!(lru_state.size_limit, u64::MAX);
 		}
 		
-		assert_eq!(artifact_cache.get(&99).await.unwrap(), None);
+		assert!(artifact_cache.get(&99).await.unwrap().is_none());
 		
-		assert_eq!(artifact_cache.get(&1).await.unwrap(), Some(CacheQuery {
-			entry_data: "stuff1".into(),
-			creation_date: datetime!(2020-01-01 00:00:00 UTC) + Duration::from_secs(1),
-			metadata: "meta1".into(),
-		}));
+		let query = artifact_cache.get(&1).await.unwrap().unwrap();
+		
+		assert_eq!(query.creation_date, datetime!(2020-01-01 00:00:00 UTC) + Duration::from_secs(1));
+		assert_eq!(query.metadata, "meta1");
+		assert_eq!(query.entry_file.size, 6);
+		assert_eq!(query.entry_file.read_all().await.unwrap(), "stuff1");
 		
 		{
 			let lru_state = artifact_cache.entry_tracker.get_mut().unwrap();
@@ -606,10 +1246,12 @@ mod tests {
 		let now = OffsetDateTime::now_utc();
 		let query = artifact_cache.get_or_generate(44).await.unwrap();
 		
-		assert_eq!(query.entry_data, "stuff44");
 		assert_eq!(query.metadata, "meta44");
 		assert!(query.creation_date > now);
 		
+		let etag = query.entry_file.etag.clone();
+		assert_eq!(query.entry_file.read_all().await.unwrap(), "stuff44");
+		
 		{
 			let lru_state = artifact_cache.entry_tracker.get_mut().unwrap();
 			assert_eq!(lru_state.entries.iter().map(|e| e.0.as_str()).collect::<Vec<&str>>(), &["key2", "key3", "key1", "key44"]);
@@ -619,17 +1261,27 @@ mod tests {
 		
 		assert_eq!(tokio::fs::read(temp_dir.path().join("key44")).await.unwrap(), "stuff44".as_bytes());
 		
+		assert!(artifact_cache.get_or_generate(13).await.is_err());
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key13")).await.unwrap());
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key13.partial")).await.unwrap());
+		assert_eq!(artifact_cache.entry_tracker.get_mut().unwrap().total_size, 25);
+		
+		tokio::fs::write(temp_dir.path().join("key7.partial"), "interrupted").await.unwrap();
+		
 		let mut artifact_cache = super::builder()
 			.cache_dir(temp_dir.path().to_owned())
 			.task_pool(task_pool.clone())
 			.build(TestGenerator)
 			.await.unwrap();
 		
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key7.partial")).await.unwrap());
+		
 		let query = artifact_cache.get(&44).await.unwrap().unwrap();
 		
-		assert_eq!(query.entry_data, "stuff44");
 		assert_eq!(query.metadata, "meta44");
 		assert!(query.creation_date > now);
+		assert_eq!(query.entry_file.etag, etag);
+		assert_eq!(query.entry_file.read_all().await.unwrap(), "stuff44");
 		
 		{
 			let lru_state = artifact_cache.entry_tracker.get_mut().unwrap();
@@ -648,9 +1300,9 @@ mod tests {
 		let now = OffsetDateTime::now_utc();
 		let query = artifact_cache.get_or_generate(5).await.unwrap();
 		
-		assert_eq!(query.entry_data, "stuff5");
 		assert_eq!(query.metadata, "meta5");
 		assert!(query.creation_date > now);
+		assert_eq!(query.entry_file.read_all().await.unwrap(), "stuff5");
 		
 		{
 			let lru_state = artifact_cache.entry_tracker.get_mut().unwrap();
@@ -663,5 +1315,208 @@ mod tests {
 		assert!(!tokio::fs::try_exists(temp_dir.path().join("key2").with_extension(ENTRY_METADATA_EXTENSION)).await.unwrap());
 		assert!(!tokio::fs::try_exists(temp_dir.path().join("key3")).await.unwrap());
 		assert!(!tokio::fs::try_exists(temp_dir.path().join("key3").with_extension(ENTRY_METADATA_EXTENSION)).await.unwrap());
+		
+		let QueryResult::Invalid(pending) = artifact_cache.get_or_reserve(6, TaskPriority::Interactive).await.unwrap() else {
+			panic!("key6 shouldn't exist yet");
+		};
+		
+		// Left behind by a process that crashed
+		tokio::fs::write(temp_dir.path().join("key9.meta.json.partial"), "{").await.unwrap();
+		tokio::fs::write(temp_dir.path().join("key9.lock"), "").await.unwrap();
+		
+		// Belongs to the generation that's in progress
+		tokio::fs::write(temp_dir.path().join("key6.partial"), "stuff").await.unwrap();
+		
+		// Another process that uses the same cache dir
+		let other_cache = Arc::new(super::builder()
+			.cache_dir(temp_dir.path().to_owned())
+			.task_pool(task_pool.clone())
+			.build(TestGenerator)
+			.await.unwrap());
+		
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key9.meta.json.partial")).await.unwrap());
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key9.lock")).await.unwrap());
+		assert!(tokio::fs::try_exists(temp_dir.path().join("key6.partial")).await.unwrap());
+		assert!(tokio::fs::try_exists(temp_dir.path().join("key6.lock")).await.unwrap());
+		
+		let other_cache2 = other_cache.clone();
+		let mut task = tokio::spawn(async move {
+			other_cache2.get_or_generate(6).await.map(|query| query.creation_date)
+		});
+		
+		// Waits for the generation instead of starting its own
+		tokio::time::sleep(Duration::from_millis(200)).await;
+		assert!(matches!(poll!(&mut task), Poll::Pending));
+		
+		let query = pending.generate().await.unwrap();
+		assert_eq!(task.await.unwrap().unwrap(), query.creation_date);
+		
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key6.lock")).await.unwrap());
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key6.meta.json.partial")).await.unwrap());
+		assert_eq!(other_cache.get(&6).await.unwrap().unwrap().entry_file.read_all().await.unwrap(), "stuff6");
+	}
+	
+	#[tokio::test]
+	async fn test_cancel_generation() {
+		let temp_dir = TempDir::new().unwrap();
+		
+		let artifact_cache = Arc::new(super::builder()
+			.cache_dir(temp_dir.path().to_owned())
+			.task_pool(Arc::new(TaskPool::new(1)))
+			.build(TestGenerator)
+			.await.unwrap());
+		
+		let artifact_cache2 = artifact_cache.clone();
+		let mut task = tokio::spawn(async move {
+			artifact_cache2.get_or_generate(100).await.map(|query| query.metadata)
+		});
+		
+		while !tokio::fs::try_exists(temp_dir.path().join("key100.partial")).await.unwrap() {
+			tokio::task::yield_now().await;
+		}
+		
+		assert!(matches!(poll!(&mut task), Poll::Pending));
+		
+		// Nobody is waiting for it anymore
+		task.abort();
+		assert!(task.await.unwrap_err().is_cancelled());
+		
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key100.partial")).await.unwrap());
+		assert!(artifact_cache.get(&100).await.unwrap().is_none());
+		
+		// The only task slot was given back
+		let query = artifact_cache.get_or_generate_with_priority(2, TaskPriority::Background).await.unwrap();
+		assert_eq!(query.metadata, "meta2");
+	}
+	
+	#[tokio::test]
+	async fn test_generation_priority() {
+		let temp_dir = TempDir::new().unwrap();
+		let task_pool = Arc::new(TaskPool::new(1));
+		
+		let artifact_cache = Arc::new(super::builder()
+			.cache_dir(temp_dir.path().to_owned())
+			.task_pool(task_pool.clone())
+			.build(TestGenerator)
+			.await.unwrap());
+		
+		let running = task_pool.reserve(TaskPriority::Interactive).await;
+		
+		// Returns whether the request generated the entry itself
+		let spawn_query = |priority: TaskPriority| {
+			let artifact_cache = artifact_cache.clone();
+			
+			tokio::spawn(async move {
+				match artifact_cache.get_or_reserve(7, priority).await.unwrap() {
+					QueryResult::Valid(_) => false,
+					QueryResult::Invalid(pending) => {
+						pending.generate().await.unwrap();
+						true
+					}
+				}
+			})
+		};
+		
+		let mut background = spawn_query(TaskPriority::Background);
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		let interactive = spawn_query(TaskPriority::Interactive);
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		
+		assert!(matches!(poll!(&mut background), Poll::Pending));
+		
+		drop(running);
+		
+		// The queued background request doesn't keep the interactive one from going first
+		assert!(interactive.await.unwrap());
+		assert!(!background.await.unwrap());
+	}
+	
+	#[tokio::test]
+	async fn test_scan_and_purge() {
+		let temp_dir = TempDir::new().unwrap();
+		
+		let artifact_cache = super::builder()
+			.cache_dir(temp_dir.path().to_owned())
+			.task_pool(Arc::new(TaskPool::new(4)))
+			.build(TestGenerator)
+			.await.unwrap();
+		
+		for input in [1, 2, 3, 10, 11] {
+			artifact_cache.get_or_generate(input).await.unwrap();
+		}
+		
+		assert_eq!(artifact_cache.stats().entry_count, 5);
+		
+		// Deleted from outside the server
+		tokio::fs::remove_file(temp_dir.path().join("key2")).await.unwrap();
+		tokio::fs::remove_file(temp_dir.path().join("key3").with_extension(ENTRY_METADATA_EXTENSION)).await.unwrap();
+		
+		let scan_stats = artifact_cache.scan().await.unwrap();
+		assert_eq!(scan_stats.valid_entries, 3);
+		assert_eq!(scan_stats.removed_entries, 2);
+		
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key2").with_extension(ENTRY_METADATA_EXTENSION)).await.unwrap());
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key3")).await.unwrap());
+		assert!(artifact_cache.get(&2).await.unwrap().is_none());
+		
+		let purge_stats = artifact_cache.purge(|cache_key| cache_key.starts_with("key1")).await.unwrap();
+		assert_eq!(purge_stats.removed_entries, 3);
+		assert_eq!(purge_stats.removed_bytes, 6 + 7 + 7);
+		
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key10")).await.unwrap());
+		assert!(artifact_cache.get(&1).await.unwrap().is_none());
+		
+		let stats = artifact_cache.stats();
+		assert_eq!(stats.entry_count, 0);
+		assert_eq!(stats.size, 0);
+	}
+	
+	#[tokio::test]
+	async fn test_shared_cache_dir() {
+		let temp_dir = TempDir::new().unwrap();
+		let task_pool = Arc::new(TaskPool::new(4));
+		
+		let artifact_cache = super::builder()
+			.cache_dir(temp_dir.path().to_owned())
+			.task_pool(task_pool.clone())
+			.build(TestGenerator)
+			.await.unwrap();
+		
+		artifact_cache.get_or_generate(1).await.unwrap();
+		
+		let metadata_partial_path = temp_dir.path().join("key1.meta.json.partial");
+		
+		// Opening the cache in another process leaves the files of an entry that's being written alone
+		{
+			let _held_entry = artifact_cache.lock_key_exclusive("key1").await.unwrap();
+			tokio::fs::write(&metadata_partial_path, "{").await.unwrap();
+			
+			super::builder()
+				.cache_dir(temp_dir.path().to_owned())
+				.task_pool(task_pool.clone())
+				.build(TestGenerator)
+				.await.unwrap();
+			
+			assert!(tokio::fs::try_exists(&metadata_partial_path).await.unwrap());
+		}
+		
+		let other_cache = super::builder()
+			.cache_dir(temp_dir.path().to_owned())
+			.task_pool(task_pool.clone())
+			.build(TestGenerator)
+			.await.unwrap();
+		
+		assert!(!tokio::fs::try_exists(&metadata_partial_path).await.unwrap());
+		
+		// Failing to update the last accessed time doesn't fail the lookup
+		tokio::fs::create_dir(&metadata_partial_path).await.unwrap();
+		
+		assert_eq!(artifact_cache.get(&1).await.unwrap().unwrap().entry_file.read_all().await.unwrap(), "stuff1");
+		assert_eq!(other_cache.get(&1).await.unwrap().unwrap().entry_file.read_all().await.unwrap(), "stuff1");
+		
+		// The entry isn't left locked after looking it up
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key1.lock")).await.unwrap());
+		assert!(other_cache.get(&2).await.unwrap().is_none());
+		assert!(!tokio::fs::try_exists(temp_dir.path().join("key2.lock")).await.unwrap());
 	}
 }
@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use anyhow::bail;
+use time::OffsetDateTime;
+use tokio::sync::Notify;
+use tracing::{debug, info};
+
+use crate::config::CacheBudgetConfig;
+use crate::utils;
+use crate::web_server::services::artifact_cache::{EntryUsage, ManagedCache};
+
+// Caches also evict their expired entries whenever something is added to them
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Once the budget is exceeded, the caches are brought this fraction below it, so that their entries aren't gone through
+//  again as soon as the next one is added
+const EVICTION_HEADROOM_DIVISOR: u64 = 20;
+
+// Keeps the caches within the global budget and removes their expired entries. The caches only let it know when they
+//  grow, the evicting happens in the background.
+pub struct CacheBudget {
+	config: CacheBudgetConfig,
+	cache_grown: Notify,
+}
+
+impl CacheBudget {
+	pub fn from_config(config: &CacheBudgetConfig, cache_names: &[&str]) -> anyhow::Result<Self> {
+		for (name, share) in &config.caches {
+			if !cache_names.contains(&name.as_str()) {
+				bail!("Unknown cache {} in the cache budget", name);
+			}
+			
+			if share.weight.is_some_and(|weight| weight <= 0.0) {
+				bail!("The weight of the {} cache has to be positive", name);
+			}
+		}
+		
+		Ok(Self {
+			config: config.clone(),
+			cache_grown: Notify::new(),
+		})
+	}
+	
+	pub fn note_growth(&self) {
+		self.cache_grown.notify_one();
+	}
+	
+	pub async fn run(&self, caches: &[(&'static str, &dyn ManagedCache)]) {
+		if self.config.enabled {
+			info!("All caches together are limited to {}B", utils::abbreviate_number(self.config.size_limit));
+		}
+		
+		self.enforce(caches).await;
+		
+		let mut expiry_interval = tokio::time::interval(EXPIRY_INTERVAL);
+		
+		loop {
+			tokio::select! {
+				_ = expiry_interval.tick() => self.expire(caches).await,
+				// Any number of caches growing while the budget is being enforced only wakes this up once
+				_ = self.cache_grown.notified() => self.enforce(caches).await,
+			}
+		}
+	}
+	
+	async fn expire(&self, caches: &[(&'static str, &dyn ManagedCache)]) {
+		for (name, cache) in caches {
+			let stats = cache.expire().await;
+			
+			if stats.removed_entries > 0 {
+				info!("Removed {} expired entries from the {} cache", stats.removed_entries, name);
+			}
+		}
+	}
+	
+	async fn enforce(&self, caches: &[(&'static str, &dyn ManagedCache)]) {
+		if !self.config.enabled {
+			return;
+		}
+		
+		// The caches keep a running total of their sizes, so their entries are only gone through once it's exceeded
+		let total_size: u64 = caches.iter().map(|(_, cache)| cache.stats().size).sum();
+		
+		if total_size <= self.config.size_limit {
+			return;
+		}
+		
+		let target_size = self.config.size_limit - self.config.size_limit / EVICTION_HEADROOM_DIVISOR;
+		
+		let usages: Vec<CacheUsage> = caches.iter()
+			.map(|(name, cache)| {
+				let share = self.config.caches.get(*name);
+				
+				CacheUsage {
+					min_size: share.map_or(0, |share| share.min_size),
+					weight: share.and_then(|share| share.weight).unwrap_or(1.0),
+					entries: cache.entry_usages(),
+				}
+			})
+			.collect();
+		
+		let evictions = select_evictions(&usages, target_size, OffsetDateTime::now_utc());
+		
+		for ((name, cache), cache_keys) in caches.iter().zip(evictions) {
+			if cache_keys.is_empty() {
+				continue;
+			}
+			
+			let stats = cache.evict(cache_keys).await;
+			
+			debug!("Evicted {} entries ({}B) from the {} cache to stay within the cache budget",
+				stats.removed_entries, utils::abbreviate_number(stats.removed_bytes), name);
+		}
+	}
+}
+
+pub struct CacheUsage {
+	pub min_size: u64,
+	pub weight: f64,
+	// From least to most recently used
+	pub entries: Vec<EntryUsage>,
+}
+
+// Picks entries to evict until everything fits in the size limit. Every step takes the least recently used entry of one
+//  of the caches, the one that has gone unused the longest after dividing by its cache's weight. Caches are never
+//  taken below their minimum size. Returns the cache keys to evict from each cache.
+pub fn select_evictions(caches: &[CacheUsage], size_limit: u64, now: OffsetDateTime) -> Vec<Vec<String>> {
+	let mut evictions = vec![Vec::new(); caches.len()];
+	let mut next_entries = vec![0; caches.len()];
+	
+	let mut cache_sizes: Vec<u64> = caches.iter()
+		.map(|cache| cache.entries.iter().map(|entry| entry.size).sum())
+		.collect();
+	
+	let mut total_size: u64 = cache_sizes.iter().sum();
+	
+	while total_size > size_limit {
+		let next_eviction = caches.iter().enumerate()
+			.filter_map(|(index, cache)| {
+				let entry = cache.entries.get(next_entries[index])?;
+				
+				if cache_sizes[index] - entry.size < cache.min_size {
+					return None;
+				}
+				
+				let unused_for = (now - entry.last_accessed).as_seconds_f64();
+				
+				Some((index, unused_for / cache.weight))
+			})
+			.max_by(|(_, a), (_, b)| a.total_cmp(b));
+		
+		let Some((index, _)) = next_eviction else { break };
+		
+		let entry = &caches[index].entries[next_entries[index]];
+		
+		next_entries[index] += 1;
+		cache_sizes[index] -= entry.size;
+		total_size -= entry.size;
+		
+		evictions[index].push(entry.cache_key.clone());
+	}
+	
+	evictions
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	
+	use time::macros::datetime;
+	
+	use crate::web_server::services::artifact_cache::EntryUsage;
+	use crate::web_server::services::cache_budget::{select_evictions, CacheUsage};
+	
+	#[test]
+	fn test_select_evictions() {
+		let now = datetime!(2020-01-01 01:00:00 UTC);
+		
+		let cache = |min_size, weight, entries: &[(&str, u64, u64)]| CacheUsage {
+			min_size,
+			weight,
+			entries: entries.iter()
+				.map(|(cache_key, size, minutes_ago)| EntryUsage {
+					cache_key: cache_key.to_string(),
+					size: *size,
+					last_accessed: now - Duration::from_secs(minutes_ago * 60),
+				})
+				.collect(),
+		};
+		
+		let caches = [
+			cache(0, 1.0, &[("a1", 10, 50), ("a2", 10, 30), ("a3", 10, 5)]),
+			// Kept twice as long
+			cache(0, 2.0, &[("b1", 10, 58), ("b2", 10, 20)]),
+			// Never taken below 20
+			cache(20, 1.0, &[("c1", 10, 59), ("c2", 10, 55), ("c3", 10, 40)]),
+		];
+		
+		assert!(select_evictions(&caches, 80, now).iter().all(Vec::is_empty));
+		
+		assert_eq!(select_evictions(&caches, 70, now), [vec![], vec![], vec!["c1"]]);
+		assert_eq!(select_evictions(&caches, 50, now), [vec!["a1", "a2"], vec![], vec!["c1"]]);
+		assert_eq!(select_evictions(&caches, 40, now), [vec!["a1", "a2"], vec!["b1"], vec!["c1"]]);
+		
+		// Stops once nothing more can be evicted
+		assert_eq!(select_evictions(&caches, 0, now), [vec!["a1", "a2", "a3"], vec!["b1", "b2"], vec!["c1"]]);
+	}
+}
@@ -0,0 +1,237 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::config::{CacheKeyStrategy, LibrariesConfig};
+use crate::web_server::metadata_cache::{FileMetadata, FileMetadataCache};
+use crate::web_server::services::artifact_cache;
+
+// Identifies media files in the cache keys of everything generated from them, using the strategy chosen for the
+//  library they're in
+pub struct CacheKeys {
+	// Library roots as configured and the strategy for each
+	library_strategies: Vec<(PathBuf, CacheKeyStrategy)>,
+	// Remembers content fingerprints until the file changes, so that they aren't hashed again on every request
+	metadata_cache: Arc<FileMetadataCache>,
+}
+
+#[derive(Clone)]
+pub struct ContentFingerprint {
+	pub fingerprint: String,
+}
+
+impl FileMetadata for ContentFingerprint {
+	async fn fetch_metadata(path: &Path, _file_metadata: &std::fs::Metadata) -> anyhow::Result<Self> {
+		Ok(Self {
+			fingerprint: create_content_fingerprint(path).await?,
+		})
+	}
+}
+
+impl CacheKeys {
+	pub fn new(libraries_config: &LibrariesConfig, metadata_cache: Arc<FileMetadataCache>) -> Self {
+		let library_strategies = libraries_config.libraries.iter()
+			.map(|library| (library.path.clone(), library.cache_keys))
+			.collect();
+		
+		Self {
+			library_strategies,
+			metadata_cache,
+		}
+	}
+	
+	pub async fn file_key(&self, media_path: &Path) -> anyhow::Result<String> {
+		let full_path = tokio::fs::canonicalize(media_path).await?;
+		
+		// Files located through a library already start with its root, anything else is compared by where it really is
+		let strategy = match Self::strategy_for(&self.library_strategies, media_path) {
+			Some(strategy) => strategy,
+			None => self.canonical_strategy_for(&full_path).await,
+		};
+		
+		match strategy {
+			CacheKeyStrategy::Metadata => artifact_cache::create_file_metadata_hash(&full_path).await,
+			CacheKeyStrategy::Content => {
+				let content_fingerprint = self.metadata_cache
+					.fetch_metadata::<ContentFingerprint>(&full_path).await?;
+				
+				// Hashed again so that both kinds of keys look the same and can't collide
+				let mut hasher = blake3::Hasher::new();
+				hasher.update(b"content");
+				hasher.update(content_fingerprint.fingerprint.as_bytes());
+				
+				Ok(hasher.finalize().to_hex().to_string())
+			}
+		}
+	}
+	
+	// Resolved on every call, since a library's mount might not have been there at startup
+	async fn canonical_strategy_for(&self, full_path: &Path) -> CacheKeyStrategy {
+		let mut canonical_strategies = Vec::new();
+		
+		for (root_path, strategy) in &self.library_strategies {
+			if let Ok(canonical_root) = tokio::fs::canonicalize(root_path).await {
+				canonical_strategies.push((canonical_root, *strategy));
+			}
+		}
+		
+		Self::strategy_for(&canonical_strategies, full_path).unwrap_or_default()
+	}
+	
+	// Libraries can be nested, in which case the innermost one decides
+	fn strategy_for(library_strategies: &[(PathBuf, CacheKeyStrategy)], path: &Path) -> Option<CacheKeyStrategy> {
+		library_strategies.iter()
+			.filter(|(root_path, _)| path.starts_with(root_path))
+			.max_by_key(|(root_path, _)| root_path.components().count())
+			.map(|(_, strategy)| *strategy)
+	}
+}
+
+const FINGERPRINT_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+// Identifies a file by its size and samples from its start, middle and end, so that it stays the same when the file
+//  gets moved or copied. Formatted as "<size>-<hash>" so that candidates can be narrowed down by size before hashing.
+pub async fn create_content_fingerprint(file_path: &Path) -> anyhow::Result<String> {
+	let mut file = tokio::fs::File::open(file_path).await?;
+	let file_size = file.metadata().await?.len();
+	
+	let sample_size = file_size.min(FINGERPRINT_SAMPLE_SIZE);
+	let mut buffer = vec![0; sample_size as usize];
+	
+	let mut hasher = blake3::Hasher::new();
+	hasher.update(&file_size.to_le_bytes());
+	
+	let last_offset = file_size - sample_size;
+	let mut sample_offsets = vec![0, last_offset / 2, last_offset];
+	sample_offsets.dedup();
+	
+	for offset in sample_offsets {
+		file.seek(io::SeekFrom::Start(offset)).await?;
+		file.read_exact(&mut buffer).await?;
+		hasher.update(&buffer);
+	}
+	
+	Ok(format!("{}-{}", file_size, hasher.finalize().to_hex()))
+}
+
+pub fn fingerprint_file_size(fingerprint: &str) -> Option<u64> {
+	fingerprint.split_once('-')?.0.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::TempDir;
+	
+	use crate::config::LibraryConfig;
+	
+	use super::*;
+	
+	#[tokio::test]
+	async fn test_cache_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		
+		let library = |id: &str, cache_keys: CacheKeyStrategy| {
+			let path = temp_dir.path().join(id);
+			std::fs::create_dir(&path).unwrap();
+			
+			LibraryConfig {
+				id: id.to_owned(),
+				display_name: id.to_owned(),
+				path,
+				global_connections_file: None,
+				cache_keys,
+			}
+		};
+		
+		// Not mounted yet when the server starts
+		let late_mount = LibraryConfig {
+			path: temp_dir.path().join("late_mount"),
+			..library("unused", CacheKeyStrategy::Content)
+		};
+		
+		let libraries_config = LibrariesConfig {
+			libraries: vec![
+				library("old_mount", CacheKeyStrategy::Content),
+				library("new_mount", CacheKeyStrategy::Content),
+				library("metadata", CacheKeyStrategy::Metadata),
+				late_mount,
+			],
+		};
+		
+		let cache_keys = CacheKeys::new(&libraries_config, Arc::new(FileMetadataCache::new()));
+		
+		let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+		
+		for library in ["old_mount", "new_mount", "metadata"] {
+			tokio::fs::write(temp_dir.path().join(library).join("video.mp4"), &data).await.unwrap();
+		}
+		
+		let old_key = cache_keys.file_key(&temp_dir.path().join("old_mount/video.mp4")).await.unwrap();
+		let new_key = cache_keys.file_key(&temp_dir.path().join("new_mount/video.mp4")).await.unwrap();
+		let metadata_key = cache_keys.file_key(&temp_dir.path().join("metadata/video.mp4")).await.unwrap();
+		
+		// Moving the file keeps its key unless the library uses metadata keys
+		assert_eq!(old_key, new_key);
+		assert_ne!(old_key, metadata_key);
+		assert_eq!(metadata_key, artifact_cache::create_file_metadata_hash(&temp_dir.path().join("metadata/video.mp4")).await.unwrap());
+		
+		// Once it shows up the library's strategy applies, even when reached through where the mount really points
+		#[cfg(unix)]
+		{
+			let mount_target = temp_dir.path().join("mount_target");
+			std::fs::create_dir(&mount_target).unwrap();
+			std::os::unix::fs::symlink(&mount_target, temp_dir.path().join("late_mount")).unwrap();
+			tokio::fs::write(mount_target.join("video.mp4"), &data).await.unwrap();
+			
+			assert_eq!(cache_keys.file_key(&temp_dir.path().join("late_mount/video.mp4")).await.unwrap(), old_key);
+			assert_eq!(cache_keys.file_key(&mount_target.join("video.mp4")).await.unwrap(), old_key);
+		}
+		
+		// Changed contents get a new key even though the fingerprint was remembered
+		let mut changed_data = data.clone();
+		changed_data[0] ^= 1;
+		changed_data.push(0);
+		tokio::fs::write(temp_dir.path().join("new_mount/video.mp4"), &changed_data).await.unwrap();
+		
+		assert_ne!(cache_keys.file_key(&temp_dir.path().join("new_mount/video.mp4")).await.unwrap(), old_key);
+	}
+	
+	#[tokio::test]
+	async fn test_content_fingerprint() {
+		let temp_dir = TempDir::new().unwrap();
+		
+		let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+		
+		let original_path = temp_dir.path().join("original.mp4");
+		let moved_path = temp_dir.path().join("moved.mp4");
+		let changed_path = temp_dir.path().join("changed.mp4");
+		let small_path = temp_dir.path().join("small.mp4");
+		
+		tokio::fs::write(&original_path, &data).await.unwrap();
+		tokio::fs::write(&moved_path, &data).await.unwrap();
+		
+		let mut changed_data = data.clone();
+		*changed_data.last_mut().unwrap() ^= 1;
+		tokio::fs::write(&changed_path, &changed_data).await.unwrap();
+		
+		// Edits that leave the start and end alone still change it
+		let middle_changed_path = temp_dir.path().join("middle_changed.mp4");
+		let mut middle_changed_data = data.clone();
+		middle_changed_data[1_500_000] ^= 1;
+		tokio::fs::write(&middle_changed_path, &middle_changed_data).await.unwrap();
+		
+		tokio::fs::write(&small_path, &data[..100]).await.unwrap();
+		
+		let fingerprint = create_content_fingerprint(&original_path).await.unwrap();
+		
+		assert_eq!(fingerprint, create_content_fingerprint(&moved_path).await.unwrap());
+		assert_ne!(fingerprint, create_content_fingerprint(&changed_path).await.unwrap());
+		assert_ne!(fingerprint, create_content_fingerprint(&middle_changed_path).await.unwrap());
+		assert_eq!(fingerprint_file_size(&fingerprint), Some(3_000_000));
+		
+		let small_fingerprint = create_content_fingerprint(&small_path).await.unwrap();
+		assert_eq!(fingerprint_file_size(&small_fingerprint), Some(100));
+	}
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use futures_util::future::BoxFuture;
+
+use crate::config::{CacheStorageBackend, CachesConfig};
+use crate::utils;
+use crate::web_server::services::s3_storage::S3Storage;
+
+pub const ENTRY_METADATA_EXTENSION: &str = "meta.json";
+pub const PARTIAL_EXTENSION: &str = "partial";
+pub const DOWNLOAD_EXTENSION: &str = "download";
+pub const LOCK_EXTENSION: &str = "lock";
+
+// Where the finished entries of a cache are kept. Artifacts are always generated into and served from local files,
+//  storages that are shared between servers keep a local copy of the entries each server uses in its cache dir.
+pub trait CacheStorage: Send + Sync {
+	// Every entry that has its data or metadata stored
+	fn list_entries(&self) -> BoxFuture<'_, io::Result<Vec<StoredEntry>>>;
+	
+	// Whether this server has the entry's data
+	fn contains<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<bool>>;
+	
+	fn read_metadata<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<Option<Vec<u8>>>>;
+	
+	// Records a change to an entry that's already stored, such as when it was last accessed. Shared storages only
+	//  update their local copy.
+	fn update_metadata<'a>(&'a self, cache_key: &'a str, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+	
+	// Takes over a finished local file as the entry's data, and returns it opened for reading. The metadata is stored
+	//  last, so that the data is never paired with the wrong metadata.
+	fn store_entry<'a>(&'a self, cache_key: &'a str, local_path: &'a Path, metadata: Vec<u8>) -> BoxFuture<'a, io::Result<tokio::fs::File>>;
+	
+	// The file stays readable even if the entry gets removed while it's being sent
+	fn open_data<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<Option<tokio::fs::File>>>;
+	
+	// Frees the space the entry takes up on this server. Shared storages only remove their local copy, since other
+	//  servers might still be using the entry.
+	fn evict<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+	
+	// Removes both the data and the metadata, it's not an error if either is already gone
+	fn remove<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StoredEntry {
+	pub cache_key: String,
+	pub has_data: bool,
+	pub has_metadata: bool,
+	// Only the entries that this server has a copy of are tracked, always true for storages that aren't shared
+	pub is_local: bool,
+}
+
+pub fn create_storage(config: &CachesConfig, cache_dir: &Path) -> anyhow::Result<Box<dyn CacheStorage>> {
+	match config.storage {
+		CacheStorageBackend::Filesystem => Ok(Box::new(FilesystemStorage::new(cache_dir.to_owned()))),
+		CacheStorageBackend::S3 => {
+			// Every cache gets its own prefix, named after its directory
+			let dir_name = cache_dir.file_name().and_then(OsStr::to_str).unwrap_or_default();
+			let prefix = format!("{}{}/", config.s3.prefix, dir_name);
+			
+			Ok(Box::new(S3Storage::new(config.s3.clone(), prefix, cache_dir.to_owned())?))
+		}
+	}
+}
+
+// Keeps the entries in the cache dir, each as a data file and a metadata file next to it
+pub struct FilesystemStorage {
+	cache_dir: PathBuf,
+}
+
+impl FilesystemStorage {
+	pub fn new(cache_dir: PathBuf) -> Self {
+		Self {
+			cache_dir,
+		}
+	}
+	
+	fn data_path(&self, cache_key: &str) -> PathBuf {
+		self.cache_dir.join(cache_key)
+	}
+	
+	fn metadata_path(&self, cache_key: &str) -> PathBuf {
+		utils::add_extension(&self.cache_dir.join(cache_key), ENTRY_METADATA_EXTENSION)
+	}
+}
+
+impl CacheStorage for FilesystemStorage {
+	fn list_entries(&self) -> BoxFuture<'_, io::Result<Vec<StoredEntry>>> {
+		Box::pin(async move {
+			let mut names = Vec::new();
+			let mut read_dir = tokio::fs::read_dir(&self.cache_dir).await?;
+			
+			while let Some(dir_entry) = read_dir.next_entry().await? {
+				let path = dir_entry.path();
+				
+				// Still being generated, or only there while the entry is in use
+				if path.extension().is_some_and(|ext| ext == PARTIAL_EXTENSION || ext == LOCK_EXTENSION) {
+					continue;
+				}
+				
+				if let Some(file_name) = path.file_name().and_then(OsStr::to_str) {
+					names.push(file_name.to_owned());
+				}
+			}
+			
+			Ok(group_stored_names(names, true))
+		})
+	}
+	
+	fn contains<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<bool>> {
+		Box::pin(tokio::fs::try_exists(self.data_path(cache_key)))
+	}
+	
+	fn read_metadata<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<Option<Vec<u8>>>> {
+		Box::pin(async move {
+			match tokio::fs::read(self.metadata_path(cache_key)).await {
+				Ok(data) => Ok(Some(data)),
+				Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+				Err(err) => Err(err),
+			}
+		})
+	}
+	
+	fn update_metadata<'a>(&'a self, cache_key: &'a str, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+		Box::pin(async move {
+			// Renamed into place, so that it's never seen half written
+			let metadata_path = self.metadata_path(cache_key);
+			let partial_path = utils::add_extension(&metadata_path, PARTIAL_EXTENSION);
+			
+			tokio::fs::write(&partial_path, data).await?;
+			tokio::fs::rename(&partial_path, &metadata_path).await
+		})
+	}
+	
+	fn store_entry<'a>(&'a self, cache_key: &'a str, local_path: &'a Path, metadata: Vec<u8>) -> BoxFuture<'a, io::Result<tokio::fs::File>> {
+		Box::pin(async move {
+			let data_path = self.data_path(cache_key);
+			
+			tokio::fs::rename(local_path, &data_path).await?;
+			self.update_metadata(cache_key, metadata).await?;
+			
+			tokio::fs::File::open(&data_path).await
+		})
+	}
+	
+	fn open_data<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<Option<tokio::fs::File>>> {
+		Box::pin(async move {
+			match tokio::fs::File::open(self.data_path(cache_key)).await {
+				Ok(file) => Ok(Some(file)),
+				Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+				Err(err) => Err(err),
+			}
+		})
+	}
+	
+	fn evict<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+		self.remove(cache_key)
+	}
+	
+	fn remove<'a>(&'a self, cache_key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+		Box::pin(async move {
+			remove_if_exists(&self.metadata_path(cache_key)).await?;
+			remove_if_exists(&self.data_path(cache_key)).await
+		})
+	}
+}
+
+// Data and metadata are stored under the same name, except for the metadata extension
+pub fn group_stored_names(names: Vec<String>, is_local: bool) -> Vec<StoredEntry> {
+	let metadata_suffix = format!(".{}", ENTRY_METADATA_EXTENSION);
+	let mut entries: BTreeMap<String, StoredEntry> = BTreeMap::new();
+	
+	for name in names {
+		let (cache_key, is_metadata) = match name.strip_suffix(&metadata_suffix) {
+			Some(cache_key) => (cache_key, true),
+			None => (name.as_str(), false),
+		};
+		
+		let entry = entries.entry(cache_key.to_owned())
+			.or_insert_with(|| StoredEntry {
+				cache_key: cache_key.to_owned(),
+				has_data: false,
+				has_metadata: false,
+				is_local,
+			});
+		
+		if is_metadata {
+			entry.has_metadata = true;
+		} else {
+			entry.has_data = true;
+		}
+	}
+	
+	entries.into_values().collect()
+}
+
+// The entry that a file in the cache dir was only there for while it was being worked on
+pub fn temporary_file_cache_key(file_name: &str) -> Option<&str> {
+	if let Some(cache_key) = file_name.strip_suffix(&format!(".{}", LOCK_EXTENSION)) {
+		return Some(cache_key);
+	}
+	
+	let name = file_name.strip_suffix(&format!(".{}", PARTIAL_EXTENSION))?;
+	
+	let cache_key = name.strip_suffix(&format!(".{}", ENTRY_METADATA_EXTENSION))
+		.or_else(|| name.strip_suffix(&format!(".{}", DOWNLOAD_EXTENSION)))
+		.unwrap_or(name);
+	
+	Some(cache_key)
+}
+
+pub async fn remove_if_exists(path: &Path) -> Result<(), io::Error> {
+	match tokio::fs::remove_file(path).await {
+		Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+		_ => Ok(()),
+	}
+}
@@ -6,15 +6,20 @@ use crate::utils;
 use crate::web_server::api_error::ApiError;
 use crate::web_server::media_backend_factory::MediaBackendFactory;
 use crate::web_server::media_metadata::{AdvancedMediaMetadata, Dimension, VideoMetadata};
-use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator};
+use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator, ArtifactOutput};
+use crate::web_server::services::cache_keys::CacheKeys;
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_storage;
+use crate::web_server::services::segment_prefetcher::SegmentPrefetcher;
 use crate::web_server::services::task_pool::TaskPool;
+use crate::web_server::services::transcoding_sessions::TranscodingSessions;
 use anyhow::Context;
-use bytes::Bytes;
 use ffmpeg_next::codec;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Instant;
-use tracing::info;
+use tracing::{info, warn};
 
 pub const SEGMENT_DURATION: f64 = 5.0;
 
@@ -158,17 +163,23 @@ pub async fn init_service(
 	config: &ServerConfig,
 	transcoding_task_pool: Arc<TaskPool>,
 	media_backend_factory: Arc<MediaBackendFactory>,
+	cache_keys: Arc<CacheKeys>,
+	cache_budget: Arc<CacheBudget>,
+	segment_prefetcher: Arc<SegmentPrefetcher>,
 ) -> anyhow::Result<ArtifactCache<HlsSegmentGenerator>> {
 	let hls_segment_generator = artifact_cache::builder()
 		.cache_dir(config.paths.transcoded_segments_cache_dir.clone())
 		.task_pool(transcoding_task_pool)
-		.file_size_limit(config.main_config.caches.segments_cache_size_limit)
-		.build(HlsSegmentGenerator::new(media_backend_factory))
+		.storage(cache_storage::create_storage(&config.main_config.caches, &config.paths.transcoded_segments_cache_dir)?)
+		.file_size_limit(config.main_config.caches.size_limit(config.main_config.caches.segments_cache_size_limit))
+		.max_entry_age(config.main_config.caches.max_entry_age())
+		.budget(cache_budget)
+		.build(HlsSegmentGenerator::new(media_backend_factory, cache_keys, segment_prefetcher))
 		.await?;
 	
 	info!("HLS segments cache contains {}B, {}B max",
 			utils::abbreviate_number(hls_segment_generator.cache_size()),
-			utils::abbreviate_number(config.main_config.caches.segments_cache_size_limit));
+			utils::abbreviate_number(config.main_config.caches.size_limit(config.main_config.caches.segments_cache_size_limit)));
 	
 	Ok(hls_segment_generator)
 }
@@ -182,32 +193,30 @@ pub struct SegmentParams {
 
 pub struct HlsSegmentGenerator {
 	media_backend_factory: Arc<MediaBackendFactory>,
+	transcoding_sessions: TranscodingSessions,
+	cache_keys: Arc<CacheKeys>,
+	segment_prefetcher: Arc<SegmentPrefetcher>,
 }
 
 impl HlsSegmentGenerator {
-	pub fn new(media_backend_factory: Arc<MediaBackendFactory>) -> Self {
+	pub fn new(media_backend_factory: Arc<MediaBackendFactory>, cache_keys: Arc<CacheKeys>, segment_prefetcher: Arc<SegmentPrefetcher>) -> Self {
 		Self {
+			transcoding_sessions: TranscodingSessions::new(media_backend_factory.clone()),
 			media_backend_factory,
+			cache_keys,
+			segment_prefetcher,
 		}
 	}
-}
-
-impl ArtifactGenerator for HlsSegmentGenerator {
-	type Input = SegmentParams;
-	type Metadata = ();
-
-	async fn create_cache_key(&self, input: &Self::Input) -> anyhow::Result<String> {
-		let file_hash = artifact_cache::create_file_metadata_hash(&input.media_path).await?;
-
-		Ok(format!("{}_{}_s{}.ts", file_hash, input.quality_level.id, input.segment_index))
-	}
-
-	async fn generate_artifact(&self, input: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)> {
+	
+	// Sets up everything just for this segment, replacing anything a failed session already wrote
+	async fn transcode_single_segment(&self, input: SegmentParams, output: &mut ArtifactOutput) -> anyhow::Result<()> {
 		let backend_factory = self.media_backend_factory.clone();
+		let cancel_flag = output.cancel_flag();
 		
-		let start_time = Instant::now();
+		output.reset().await?;
+		let mut writer = output.blocking_writer().await?;
 		
-		let data = tokio::task::spawn_blocking(move || {
+		tokio::task::spawn_blocking(move || {
 			let opts = TranscodingOptions {
 				backend_factory: backend_factory.as_ref(),
 				media_path: input.media_path,
@@ -216,6 +225,7 @@ impl ArtifactGenerator for HlsSegmentGenerator {
 				// target_video_framerate: 60,
 				video_bitrate: input.quality_level.video_bitrate,
 				audio_bitrate: input.quality_level.audio_bitrate,
+				cancel_flag: &cancel_flag,
 			};
 			
 			info!("Generating segment {} at {} for {:?}", input.segment_index, input.quality_level.id, &opts.media_path);
@@ -223,11 +233,50 @@ impl ArtifactGenerator for HlsSegmentGenerator {
 			let start_time = input.segment_index as f64 * SEGMENT_DURATION;
 			let time_range = start_time..(start_time + SEGMENT_DURATION);
 			
-			transcoding::transcode_segment(opts, time_range)
-		}).await.context("Panic")??;
+			transcoding::transcode_segment(opts, time_range, &mut writer)
+		}).await.context("Panic")?
+	}
+}
+
+impl ArtifactGenerator for HlsSegmentGenerator {
+	type Input = SegmentParams;
+	type Metadata = ();
+
+	async fn create_cache_key(&self, input: &Self::Input) -> anyhow::Result<String> {
+		let file_hash = self.cache_keys.file_key(&input.media_path).await?;
+
+		Ok(format!("{}_{}_s{}.ts", file_hash, input.quality_level.id, input.segment_index))
+	}
+
+	async fn generate_artifact(&self, input: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
+		let cancel_flag = output.cancel_flag();
+		
+		let mut queued_segment = self.transcoding_sessions.queue_segment(input.clone());
+		
+		// The task slot isn't needed while the session is busy with earlier segments
+		output.without_task_slot(queued_segment.wait_for_turn()).await;
+		
+		// Timed from here, so that waiting for a slot or for earlier segments doesn't count towards the transcode speed
+		let start_time = Instant::now();
+		let session_result = queued_segment.transcode(output.blocking_writer().await?).await;
+		
+		match session_result {
+			Some(Ok(())) => {}
+			Some(Err(err)) if cancel_flag.load(Ordering::Relaxed) => return Err(err),
+			Some(Err(err)) => {
+				warn!("Transcoding session failed, retrying segment {} on its own: {:?}", input.segment_index, err);
+				
+				self.transcode_single_segment(input.clone(), output).await?;
+			}
+			// The session stopped because of an earlier failure, or had already gone past this segment
+			None => self.transcode_single_segment(input.clone(), output).await?,
+		}
+		
+		let transcode_time = start_time.elapsed();
+		info!("Generated segment in {:?}", transcode_time);
 		
-		info!("Generated segment in {:?}", Instant::now() - start_time);
+		self.segment_prefetcher.record_transcode_time(&input, transcode_time);
 		
-		Ok((data, ()))
+		Ok(())
 	}
 }
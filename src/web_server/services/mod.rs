@@ -1,7 +1,13 @@
 pub mod artifact_cache;
+pub mod cache_budget;
+pub mod cache_keys;
+pub mod cache_storage;
+pub mod s3_storage;
 pub mod thumbnail_service;
 pub mod thumbnail_sheet_service;
 pub mod hls_segment_service;
+pub mod segment_prefetcher;
+pub mod transcoding_sessions;
 pub mod task_pool;
 pub mod subtitle_service;
 pub mod scaled_thumbnail_service;
@@ -7,7 +7,9 @@ use tracing::info;
 
 use crate::config::ServerConfig;
 use crate::utils;
-use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator};
+use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator, ArtifactOutput};
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_storage;
 use crate::web_server::services::task_pool::TaskPool;
 
 pub const TARGET_WIDTH: u32 = 640;
@@ -17,17 +19,21 @@ const WEBP_QUALITY: f32 = 80.0;
 
 pub async fn init_service(
 	config: &ServerConfig,
+	cache_budget: Arc<CacheBudget>,
 ) -> anyhow::Result<ArtifactCache<ScaledThumbnailGenerator>> {
 	let scaled_thumbnail_generator = artifact_cache::builder()
 		.cache_dir(config.paths.scaled_thumbnail_cache_dir.clone())
 		.task_pool(Arc::new(TaskPool::new(8)))
-		.file_size_limit(config.main_config.caches.scaled_thumbnail_cache_size_limit)
+		.storage(cache_storage::create_storage(&config.main_config.caches, &config.paths.scaled_thumbnail_cache_dir)?)
+		.file_size_limit(config.main_config.caches.size_limit(config.main_config.caches.scaled_thumbnail_cache_size_limit))
+		.max_entry_age(config.main_config.caches.max_entry_age())
+		.budget(cache_budget)
 		.build(ScaledThumbnailGenerator::new())
 		.await?;
 
 	info!("Scaled thumbnail cache contains {}B, {}B max",
 		utils::abbreviate_number(scaled_thumbnail_generator.cache_size()),
-		utils::abbreviate_number(config.main_config.caches.scaled_thumbnail_cache_size_limit));
+		utils::abbreviate_number(config.main_config.caches.size_limit(config.main_config.caches.scaled_thumbnail_cache_size_limit)));
 
 	Ok(scaled_thumbnail_generator)
 }
@@ -48,7 +54,7 @@ impl ArtifactGenerator for ScaledThumbnailGenerator {
 		Ok(format!("{}.webp", blake3::hash(&thumbnail_data).to_hex()))
 	}
 
-	async fn generate_artifact(&self, thumbnail_data: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)> {
+	async fn generate_artifact(&self, thumbnail_data: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
 		let mut image = ImageReader::new(Cursor::new(thumbnail_data))
 			.with_guessed_format()?
 			.decode()?;
@@ -62,9 +68,10 @@ impl ArtifactGenerator for ScaledThumbnailGenerator {
 		let encoder = webp::Encoder::from_image(&image)
 			.map_err(|err| anyhow::anyhow!("webp error: {}", err))?;
 		
-		let encoded_image = encoder.encode(WEBP_QUALITY);
-		let output_bytes = encoded_image.to_vec().into();
+		let encoded_image = encoder.encode(WEBP_QUALITY).to_vec();
 		
-		Ok((output_bytes, ()))
+		output.write_all(&encoded_image).await?;
+		
+		Ok(())
 	}
 }
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::task::AbortHandle;
+use tracing::debug;
+
+use crate::config::TranscodingConfig;
+use crate::web_server::services::hls_segment_service::{HlsQualityLevel, SegmentParams, SEGMENT_DURATION};
+
+// Weight given to the newest transcode time when averaging
+const SPEED_SMOOTHING: f64 = 0.3;
+
+// Keeps track of what each viewer is playing, so that enough segments are generated ahead of them to keep up with
+//  playback without transcoding far more than will be watched. The budget is shared by all viewers.
+pub struct SegmentPrefetcher {
+	min_segments: usize,
+	max_segments: usize,
+	budget: u64,
+	idle_timeout: Duration,
+	sessions: Mutex<HashMap<SessionKey, PrefetchSession>>,
+}
+
+// User id, media path and quality level id
+type SessionKey = (String, PathBuf, &'static str);
+
+struct PrefetchSession {
+	last_request: Instant,
+	// Time taken to transcode a segment relative to its duration, so anything above 1 is slower than playback
+	transcode_ratio: Option<f64>,
+	// Estimated size of each of its segments, in bytes
+	segment_size: f64,
+	prefetches: Vec<(usize, AbortHandle)>,
+}
+
+impl PrefetchSession {
+	fn prefetched_size(&self) -> f64 {
+		let in_flight = self.prefetches.iter()
+			.filter(|(_, abort_handle)| !abort_handle.is_finished())
+			.count();
+		
+		in_flight as f64 * self.segment_size
+	}
+}
+
+impl SegmentPrefetcher {
+	pub fn new(config: &TranscodingConfig) -> Self {
+		Self {
+			min_segments: config.min_prefetch_segments,
+			max_segments: config.max_prefetch_segments.max(config.min_prefetch_segments),
+			budget: config.prefetch_budget,
+			idle_timeout: Duration::from_secs(config.prefetch_idle_timeout),
+			sessions: Mutex::new(HashMap::new()),
+		}
+	}
+	
+	// Called for every requested segment. Cancels prefetches that fell outside the window, and returns the segments
+	//  that should start being prefetched.
+	pub fn segment_requested(&self, user_id: &str, params: &SegmentParams) -> Vec<usize> {
+		self.segment_requested_at(user_id, params, Instant::now())
+	}
+	
+	fn segment_requested_at(&self, user_id: &str, params: &SegmentParams, now: Instant) -> Vec<usize> {
+		let mut sessions = self.sessions.lock().unwrap();
+		
+		self.end_idle_sessions(&mut sessions, now);
+		
+		let key = session_key(user_id, params);
+		
+		// What the other viewers are already prefetching comes out of the shared budget
+		let prefetched_elsewhere: f64 = sessions.iter()
+			.filter(|(other_key, _)| **other_key != key)
+			.map(|(_, other_session)| other_session.prefetched_size())
+			.sum();
+		
+		let session = sessions.entry(key)
+			.or_insert_with(|| PrefetchSession {
+				last_request: now,
+				transcode_ratio: None,
+				segment_size: segment_size(&params.quality_level),
+				prefetches: Vec::new(),
+			});
+		
+		session.last_request = now;
+		
+		let window = self.window_size(session.transcode_ratio, session.segment_size, prefetched_elsewhere);
+		let wanted = (params.segment_index + 1)..=(params.segment_index + window);
+		
+		session.prefetches.retain(|(segment_index, abort_handle)| {
+			if abort_handle.is_finished() {
+				return false;
+			}
+			
+			// The requested segment itself may still be generating as a prefetch, and is now being waited on
+			if *segment_index == params.segment_index || wanted.contains(segment_index) {
+				return true;
+			}
+			
+			debug!("Cancelling prefetch of segment {}", segment_index);
+			abort_handle.abort();
+			
+			false
+		});
+		
+		wanted
+			.filter(|segment_index| !session.prefetches.iter().any(|(index, _)| index == segment_index))
+			.collect()
+	}
+	
+	pub fn add_prefetch(&self, user_id: &str, params: &SegmentParams, abort_handle: AbortHandle) {
+		let mut sessions = self.sessions.lock().unwrap();
+		
+		match sessions.get_mut(&session_key(user_id, params)) {
+			Some(session) => session.prefetches.push((params.segment_index, abort_handle)),
+			// The session went idle in the meantime
+			None => abort_handle.abort(),
+		}
+	}
+	
+	// Prefetches waiting for a transcoding slot check this before starting, so they don't run for viewers that left
+	pub fn is_session_active(&self, user_id: &str, params: &SegmentParams) -> bool {
+		let sessions = self.sessions.lock().unwrap();
+		
+		sessions.get(&session_key(user_id, params))
+			.is_some_and(|session| session.last_request.elapsed() < self.idle_timeout)
+	}
+	
+	// Called by the generator with the time the transcode itself took, which applies to everyone watching the same
+	//  file at the same quality
+	pub fn record_transcode_time(&self, params: &SegmentParams, transcode_time: Duration) {
+		let mut sessions = self.sessions.lock().unwrap();
+		
+		let ratio = transcode_time.as_secs_f64() / SEGMENT_DURATION;
+		
+		let matching_sessions = sessions.iter_mut()
+			.filter(|((_, media_path, quality_level_id), _)| {
+				*media_path == params.media_path && *quality_level_id == params.quality_level.id
+			});
+		
+		for (_, session) in matching_sessions {
+			session.transcode_ratio = Some(match session.transcode_ratio {
+				Some(average) => average + (ratio - average) * SPEED_SMOOTHING,
+				None => ratio,
+			});
+		}
+	}
+	
+	// Enough segments to cover the time it takes to transcode one, limited by how much data that would be
+	fn window_size(&self, transcode_ratio: Option<f64>, segment_size: f64, prefetched_elsewhere: f64) -> usize {
+		let wanted = match transcode_ratio {
+			Some(ratio) => ratio.ceil() as usize + 1,
+			None => self.min_segments,
+		};
+		
+		let affordable = ((self.budget as f64 - prefetched_elsewhere).max(0.0) / segment_size) as usize;
+		
+		wanted.min(affordable).clamp(self.min_segments, self.max_segments)
+	}
+	
+	fn end_idle_sessions(&self, sessions: &mut HashMap<SessionKey, PrefetchSession>, now: Instant) {
+		sessions.retain(|_, session| {
+			if now.duration_since(session.last_request) < self.idle_timeout {
+				return true;
+			}
+			
+			for (_, abort_handle) in &session.prefetches {
+				abort_handle.abort();
+			}
+			
+			false
+		});
+	}
+}
+
+fn session_key(user_id: &str, params: &SegmentParams) -> SessionKey {
+	(user_id.to_owned(), params.media_path.clone(), params.quality_level.id)
+}
+
+fn segment_size(quality_level: &HlsQualityLevel) -> f64 {
+	(quality_level.video_bitrate + quality_level.audio_bitrate) as f64 / 8.0 * SEGMENT_DURATION
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::web_server::services::hls_segment_service;
+	
+	use super::*;
+	
+	fn segment(segment_index: usize) -> SegmentParams {
+		SegmentParams {
+			media_path: PathBuf::from("/media/video.mkv"),
+			segment_index,
+			// Small enough to never hit the budget
+			quality_level: hls_segment_service::get_quality_level("480p_1M_HEVC").unwrap(),
+		}
+	}
+	
+	fn test_config() -> TranscodingConfig {
+		TranscodingConfig {
+			min_prefetch_segments: 1,
+			max_prefetch_segments: 4,
+			prefetch_budget: 20_000_000,
+			prefetch_idle_timeout: 30,
+			..TranscodingConfig::default()
+		}
+	}
+	
+	#[tokio::test]
+	async fn test_prefetch_window() {
+		let prefetcher = SegmentPrefetcher::new(&test_config());
+		let start = Instant::now();
+		
+		// Nothing is known about the transcode speed yet
+		assert_eq!(prefetcher.segment_requested_at("user", &segment(0), start), vec![1]);
+		
+		// Slower than playback, so more segments are needed in flight
+		prefetcher.record_transcode_time(&segment(1), Duration::from_secs_f64(SEGMENT_DURATION * 1.5));
+		assert_eq!(prefetcher.segment_requested_at("user", &segment(1), start), vec![2, 3, 4]);
+		
+		// Very slow, but limited by the maximum
+		prefetcher.record_transcode_time(&segment(2), Duration::from_secs_f64(SEGMENT_DURATION * 20.0));
+		assert_eq!(prefetcher.segment_requested_at("user", &segment(2), start), vec![3, 4, 5, 6]);
+		
+		// High bitrates are limited by the budget
+		let mut high_bitrate = segment(0);
+		high_bitrate.quality_level = hls_segment_service::get_quality_level("1080p_15M").unwrap();
+		prefetcher.segment_requested_at("user", &high_bitrate, start);
+		prefetcher.record_transcode_time(&high_bitrate, Duration::from_secs_f64(SEGMENT_DURATION * 20.0));
+		assert_eq!(prefetcher.segment_requested_at("user", &high_bitrate, start), vec![1, 2]);
+		
+		// Other users have their own sessions
+		assert_eq!(prefetcher.segment_requested_at("other", &segment(0), start), vec![1]);
+	}
+	
+	#[tokio::test]
+	async fn test_shared_budget() {
+		let prefetcher = SegmentPrefetcher::new(&test_config());
+		let start = Instant::now();
+		
+		let mut high_bitrate = segment(0);
+		high_bitrate.quality_level = hls_segment_service::get_quality_level("1080p_15M").unwrap();
+		
+		prefetcher.segment_requested_at("user", &high_bitrate, start);
+		prefetcher.segment_requested_at("other", &high_bitrate, start);
+		prefetcher.record_transcode_time(&high_bitrate, Duration::from_secs_f64(SEGMENT_DURATION * 20.0));
+		
+		assert_eq!(prefetcher.segment_requested_at("user", &high_bitrate, start), vec![1, 2]);
+		
+		let prefetches: Vec<_> = [1, 2].into_iter()
+			.map(|segment_index| {
+				let task = tokio::spawn(std::future::pending::<()>());
+				let params = SegmentParams { segment_index, ..high_bitrate.clone() };
+				
+				prefetcher.add_prefetch("user", &params, task.abort_handle());
+				task
+			})
+			.collect();
+		
+		// The first viewer's prefetches use up the budget, so the other only gets the minimum
+		assert_eq!(prefetcher.segment_requested_at("other", &high_bitrate, start), vec![1]);
+		
+		for prefetch in prefetches {
+			prefetch.abort();
+		}
+	}
+	
+	#[tokio::test]
+	async fn test_prefetch_cancellation() {
+		let prefetcher = SegmentPrefetcher::new(&test_config());
+		let start = Instant::now();
+		
+		let spawn_prefetch = |segment_index: usize| {
+			let task = tokio::spawn(std::future::pending::<()>());
+			prefetcher.add_prefetch("user", &segment(segment_index), task.abort_handle());
+			task
+		};
+		
+		prefetcher.segment_requested_at("user", &segment(0), start);
+		prefetcher.record_transcode_time(&segment(0), Duration::from_secs_f64(SEGMENT_DURATION * 1.5));
+		assert_eq!(prefetcher.segment_requested_at("user", &segment(0), start), vec![1, 2, 3]);
+		
+		let prefetch_1 = spawn_prefetch(1);
+		let prefetch_2 = spawn_prefetch(2);
+		let prefetch_3 = spawn_prefetch(3);
+		
+		// Segments already being prefetched aren't returned again
+		assert_eq!(prefetcher.segment_requested_at("user", &segment(1), start), vec![4]);
+		
+		// Seeking away cancels everything outside the new window
+		assert_eq!(prefetcher.segment_requested_at("user", &segment(2), start), vec![4, 5]);
+		let prefetch_5 = spawn_prefetch(5);
+		
+		assert_eq!(prefetcher.segment_requested_at("user", &segment(40), start), vec![41, 42, 43]);
+		
+		assert!(prefetch_1.await.unwrap_err().is_cancelled());
+		assert!(prefetch_2.await.unwrap_err().is_cancelled());
+		assert!(prefetch_3.await.unwrap_err().is_cancelled());
+		assert!(prefetch_5.await.unwrap_err().is_cancelled());
+		
+		// Idle sessions are ended along with their prefetches
+		let prefetch_41 = spawn_prefetch(41);
+		
+		assert!(prefetcher.is_session_active("user", &segment(41)));
+		prefetcher.segment_requested_at("other", &segment(0), start + Duration::from_secs(31));
+		assert!(!prefetcher.is_session_active("user", &segment(41)));
+		
+		assert!(prefetch_41.await.unwrap_err().is_cancelled());
+	}
+}
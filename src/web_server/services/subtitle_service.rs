@@ -3,13 +3,15 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use anyhow::Context;
-use bytes::Bytes;
 use tracing::info;
 
 use crate::config::ServerConfig;
 use crate::media_manipulation::transcoding;
 use crate::utils;
-use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator};
+use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator, ArtifactOutput};
+use crate::web_server::services::cache_keys::CacheKeys;
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_storage;
 use crate::web_server::services::task_pool::TaskPool;
 
 #[derive(Debug, Clone)]
@@ -20,26 +22,35 @@ pub struct SubtitleParams {
 
 pub async fn init_service(
 	config: &ServerConfig,
+	cache_keys: Arc<CacheKeys>,
+	cache_budget: Arc<CacheBudget>,
 ) -> anyhow::Result<ArtifactCache<TranscodedSubtitleGenerator>> {
 	let transcoded_subtitle_generator = artifact_cache::builder()
 		.cache_dir(config.paths.subtitles_cache_dir.clone())
 		.task_pool(Arc::new(TaskPool::new(8)))
-		.file_size_limit(config.main_config.caches.subtitles_cache_size_limit)
-		.build(TranscodedSubtitleGenerator::new())
+		.storage(cache_storage::create_storage(&config.main_config.caches, &config.paths.subtitles_cache_dir)?)
+		.file_size_limit(config.main_config.caches.size_limit(config.main_config.caches.subtitles_cache_size_limit))
+		.max_entry_age(config.main_config.caches.max_entry_age())
+		.budget(cache_budget)
+		.build(TranscodedSubtitleGenerator::new(cache_keys))
 		.await?;
 
 	info!("Transcoded subtitles cache contains {}B, {}B max",
 		utils::abbreviate_number(transcoded_subtitle_generator.cache_size()),
-		utils::abbreviate_number(config.main_config.caches.subtitles_cache_size_limit));
+		utils::abbreviate_number(config.main_config.caches.size_limit(config.main_config.caches.subtitles_cache_size_limit)));
 
 	Ok(transcoded_subtitle_generator)
 }
 
-pub struct TranscodedSubtitleGenerator;
+pub struct TranscodedSubtitleGenerator {
+	cache_keys: Arc<CacheKeys>,
+}
 
 impl TranscodedSubtitleGenerator {
-	pub fn new() -> Self {
-		Self
+	pub fn new(cache_keys: Arc<CacheKeys>) -> Self {
+		Self {
+			cache_keys,
+		}
 	}
 }
 
@@ -48,12 +59,12 @@ impl ArtifactGenerator for TranscodedSubtitleGenerator {
 	type Metadata = ();
 
 	async fn create_cache_key(&self, input: &Self::Input) -> anyhow::Result<String> {
-		let file_hash = artifact_cache::create_file_metadata_hash(&input.media_path).await?;
+		let file_hash = self.cache_keys.file_key(&input.media_path).await?;
 
 		Ok(format!("{}_track_{}.vtt", file_hash, input.stream_index))
 	}
 
-	async fn generate_artifact(&self, input: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)> {
+	async fn generate_artifact(&self, input: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
 		info!("Transcoding subtitle for track {} of {:?}", input.stream_index, &input.media_path);
 		let start_time = Instant::now();
 		
@@ -63,6 +74,8 @@ impl ArtifactGenerator for TranscodedSubtitleGenerator {
 		
 		info!("Generated subtitle in {:?}", start_time.elapsed());
 		
-		Ok((data, ()))
+		output.write_all(&data).await?;
+		
+		Ok(())
 	}
 }
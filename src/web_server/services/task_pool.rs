@@ -1,35 +1,207 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::future::Future;
-use std::sync::Arc;
-use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+// Waiting tasks are started highest priority first, and in the order they arrived within a priority
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum TaskPriority {
+	// Filling the cache ahead of time
+	Background,
+	// Segments that will probably be needed soon
+	Prefetch,
+	// Someone is waiting for the result right now
+	Interactive,
+}
 
 pub struct TaskPool {
-	limiter: Arc<Semaphore>,
+	state: Arc<Mutex<PoolState>>,
+}
+
+struct PoolState {
+	available: usize,
+	// Only non-empty while nothing is available
+	waiting: BTreeMap<TaskPriority, VecDeque<oneshot::Sender<ReservedTask>>>,
 }
 
 impl TaskPool {
 	pub fn new(task_limit: usize) -> Self {
+		let state = PoolState {
+			available: task_limit,
+			waiting: BTreeMap::new(),
+		};
+		
 		Self {
-			limiter: Arc::new(Semaphore::new(task_limit)),
+			state: Arc::new(Mutex::new(state)),
 		}
 	}
 	
-	pub async fn reserve(&self) -> ReservedTask {
-		let permit = self.limiter.clone()
-			.acquire_owned()
-			.await
-			.unwrap();
-		
-		ReservedTask { _permit: permit }
+	// Dropping the returned future while it's waiting gives up its place in the queue
+	pub async fn reserve(&self, priority: TaskPriority) -> ReservedTask {
+		reserve(&self.state, priority).await
 	}
 }
 
+async fn reserve(pool: &Arc<Mutex<PoolState>>, priority: TaskPriority) -> ReservedTask {
+	let receiver = {
+		let mut state = pool.lock().unwrap();
+		
+		if state.available > 0 {
+			state.available -= 1;
+			
+			return ReservedTask { pool: Some(pool.clone()), priority };
+		}
+		
+		let (sender, receiver) = oneshot::channel();
+		state.waiting.entry(priority).or_default().push_back(sender);
+		
+		receiver
+	};
+	
+	// The sender is only dropped after handing over a task
+	receiver.await.unwrap()
+}
+
 pub struct ReservedTask {
-	// We hold the permit to reserve it, the field is never actually accessed.
-	_permit: OwnedSemaphorePermit,
+	// Only missing while being handed over, or while the slot is given up
+	pool: Option<Arc<Mutex<PoolState>>>,
+	priority: TaskPriority,
 }
 
 impl ReservedTask {
-	pub async fn execute_task<T>(self, task: impl Future<Output = T>) -> T {
-		task.await
+	// Lets someone else use the slot while waiting for something that doesn't need it, then waits for a slot again
+	pub async fn yield_while<T>(&mut self, future: impl Future<Output = T>) -> T {
+		let Some(pool) = self.pool.clone() else { return future.await };
+		
+		// Replacing it gives the slot back
+		*self = ReservedTask { pool: None, priority: self.priority };
+		
+		let result = future.await;
+		
+		*self = reserve(&pool, self.priority).await;
+		
+		result
+	}
+}
+
+impl Drop for ReservedTask {
+	fn drop(&mut self) {
+		let Some(mut pool) = self.pool.take() else { return };
+		
+		loop {
+			let (priority, sender) = {
+				let mut state = pool.lock().unwrap();
+				
+				let Some(mut queue) = state.waiting.last_entry() else {
+					state.available += 1;
+					return;
+				};
+				
+				let priority = *queue.key();
+				let sender = queue.get_mut().pop_front().unwrap();
+				
+				if queue.get().is_empty() {
+					queue.remove();
+				}
+				
+				(priority, sender)
+			};
+			
+			// Hand the slot straight to the next task, unless it stopped waiting
+			match sender.send(ReservedTask { pool: Some(pool), priority }) {
+				Ok(()) => return,
+				Err(mut task) => pool = task.pool.take().unwrap(),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::task::Poll;
+	
+	use futures_util::poll;
+	
+	use super::*;
+	
+	#[tokio::test]
+	async fn test_task_pool() {
+		let task_pool = Arc::new(TaskPool::new(1));
+		
+		let running = task_pool.reserve(TaskPriority::Background).await;
+		
+		let spawn_reserve = |priority: TaskPriority, id: u32| {
+			let task_pool = task_pool.clone();
+			
+			tokio::spawn(async move {
+				let reserved = task_pool.reserve(priority).await;
+				(reserved, id)
+			})
+		};
+		
+		let mut background = spawn_reserve(TaskPriority::Background, 1);
+		tokio::task::yield_now().await;
+		let mut prefetch = spawn_reserve(TaskPriority::Prefetch, 2);
+		tokio::task::yield_now().await;
+		let mut interactive = spawn_reserve(TaskPriority::Interactive, 3);
+		tokio::task::yield_now().await;
+		let mut interactive_2 = spawn_reserve(TaskPriority::Interactive, 4);
+		tokio::task::yield_now().await;
+		
+		assert!(matches!(poll!(&mut background), Poll::Pending));
+		assert!(matches!(poll!(&mut prefetch), Poll::Pending));
+		assert!(matches!(poll!(&mut interactive), Poll::Pending));
+		assert!(matches!(poll!(&mut interactive_2), Poll::Pending));
+		
+		// Giving up while waiting doesn't take a slot
+		interactive.abort();
+		assert!(matches!(interactive.await, Err(err) if err.is_cancelled()));
+		
+		drop(running);
+		
+		let (running, id) = interactive_2.await.unwrap();
+		assert_eq!(id, 4);
+		
+		tokio::task::yield_now().await;
+		assert!(matches!(poll!(&mut background), Poll::Pending));
+		assert!(matches!(poll!(&mut prefetch), Poll::Pending));
+		
+		drop(running);
+		
+		let (running, id) = prefetch.await.unwrap();
+		assert_eq!(id, 2);
+		
+		drop(running);
+		
+		let (running, id) = background.await.unwrap();
+		assert_eq!(id, 1);
+		
+		drop(running);
+		
+		assert_eq!(task_pool.state.lock().unwrap().available, 1);
+		assert!(task_pool.state.lock().unwrap().waiting.is_empty());
+	}
+	
+	#[tokio::test]
+	async fn test_yield_while() {
+		let task_pool = TaskPool::new(1);
+		
+		let mut running = task_pool.reserve(TaskPriority::Prefetch).await;
+		
+		let result = running.yield_while(async {
+			// The slot can be used while the task waits
+			let other = task_pool.reserve(TaskPriority::Background).await;
+			assert_eq!(task_pool.state.lock().unwrap().available, 0);
+			drop(other);
+			
+			5
+		}).await;
+		
+		assert_eq!(result, 5);
+		assert_eq!(task_pool.state.lock().unwrap().available, 0);
+		
+		drop(running);
+		
+		assert_eq!(task_pool.state.lock().unwrap().available, 1);
 	}
 }
@@ -1,45 +1,64 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
 use anyhow::Context;
 use bytes::Bytes;
+use mime::Mime;
 use tracing::info;
 
 use crate::config::ServerConfig;
 use crate::media_manipulation::thumbnail;
 use crate::utils;
 use crate::web_server::media_backend_factory::MediaBackendFactory;
-use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator};
-use crate::web_server::services::task_pool::TaskPool;
+use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator, ArtifactOutput};
+use crate::web_server::services::cache_keys::CacheKeys;
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_storage;
+use crate::web_server::services::task_pool::{TaskPool, TaskPriority};
+use crate::web_server::video_locator::LocatedFile;
+
+const IMAGE_EXTENSIONS: &[&str] = &[
+	"jpg",
+	"jpeg",
+	"png",
+	"webp"
+];
 
 pub async fn init_service(
 	config: &ServerConfig,
 	transcoding_task_pool: Arc<TaskPool>,
 	media_backend_factory: Arc<MediaBackendFactory>,
+	cache_keys: Arc<CacheKeys>,
+	cache_budget: Arc<CacheBudget>,
 ) -> anyhow::Result<ArtifactCache<ThumbnailGenerator>> {
 	let thumbnail_generator = artifact_cache::builder()
 		.cache_dir(config.paths.thumbnail_cache_dir.clone())
 		.task_pool(transcoding_task_pool)
-		.file_size_limit(config.main_config.caches.thumbnail_cache_size_limit)
-		.build(ThumbnailGenerator::new(media_backend_factory))
+		.storage(cache_storage::create_storage(&config.main_config.caches, &config.paths.thumbnail_cache_dir)?)
+		.file_size_limit(config.main_config.caches.size_limit(config.main_config.caches.thumbnail_cache_size_limit))
+		.max_entry_age(config.main_config.caches.max_entry_age())
+		.budget(cache_budget)
+		.build(ThumbnailGenerator::new(media_backend_factory, cache_keys))
 		.await?;
 
 	info!("Thumbnail cache contains {}B, {}B max",
 		utils::abbreviate_number(thumbnail_generator.cache_size()),
-		utils::abbreviate_number(config.main_config.caches.thumbnail_cache_size_limit));
+		utils::abbreviate_number(config.main_config.caches.size_limit(config.main_config.caches.thumbnail_cache_size_limit)));
 
 	Ok(thumbnail_generator)
 }
 
 pub struct ThumbnailGenerator {
 	media_backend_factory: Arc<MediaBackendFactory>,
+	cache_keys: Arc<CacheKeys>,
 }
 
 impl ThumbnailGenerator {
-	pub fn new(media_backend_factory: Arc<MediaBackendFactory>) -> Self {
+	pub fn new(media_backend_factory: Arc<MediaBackendFactory>, cache_keys: Arc<CacheKeys>) -> Self {
 		Self {
 			media_backend_factory,
+			cache_keys,
 		}
 	}
 }
@@ -49,12 +68,12 @@ impl ArtifactGenerator for ThumbnailGenerator {
 	type Metadata = ();
 
 	async fn create_cache_key(&self, media_path: &Self::Input) -> anyhow::Result<String> {
-		let file_hash = artifact_cache::create_file_metadata_hash(media_path).await?;
+		let file_hash = self.cache_keys.file_key(media_path).await?;
 
 		Ok(format!("{}.jpg", file_hash))
 	}
 
-	async fn generate_artifact(&self, media_path: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)> {
+	async fn generate_artifact(&self, media_path: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
 		let backend_factory = self.media_backend_factory.clone();
 		
 		info!("Generating thumbnail for {:?}", &media_path);
@@ -66,6 +85,61 @@ impl ArtifactGenerator for ThumbnailGenerator {
 		
 		info!("Generated thumbnail in {:?}", Instant::now() - start_time);
 		
-		Ok((data, ()))
+		output.write_all(&data).await?;
+		
+		Ok(())
 	}
 }
+
+pub struct ThumbnailFile {
+	pub file_data: Bytes,
+	pub mod_time: SystemTime,
+	pub mime_type: Mime,
+}
+
+pub async fn get_thumbnail(
+	located_file: LocatedFile,
+	thumbnail_generator: &ArtifactCache<ThumbnailGenerator>,
+	priority: TaskPriority,
+) -> anyhow::Result<Option<ThumbnailFile>> {
+	match located_file {
+		LocatedFile::File(media_path) => {
+			if let Some(file) = find_first_image(&media_path).await? {
+				Ok(Some(file))
+			} else {
+				let generated_thumbnail = thumbnail_generator.get_or_generate_with_priority(media_path, priority).await?;
+				
+				// Still read into memory since it's scaled down from the data
+				Ok(Some(ThumbnailFile {
+					file_data: generated_thumbnail.entry_file.read_all().await?,
+					mod_time: generated_thumbnail.creation_date.into(),
+					mime_type: mime::IMAGE_JPEG,
+				}))
+			}
+		}
+		LocatedFile::Directory(dir_path) => {
+			find_first_image(&dir_path.join("thumbnail")).await
+		}
+	}
+}
+
+async fn find_first_image(path: &Path) -> anyhow::Result<Option<ThumbnailFile>> {
+	for ext in IMAGE_EXTENSIONS {
+		let thumbnail_path = path.with_extension(ext);
+		
+		if let Some(thumbnail_metadata) = tokio::fs::metadata(&thumbnail_path).await.ok() {
+			let mod_time = thumbnail_metadata.modified()?;
+			
+			let mime_type = mime_guess::from_path(&thumbnail_path).first_or_octet_stream();
+			let data = tokio::fs::read(&thumbnail_path).await?;
+			
+			return Ok(Some(ThumbnailFile {
+				file_data: data.into(),
+				mod_time,
+				mime_type,
+			}));
+		}
+	}
+	
+	Ok(None)
+}
\ No newline at end of file
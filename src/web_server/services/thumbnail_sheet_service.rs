@@ -3,7 +3,6 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use anyhow::Context;
-use bytes::Bytes;
 use tracing::info;
 
 use crate::config::ServerConfig;
@@ -11,36 +10,46 @@ use crate::media_manipulation::thumbnail_sheet;
 use crate::media_manipulation::thumbnail_sheet::ThumbnailSheetParams;
 use crate::utils;
 use crate::web_server::media_backend_factory::MediaBackendFactory;
-use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator};
+use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator, ArtifactOutput};
+use crate::web_server::services::cache_keys::CacheKeys;
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_storage;
 use crate::web_server::services::task_pool::TaskPool;
 
 pub async fn init_service(
 	config: &ServerConfig,
 	transcoding_task_pool: Arc<TaskPool>,
 	media_backend_factory: Arc<MediaBackendFactory>,
+	cache_keys: Arc<CacheKeys>,
+	cache_budget: Arc<CacheBudget>,
 ) -> anyhow::Result<ArtifactCache<ThumbnailSheetGenerator>> {
 	let thumbnail_sheet_generator = artifact_cache::builder()
 		.cache_dir(config.paths.thumbnail_sheet_cache_dir.clone())
 		.task_pool(transcoding_task_pool)
-		.file_size_limit(config.main_config.caches.thumbnail_sheet_cache_size_limit)
-		.build(ThumbnailSheetGenerator::new(media_backend_factory.clone()))
+		.storage(cache_storage::create_storage(&config.main_config.caches, &config.paths.thumbnail_sheet_cache_dir)?)
+		.file_size_limit(config.main_config.caches.size_limit(config.main_config.caches.thumbnail_sheet_cache_size_limit))
+		.max_entry_age(config.main_config.caches.max_entry_age())
+		.budget(cache_budget)
+		.build(ThumbnailSheetGenerator::new(media_backend_factory.clone(), cache_keys))
 		.await?;
 
 	info!("Thumbnail sheet cache contains {}B, {}B max",
 		utils::abbreviate_number(thumbnail_sheet_generator.cache_size()),
-		utils::abbreviate_number(config.main_config.caches.thumbnail_sheet_cache_size_limit));
+		utils::abbreviate_number(config.main_config.caches.size_limit(config.main_config.caches.thumbnail_sheet_cache_size_limit)));
 
 	Ok(thumbnail_sheet_generator)
 }
 
 pub struct ThumbnailSheetGenerator {
 	media_backend_factory: Arc<MediaBackendFactory>,
+	cache_keys: Arc<CacheKeys>,
 }
 
 impl ThumbnailSheetGenerator {
-	pub fn new(media_backend_factory: Arc<MediaBackendFactory>) -> Self {
+	pub fn new(media_backend_factory: Arc<MediaBackendFactory>, cache_keys: Arc<CacheKeys>) -> Self {
 		Self {
 			media_backend_factory,
+			cache_keys,
 		}
 	}
 }
@@ -50,23 +59,25 @@ impl ArtifactGenerator for ThumbnailSheetGenerator {
 	type Metadata = ThumbnailSheetParams;
 
 	async fn create_cache_key(&self, media_path: &Self::Input) -> anyhow::Result<String> {
-		let file_hash = artifact_cache::create_file_metadata_hash(media_path).await?;
+		let file_hash = self.cache_keys.file_key(media_path).await?;
 
 		Ok(format!("{}.webp", file_hash))
 	}
 
-	async fn generate_artifact(&self, media_path: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)> {
+	async fn generate_artifact(&self, media_path: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
 		let backend_factory = self.media_backend_factory.clone();
 		
 		info!("Generating thumbnail sheet for {:?}", &media_path);
 		let start_time = Instant::now();
 		
-		let result = tokio::task::spawn_blocking(move || {
-			thumbnail_sheet::generate_sheet(backend_factory.as_ref(), media_path)
+		let mut writer = output.blocking_writer().await?;
+		
+		let sheet_params = tokio::task::spawn_blocking(move || {
+			thumbnail_sheet::generate_sheet(backend_factory.as_ref(), media_path, &mut writer)
 		}).await.context("Panic")??;
 		
 		info!("Generated thumbnail sheet in {:?}", Instant::now() - start_time);
 		
-		Ok(result)
+		Ok(sheet_params)
 	}
 }
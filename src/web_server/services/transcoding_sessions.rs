@@ -0,0 +1,521 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+use tracing::{debug, info};
+
+use crate::media_manipulation::transcoding::session::TranscodingSession;
+use crate::media_manipulation::transcoding::TranscodingOptions;
+use crate::web_server::media_backend_factory::MediaBackendFactory;
+use crate::web_server::services::hls_segment_service::{SegmentParams, SEGMENT_DURATION};
+
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Every session keeps its decoder and encoder around, which can take a lot of memory
+const MAX_SESSIONS: usize = 8;
+
+// Prefetched segments can be requested out of order, so requests a little ahead of a session still go to it
+const MAX_SEGMENT_LOOKAHEAD: usize = 8;
+
+// How long a session waits for a segment it skipped over to be requested before transcoding through it anyway
+const SKIPPED_SEGMENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Sessions always finish the segment they're on, see transcode_up_to
+static NOT_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+// Starts a session at the given segment
+type SessionStarter = Arc<dyn Fn(&SegmentParams, usize) -> anyhow::Result<Box<dyn SegmentTranscoder>> + Send + Sync>;
+
+trait SegmentTranscoder {
+	fn transcode_next_segment(&mut self, end_time: f64, cancel_flag: &AtomicBool, output: &mut dyn Write) -> anyhow::Result<()>;
+}
+
+impl SegmentTranscoder for TranscodingSession {
+	fn transcode_next_segment(&mut self, end_time: f64, cancel_flag: &AtomicBool, output: &mut dyn Write) -> anyhow::Result<()> {
+		TranscodingSession::transcode_next_segment(self, end_time, cancel_flag, output)
+	}
+}
+
+// Transcoding sessions that continue from where the last segment ended, so sequential playback doesn't pay for opening
+//  the file, seeking and setting up codecs on every segment. Each runs on its own thread, since the codecs can't be
+//  moved between threads, and stops once it has been idle for a while, or once its handle is dropped and it has nothing
+//  left to do.
+pub struct TranscodingSessions {
+	start_session: SessionStarter,
+	idle_timeout: Duration,
+	sessions: Mutex<Vec<SessionHandle>>,
+}
+
+struct SessionHandle {
+	media_path: PathBuf,
+	quality_level_id: &'static str,
+	// The next segment the session will transcode
+	position: Arc<AtomicUsize>,
+	last_used: Instant,
+	requests: mpsc::Sender<SegmentRequest>,
+	// Requests that were sent to the session and haven't been finished or dropped yet
+	in_flight: Arc<AtomicUsize>,
+}
+
+impl SessionHandle {
+	fn is_busy(&self) -> bool {
+		self.in_flight.load(Ordering::Relaxed) > 0
+	}
+	
+	// Gives the request back if the session has stopped
+	fn send(&self, mut request: SegmentRequest) -> Result<(), SegmentRequest> {
+		request.in_flight = Some(InFlightGuard::new(self.in_flight.clone()));
+		
+		self.requests.send(request).map_err(|mpsc::SendError(request)| request)
+	}
+}
+
+struct SegmentRequest {
+	params: SegmentParams,
+	// Sent once the session gets to this request, after which it waits for the go-ahead and the file to write to
+	turn: oneshot::Sender<()>,
+	start: oneshot::Receiver<File>,
+	result: oneshot::Sender<anyhow::Result<()>>,
+	in_flight: Option<InFlightGuard>,
+}
+
+// Counts a request as in flight for its session until it's dropped
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightGuard {
+	fn new(in_flight: Arc<AtomicUsize>) -> Self {
+		in_flight.fetch_add(1, Ordering::Relaxed);
+		
+		Self(in_flight)
+	}
+}
+
+impl Drop for InFlightGuard {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+// A segment waiting for its session. Dropping it cancels the request without stopping the session.
+pub struct QueuedSegment {
+	turn: oneshot::Receiver<()>,
+	start: oneshot::Sender<File>,
+	result: oneshot::Receiver<anyhow::Result<()>>,
+}
+
+impl QueuedSegment {
+	// Resolves once the session gets to this segment, which takes a while if earlier segments are queued before it
+	pub async fn wait_for_turn(&mut self) {
+		let _ = (&mut self.turn).await;
+	}
+	
+	// Writes the segment to the output as it's transcoded. Returns None if the session stopped before getting to this
+	//  segment, in which case nothing was written.
+	pub async fn transcode(self, output: File) -> Option<anyhow::Result<()>> {
+		self.start.send(output).ok()?;
+		self.result.await.ok()
+	}
+}
+
+impl TranscodingSessions {
+	pub fn new(media_backend_factory: Arc<MediaBackendFactory>) -> Self {
+		let start_session: SessionStarter = Arc::new(move |params: &SegmentParams, segment_index: usize| {
+			let opts = TranscodingOptions {
+				backend_factory: media_backend_factory.as_ref(),
+				media_path: params.media_path.clone(),
+				target_video_height: params.quality_level.target_video_height,
+				video_codec: params.quality_level.video_codec.as_ffmpeg_codec(),
+				video_bitrate: params.quality_level.video_bitrate,
+				audio_bitrate: params.quality_level.audio_bitrate,
+				cancel_flag: &NOT_CANCELLED,
+			};
+			
+			info!("Starting transcoding session at segment {} at {} for {:?}",
+				segment_index, params.quality_level.id, &params.media_path);
+			
+			let session = TranscodingSession::new(opts, segment_index as f64 * SEGMENT_DURATION)?;
+			
+			Ok(Box::new(session) as Box<dyn SegmentTranscoder>)
+		});
+		
+		Self::with_starter(start_session, SESSION_IDLE_TIMEOUT)
+	}
+	
+	fn with_starter(start_session: SessionStarter, idle_timeout: Duration) -> Self {
+		Self {
+			start_session,
+			idle_timeout,
+			sessions: Mutex::new(Vec::new()),
+		}
+	}
+	
+	// Queues the segment on the session that's positioned at or a little before it, or starts a new one from it after a
+	//  seek. Sessions transcode their requests in order.
+	pub fn queue_segment(&self, params: SegmentParams) -> QueuedSegment {
+		let (turn_sender, turn_receiver) = oneshot::channel();
+		let (start_sender, start_receiver) = oneshot::channel();
+		let (result_sender, result_receiver) = oneshot::channel();
+		
+		self.send_request(SegmentRequest {
+			params,
+			turn: turn_sender,
+			start: start_receiver,
+			result: result_sender,
+			in_flight: None,
+		});
+		
+		QueuedSegment {
+			turn: turn_receiver,
+			start: start_sender,
+			result: result_receiver,
+		}
+	}
+	
+	fn send_request(&self, request: SegmentRequest) {
+		let mut sessions = self.sessions.lock().unwrap();
+		let now = Instant::now();
+		
+		// The threads of these have stopped by themselves already. Sessions that are still working through their
+		//  requests are kept, however long ago those were queued.
+		sessions.retain(|session| session.is_busy() || now.duration_since(session.last_used) < self.idle_timeout);
+		
+		let media_path = request.params.media_path.clone();
+		let quality_level_id = request.params.quality_level.id;
+		let segment_index = request.params.segment_index;
+		
+		let existing_session = sessions.iter().position(|session| {
+			let position = session.position.load(Ordering::Relaxed);
+			
+			session.media_path == media_path &&
+				session.quality_level_id == quality_level_id &&
+				(position..position + MAX_SEGMENT_LOOKAHEAD).contains(&segment_index)
+		});
+		
+		let request = match existing_session {
+			Some(session_index) => {
+				let session = &mut sessions[session_index];
+				
+				match session.send(request) {
+					Ok(()) => {
+						session.last_used = now;
+						
+						return;
+					}
+					// The session stopped after a failure
+					Err(request) => {
+						sessions.remove(session_index);
+						request
+					}
+				}
+			}
+			None => request,
+		};
+		
+		// Busy sessions are left to finish, so there can be more than MAX_SESSIONS for a while
+		if sessions.len() >= MAX_SESSIONS &&
+			let Some((oldest_index, _)) = sessions.iter().enumerate()
+				.filter(|(_, session)| !session.is_busy())
+				.min_by_key(|(_, session)| session.last_used) {
+			sessions.remove(oldest_index);
+		}
+		
+		let (requests, requests_receiver) = mpsc::channel();
+		let position = Arc::new(AtomicUsize::new(segment_index));
+		
+		let thread_state = SessionThread {
+			start_session: self.start_session.clone(),
+			idle_timeout: self.idle_timeout,
+			position: position.clone(),
+		};
+		
+		std::thread::spawn(move || thread_state.run(requests_receiver));
+		
+		let session = SessionHandle {
+			media_path,
+			quality_level_id,
+			position,
+			last_used: now,
+			requests,
+			in_flight: Arc::new(AtomicUsize::new(0)),
+		};
+		
+		// The receiver is owned by the new thread, so this can't fail
+		let _ = session.send(request);
+		
+		sessions.push(session);
+	}
+}
+
+struct SessionThread {
+	start_session: SessionStarter,
+	idle_timeout: Duration,
+	position: Arc<AtomicUsize>,
+}
+
+impl SessionThread {
+	fn run(self, requests: mpsc::Receiver<SegmentRequest>) {
+		let mut session = None;
+		let mut queued: BTreeMap<usize, SegmentRequest> = BTreeMap::new();
+		let mut skipped_since = None;
+		
+		loop {
+			// Requests that were cancelled while queued are just dropped, the rest of the session carries on
+			queued.retain(|_, request| !request.turn.is_closed());
+			
+			let position = self.position.load(Ordering::Relaxed);
+			
+			let wait_time = match queued.first_key_value() {
+				Some((&segment_index, _)) if segment_index == position => None,
+				// A segment before the queued ones might still be requested
+				Some(_) => {
+					let skipped_since = *skipped_since.get_or_insert_with(Instant::now);
+					
+					Some(SKIPPED_SEGMENT_TIMEOUT.saturating_sub(skipped_since.elapsed()))
+				}
+				None => Some(self.idle_timeout),
+			};
+			
+			if let Some(wait_time) = wait_time {
+				match requests.recv_timeout(wait_time) {
+					Ok(request) => {
+						// Requests for segments the session has already passed are dropped, and done on their own
+						if request.params.segment_index >= position {
+							queued.insert(request.params.segment_index, request);
+						}
+						
+						continue;
+					}
+					// Whatever was queued before the handle was dropped still gets done
+					Err(_) if !queued.is_empty() => {}
+					// Idle for too long, or replaced by another session
+					Err(_) => return,
+				}
+			}
+			
+			let (_, request) = queued.pop_first().unwrap();
+			skipped_since = None;
+			
+			// The requester only takes a task slot once the session is ready for it
+			if request.turn.send(()).is_err() {
+				continue;
+			}
+			
+			let Ok(mut output) = request.start.blocking_recv() else { continue };
+			
+			let result = self.transcode_up_to(&mut session, &request.params, &mut output);
+			let failed = result.is_err();
+			
+			let _ = request.result.send(result);
+			
+			// A failure can leave the session partway through a segment, so it's only continued after successes
+			if failed {
+				return;
+			}
+		}
+	}
+	
+	// Sessions only move forward, so segments that weren't requested are transcoded on the way and thrown away. Segments
+	//  that get cancelled partway are finished anyway, stopping would leave the session unusable.
+	fn transcode_up_to(
+		&self,
+		session: &mut Option<Box<dyn SegmentTranscoder>>,
+		params: &SegmentParams,
+		output: &mut File,
+	) -> anyhow::Result<()> {
+		if session.is_none() {
+			*session = Some((self.start_session)(params, self.position.load(Ordering::Relaxed))?);
+		}
+		
+		let session = session.as_mut().unwrap();
+		
+		loop {
+			let segment_index = self.position.load(Ordering::Relaxed);
+			let segment_end = (segment_index + 1) as f64 * SEGMENT_DURATION;
+			
+			if segment_index == params.segment_index {
+				session.transcode_next_segment(segment_end, &NOT_CANCELLED, output)?;
+				self.position.store(segment_index + 1, Ordering::Relaxed);
+				
+				return Ok(());
+			}
+			
+			session.transcode_next_segment(segment_end, &NOT_CANCELLED, &mut io::sink())?;
+			self.position.store(segment_index + 1, Ordering::Relaxed);
+			
+			debug!("Transcoded skipped segment {} of {:?}", segment_index, &params.media_path);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Read, Seek};
+	
+	use super::*;
+	use crate::web_server::services::hls_segment_service::QUALITY_LEVELS;
+	
+	// Produces the index of each segment, and logs when sessions start and stop
+	struct TestSession {
+		segment_index: usize,
+		log: Arc<Mutex<Vec<String>>>,
+	}
+	
+	impl SegmentTranscoder for TestSession {
+		fn transcode_next_segment(&mut self, end_time: f64, _cancel_flag: &AtomicBool, output: &mut dyn Write) -> anyhow::Result<()> {
+			assert_eq!(end_time, (self.segment_index + 1) as f64 * SEGMENT_DURATION);
+			
+			self.segment_index += 1;
+			
+			Ok(write!(output, "{}", self.segment_index - 1)?)
+		}
+	}
+	
+	impl Drop for TestSession {
+		fn drop(&mut self) {
+			self.log.lock().unwrap().push(format!("stop {}", self.segment_index));
+		}
+	}
+	
+	fn create_test_sessions(idle_timeout: Duration) -> (TranscodingSessions, Arc<Mutex<Vec<String>>>) {
+		let log = Arc::new(Mutex::new(Vec::new()));
+		let session_log = log.clone();
+		
+		let start_session: SessionStarter = Arc::new(move |_params: &SegmentParams, segment_index: usize| {
+			session_log.lock().unwrap().push(format!("start {}", segment_index));
+			
+			Ok(Box::new(TestSession {
+				segment_index,
+				log: session_log.clone(),
+			}) as Box<dyn SegmentTranscoder>)
+		});
+		
+		(TranscodingSessions::with_starter(start_session, idle_timeout), log)
+	}
+	
+	fn queue(sessions: &TranscodingSessions, segment_index: usize) -> QueuedSegment {
+		sessions.queue_segment(SegmentParams {
+			media_path: PathBuf::from("video.mp4"),
+			segment_index,
+			quality_level: QUALITY_LEVELS[0].clone(),
+		})
+	}
+	
+	async fn transcode(mut queued_segment: QueuedSegment) -> String {
+		queued_segment.wait_for_turn().await;
+		
+		transcode_after_turn(queued_segment).await
+	}
+	
+	async fn transcode_after_turn(queued_segment: QueuedSegment) -> String {
+		let mut output = tempfile::tempfile().unwrap();
+		queued_segment.transcode(output.try_clone().unwrap()).await.unwrap().unwrap();
+		
+		let mut data = String::new();
+		output.rewind().unwrap();
+		output.read_to_string(&mut data).unwrap();
+		
+		data
+	}
+	
+	fn take_log(log: &Mutex<Vec<String>>) -> Vec<String> {
+		std::mem::take(&mut *log.lock().unwrap())
+	}
+	
+	#[tokio::test]
+	async fn test_session_reuse() {
+		let (sessions, log) = create_test_sessions(SESSION_IDLE_TIMEOUT);
+		
+		for segment_index in 0..3 {
+			assert_eq!(transcode(queue(&sessions, segment_index)).await, segment_index.to_string());
+		}
+		
+		// Out of order requests from prefetching go to the same session
+		let (segment_4, segment_3) = tokio::join!(transcode(queue(&sessions, 4)), transcode(queue(&sessions, 3)));
+		assert_eq!((segment_4.as_str(), segment_3.as_str()), ("4", "3"));
+		
+		// Segments that are never requested are skipped over
+		assert_eq!(transcode(queue(&sessions, 6)).await, "6");
+		
+		// As are requests that get cancelled, without stopping the session
+		drop(queue(&sessions, 7));
+		assert_eq!(transcode(queue(&sessions, 8)).await, "8");
+		
+		assert_eq!(take_log(&log), ["start 0"]);
+		assert_eq!(sessions.sessions.lock().unwrap().len(), 1);
+	}
+	
+	#[tokio::test]
+	async fn test_seek() {
+		let (sessions, log) = create_test_sessions(SESSION_IDLE_TIMEOUT);
+		
+		for segment_index in 0..3 {
+			assert_eq!(transcode(queue(&sessions, segment_index)).await, segment_index.to_string());
+		}
+		
+		// Too far ahead to transcode through
+		assert_eq!(transcode(queue(&sessions, 20)).await, "20");
+		assert_eq!(transcode(queue(&sessions, 21)).await, "21");
+		
+		// Sessions can't go back
+		assert_eq!(transcode(queue(&sessions, 1)).await, "1");
+		
+		assert_eq!(take_log(&log), ["start 0", "start 20", "start 1"]);
+		
+		// Each continues where it left off
+		assert_eq!(transcode(queue(&sessions, 22)).await, "22");
+		assert_eq!(transcode(queue(&sessions, 2)).await, "2");
+		
+		assert!(take_log(&log).is_empty());
+	}
+	
+	#[tokio::test]
+	async fn test_idle_timeout() {
+		let (sessions, log) = create_test_sessions(Duration::from_millis(100));
+		
+		assert_eq!(transcode(queue(&sessions, 0)).await, "0");
+		assert_eq!(transcode(queue(&sessions, 1)).await, "1");
+		
+		// Stops without waiting for another request
+		tokio::time::sleep(Duration::from_millis(300)).await;
+		assert_eq!(take_log(&log), ["start 0", "stop 2"]);
+		
+		assert_eq!(transcode(queue(&sessions, 2)).await, "2");
+		assert_eq!(take_log(&log), ["start 2"]);
+		assert_eq!(sessions.sessions.lock().unwrap().len(), 1);
+	}
+	
+	#[tokio::test]
+	async fn test_busy_sessions() {
+		let (sessions, log) = create_test_sessions(Duration::from_millis(100));
+		
+		// Waits for the go-ahead for longer than the idle timeout
+		let mut segment_0 = queue(&sessions, 0);
+		segment_0.wait_for_turn().await;
+		tokio::time::sleep(Duration::from_millis(300)).await;
+		
+		let segment_1 = queue(&sessions, 1);
+		
+		// Busy sessions aren't replaced to make room for new ones either
+		let mut other_segments = Vec::new();
+		
+		for session_index in 1..=MAX_SESSIONS {
+			let mut other_segment = queue(&sessions, session_index * 100);
+			other_segment.wait_for_turn().await;
+			
+			other_segments.push(other_segment);
+		}
+		
+		assert_eq!(sessions.sessions.lock().unwrap().len(), MAX_SESSIONS + 1);
+		drop(other_segments);
+		
+		assert_eq!(transcode_after_turn(segment_0).await, "0");
+		assert_eq!(transcode(segment_1).await, "1");
+		assert_eq!(take_log(&log), ["start 0"]);
+	}
+}
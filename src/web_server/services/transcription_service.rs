@@ -5,15 +5,19 @@ use std::time::Instant;
 use crate::config::ServerConfig;
 use crate::media_manipulation::transcription;
 use crate::utils;
-use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator};
+use crate::web_server::services::artifact_cache::{self, ArtifactCache, ArtifactGenerator, ArtifactOutput};
+use crate::web_server::services::cache_keys::CacheKeys;
+use crate::web_server::services::cache_budget::CacheBudget;
+use crate::web_server::services::cache_storage;
 use crate::web_server::services::task_pool::TaskPool;
 use anyhow::Context;
-use bytes::Bytes;
 use parakeet_rs::{ExecutionConfig, ParakeetTDT};
 use tracing::info;
 
 pub async fn init_service(
 	config: &ServerConfig,
+	cache_keys: Arc<CacheKeys>,
+	cache_budget: Arc<CacheBudget>,
 ) -> anyhow::Result<Option<ArtifactCache<AutoTranscriptionGenerator>>> {
 	let Some(model_path) = config.main_config.transcription.parakeet_model.clone() else { return Ok(None) };
 	
@@ -34,13 +38,16 @@ pub async fn init_service(
 	let auto_subtitle_generator = artifact_cache::builder()
 		.cache_dir(config.paths.auto_subtitles_cache_dir.clone())
 		.task_pool(Arc::new(TaskPool::new(1)))
-		.file_size_limit(config.main_config.caches.auto_subtitles_cache_size_limit)
-		.build(AutoTranscriptionGenerator::new(model))
+		.storage(cache_storage::create_storage(&config.main_config.caches, &config.paths.auto_subtitles_cache_dir)?)
+		.file_size_limit(config.main_config.caches.size_limit(config.main_config.caches.auto_subtitles_cache_size_limit))
+		.max_entry_age(config.main_config.caches.max_entry_age())
+		.budget(cache_budget)
+		.build(AutoTranscriptionGenerator::new(model, cache_keys))
 		.await?;
 	
 	info!("Transcribed subtitle cache contains {}B, {}B max",
 		utils::abbreviate_number(auto_subtitle_generator.cache_size()),
-		utils::abbreviate_number(config.main_config.caches.auto_subtitles_cache_size_limit));
+		utils::abbreviate_number(config.main_config.caches.size_limit(config.main_config.caches.auto_subtitles_cache_size_limit)));
 	
 	Ok(Some(auto_subtitle_generator))
 }
@@ -53,12 +60,14 @@ pub struct AutoSubtitleParams {
 
 pub struct AutoTranscriptionGenerator {
 	model: Arc<Mutex<ParakeetTDT>>,
+	cache_keys: Arc<CacheKeys>,
 }
 
 impl AutoTranscriptionGenerator {
-	pub fn new(model: Arc<Mutex<ParakeetTDT>>) -> Self {
+	pub fn new(model: Arc<Mutex<ParakeetTDT>>, cache_keys: Arc<CacheKeys>) -> Self {
 		Self {
 			model,
+			cache_keys,
 		}
 	}
 }
@@ -71,12 +80,12 @@ impl ArtifactGenerator for AutoTranscriptionGenerator {
 	type Metadata = ();
 
 	async fn create_cache_key(&self, input: &Self::Input) -> anyhow::Result<String> {
-		let file_hash = artifact_cache::create_file_metadata_hash(&input.media_path).await?;
+		let file_hash = self.cache_keys.file_key(&input.media_path).await?;
 
 		Ok(format!("{}_auto_s{}.vtt", file_hash, input.segment_index))
 	}
 
-	async fn generate_artifact(&self, input: Self::Input) -> anyhow::Result<(Bytes, Self::Metadata)> {
+	async fn generate_artifact(&self, input: Self::Input, output: &mut ArtifactOutput) -> anyhow::Result<Self::Metadata> {
 		info!("Transcribing subtitle for segment {} of {:?}", input.segment_index, &input.media_path);
 		let start_time = Instant::now();
 		
@@ -93,6 +102,8 @@ impl ArtifactGenerator for AutoTranscriptionGenerator {
 		
 		info!("Transcribed segment in {:?}", start_time.elapsed());
 		
-		Ok((data.as_bytes().to_vec().into(), ()))
+		output.write_all(data.as_bytes()).await?;
+		
+		Ok(())
 	}
 }
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use relative_path::{RelativePath, RelativePathBuf};
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+use tracing::error;
+
+use crate::config::ViewingStatsConfig;
+use crate::web_server::data_store::{DataStore, DataStores, JsonData, JsonFile};
+use crate::web_server::api_types::{ApiActivityBucket, ApiDirectoryViewingStats, ApiLibraryActivity, ApiUserViewingStats, ApiViewingStats};
+
+const STATS_VERSION: u32 = 1;
+
+// Progress updates further apart than this start a new session. The player reports progress every minute.
+const SESSION_TIMEOUT: time::Duration = time::Duration::minutes(5);
+// Progress that moved faster than this between two updates was a seek, so only this much of it counts as watched
+const MAX_PLAYBACK_RATE: f64 = 2.0;
+const TOP_DIRECTORIES: usize = 20;
+const STALE_SESSION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Log of playback sessions derived from watch progress updates, used for activity reports
+pub struct ViewingStats {
+	sessions: Vec<PlaybackSession>,
+	// Sessions still receiving progress updates, only written out once they end
+	open_sessions: HashMap<(String, String, String), OpenSession>,
+	config: ViewingStatsConfig,
+	json_file: JsonFile,
+}
+
+impl ViewingStats {
+	pub async fn load(stats_file: PathBuf, config: ViewingStatsConfig, data_stores: &DataStores) -> anyhow::Result<Arc<Mutex<Self>>> {
+		// These are only statistics, so losing them isn't worth refusing to start over
+		let (json_file, serialized) = match JsonFile::load::<Self>(stats_file.clone()).await {
+			Ok(loaded) => loaded,
+			Err(err) => {
+				error!("Viewing stats are unreadable, starting over: {:?}", err);
+				(JsonFile::new(stats_file), None)
+			}
+		};
+		
+		let dirty_notify = json_file.dirty_notify();
+		
+		let mut viewing_stats = Self {
+			sessions: serialized.map_or_else(Vec::new, |serialized| serialized.sessions),
+			open_sessions: HashMap::new(),
+			config,
+			json_file,
+		};
+		
+		viewing_stats.prune(OffsetDateTime::now_utc());
+		
+		let arc_self = Arc::new(Mutex::new(viewing_stats));
+		
+		data_stores.start_saving(arc_self.clone(), dirty_notify);
+		tokio::spawn(Self::stale_session_task(arc_self.clone()));
+		
+		Ok(arc_self)
+	}
+	
+	#[cfg(test)]
+	fn new(stats_file: PathBuf, config: ViewingStatsConfig) -> Self {
+		Self {
+			sessions: Vec::new(),
+			open_sessions: HashMap::new(),
+			config,
+			json_file: JsonFile::new(stats_file),
+		}
+	}
+	
+	// Otherwise a session would stay open until the next progress update from anyone
+	async fn stale_session_task(arc_self: Arc<Mutex<Self>>) {
+		let mut interval = tokio::time::interval(STALE_SESSION_CHECK_INTERVAL);
+		
+		loop {
+			interval.tick().await;
+			
+			arc_self.lock().unwrap().end_stale_sessions(OffsetDateTime::now_utc());
+		}
+	}
+	
+	pub fn mark_dirty(&mut self) {
+		self.json_file.mark_dirty();
+	}
+	
+	pub fn record_progress(&mut self, user_id: &str, library_id: &str, media_path: &RelativePath, position: u64) {
+		self.record_progress_at(user_id, library_id, media_path, position, OffsetDateTime::now_utc());
+	}
+	
+	fn record_progress_at(&mut self, user_id: &str, library_id: &str, media_path: &RelativePath, position: u64, now: OffsetDateTime) {
+		if !self.config.enabled {
+			return;
+		}
+		
+		self.end_stale_sessions(now);
+		
+		let key = (user_id.to_owned(), library_id.to_owned(), media_path.normalize().as_str().to_lowercase());
+		
+		if let Some(session) = self.open_sessions.get_mut(&key) {
+			let elapsed = (now - session.last_update).as_seconds_f64().max(0.0);
+			
+			if position > session.last_position {
+				session.watched_seconds += ((position - session.last_position) as f64).min(elapsed * MAX_PLAYBACK_RATE);
+			}
+			
+			session.last_position = position;
+			session.last_update = now;
+		} else {
+			self.open_sessions.insert(key, OpenSession {
+				library_id: library_id.to_owned(),
+				media_path: media_path.normalize(),
+				started: now,
+				last_update: now,
+				last_position: position,
+				watched_seconds: 0.0,
+			});
+		}
+	}
+	
+	fn end_stale_sessions(&mut self, now: OffsetDateTime) {
+		let stale_keys: Vec<_> = self.open_sessions.iter()
+			.filter(|(_, session)| now - session.last_update > SESSION_TIMEOUT)
+			.map(|(key, _)| key.clone())
+			.collect();
+		
+		for key in stale_keys {
+			let session = self.open_sessions.remove(&key).unwrap();
+			self.finish_session(&key.0, session);
+		}
+	}
+	
+	// Called on shutdown, since open sessions aren't saved
+	fn end_open_sessions(&mut self) {
+		for ((user_id, _, _), session) in std::mem::take(&mut self.open_sessions) {
+			self.finish_session(&user_id, session);
+		}
+	}
+	
+	fn finish_session(&mut self, user_id: &str, session: OpenSession) {
+		// Opening a video and leaving straight away isn't worth keeping
+		if session.watched_seconds < 1.0 {
+			return;
+		}
+		
+		self.sessions.push(session.to_session(user_id));
+		self.mark_dirty();
+	}
+	
+	fn prune(&mut self, now: OffsetDateTime) {
+		let cutoff = now - time::Duration::days(self.config.retention_days.into());
+		
+		self.sessions.retain(|session| session.ended >= cutoff);
+		
+		if self.sessions.len() > self.config.max_sessions {
+			let excess = self.sessions.len() - self.config.max_sessions;
+			self.sessions.drain(..excess);
+		}
+	}
+	
+	// Every session that started in the range, including ones that are still going
+	pub fn sessions_between(&self, from: OffsetDateTime, to: OffsetDateTime) -> Vec<PlaybackSession> {
+		let open_sessions = self.open_sessions.iter()
+			.map(|((user_id, _, _), session)| session.to_session(user_id));
+		
+		self.sessions.iter()
+			.cloned()
+			.chain(open_sessions)
+			.filter(|session| session.started >= from && session.started < to)
+			.collect()
+	}
+	
+	pub fn remove_user(&mut self, user_id: &str) {
+		self.sessions.retain(|session| session.user_id != user_id);
+		self.open_sessions.retain(|(session_user_id, _, _), _| session_user_id != user_id);
+		
+		self.mark_dirty();
+	}
+}
+
+struct OpenSession {
+	library_id: String,
+	media_path: RelativePathBuf,
+	started: OffsetDateTime,
+	last_update: OffsetDateTime,
+	last_position: u64,
+	watched_seconds: f64,
+}
+
+impl OpenSession {
+	fn to_session(&self, user_id: &str) -> PlaybackSession {
+		PlaybackSession {
+			user_id: user_id.to_owned(),
+			library_id: self.library_id.clone(),
+			media_path: self.media_path.clone(),
+			started: self.started,
+			ended: self.last_update,
+			watched_seconds: self.watched_seconds.round() as u64,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackSession {
+	pub user_id: String,
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	#[serde(with = "time::serde::iso8601")]
+	pub started: OffsetDateTime,
+	#[serde(with = "time::serde::iso8601")]
+	pub ended: OffsetDateTime,
+	pub watched_seconds: u64,
+}
+
+impl JsonData for ViewingStats {
+	const NAME: &'static str = "viewing stats";
+	const VERSION: u32 = STATS_VERSION;
+	const PRETTY: bool = false;
+	
+	type Serialized = SerializedViewingStats;
+	
+	fn json_file(&mut self) -> &mut JsonFile {
+		&mut self.json_file
+	}
+	
+	fn to_serialized(&mut self) -> SerializedViewingStats {
+		self.prune(OffsetDateTime::now_utc());
+		
+		SerializedViewingStats {
+			sessions: self.sessions.clone(),
+		}
+	}
+}
+
+impl DataStore for ViewingStats {
+	fn save(arc_self: &Arc<Mutex<Self>>) -> impl Future<Output = ()> + Send {
+		JsonFile::save(arc_self)
+	}
+	
+	fn prepare_shutdown(&mut self) {
+		self.end_open_sessions();
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializedViewingStats {
+	sessions: Vec<PlaybackSession>,
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsInterval {
+	#[default]
+	Day,
+	Week,
+	Month,
+}
+
+impl StatsInterval {
+	fn bucket_start(self, date: Date) -> Date {
+		match self {
+			StatsInterval::Day => date,
+			StatsInterval::Week => date - time::Duration::days(date.weekday().number_days_from_monday().into()),
+			StatsInterval::Month => date.replace_day(1).unwrap(),
+		}
+	}
+}
+
+// Totals per user, the most watched directories and each library's activity over time. Sessions count towards
+//  the bucket they started in.
+pub fn aggregate(
+	sessions: &[PlaybackSession],
+	interval: StatsInterval,
+	display_name: impl Fn(&str) -> Option<String>,
+) -> ApiViewingStats {
+	let mut users: HashMap<&str, Totals> = HashMap::new();
+	let mut directories: HashMap<(&str, RelativePathBuf), Totals> = HashMap::new();
+	let mut libraries: HashMap<&str, HashMap<Date, Totals>> = HashMap::new();
+	
+	for session in sessions {
+		users.entry(&session.user_id).or_default().add(session);
+		
+		let directory = session.media_path.parent().map(RelativePath::to_owned).unwrap_or_default();
+		directories.entry((&session.library_id, directory)).or_default().add(session);
+		
+		libraries.entry(&session.library_id).or_default()
+			.entry(interval.bucket_start(session.started.date())).or_default()
+			.add(session);
+	}
+	
+	let mut users: Vec<ApiUserViewingStats> = users.into_iter()
+		.map(|(user_id, totals)| ApiUserViewingStats {
+			user_id: user_id.to_owned(),
+			display_name: display_name(user_id),
+			hours: totals.hours(),
+			sessions: totals.sessions,
+		})
+		.collect();
+	
+	users.sort_by(|a, b| b.hours.total_cmp(&a.hours).then_with(|| a.user_id.cmp(&b.user_id)));
+	
+	let mut directories: Vec<ApiDirectoryViewingStats> = directories.into_iter()
+		.map(|((library_id, path), totals)| ApiDirectoryViewingStats {
+			library_id: library_id.to_owned(),
+			path,
+			hours: totals.hours(),
+			sessions: totals.sessions,
+		})
+		.collect();
+	
+	directories.sort_by(|a, b| b.hours.total_cmp(&a.hours)
+		.then_with(|| a.library_id.cmp(&b.library_id))
+		.then_with(|| a.path.cmp(&b.path)));
+	directories.truncate(TOP_DIRECTORIES);
+	
+	let mut libraries: Vec<ApiLibraryActivity> = libraries.into_iter()
+		.map(|(library_id, buckets)| {
+			let mut buckets: Vec<ApiActivityBucket> = buckets.into_iter()
+				.map(|(start, totals)| ApiActivityBucket {
+					start,
+					hours: totals.hours(),
+					sessions: totals.sessions,
+				})
+				.collect();
+			
+			buckets.sort_by_key(|bucket| bucket.start);
+			
+			ApiLibraryActivity {
+				library_id: library_id.to_owned(),
+				buckets,
+			}
+		})
+		.collect();
+	
+	libraries.sort_by(|a, b| a.library_id.cmp(&b.library_id));
+	
+	ApiViewingStats {
+		users,
+		directories,
+		libraries,
+	}
+}
+
+#[derive(Default)]
+struct Totals {
+	watched_seconds: u64,
+	sessions: u64,
+}
+
+impl Totals {
+	fn add(&mut self, session: &PlaybackSession) {
+		self.watched_seconds += session.watched_seconds;
+		self.sessions += 1;
+	}
+	
+	fn hours(&self) -> f64 {
+		self.watched_seconds as f64 / 3600.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::datetime;
+	
+	use super::*;
+	
+	#[test]
+	fn test_sessions() {
+		let mut stats = ViewingStats::new(PathBuf::new(), ViewingStatsConfig::default());
+		let path = RelativePath::new("Shows/Episode 1");
+		let start = datetime!(2024-03-01 20:00 UTC);
+		
+		stats.record_progress_at("user", "lib", path, 0, start);
+		stats.record_progress_at("user", "lib", path, 60, start + time::Duration::minutes(1));
+		// Skipping ahead only counts for as long as could have been watched
+		stats.record_progress_at("user", "lib", path, 1000, start + time::Duration::minutes(2));
+		stats.record_progress_at("user", "lib", path, 1060, start + time::Duration::minutes(3));
+		
+		assert!(stats.sessions.is_empty());
+		
+		let open_sessions = stats.sessions_between(start, start + time::Duration::days(1));
+		assert_eq!(open_sessions.len(), 1);
+		assert_eq!(open_sessions[0].watched_seconds, 240);
+		
+		// Coming back later is a new session
+		stats.record_progress_at("user", "lib", RelativePath::new("shows/episode 1"), 1060, start + time::Duration::hours(2));
+		stats.record_progress_at("other", "lib", path, 0, start + time::Duration::hours(2));
+		
+		assert_eq!(stats.sessions.len(), 1);
+		assert_eq!(stats.sessions[0].started, start);
+		assert_eq!(stats.sessions[0].ended, start + time::Duration::minutes(3));
+		
+		// Nothing was watched in these
+		stats.end_open_sessions();
+		assert_eq!(stats.sessions.len(), 1);
+		
+		stats.remove_user("user");
+		assert!(stats.sessions.is_empty());
+		
+		// Sessions nobody updates anymore get closed without waiting for someone else's progress
+		let later = start + time::Duration::hours(4);
+		
+		stats.record_progress_at("user", "lib", path, 0, later);
+		stats.record_progress_at("user", "lib", path, 60, later + time::Duration::minutes(1));
+		
+		stats.end_stale_sessions(later + time::Duration::minutes(3));
+		assert!(stats.sessions.is_empty());
+		
+		stats.end_stale_sessions(later + time::Duration::hours(1));
+		assert_eq!(stats.sessions.len(), 1);
+		assert!(stats.open_sessions.is_empty());
+	}
+	
+	#[test]
+	fn test_prune() {
+		let mut stats = ViewingStats::new(PathBuf::new(), ViewingStatsConfig {
+			enabled: true,
+			retention_days: 30,
+			max_sessions: 2,
+		});
+		
+		let now = datetime!(2024-03-01 00:00 UTC);
+		
+		for days_ago in [40, 3, 2, 1] {
+			let started = now - time::Duration::days(days_ago);
+			
+			stats.sessions.push(PlaybackSession {
+				user_id: "user".to_owned(),
+				library_id: "lib".to_owned(),
+				media_path: RelativePathBuf::from(format!("Movie {}", days_ago)),
+				started,
+				ended: started + time::Duration::hours(1),
+				watched_seconds: 3600,
+			});
+		}
+		
+		stats.prune(now);
+		
+		let paths: Vec<_> = stats.sessions.iter().map(|session| session.media_path.as_str()).collect();
+		assert_eq!(paths, vec!["Movie 2", "Movie 1"]);
+	}
+	
+	#[test]
+	fn test_aggregate() {
+		let session = |user_id: &str, library_id: &str, media_path: &str, started: OffsetDateTime, watched_seconds: u64| PlaybackSession {
+			user_id: user_id.to_owned(),
+			library_id: library_id.to_owned(),
+			media_path: RelativePathBuf::from(media_path),
+			started,
+			ended: started,
+			watched_seconds,
+		};
+		
+		let sessions = vec![
+			session("a", "shows", "Show/Season 1/Episode 1", datetime!(2024-03-04 20:00 UTC), 1800),
+			session("a", "shows", "Show/Season 1/Episode 2", datetime!(2024-03-10 20:00 UTC), 1800),
+			session("b", "shows", "Show/Season 1/Episode 1", datetime!(2024-03-11 20:00 UTC), 3600),
+			session("b", "movies", "Movie", datetime!(2024-03-12 20:00 UTC), 7200),
+		];
+		
+		let stats = aggregate(&sessions, StatsInterval::Week, |user_id| Some(user_id.to_uppercase()));
+		
+		assert_eq!(stats.users.len(), 2);
+		assert_eq!(stats.users[0].display_name.as_deref(), Some("B"));
+		assert_eq!(stats.users[0].hours, 3.0);
+		assert_eq!(stats.users[1].hours, 1.0);
+		assert_eq!(stats.users[1].sessions, 2);
+		
+		assert_eq!(stats.directories[0].library_id, "movies");
+		assert_eq!(stats.directories[0].path.as_str(), "");
+		assert_eq!(stats.directories[1].path.as_str(), "Show/Season 1");
+		assert_eq!(stats.directories[1].hours, 2.0);
+		
+		let shows = stats.libraries.iter().find(|library| library.library_id == "shows").unwrap();
+		let buckets: Vec<_> = shows.buckets.iter().map(|bucket| (bucket.start, bucket.sessions)).collect();
+		assert_eq!(buckets, vec![(time::macros::date!(2024-03-04), 2), (time::macros::date!(2024-03-11), 1)]);
+	}
+}
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use anyhow::bail;
 use futures_util::future::join_all;
 use hashlink::linked_hash_map::Entry;
 use hashlink::LinkedHashMap;
@@ -10,72 +12,166 @@ use relative_path::{RelativePath, RelativePathBuf};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use tokio::sync::Notify;
+use tracing::{error, warn};
 
+use crate::utils::{add_extension, write_data_file, BACKUP_EXT};
 use crate::web_server::auth::AuthManager;
-use crate::web_server::video_locator;
+use crate::web_server::data_store::{DataStore, DataStores};
+use crate::web_server::history_remap;
+use crate::web_server::libraries::Libraries;
+use crate::web_server::media_metadata::BasicMediaMetadata;
+use crate::web_server::metadata_cache::FileMetadataCache;
+use crate::web_server::video_locator::{self, LocatedFile};
 
 pub struct UserWatchHistories {
 	watch_histories: HashMap<String, WatchHistory>,
+	removed_users: Vec<String>,
 	dirty_notify: Arc<Notify>,
+	watch_histories_dir: PathBuf,
+	// Keeps a periodic save and a shutdown flush from writing the same files at once
+	save_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl UserWatchHistories {
-	pub async fn load(users: &AuthManager, watch_histories_dir: PathBuf) -> anyhow::Result<Arc<Mutex<Self>>> {
+	pub async fn load(
+		users: &AuthManager,
+		watch_histories_dir: PathBuf,
+		migration: &HistoryMigration<'_>,
+		data_stores: &DataStores,
+	) -> anyhow::Result<Arc<Mutex<Self>>> {
 		tokio::fs::create_dir_all(&watch_histories_dir).await?;
 		
 		let mut watch_histories = HashMap::new();
 		
-		for user in users.iter_users() {
+		for user in users.list_users() {
 			let history_file = watch_histories_dir.join(format!("{}.json", user.id));
 			
-			watch_histories.insert(user.id.clone(), WatchHistory::load(history_file).await?);
+			watch_histories.insert(user.id.clone(), WatchHistory::load(history_file, migration).await?);
 		}
 		
 		let dirty_notify = Arc::new(Notify::new());
 		
 		let arc_self = Arc::new(Mutex::new(Self {
 			watch_histories,
+			removed_users: Vec::new(),
 			dirty_notify: dirty_notify.clone(),
+			watch_histories_dir,
+			save_lock: Arc::new(tokio::sync::Mutex::new(())),
 		}));
 		
-		tokio::spawn(Self::save_task(arc_self.clone(), dirty_notify, watch_histories_dir));
+		data_stores.start_saving(arc_self.clone(), dirty_notify);
 		
 		Ok(arc_self)
 	}
 	
-	async fn save_task(arc_self: Arc<Mutex<Self>>, dirty_notify: Arc<Notify>, watch_histories_dir: PathBuf) {
-		loop {
-			dirty_notify.notified().await;
+	// Writes out every history that changed since the last save
+	async fn save_histories(arc_self: &Arc<Mutex<Self>>) {
+		let save_lock = arc_self.lock().unwrap().save_lock.clone();
+		let _save_guard = save_lock.lock().await;
+		
+		let mut pending_writes = Vec::new();
+		let removed_users;
+		let watch_histories_dir;
+		
+		{
+			let mut mut_self = arc_self.lock().unwrap();
 			
-			let mut futures = Vec::new();
+			removed_users = std::mem::take(&mut mut_self.removed_users);
+			watch_histories_dir = mut_self.watch_histories_dir.clone();
 			
-			{
-				let mut_self = arc_self.lock().unwrap();
-				
-				for (user_id, watch_history) in mut_self.watch_histories.iter() {
-					if watch_history.dirty {
-						let history_file = watch_histories_dir.join(format!("{}.json", user_id));
-						let ser_watch_history = watch_history.serialize();
-						let data = serde_json::to_vec_pretty(&ser_watch_history).unwrap();
-						
-						futures.push(tokio::fs::write(history_file, data));
-					}
+			for (user_id, watch_history) in mut_self.watch_histories.iter_mut() {
+				if watch_history.dirty {
+					let data = serde_json::to_vec_pretty(&watch_history.serialize()).unwrap();
+					
+					// Cleared before writing so that changes made while the write is in progress aren't lost
+					watch_history.dirty = false;
+					
+					pending_writes.push((user_id.clone(), data));
 				}
 			}
+		}
+		
+		let results = join_all(pending_writes.into_iter().map(|(user_id, data)| {
+			let history_file = watch_histories_dir.join(format!("{}.json", user_id));
 			
-			join_all(futures).await;
+			async move { (user_id, write_data_file(&history_file, &data).await) }
+		})).await;
+		
+		let mut failed = false;
+		
+		for (user_id, result) in results {
+			if let Err(err) = result {
+				error!("Error saving watch history for {}: {:?}", user_id, err);
+				
+				if let Some(watch_history) = arc_self.lock().unwrap().watch_histories.get_mut(&user_id) {
+					watch_history.dirty = true;
+					failed = true;
+				}
+			}
+		}
+		
+		for user_id in removed_users {
+			let history_file = watch_histories_dir.join(format!("{}.json", user_id));
 			
-			tokio::time::sleep(Duration::from_secs(60)).await;
+			let _ = tokio::fs::remove_file(&history_file).await;
+			let _ = tokio::fs::remove_file(add_extension(&history_file, BACKUP_EXT)).await;
+		}
+		
+		// Try again on the next round instead of waiting for another change
+		if failed {
+			arc_self.lock().unwrap().mark_dirty();
 		}
 	}
 	
 	pub fn get_watch_history(&mut self, user_id: &str) -> &mut WatchHistory {
-		self.watch_histories.get_mut(user_id).expect("Unknown user id")
+		// Users can be created at runtime, so they won't have a history loaded yet
+		if !self.watch_histories.contains_key(user_id) {
+			self.removed_users.retain(|id| id != user_id);
+		}
+		
+		self.watch_histories.entry(user_id.to_owned())
+			.or_insert_with(|| WatchHistory::new(Vec::new()))
+	}
+	
+	pub fn remove_user(&mut self, user_id: &str) {
+		self.watch_histories.remove(user_id);
+		self.removed_users.push(user_id.to_owned());
+		
+		self.mark_dirty();
 	}
 	
 	pub fn mark_dirty(&self) {
 		self.dirty_notify.notify_one();
 	}
+	
+	// Rewrites the entries of every user, for when files get moved around on disk
+	pub fn remap_prefix(
+		&mut self,
+		library_id: &str,
+		old_prefix: &RelativePath,
+		new_library_id: &str,
+		new_prefix: &RelativePath,
+	) -> usize {
+		let moved_count = self.watch_histories.values_mut()
+			.map(|watch_history| watch_history.remap_prefix(library_id, old_prefix, new_library_id, new_prefix))
+			.sum();
+		
+		if moved_count > 0 {
+			self.mark_dirty();
+		}
+		
+		moved_count
+	}
+	
+	pub fn iter_all_entries(&self) -> impl Iterator<Item = &WatchHistoryEntry> {
+		self.watch_histories.values().flat_map(WatchHistory::iter_entries)
+	}
+}
+
+impl DataStore for UserWatchHistories {
+	fn save(arc_self: &Arc<Mutex<Self>>) -> impl Future<Output = ()> + Send {
+		Self::save_histories(arc_self)
+	}
 }
 
 pub struct WatchHistory {
@@ -84,17 +180,68 @@ pub struct WatchHistory {
 }
 
 impl WatchHistory {
-	pub async fn load(history_file: PathBuf) -> anyhow::Result<Self> {
-		let entries = if tokio::fs::try_exists(&history_file).await? {
-			let data = tokio::fs::read(&history_file).await?;
-			let watch_history: SerializedWatchHistory = serde_json::from_slice(&data)?;
-			
-			watch_history.entries
-		} else {
-			Vec::new()
+	// Falls back to the backup from the previous save if the history file is missing or damaged, and starts
+	//  over with an empty history if both are unusable rather than keeping the server from starting
+	pub async fn load(history_file: PathBuf, migration: &HistoryMigration<'_>) -> anyhow::Result<Self> {
+		let backup_file = add_extension(&history_file, BACKUP_EXT);
+		
+		let main_result = Self::load_file(&history_file, migration).await;
+		
+		let err = match main_result {
+			Ok(Some(watch_history)) => return Ok(watch_history),
+			Ok(None) => None,
+			Err(err) => Some(err),
 		};
 		
-		Ok(Self::new(entries))
+		match Self::load_file(&backup_file, migration).await {
+			Ok(Some(mut watch_history)) => {
+				warn!("Watch history {:?} is unreadable, restored it from backup: {:?}", &history_file, err);
+				
+				// The next save would otherwise replace the good backup with the damaged file
+				if err.is_some() {
+					Self::set_aside_damaged_file(&history_file).await?;
+				}
+				
+				watch_history.dirty = true;
+				
+				Ok(watch_history)
+			}
+			Ok(None) if err.is_none() => Ok(Self::new(Vec::new())),
+			backup_result => {
+				error!("Watch history {:?} and its backup are unreadable, starting over: {:?}, {:?}",
+					&history_file, err, backup_result.err());
+				
+				if err.is_some() {
+					Self::set_aside_damaged_file(&history_file).await?;
+				}
+				
+				Ok(Self::new(Vec::new()))
+			}
+		}
+	}
+	
+	// Keeps a damaged file around in case it can be fixed by hand
+	async fn set_aside_damaged_file(history_file: &Path) -> anyhow::Result<()> {
+		tokio::fs::rename(history_file, add_extension(history_file, CORRUPT_EXT)).await?;
+		
+		Ok(())
+	}
+	
+	async fn load_file(history_file: &Path, migration: &HistoryMigration<'_>) -> anyhow::Result<Option<Self>> {
+		if !tokio::fs::try_exists(history_file).await? {
+			return Ok(None);
+		}
+		
+		let data = tokio::fs::read(history_file).await?;
+		let watch_history: SerializedWatchHistory = serde_json::from_slice(&data)?;
+		
+		let migrated = watch_history.version < WATCH_HISTORY_VERSION;
+		let mut watch_history = Self::new(migration.migrate(watch_history).await?);
+		
+		// Write the new format back out on the next save
+		watch_history.dirty = migrated;
+		
+		Ok(Some(watch_history))
 	}
 	
 	pub fn new(mut ser_entries: Vec<SerializedWatchHistoryEntry>) -> Self {
@@ -110,8 +257,12 @@ impl WatchHistory {
 			let entry = WatchHistoryEntry {
 				library_id: ser_entry.library_id,
 				media_path,
+				first_watched: ser_entry.first_watched.unwrap_or(ser_entry.last_watched),
 				last_watched: ser_entry.last_watched,
 				progress: ser_entry.progress,
+				watched: ser_entry.watched,
+				play_count: ser_entry.play_count,
+				fingerprint: ser_entry.fingerprint,
 			};
 			
 			entries.insert(key, entry);
@@ -137,26 +288,86 @@ impl WatchHistory {
 		self.entries.get(&MediaKey::new(library_id, media_path))
 	}
 	
-	pub fn update_progress(&mut self, library_id: &str, media_path: &RelativePath, new_progress: u64) {
+	// Returns whether this update finished the video
+	pub fn update_progress(&mut self, library_id: &str, media_path: &RelativePath, new_progress: u64, completion: Completion) -> bool {
 		let media_path = normalize_path(media_path);
 		let updated_time = OffsetDateTime::now_utc();
+		let completed;
 		
 		match self.entries.entry(MediaKey::new(library_id, media_path.clone())) {
 			Entry::Occupied(mut entry) => {
 				let key = entry.key().clone();
 				
 				let entry = entry.get_mut();
+				
+				// Only count a play when crossing the threshold, not on every update past it
+				completed = completion.is_finished(new_progress) && !completion.is_finished(entry.progress);
+				
+				if completed {
+					entry.watched = true;
+					entry.play_count += 1;
+				}
+				
 				entry.last_watched = updated_time;
 				entry.progress = new_progress;
 				
 				self.entries.to_back(&key);
 			}
 			Entry::Vacant(entry) => {
+				completed = completion.is_finished(new_progress);
+				
 				entry.insert(WatchHistoryEntry {
 					library_id: library_id.to_owned(),
 					media_path,
+					first_watched: updated_time,
 					last_watched: updated_time,
 					progress: new_progress,
+					watched: completed,
+					play_count: completed as u32,
+					fingerprint: None,
+				});
+			}
+		}
+		
+		self.dirty = true;
+		
+		completed
+	}
+	
+	// Marking a video as watched moves its progress to the end, and marking it unwatched resets it while keeping
+	//  its play count
+	pub fn set_watched(&mut self, library_id: &str, media_path: &RelativePath, watched: bool, duration: Duration) {
+		let media_path = normalize_path(media_path);
+		let progress = if watched { duration.as_secs() } else { 0 };
+		
+		match self.entries.entry(MediaKey::new(library_id, media_path.clone())) {
+			Entry::Occupied(mut entry) => {
+				let entry = entry.get_mut();
+				
+				if watched && !entry.watched {
+					entry.play_count = entry.play_count.max(1);
+				}
+				
+				entry.watched = watched;
+				entry.progress = progress;
+			}
+			Entry::Vacant(entry) => {
+				// There's nothing to reset for videos that were never watched
+				if !watched {
+					return;
+				}
+				
+				let updated_time = OffsetDateTime::now_utc();
+				
+				entry.insert(WatchHistoryEntry {
+					library_id: library_id.to_owned(),
+					media_path,
+					first_watched: updated_time,
+					last_watched: updated_time,
+					progress,
+					watched: true,
+					play_count: 1,
+					fingerprint: None,
 				});
 			}
 		}
@@ -172,22 +383,211 @@ impl WatchHistory {
 		self.dirty = true;
 	}
 	
-	fn serialize(&self) -> SerializedWatchHistory {
+	// Merges in entries exported from somewhere else. Progress is taken from whichever side watched the video
+	//  more recently, and play counts aren't added together since the histories might overlap.
+	pub fn import_entries(&mut self, ser_entries: Vec<SerializedWatchHistoryEntry>) {
+		for ser_entry in ser_entries {
+			self.merge_entry(WatchHistoryEntry {
+				library_id: ser_entry.library_id,
+				media_path: normalize_path(&ser_entry.media_path),
+				first_watched: ser_entry.first_watched.unwrap_or(ser_entry.last_watched),
+				last_watched: ser_entry.last_watched,
+				progress: ser_entry.progress,
+				watched: ser_entry.watched,
+				play_count: ser_entry.play_count,
+				fingerprint: ser_entry.fingerprint,
+			});
+		}
+		
+		self.sort_entries();
+		
+		self.dirty = true;
+	}
+	
+	// Moves every entry under a path prefix to a new prefix, which might be in another library. Returns how many
+	//  entries were moved.
+	pub fn remap_prefix(
+		&mut self,
+		library_id: &str,
+		old_prefix: &RelativePath,
+		new_library_id: &str,
+		new_prefix: &RelativePath,
+	) -> usize {
+		let (moved, kept): (Vec<_>, Vec<_>) = self.entries.drain()
+			.map(|(_, entry)| {
+				let new_path = (entry.library_id == library_id)
+					.then(|| history_remap::remap_path(&entry.media_path, old_prefix, new_prefix))
+					.flatten();
+				
+				(entry, new_path)
+			})
+			.partition(|(_, new_path)| new_path.is_some());
+		
+		let moved_count = moved.len();
+		
+		for (entry, _) in kept {
+			self.entries.insert(MediaKey::new(&entry.library_id, entry.media_path.clone()), entry);
+		}
+		
+		for (mut entry, new_path) in moved {
+			entry.library_id = new_library_id.to_owned();
+			entry.media_path = normalize_path(&new_path.unwrap());
+			
+			self.merge_entry(entry);
+		}
+		
+		if moved_count > 0 {
+			self.sort_entries();
+			
+			self.dirty = true;
+		}
+		
+		moved_count
+	}
+	
+	pub fn set_fingerprint(&mut self, library_id: &str, media_path: &RelativePath, fingerprint: String) {
+		let media_path = normalize_path(media_path);
+		
+		if let Some(entry) = self.entries.get_mut(&MediaKey::new(library_id, media_path)) &&
+			entry.fingerprint.as_ref() != Some(&fingerprint) {
+			entry.fingerprint = Some(fingerprint);
+			
+			self.dirty = true;
+		}
+	}
+	
+	// Adds an entry, combining it with any existing entry for the same video. Progress is taken from whichever side
+	//  watched the video more recently, and play counts aren't added together since the two might overlap.
+	fn merge_entry(&mut self, new_entry: WatchHistoryEntry) {
+		match self.entries.entry(MediaKey::new(&new_entry.library_id, new_entry.media_path.clone())) {
+			Entry::Occupied(mut entry) => {
+				let entry = entry.get_mut();
+				
+				entry.first_watched = entry.first_watched.min(new_entry.first_watched);
+				entry.play_count = entry.play_count.max(new_entry.play_count);
+				
+				if new_entry.last_watched > entry.last_watched {
+					entry.last_watched = new_entry.last_watched;
+					entry.progress = new_entry.progress;
+					entry.watched = new_entry.watched;
+					entry.fingerprint = new_entry.fingerprint.or(entry.fingerprint.take());
+				}
+			}
+			Entry::Vacant(entry) => {
+				entry.insert(new_entry);
+			}
+		}
+	}
+	
+	// Keep entries ordered by when they were last watched
+	fn sort_entries(&mut self) {
+		let mut entries: Vec<_> = self.entries.drain().collect();
+		entries.sort_by_key(|(_, entry)| entry.last_watched);
+		
+		self.entries.extend(entries);
+	}
+	
+	pub fn serialize(&self) -> SerializedWatchHistory {
 		SerializedWatchHistory {
-			version: 1,
+			version: WATCH_HISTORY_VERSION,
 			entries: self.entries.values()
 				.map(|entry| SerializedWatchHistoryEntry {
 					library_id: entry.library_id.clone(),
 					media_path: entry.media_path.clone(),
+					first_watched: Some(entry.first_watched),
 					last_watched: entry.last_watched,
 					progress: entry.progress,
+					watched: entry.watched,
+					play_count: entry.play_count,
+					fingerprint: entry.fingerprint.clone(),
 				})
 				.collect(),
 		}
 	}
 }
 
-fn normalize_path(path: &RelativePath) -> RelativePathBuf {
+// Whether a video has been watched far enough to count as finished, with the threshold being a fraction of its duration
+pub fn is_finished(progress: u64, duration: Duration, finished_threshold: f64) -> bool {
+	progress as f64 >= duration.as_secs_f64() * finished_threshold
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Completion {
+	pub duration: Duration,
+	pub finished_threshold: f64,
+}
+
+impl Completion {
+	fn is_finished(&self, progress: u64) -> bool {
+		is_finished(progress, self.duration, self.finished_threshold)
+	}
+}
+
+const WATCH_HISTORY_VERSION: u32 = 2;
+
+const CORRUPT_EXT: &str = "corrupt";
+
+// Looking up durations reads every video, which is slow on network storage when done one at a time
+const MIGRATION_CONCURRENCY: usize = 16;
+
+// Version 1 histories didn't record whether videos were finished, so migrating them needs the videos' durations
+pub struct HistoryMigration<'a> {
+	pub libraries: &'a Libraries,
+	pub metadata_cache: &'a FileMetadataCache,
+	pub finished_threshold: f64,
+}
+
+impl HistoryMigration<'_> {
+	async fn migrate(&self, watch_history: SerializedWatchHistory) -> anyhow::Result<Vec<SerializedWatchHistoryEntry>> {
+		let mut durations = Vec::new();
+		
+		if watch_history.version == 1 {
+			for chunk in watch_history.entries.chunks(MIGRATION_CONCURRENCY) {
+				durations.extend(join_all(chunk.iter().map(|entry| self.video_duration(&entry.library_id, &entry.media_path))).await);
+			}
+		}
+		
+		migrate_entries(watch_history, &durations, self.finished_threshold)
+	}
+	
+	async fn video_duration(&self, library_id: &str, media_path: &RelativePath) -> Option<Duration> {
+		let resolved_path = self.libraries.resolve_path(library_id, media_path.to_owned()).ok()?;
+		let media_path = video_locator::locate_video(&resolved_path).await.and_then(LocatedFile::file).ok()?;
+		
+		let metadata = self.metadata_cache.fetch_metadata::<BasicMediaMetadata>(&media_path).await.ok()?;
+		
+		Some(metadata.duration)
+	}
+}
+
+// Durations are given in the same order as the entries, and are only needed for version 1
+fn migrate_entries(
+	watch_history: SerializedWatchHistory,
+	durations: &[Option<Duration>],
+	finished_threshold: f64,
+) -> anyhow::Result<Vec<SerializedWatchHistoryEntry>> {
+	let mut entries = watch_history.entries;
+	
+	match watch_history.version {
+		1 => {
+			// Version 1 only kept the last time something was watched and how far, and every entry was played at
+			//  least once. Videos that can't be found anymore are left unwatched.
+			for (i, entry) in entries.iter_mut().enumerate() {
+				entry.first_watched = Some(entry.last_watched);
+				entry.play_count = 1;
+				entry.watched = durations.get(i).copied().flatten()
+					.is_some_and(|duration| is_finished(entry.progress, duration, finished_threshold));
+			}
+		}
+		WATCH_HISTORY_VERSION => {}
+		version => bail!("Unsupported watch history version {}", version),
+	}
+	
+	Ok(entries)
+}
+
+// Videos are tracked without their extension, the same way they're listed
+pub fn normalize_path(path: &RelativePath) -> RelativePathBuf {
 	let mut path = path.normalize();
 	
 	if path.extension().is_some_and(|ext| video_locator::MEDIA_EXTENSIONS.contains(&ext)) {
@@ -223,21 +623,316 @@ impl MediaKey {
 pub struct WatchHistoryEntry {
 	pub library_id: String,
 	pub media_path: RelativePathBuf,
+	pub first_watched: OffsetDateTime,
 	pub last_watched: OffsetDateTime,
 	pub progress: u64,
+	pub watched: bool,
+	pub play_count: u32,
+	// Used to find the video again if it gets moved
+	pub fingerprint: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SerializedWatchHistory {
-	version: u32,
-	entries: Vec<SerializedWatchHistoryEntry>,
+	pub version: u32,
+	pub entries: Vec<SerializedWatchHistoryEntry>,
+}
+
+impl SerializedWatchHistory {
+	pub async fn from_json(data: &[u8], migration: &HistoryMigration<'_>) -> anyhow::Result<Self> {
+		let watch_history: SerializedWatchHistory = serde_json::from_slice(data)?;
+		
+		Ok(SerializedWatchHistory {
+			version: WATCH_HISTORY_VERSION,
+			entries: migration.migrate(watch_history).await?,
+		})
+	}
+	
+	// CSV exports have one entry per row, always in the current format
+	pub fn from_csv(data: &[u8]) -> anyhow::Result<Self> {
+		let entries = csv::Reader::from_reader(data)
+			.deserialize()
+			.collect::<Result<Vec<SerializedWatchHistoryEntry>, _>>()?;
+		
+		Ok(SerializedWatchHistory {
+			version: WATCH_HISTORY_VERSION,
+			entries,
+		})
+	}
+	
+	pub fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+		let mut writer = csv::Writer::from_writer(Vec::new());
+		
+		for entry in &self.entries {
+			writer.serialize(entry)?;
+		}
+		
+		Ok(writer.into_inner()?)
+	}
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SerializedWatchHistoryEntry {
-	library_id: String,
-	media_path: RelativePathBuf,
+	pub library_id: String,
+	pub media_path: RelativePathBuf,
+	#[serde(default, with = "time::serde::iso8601::option")]
+	pub first_watched: Option<OffsetDateTime>,
 	#[serde(with = "time::serde::iso8601")]
-	last_watched: OffsetDateTime,
-	progress: u64,
-}
\ No newline at end of file
+	pub last_watched: OffsetDateTime,
+	pub progress: u64,
+	#[serde(default)]
+	pub watched: bool,
+	#[serde(default)]
+	pub play_count: u32,
+	#[serde(default)]
+	pub fingerprint: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::TempDir;
+	
+	use crate::config::LibrariesConfig;
+	
+	use super::*;
+	
+	#[test]
+	fn test_is_finished() {
+		let duration = Duration::from_secs(1000);
+		
+		assert!(!is_finished(0, duration, 0.9));
+		assert!(!is_finished(899, duration, 0.9));
+		assert!(is_finished(900, duration, 0.9));
+		assert!(is_finished(1000, duration, 0.9));
+		assert!(is_finished(0, Duration::ZERO, 0.9));
+	}
+	
+	#[test]
+	fn test_migrate_v1() {
+		let data = r#"{
+			"version": 1,
+			"entries": [
+				{
+					"library_id": "lib",
+					"media_path": "Shows/Episode 1.mp4",
+					"last_watched": "+002024-05-01T12:00:00.000000000Z",
+					"progress": 120
+				},
+				{
+					"library_id": "lib",
+					"media_path": "Shows/Episode 2.mp4",
+					"last_watched": "+002024-05-02T12:00:00.000000000Z",
+					"progress": 1150
+				},
+				{
+					"library_id": "lib",
+					"media_path": "Deleted.mp4",
+					"last_watched": "+002024-05-03T12:00:00.000000000Z",
+					"progress": 5000
+				}
+			]
+		}"#;
+		
+		let durations = [Some(Duration::from_secs(1200)), Some(Duration::from_secs(1200)), None];
+		
+		let watch_history: SerializedWatchHistory = serde_json::from_str(data).unwrap();
+		let watch_history = WatchHistory::new(migrate_entries(watch_history, &durations, 0.9).unwrap());
+		
+		let entry = watch_history.get_entry("lib", RelativePath::new("shows/episode 1")).unwrap();
+		
+		assert_eq!(entry.first_watched, entry.last_watched);
+		assert_eq!(entry.progress, 120);
+		assert_eq!(entry.play_count, 1);
+		assert!(!entry.watched);
+		
+		// Finished videos stay watched, but ones that can't be found anymore can't be told apart
+		assert!(watch_history.get_entry("lib", RelativePath::new("Shows/Episode 2")).unwrap().watched);
+		assert!(!watch_history.get_entry("lib", RelativePath::new("Deleted")).unwrap().watched);
+		
+		assert_eq!(watch_history.serialize().version, WATCH_HISTORY_VERSION);
+		
+		let future_history = SerializedWatchHistory {
+			version: WATCH_HISTORY_VERSION + 1,
+			entries: Vec::new(),
+		};
+		
+		assert!(migrate_entries(future_history, &[], 0.9).is_err());
+	}
+	
+	#[test]
+	fn test_completion() {
+		let completion = Completion {
+			duration: Duration::from_secs(100),
+			finished_threshold: 0.9,
+		};
+		let path = RelativePath::new("Video");
+		
+		let mut watch_history = WatchHistory::new(Vec::new());
+		
+		watch_history.update_progress("lib", path, 50, completion);
+		assert!(!watch_history.get_entry("lib", path).unwrap().watched);
+		
+		watch_history.update_progress("lib", path, 95, completion);
+		watch_history.update_progress("lib", path, 100, completion);
+		
+		let entry = watch_history.get_entry("lib", path).unwrap();
+		assert!(entry.watched);
+		assert_eq!(entry.play_count, 1);
+		
+		// Rewatching counts as another play
+		watch_history.update_progress("lib", path, 10, completion);
+		watch_history.update_progress("lib", path, 92, completion);
+		assert_eq!(watch_history.get_entry("lib", path).unwrap().play_count, 2);
+		
+		watch_history.set_watched("lib", path, false, completion.duration);
+		
+		let entry = watch_history.get_entry("lib", path).unwrap();
+		assert!(!entry.watched);
+		assert_eq!(entry.progress, 0);
+		assert_eq!(entry.play_count, 2);
+		
+		let other_path = RelativePath::new("Other Video");
+		
+		watch_history.set_watched("lib", other_path, false, completion.duration);
+		assert!(watch_history.get_entry("lib", other_path).is_none());
+		
+		watch_history.set_watched("lib", other_path, true, completion.duration);
+		
+		let entry = watch_history.get_entry("lib", other_path).unwrap();
+		assert!(entry.watched);
+		assert_eq!(entry.progress, 100);
+		assert_eq!(entry.play_count, 1);
+	}
+	
+	#[tokio::test]
+	async fn test_save_and_recover() {
+		let temp_dir = TempDir::new().unwrap();
+		let libraries = Libraries::from_config(LibrariesConfig { libraries: Vec::new() });
+		let metadata_cache = FileMetadataCache::new();
+		let migration = HistoryMigration {
+			libraries: &libraries,
+			metadata_cache: &metadata_cache,
+			finished_threshold: 0.9,
+		};
+		let history_file = temp_dir.path().join("user.json");
+		let path = RelativePath::new("Video");
+		
+		let completion = Completion {
+			duration: Duration::from_secs(100),
+			finished_threshold: 0.9,
+		};
+		
+		let mut watch_history = WatchHistory::new(Vec::new());
+		
+		watch_history.update_progress("lib", path, 10, completion);
+		write_data_file(&history_file, &serde_json::to_vec(&watch_history.serialize()).unwrap()).await.unwrap();
+		
+		watch_history.update_progress("lib", path, 20, completion);
+		write_data_file(&history_file, &serde_json::to_vec(&watch_history.serialize()).unwrap()).await.unwrap();
+		
+		let loaded = WatchHistory::load(history_file.clone(), &migration).await.unwrap();
+		assert_eq!(loaded.get_entry("lib", path).unwrap().progress, 20);
+		assert!(!loaded.dirty);
+		
+		// A crash in the middle of a write falls back to the previous save
+		tokio::fs::write(&history_file, b"{\"version\": 2, \"entr").await.unwrap();
+		
+		let loaded = WatchHistory::load(history_file.clone(), &migration).await.unwrap();
+		assert_eq!(loaded.get_entry("lib", path).unwrap().progress, 10);
+		assert!(loaded.dirty);
+		assert!(tokio::fs::try_exists(add_extension(&history_file, CORRUPT_EXT)).await.unwrap());
+		
+		// So does a crash between the renames
+		tokio::fs::remove_file(&history_file).await.ok();
+		
+		let loaded = WatchHistory::load(history_file.clone(), &migration).await.unwrap();
+		assert_eq!(loaded.get_entry("lib", path).unwrap().progress, 10);
+		
+		// Having nothing usable at all starts over instead of failing
+		tokio::fs::write(&history_file, b"not json").await.unwrap();
+		tokio::fs::write(add_extension(&history_file, BACKUP_EXT), b"not json either").await.unwrap();
+		
+		let loaded = WatchHistory::load(history_file.clone(), &migration).await.unwrap();
+		assert_eq!(loaded.entry_count(), 0);
+		
+		let loaded = WatchHistory::load(temp_dir.path().join("new_user.json"), &migration).await.unwrap();
+		assert_eq!(loaded.entry_count(), 0);
+	}
+	
+	#[tokio::test]
+	async fn test_import_export() {
+		let completion = Completion {
+			duration: Duration::from_secs(100),
+			finished_threshold: 0.9,
+		};
+		
+		let mut source = WatchHistory::new(Vec::new());
+		source.update_progress("lib", RelativePath::new("Shows/Episode 1"), 95, completion);
+		source.update_progress("lib", RelativePath::new("Shows/Episode 2"), 30, completion);
+		
+		let csv = source.serialize().to_csv().unwrap();
+		let imported = SerializedWatchHistory::from_csv(&csv).unwrap();
+		
+		assert_eq!(imported.entries.len(), 2);
+		assert_eq!(imported.entries[0].first_watched, source.serialize().entries[0].first_watched);
+		
+		let libraries = Libraries::from_config(LibrariesConfig { libraries: Vec::new() });
+		let metadata_cache = FileMetadataCache::new();
+		let migration = HistoryMigration {
+			libraries: &libraries,
+			metadata_cache: &metadata_cache,
+			finished_threshold: 0.9,
+		};
+		
+		let json = serde_json::to_vec(&source.serialize()).unwrap();
+		assert_eq!(SerializedWatchHistory::from_json(&json, &migration).await.unwrap().entries.len(), 2);
+		
+		let mut destination = WatchHistory::new(Vec::new());
+		destination.update_progress("lib", RelativePath::new("Shows/Episode 2"), 60, completion);
+		destination.update_progress("lib", RelativePath::new("Movie"), 10, completion);
+		
+		destination.import_entries(imported.entries);
+		
+		assert_eq!(destination.entry_count(), 3);
+		
+		// The more recent local progress wins over the imported one
+		assert_eq!(destination.get_entry("lib", RelativePath::new("Shows/Episode 2")).unwrap().progress, 60);
+		
+		let episode_1 = destination.get_entry("lib", RelativePath::new("shows/episode 1")).unwrap();
+		assert!(episode_1.watched);
+		assert_eq!(episode_1.play_count, 1);
+		
+		assert!(destination.iter_entries().is_sorted_by_key(|entry| entry.last_watched));
+	}
+	
+	#[test]
+	fn test_remap_prefix() {
+		let completion = Completion {
+			duration: Duration::from_secs(100),
+			finished_threshold: 0.9,
+		};
+		
+		let mut watch_history = WatchHistory::new(Vec::new());
+		watch_history.update_progress("lib", RelativePath::new("Old Shows/Show/Episode 1"), 10, completion);
+		watch_history.update_progress("lib", RelativePath::new("Old Shows/Show/Episode 2"), 20, completion);
+		watch_history.update_progress("lib", RelativePath::new("Old Showcase/Video"), 30, completion);
+		watch_history.update_progress("lib", RelativePath::new("Shows/Show/Episode 2"), 40, completion);
+		
+		let moved = watch_history.remap_prefix("lib", RelativePath::new("old shows"), "lib", RelativePath::new("Shows"));
+		
+		assert_eq!(moved, 2);
+		assert_eq!(watch_history.entry_count(), 3);
+		
+		assert_eq!(watch_history.get_entry("lib", RelativePath::new("Shows/Show/Episode 1")).unwrap().media_path, "Shows/Show/Episode 1");
+		assert!(watch_history.get_entry("lib", RelativePath::new("Old Showcase/Video")).is_some());
+		
+		// Entries that end up in the same place keep the more recent progress
+		assert_eq!(watch_history.get_entry("lib", RelativePath::new("Shows/Show/Episode 2")).unwrap().progress, 40);
+		
+		let moved = watch_history.remap_prefix("lib", RelativePath::new("Shows/Show/Episode 1"), "other", RelativePath::new("Episode 1"));
+		
+		assert_eq!(moved, 1);
+		assert!(watch_history.get_entry("other", RelativePath::new("Episode 1")).is_some());
+		assert!(watch_history.iter_entries().is_sorted_by_key(|entry| entry.last_watched));
+	}
+}
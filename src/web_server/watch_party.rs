@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+
+use crate::utils;
+use crate::web_server::api_types::ApiWatchPartyState;
+
+// Rooms that nobody has been connected to for this long get removed
+const ROOM_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// Keeps a single user from filling up memory with rooms
+pub const MAX_ROOMS_PER_USER: usize = 10;
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartyAction {
+	Play,
+	Pause,
+	Seek,
+}
+
+#[derive(Debug, Clone)]
+pub enum RoomEvent {
+	State(ApiWatchPartyState),
+	Closed,
+}
+
+// Rooms where several users watch the same video in sync. Only kept in memory, since they don't outlive the
+//  connections to them.
+pub struct WatchParties {
+	rooms: Arc<Mutex<HashMap<String, Room>>>,
+	next_connection_id: AtomicU64,
+}
+
+struct Room {
+	library_id: String,
+	media_path: RelativePathBuf,
+	host_id: String,
+	host_name: String,
+	playing: bool,
+	// Where playback was when it was last changed, the actual position moves on from there while playing
+	position: f64,
+	position_updated: Instant,
+	participants: Vec<(u64, String)>,
+	sender: broadcast::Sender<RoomEvent>,
+	last_active: Instant,
+}
+
+impl Room {
+	fn current_position(&self) -> f64 {
+		if self.playing {
+			self.position + self.position_updated.elapsed().as_secs_f64()
+		} else {
+			self.position
+		}
+	}
+	
+	fn snapshot(&self, room_id: &str, action: Option<PartyAction>, actor: Option<String>) -> ApiWatchPartyState {
+		ApiWatchPartyState {
+			room_id: room_id.to_owned(),
+			library_id: self.library_id.clone(),
+			media_path: self.media_path.clone(),
+			host_name: self.host_name.clone(),
+			playing: self.playing,
+			position: self.current_position(),
+			participants: self.participants.iter().map(|(_, name)| name.clone()).collect(),
+			action,
+			actor,
+			sent_at: OffsetDateTime::now_utc(),
+		}
+	}
+	
+	fn broadcast_state(&self, room_id: &str, action: Option<PartyAction>, actor: Option<String>) {
+		let _ = self.sender.send(RoomEvent::State(self.snapshot(room_id, action, actor)));
+	}
+}
+
+impl WatchParties {
+	pub fn new() -> Self {
+		let rooms = Arc::new(Mutex::new(HashMap::new()));
+		
+		tokio::spawn(Self::prune_task(rooms.clone()));
+		
+		Self {
+			rooms,
+			next_connection_id: AtomicU64::new(0),
+		}
+	}
+	
+	// Otherwise rooms that were created but never joined would stay around until someone creates another one
+	async fn prune_task(rooms: Arc<Mutex<HashMap<String, Room>>>) {
+		let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+		
+		loop {
+			interval.tick().await;
+			
+			Self::prune_idle_rooms(&mut rooms.lock().unwrap());
+		}
+	}
+	
+	fn prune_idle_rooms(rooms: &mut HashMap<String, Room>) {
+		rooms.retain(|_, room| !room.participants.is_empty() || room.last_active.elapsed() < ROOM_IDLE_TIMEOUT);
+	}
+	
+	// Returns None if the user already has MAX_ROOMS_PER_USER rooms
+	pub fn create_room(&self, host_id: &str, host_name: &str, library_id: &str, media_path: RelativePathBuf) -> Option<String> {
+		let mut rooms = self.rooms.lock().unwrap();
+		
+		Self::prune_idle_rooms(&mut rooms);
+		
+		if rooms.values().filter(|room| room.host_id == host_id).count() >= MAX_ROOMS_PER_USER {
+			return None;
+		}
+		
+		let room_id = utils::generate_random_id();
+		let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+		
+		rooms.insert(room_id.clone(), Room {
+			library_id: library_id.to_owned(),
+			media_path,
+			host_id: host_id.to_owned(),
+			host_name: host_name.to_owned(),
+			playing: false,
+			position: 0.0,
+			position_updated: Instant::now(),
+			participants: Vec::new(),
+			sender,
+			last_active: Instant::now(),
+		});
+		
+		Some(room_id)
+	}
+	
+	// The video a room is for, so that access to it can be checked before joining or controlling it
+	pub fn room_media(&self, room_id: &str) -> Option<(String, RelativePathBuf)> {
+		self.rooms.lock().unwrap()
+			.get(room_id)
+			.map(|room| (room.library_id.clone(), room.media_path.clone()))
+	}
+	
+	// Stays joined until the returned guard is dropped
+	pub fn join(&self, room_id: &str, display_name: &str) -> Option<(ParticipantGuard, broadcast::Receiver<RoomEvent>)> {
+		let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed) + 1;
+		
+		let mut rooms = self.rooms.lock().unwrap();
+		let room = rooms.get_mut(room_id)?;
+		
+		room.participants.push((connection_id, display_name.to_owned()));
+		room.last_active = Instant::now();
+		
+		let receiver = room.sender.subscribe();
+		room.broadcast_state(room_id, None, None);
+		
+		let guard = ParticipantGuard {
+			rooms: self.rooms.clone(),
+			room_id: room_id.to_owned(),
+			connection_id,
+		};
+		
+		Some((guard, receiver))
+	}
+	
+	// Returns false if the room doesn't exist
+	pub fn control(&self, room_id: &str, action: PartyAction, position: f64, actor: &str) -> bool {
+		let mut rooms = self.rooms.lock().unwrap();
+		let Some(room) = rooms.get_mut(room_id) else { return false };
+		
+		match action {
+			PartyAction::Play => room.playing = true,
+			PartyAction::Pause => room.playing = false,
+			PartyAction::Seek => {}
+		}
+		
+		room.position = position;
+		room.position_updated = Instant::now();
+		room.last_active = Instant::now();
+		
+		room.broadcast_state(room_id, Some(action), Some(actor.to_owned()));
+		
+		true
+	}
+	
+	// Only the host can close a room, returns None if it doesn't exist
+	pub fn close(&self, room_id: &str, user_id: &str) -> Option<bool> {
+		let mut rooms = self.rooms.lock().unwrap();
+		
+		if rooms.get(room_id)?.host_id != user_id {
+			return Some(false);
+		}
+		
+		let room = rooms.remove(room_id)?;
+		let _ = room.sender.send(RoomEvent::Closed);
+		
+		Some(true)
+	}
+}
+
+impl Default for WatchParties {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+pub struct ParticipantGuard {
+	rooms: Arc<Mutex<HashMap<String, Room>>>,
+	room_id: String,
+	connection_id: u64,
+}
+
+impl ParticipantGuard {
+	// Missing once the room has been closed
+	pub fn current_state(&self) -> Option<ApiWatchPartyState> {
+		self.rooms.lock().unwrap()
+			.get(&self.room_id)
+			.map(|room| room.snapshot(&self.room_id, None, None))
+	}
+}
+
+impl Drop for ParticipantGuard {
+	fn drop(&mut self) {
+		let mut rooms = self.rooms.lock().unwrap();
+		
+		if let Some(room) = rooms.get_mut(&self.room_id) {
+			room.participants.retain(|(connection_id, _)| *connection_id != self.connection_id);
+			room.last_active = Instant::now();
+			
+			room.broadcast_state(&self.room_id, None, None);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn next_state(receiver: &mut broadcast::Receiver<RoomEvent>) -> ApiWatchPartyState {
+		match receiver.try_recv().unwrap() {
+			RoomEvent::State(state) => state,
+			RoomEvent::Closed => panic!("Room closed"),
+		}
+	}
+	
+	#[tokio::test]
+	async fn test_watch_party() {
+		let parties = WatchParties::new();
+		let room_id = parties.create_room("host", "Host", "lib", RelativePathBuf::from("Videos/Birthday")).unwrap();
+		
+		assert_eq!(parties.room_media(&room_id), Some(("lib".to_owned(), RelativePathBuf::from("Videos/Birthday"))));
+		assert!(parties.join("missing", "Guest").is_none());
+		
+		let (host_guard, mut host_receiver) = parties.join(&room_id, "Host").unwrap();
+		assert_eq!(next_state(&mut host_receiver).participants, vec!["Host"]);
+		
+		let (guest_guard, mut guest_receiver) = parties.join(&room_id, "Guest").unwrap();
+		assert_eq!(next_state(&mut host_receiver).participants, vec!["Host", "Guest"]);
+		assert_eq!(next_state(&mut guest_receiver).participants, vec!["Host", "Guest"]);
+		
+		assert!(parties.control(&room_id, PartyAction::Play, 12.0, "Guest"));
+		
+		let state = next_state(&mut host_receiver);
+		assert!(state.playing);
+		assert!(state.position >= 12.0);
+		assert_eq!(state.action, Some(PartyAction::Play));
+		assert_eq!(state.actor.as_deref(), Some("Guest"));
+		
+		parties.control(&room_id, PartyAction::Pause, 20.0, "Host");
+		parties.control(&room_id, PartyAction::Seek, 45.0, "Host");
+		
+		let state = host_guard.current_state().unwrap();
+		assert!(!state.playing);
+		assert_eq!(state.position, 45.0);
+		
+		drop(guest_guard);
+		assert_eq!(host_guard.current_state().unwrap().participants, vec!["Host"]);
+		
+		assert_eq!(parties.close(&room_id, "guest"), Some(false));
+		assert_eq!(parties.close(&room_id, "host"), Some(true));
+		assert_eq!(parties.close(&room_id, "host"), None);
+		assert!(host_guard.current_state().is_none());
+		
+		// Leaving after the room is gone is fine
+		drop(host_guard);
+	}
+	
+	#[tokio::test]
+	async fn test_room_limits() {
+		let parties = WatchParties::new();
+		let media_path = RelativePathBuf::from("Videos/Birthday");
+		
+		let room_ids: Vec<String> = (0..MAX_ROOMS_PER_USER)
+			.map(|_| parties.create_room("host", "Host", "lib", media_path.clone()).unwrap())
+			.collect();
+		
+		assert!(parties.create_room("host", "Host", "lib", media_path.clone()).is_none());
+		assert!(parties.create_room("other", "Other", "lib", media_path.clone()).is_some());
+		
+		let (_guard, _) = parties.join(&room_ids[0], "Host").unwrap();
+		
+		let Some(idle_since) = Instant::now().checked_sub(ROOM_IDLE_TIMEOUT) else { return };
+		
+		for room in parties.rooms.lock().unwrap().values_mut() {
+			room.last_active = idle_since;
+		}
+		
+		// Only the room that somebody is still in is kept
+		WatchParties::prune_idle_rooms(&mut parties.rooms.lock().unwrap());
+		
+		let rooms = parties.rooms.lock().unwrap();
+		assert_eq!(rooms.len(), 1);
+		assert!(rooms.contains_key(&room_ids[0]));
+		drop(rooms);
+		
+		assert!(parties.create_room("host", "Host", "lib", media_path).is_some());
+	}
+}
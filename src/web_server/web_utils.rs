@@ -1,20 +1,22 @@
-use std::io::Write;
+use std::io::{SeekFrom, Write};
+use std::ops::Bound;
 use std::path::PathBuf;
 use std::time::SystemTime;
 
 use anyhow::{anyhow, Context};
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use flate2::write::GzEncoder;
-use headers::{HeaderMap, HeaderMapExt, IfModifiedSince, LastModified};
+use headers::{AcceptRanges, ContentLength, ContentRange, ETag, HeaderMap, HeaderMapExt, IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range};
 use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
 use http::{Method, Request, Response, StatusCode, Uri};
 use http_body_util::combinators::UnsyncBoxBody;
-use http_body_util::{BodyExt, Empty, Full};
-use hyper::body::Incoming;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
 use mime::Mime;
 use relative_path::RelativePath;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 use crate::web_server::api_error::ApiError;
 
@@ -67,7 +69,11 @@ pub fn restrict_method(request: &HyperRequest, allowed_methods: &[Method]) -> Re
 }
 
 pub async fn collect_body(body: Incoming) -> Result<Bytes, ApiError> {
-	http_body_util::Limited::new(body, 1_000_000)
+	collect_body_limited(body, 1_000_000).await
+}
+
+pub async fn collect_body_limited(body: Incoming, limit: usize) -> Result<Bytes, ApiError> {
+	http_body_util::Limited::new(body, limit)
 		.collect()
 		.await
 		.map(|collected| collected.to_bytes())
@@ -84,10 +90,10 @@ pub async fn parse_form_body<T: DeserializeOwned>(body: Incoming) -> Result<T, A
 		.and_then(|data| serde_urlencoded::from_bytes(&data).map_err(|_| ApiError::InvalidBody))
 }
 
+// A missing query is parsed like an empty one, so that parameters with defaults can be left out entirely
 pub fn parse_query<T: DeserializeOwned>(uri: &Uri) -> Result<T, ApiError> {
-	uri.query()
-		.and_then(|query| serde_urlencoded::from_str(query).ok())
-		.ok_or(ApiError::InvalidQuery)
+	serde_urlencoded::from_str(uri.query().unwrap_or_default())
+		.map_err(|_| ApiError::InvalidQuery)
 }
 
 const COMPRESSION_LEVEL: flate2::Compression = flate2::Compression::new(3);
@@ -191,6 +197,122 @@ pub async fn serve_file_compressed(
 	Ok(res)
 }
 
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// Sends a file straight from disk without reading it into memory, with support for conditional and range requests
+pub async fn serve_file_streamed(
+	mut file: tokio::fs::File,
+	file_size: u64,
+	etag: &str,
+	mod_time: SystemTime,
+	mime_type: Mime,
+	request_headers: &HeaderMap
+) -> anyhow::Result<HyperResponse> {
+	let etag: ETag = etag.parse().map_err(|_| anyhow!("Invalid ETag {}", etag))?;
+	let last_modified = LastModified::from(mod_time);
+	
+	let if_none_match: Option<IfNoneMatch> = request_headers.typed_get();
+	
+	let not_modified = match if_none_match {
+		Some(if_none_match) => !if_none_match.precondition_passes(&etag),
+		None => check_if_modified_since(mod_time, request_headers)?.is_some(),
+	};
+	
+	if not_modified {
+		let mut res = Response::builder()
+			.status(StatusCode::NOT_MODIFIED)
+			.body(empty_body())
+			.unwrap();
+		
+		res.headers_mut().typed_insert(etag);
+		res.headers_mut().typed_insert(last_modified);
+		
+		return Ok(res);
+	}
+	
+	// Ranges only apply if the client's copy of the file is still the same one
+	let range: Option<Range> = request_headers.typed_get();
+	let if_range: Option<IfRange> = request_headers.typed_get();
+	
+	let range = range.filter(|_| if_range.is_none_or(|if_range| !if_range.is_modified(Some(&etag), Some(&last_modified))));
+	
+	let mut builder = Response::builder()
+		.header(CONTENT_TYPE, mime_type.essence_str());
+	
+	let (start, length) = match range.map(|range| satisfiable_range(&range, file_size)) {
+		Some(Some((start, end))) => {
+			builder = builder.status(StatusCode::PARTIAL_CONTENT);
+			builder.headers_mut().unwrap().typed_insert(ContentRange::bytes(start..=end, file_size).unwrap());
+			
+			(start, end - start + 1)
+		}
+		Some(None) => {
+			let mut res = Response::builder()
+				.status(StatusCode::RANGE_NOT_SATISFIABLE)
+				.body(empty_body())
+				.unwrap();
+			
+			res.headers_mut().typed_insert(ContentRange::unsatisfied_bytes(file_size));
+			
+			return Ok(res);
+		}
+		None => (0, file_size),
+	};
+	
+	file.seek(SeekFrom::Start(start)).await?;
+	
+	let chunks = futures_util::stream::unfold((file, length), |(mut file, remaining)| async move {
+		if remaining == 0 {
+			return None;
+		}
+		
+		let mut buffer = BytesMut::zeroed(STREAM_CHUNK_SIZE.min(remaining as usize));
+		
+		match file.read(&mut buffer).await {
+			Ok(0) => Some((Err(anyhow!("File ended early")), (file, 0))),
+			Ok(read) => {
+				buffer.truncate(read);
+				Some((Ok(Frame::data(buffer.freeze())), (file, remaining - read as u64)))
+			}
+			Err(err) => Some((Err(err.into()), (file, 0))),
+		}
+	});
+	
+	let mut res = builder
+		.body(StreamBody::new(chunks).boxed_unsync())
+		.unwrap();
+	
+	res.headers_mut().typed_insert(ContentLength(length));
+	res.headers_mut().typed_insert(AcceptRanges::bytes());
+	res.headers_mut().typed_insert(etag);
+	res.headers_mut().typed_insert(last_modified);
+	
+	Ok(res)
+}
+
+// Multiple ranges aren't supported, so only the first one is used. Returns the first and last byte, inclusive.
+fn satisfiable_range(range: &Range, file_size: u64) -> Option<(u64, u64)> {
+	let (start, end) = range.satisfiable_ranges(file_size).next()?;
+	
+	let start = match start {
+		Bound::Included(start) => start,
+		Bound::Excluded(start) => start.checked_add(1)?,
+		Bound::Unbounded => 0,
+	};
+	
+	let end = match end {
+		Bound::Included(end) => end.min(file_size.checked_sub(1)?),
+		Bound::Excluded(end) => end.min(file_size).checked_sub(1)?,
+		Bound::Unbounded => file_size.checked_sub(1)?,
+	};
+	
+	if start > end {
+		return None;
+	}
+	
+	Some((start, end))
+}
+
 pub fn split_path(path: &str) -> anyhow::Result<Vec<String>> {
 	if !path.starts_with('/') {
 		return Err(anyhow!("Path doesn't begin with a slash"));
@@ -245,3 +367,48 @@ pub fn sanitize_path(path: &RelativePath) -> Option<PathBuf> {
 	
 	Some(out_path)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn test_satisfiable_range() {
+		let range = |value: &str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(http::header::RANGE, value.parse().unwrap());
+			
+			headers.typed_get::<Range>().unwrap()
+		};
+		
+		assert_eq!(satisfiable_range(&range("bytes=0-99"), 1000), Some((0, 99)));
+		assert_eq!(satisfiable_range(&range("bytes=500-"), 1000), Some((500, 999)));
+		assert_eq!(satisfiable_range(&range("bytes=-100"), 1000), Some((900, 999)));
+		assert_eq!(satisfiable_range(&range("bytes=900-5000"), 1000), Some((900, 999)));
+		assert_eq!(satisfiable_range(&range("bytes=10-20, 30-40"), 1000), Some((10, 20)));
+		
+		assert_eq!(satisfiable_range(&range("bytes=1000-"), 1000), None);
+		assert_eq!(satisfiable_range(&range("bytes=0-10"), 0), None);
+	}
+	
+	#[test]
+	fn test_parse_query() {
+		#[derive(Debug, PartialEq, serde::Deserialize)]
+		struct Params {
+			limit: Option<u32>,
+		}
+		
+		#[derive(Debug, serde::Deserialize)]
+		struct RequiredParams {
+			#[allow(dead_code)]
+			id: String,
+		}
+		
+		let uri = |value: &str| value.parse::<Uri>().unwrap();
+		
+		assert_eq!(parse_query::<Params>(&uri("/api/continue_watching")).unwrap(), Params { limit: None });
+		assert_eq!(parse_query::<Params>(&uri("/api/continue_watching?limit=5")).unwrap(), Params { limit: Some(5) });
+		assert!(matches!(parse_query::<Params>(&uri("/api/continue_watching?limit=x")), Err(ApiError::InvalidQuery)));
+		assert!(matches!(parse_query::<RequiredParams>(&uri("/api/playlist")), Err(ApiError::InvalidQuery)));
+	}
+}
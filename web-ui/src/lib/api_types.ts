@@ -16,12 +16,44 @@ interface ApiWatchHistoryResponse {
 	entries: ApiWatchHistoryEntry[],
 }
 
+interface ApiWatchHistoryImportResponse {
+	imported_entries: number,
+	skipped_entries: number,
+}
+
+interface ApiContinueWatchingResponse {
+	entries: ApiContinueWatchingEntry[],
+}
+
+interface ApiListPlaylistsResponse {
+	playlists: ApiPlaylistSummary[],
+}
+
+interface ApiGetPlaylistResponse {
+	playlist: ApiPlaylistSummary,
+	entries: ApiPlaylistEntry[],
+}
+
+interface ApiListFavoritesResponse {
+	entries: ApiFavoriteEntry[],
+}
+
+interface ApiListBookmarksResponse {
+	bookmarks: ApiBookmark[],
+}
+
+interface ApiCreateWatchPartyResponse {
+	room_id: string,
+}
+
 // Params
 
 interface UpdateWatchProgressParams {
 	library_id: string,
 	media_path: string,
 	new_watch_progress: number,
+	playback_state?: "playing" | "paused",
+	client_id?: string,
 }
 
 interface DeleteWatchProgressParams {
@@ -29,11 +61,123 @@ interface DeleteWatchProgressParams {
 	media_path: string,
 }
 
+interface SetWatchedParams {
+	library_id: string,
+	media_path: string,
+	watched: boolean,
+}
+
+interface PlaylistEntryParams {
+	library_id: string,
+	media_path: string,
+}
+
+interface CreatePlaylistParams {
+	name: string,
+	shared?: boolean,
+	entries?: PlaylistEntryParams[],
+}
+
+interface UpdatePlaylistParams {
+	playlist_id: string,
+	name?: string,
+	shared?: boolean,
+	entries?: PlaylistEntryParams[],
+}
+
+interface DeletePlaylistParams {
+	playlist_id: string,
+}
+
+interface FavoriteParams {
+	library_id: string,
+	media_path: string,
+}
+
+interface AddBookmarkParams {
+	library_id: string,
+	media_path: string,
+	timestamp: number,
+	name: string,
+}
+
+interface RemoveBookmarkParams {
+	bookmark_id: string,
+}
+
+interface CreateWatchPartyParams {
+	library_id: string,
+	media_path: string,
+}
+
+interface ControlWatchPartyParams {
+	room_id: string,
+	action: WatchPartyAction,
+	position: number,
+}
+
+interface CloseWatchPartyParams {
+	room_id: string,
+}
+
+interface AdminCreateUserParams {
+	id: string,
+	display_name: string,
+	username: string,
+	password: string,
+	allowed_libraries: string[],
+	admin?: boolean,
+	path_rules?: PathRule[],
+}
+
+interface AdminUpdateUserParams {
+	user_id: string,
+	display_name?: string,
+	allowed_libraries?: string[],
+	admin?: boolean,
+	disabled?: boolean,
+	path_rules?: PathRule[],
+}
+
 // Shared Types
 
 interface ApiUserInfo {
 	display_name: string,
 	username: string,
+	admin: boolean,
+	max_content_rating: ContentRating | null,
+	ratings_unlocked: boolean,
+}
+
+type ContentRating = "G" | "PG" | "PG-13" | "R" | "NC-17";
+
+interface ApiLoginOptions {
+	oidc_enabled: boolean,
+}
+
+interface ApiAdminUserEntry {
+	id: string,
+	display_name: string,
+	username: string,
+	allowed_libraries: string[],
+	admin: boolean,
+	disabled: boolean,
+	path_rules: PathRule[],
+	max_content_rating: ContentRating | null,
+	block_unrated: boolean,
+	has_rating_pin: boolean,
+	oidc_link: OidcLink | null,
+}
+
+interface OidcLink {
+	issuer: string,
+	subject: string,
+}
+
+interface PathRule {
+	library: string,
+	path: string,
+	access: "allow" | "deny",
 }
 
 interface ApiLibraryEntry {
@@ -50,6 +194,10 @@ interface ApiFileEntry {
 	file_size: number,
 	artist: string | null,
 	watch_progress: number | null,
+	watched: boolean,
+	play_count: number,
+	first_watched: string | null,
+	favorite: boolean,
 	creation_date: string,
 }
 
@@ -79,6 +227,9 @@ interface ApiFileInfo extends ApiInfoCommon {
 	prev_video: string | null,
 	next_video: string | null,
 	watch_progress: number | null,
+	watched: boolean,
+	favorite: boolean,
+	bookmarks: ApiBookmark[],
 	description: string | null,
 	connections: ApiVideoConnection[],
 	comments: ApiCommentThread[],
@@ -122,14 +273,171 @@ interface ApiComment {
 
 interface ApiDirectoryInfo extends ApiInfoCommon {}
 
+interface ApiContinueWatchingEntry {
+	kind: "in_progress" | "next_up",
+	library_id: string,
+	media_path: string,
+	file: ApiFileEntry,
+}
+
 interface ApiWatchHistoryEntry {
 	library_id: string,
 	media_path: string,
+	first_watched: string,
 	last_watched: string,
 	progress: number,
+	watched: boolean,
+	play_count: number,
 	file: ApiFileEntry | null,
 }
 
+interface ApiPlaylistSummary {
+	id: string,
+	name: string,
+	owner_name: string,
+	owned: boolean,
+	shared: boolean,
+	entry_count: number,
+	created: string,
+	updated: string,
+}
+
+interface ApiPlaylistEntry {
+	library_id: string,
+	media_path: string,
+	file: ApiFileEntry | null,
+}
+
+interface ApiWatchProgressEvent {
+	library_id: string,
+	media_path: string,
+	progress: number | null,
+	watched: boolean,
+	play_count: number,
+	last_watched: string | null,
+	client_id: string | null,
+}
+
+interface ApiWatchedDirectoryEvent {
+	library_id: string,
+	media_path: string,
+	watched: boolean,
+}
+
+type WatchPartyAction = "play" | "pause" | "seek";
+
+interface ApiWatchPartyState {
+	room_id: string,
+	library_id: string,
+	media_path: string,
+	host_name: string,
+	playing: boolean,
+	position: number,
+	participants: string[],
+	action: WatchPartyAction | null,
+	actor: string | null,
+	sent_at: string,
+}
+
+interface ApiFavoriteEntry {
+	library_id: string,
+	media_path: string,
+	added: string,
+	file: ApiFileEntry | null,
+}
+
+interface ApiBookmark {
+	id: string,
+	library_id: string,
+	media_path: string,
+	timestamp: number,
+	name: string,
+	created: string,
+}
+
+type StatsInterval = "day" | "week" | "month";
+
+interface ViewingStatsParams {
+	from?: string,
+	to?: string,
+	interval?: StatsInterval,
+}
+
+interface ApiViewingStats {
+	users: ApiUserViewingStats[],
+	directories: ApiDirectoryViewingStats[],
+	libraries: ApiLibraryActivity[],
+}
+
+interface ApiUserViewingStats {
+	user_id: string,
+	display_name: string | null,
+	hours: number,
+	sessions: number,
+}
+
+interface ApiDirectoryViewingStats {
+	library_id: string,
+	path: string,
+	hours: number,
+	sessions: number,
+}
+
+interface ApiLibraryActivity {
+	library_id: string,
+	buckets: ApiActivityBucket[],
+}
+
+interface ApiActivityBucket {
+	start: string,
+	hours: number,
+	sessions: number,
+}
+
+interface ApiCacheInfo {
+	name: string,
+	size: number,
+	entry_count: number,
+	size_limit: number,
+}
+
+interface PurgeCacheParams {
+	cache?: string,
+	library_id?: string,
+	media_path?: string,
+}
+
+interface ApiPurgeCacheResponse {
+	removed_entries: number,
+	removed_bytes: number,
+}
+
+interface ScanCachesParams {
+	cache?: string,
+}
+
+interface ApiCacheScanResult {
+	name: string,
+	valid_entries: number,
+	removed_entries: number,
+}
+
+interface ApiPrewarmJob {
+	id: string,
+	library_id: string,
+	path: string,
+	state: "idle" | "queued" | "running",
+	last_started: string | null,
+	last_finished: string | null,
+	files_total: number,
+	files_done: number,
+	files_failed: number,
+}
+
+interface PrewarmJobParams {
+	id: string,
+}
+
 interface ApiDimension {
 	width: number,
 	height: number,
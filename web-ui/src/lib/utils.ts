@@ -1,3 +1,6 @@
+// Identifies this tab in watch progress sync events, so that it can skip its own updates
+export const clientId = Math.random().toString(36).slice(2);
+
 export function formatDuration(time: number): string {
 	let hours = Math.floor(time / 3600);
 	let minutes = Math.floor(time % 3600 / 60).toString();
@@ -0,0 +1,9 @@
+import type { PageLoad } from "./$types";
+
+export const load: PageLoad = async ({ fetch }) => {
+	const res = await fetch("/api/login_options");
+	
+	const loginOptions: ApiLoginOptions = res.status == 200 ? await res.json() : { oidc_enabled: false };
+	
+	return { loginOptions };
+}